@@ -0,0 +1,2 @@
+-- Migration to record why a transaction ended up in the failed state
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS failure_reason TEXT;
@@ -0,0 +1,36 @@
+-- Migration to add a double-entry journal underneath accounts.balance
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    journal_id UUID NOT NULL, -- groups the balanced lines of one posting
+    transaction_id UUID REFERENCES transactions(id),
+    account_id UUID REFERENCES accounts(id),
+    system_account VARCHAR(50), -- e.g. 'funding' for money entering the platform
+    entry_type VARCHAR(6) NOT NULL CHECK (entry_type IN ('debit', 'credit')),
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    currency VARCHAR(3) NOT NULL,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((account_id IS NULL) <> (system_account IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_account_id ON ledger_entries(account_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_journal_id ON ledger_entries(journal_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_transaction_id ON ledger_entries(transaction_id);
+
+-- Open the journal with the balances accounts already hold
+WITH opening AS (
+    SELECT a.id AS account_id, a.balance, a.currency, gen_random_uuid() AS journal_id
+    FROM accounts a
+    WHERE a.balance <> 0
+      AND NOT EXISTS (SELECT 1 FROM ledger_entries l WHERE l.account_id = a.id)
+)
+INSERT INTO ledger_entries (journal_id, account_id, system_account, entry_type, amount, currency, description)
+SELECT journal_id, NULL, 'opening_balance',
+       CASE WHEN balance > 0 THEN 'debit' ELSE 'credit' END,
+       ABS(balance), currency, 'Opening balance'
+FROM opening
+UNION ALL
+SELECT journal_id, account_id, NULL,
+       CASE WHEN balance > 0 THEN 'credit' ELSE 'debit' END,
+       ABS(balance), currency, 'Opening balance'
+FROM opening;
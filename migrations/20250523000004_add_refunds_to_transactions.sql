@@ -0,0 +1,5 @@
+-- Migration to link refunds to the transaction they reverse
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS refund_of UUID REFERENCES transactions(id);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS refunded_amount NUMERIC(19, 4) NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_transactions_refund_of ON transactions(refund_of);
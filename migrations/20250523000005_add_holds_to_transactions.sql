@@ -0,0 +1,6 @@
+-- Migration to let pending transactions hold funds until captured or voided
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS authorized_amount NUMERIC(19, 4);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS hold_expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_transactions_active_holds ON transactions(sender_id, hold_expires_at)
+    WHERE status = 0 AND hold_expires_at IS NOT NULL;
@@ -0,0 +1,22 @@
+-- Migration for currency conversion: locked-rate quotes and the conversion applied to a transaction
+CREATE TABLE IF NOT EXISTS fx_quotes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    from_currency VARCHAR(3) NOT NULL,
+    to_currency VARCHAR(3) NOT NULL,
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    rate NUMERIC(24, 10) NOT NULL CHECK (rate > 0),
+    fee NUMERIC(19, 4) NOT NULL CHECK (fee >= 0),
+    converted_amount NUMERIC(19, 4) NOT NULL CHECK (converted_amount > 0),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS fx_quote_id UUID REFERENCES fx_quotes(id);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS fx_rate NUMERIC(24, 10);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS fx_fee NUMERIC(19, 4);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS converted_amount NUMERIC(19, 4);
+ALTER TABLE transactions ADD COLUMN IF NOT EXISTS converted_currency VARCHAR(3);
+
+CREATE INDEX IF NOT EXISTS idx_fx_quotes_user_id ON fx_quotes(user_id);
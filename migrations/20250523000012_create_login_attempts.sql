@@ -0,0 +1,12 @@
+-- Migration for brute-force protection on login
+CREATE TABLE IF NOT EXISTS login_attempts (
+    username VARCHAR(50) PRIMARY KEY, -- as given at login, whether or not such a user exists
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    blocked_until TIMESTAMPTZ -- logins are refused until then, without checking the password
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_attempts_last_failed_at ON login_attempts(last_failed_at);
+
+-- Lockouts are recorded in the audit log without an acting user
+ALTER TABLE admin_audit_log ALTER COLUMN actor_id DROP NOT NULL;
@@ -0,0 +1,10 @@
+-- Migration for rate limit counters shared by every app instance
+CREATE TABLE IF NOT EXISTS rate_limit_counters (
+    key VARCHAR(255) PRIMARY KEY,
+    window_index BIGINT NOT NULL, -- seconds since the epoch divided by the window length
+    count BIGINT NOT NULL DEFAULT 0,
+    previous_count BIGINT NOT NULL DEFAULT 0, -- requests in the window before, weighted into the sliding estimate
+    expires_at TIMESTAMPTZ NOT NULL -- once both windows have passed the row no longer matters
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limit_counters_expires_at ON rate_limit_counters(expires_at);
@@ -0,0 +1,16 @@
+-- Migration for scoped API keys used by other services instead of a login
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    prefix VARCHAR(16) NOT NULL UNIQUE, -- shown in listings so a key can be recognised
+    key_hash VARCHAR(64) NOT NULL UNIQUE, -- the key itself is only shown once, when created
+    scopes TEXT[] NOT NULL,
+    allowed_ips TEXT[], -- addresses and CIDR ranges the key may be used from; NULL allows any
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
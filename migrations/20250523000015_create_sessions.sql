@@ -0,0 +1,20 @@
+-- Migration for login sessions, one per refresh token family
+CREATE TABLE IF NOT EXISTS sessions (
+    id UUID PRIMARY KEY, -- the refresh token family id, carried in access tokens as `sid`
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user_agent VARCHAR(512),
+    ip_address VARCHAR(45),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ -- set on logout; access and refresh tokens of the session stop working
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
+
+-- Logins from before sessions were recorded, without their device details
+INSERT INTO sessions (id, user_id, created_at, last_seen_at)
+SELECT family_id, MIN(user_id::TEXT)::UUID, MIN(created_at), MAX(created_at)
+FROM refresh_tokens
+GROUP BY family_id
+HAVING BOOL_AND(revoked_at IS NULL) AND MAX(expires_at) > NOW()
+ON CONFLICT (id) DO NOTHING;
@@ -0,0 +1,41 @@
+-- Migration for one-off future-dated and recurring transfers
+CREATE TABLE IF NOT EXISTS scheduled_transfers (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- the sender
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    currency VARCHAR(3) NOT NULL,
+    description VARCHAR(200),
+    frequency VARCHAR(10) NOT NULL CHECK (frequency IN ('once', 'daily', 'weekly', 'monthly')),
+    start_at TIMESTAMPTZ NOT NULL, -- the first occurrence; later ones are counted from it
+    end_at TIMESTAMPTZ, -- no occurrences after this
+    max_occurrences INTEGER CHECK (max_occurrences > 0),
+    on_insufficient_funds VARCHAR(10) NOT NULL DEFAULT 'skip' CHECK (on_insufficient_funds IN ('skip', 'retry')),
+    status VARCHAR(10) NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'paused', 'cancelled', 'completed')),
+    occurrence INTEGER NOT NULL DEFAULT 0, -- index of the occurrence due at next_run_at
+    attempt INTEGER NOT NULL DEFAULT 0, -- retries already made for that occurrence
+    next_run_at TIMESTAMPTZ, -- NULL once the schedule is cancelled or completed
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS scheduled_transfer_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    scheduled_transfer_id UUID NOT NULL REFERENCES scheduled_transfers(id) ON DELETE CASCADE,
+    occurrence INTEGER NOT NULL,
+    attempt INTEGER NOT NULL,
+    status VARCHAR(10) NOT NULL CHECK (status IN ('succeeded', 'failed', 'skipped', 'retrying')),
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+DROP TRIGGER IF EXISTS update_scheduled_transfers_updated_at ON scheduled_transfers;
+CREATE TRIGGER update_scheduled_transfers_updated_at
+BEFORE UPDATE ON scheduled_transfers
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at_column();
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_transfers_user_id ON scheduled_transfers(user_id);
+CREATE INDEX IF NOT EXISTS idx_scheduled_transfers_due ON scheduled_transfers(next_run_at) WHERE status = 'active';
+CREATE INDEX IF NOT EXISTS idx_scheduled_transfer_runs_scheduled_transfer_id ON scheduled_transfer_runs(scheduled_transfer_id);
@@ -0,0 +1,25 @@
+-- Migration for pull-style payment requests between users
+CREATE TABLE IF NOT EXISTS payment_requests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    requester_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- receives the money
+    payer_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- asked to send it
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    currency VARCHAR(3) NOT NULL,
+    note VARCHAR(200),
+    status VARCHAR(10) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'declined', 'cancelled', 'expired')),
+    transaction_id UUID REFERENCES transactions(id), -- the transfer made on acceptance
+    expires_at TIMESTAMPTZ NOT NULL,
+    responded_at TIMESTAMPTZ, -- when it left pending
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+DROP TRIGGER IF EXISTS update_payment_requests_updated_at ON payment_requests;
+CREATE TRIGGER update_payment_requests_updated_at
+BEFORE UPDATE ON payment_requests
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at_column();
+
+CREATE INDEX IF NOT EXISTS idx_payment_requests_requester_id ON payment_requests(requester_id);
+CREATE INDEX IF NOT EXISTS idx_payment_requests_payer_id ON payment_requests(payer_id);
+CREATE INDEX IF NOT EXISTS idx_payment_requests_pending_expiry ON payment_requests(expires_at) WHERE status = 'pending';
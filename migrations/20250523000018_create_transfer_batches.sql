@@ -0,0 +1,26 @@
+-- Migration for batches of transfers submitted together
+CREATE TABLE IF NOT EXISTS transfer_batches (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- the sender of every item
+    mode VARCHAR(20) NOT NULL CHECK (mode IN ('all_or_nothing', 'best_effort')),
+    status VARCHAR(20) NOT NULL CHECK (status IN ('completed', 'partially_completed', 'failed')),
+    currency VARCHAR(3) NOT NULL,
+    total_amount NUMERIC(19, 4) NOT NULL,
+    item_count INTEGER NOT NULL,
+    succeeded_count INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS transfer_batch_items (
+    batch_id UUID NOT NULL REFERENCES transfer_batches(id) ON DELETE CASCADE,
+    item_index INTEGER NOT NULL, -- position in the submitted batch, from 0
+    recipient_id UUID NOT NULL,
+    amount NUMERIC(19, 4) NOT NULL,
+    description VARCHAR(200),
+    status VARCHAR(10) NOT NULL CHECK (status IN ('succeeded', 'failed', 'aborted')),
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+    PRIMARY KEY (batch_id, item_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_transfer_batches_user_id ON transfer_batches(user_id);
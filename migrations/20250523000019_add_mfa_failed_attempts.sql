@@ -0,0 +1,3 @@
+-- Migration to count wrong MFA codes per user and lock out further guesses
+ALTER TABLE user_mfa ADD COLUMN IF NOT EXISTS failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE user_mfa ADD COLUMN IF NOT EXISTS locked_until TIMESTAMPTZ;
@@ -0,0 +1,8 @@
+-- Migration to record best-effort batches before their transfers are made
+ALTER TABLE transfer_batches DROP CONSTRAINT IF EXISTS transfer_batches_status_check;
+ALTER TABLE transfer_batches ADD CONSTRAINT transfer_batches_status_check
+    CHECK (status IN ('processing', 'completed', 'partially_completed', 'failed'));
+
+ALTER TABLE transfer_batch_items DROP CONSTRAINT IF EXISTS transfer_batch_items_status_check;
+ALTER TABLE transfer_batch_items ADD CONSTRAINT transfer_batch_items_status_check
+    CHECK (status IN ('pending', 'succeeded', 'failed', 'aborted'));
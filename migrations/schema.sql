@@ -6,7 +6,11 @@ CREATE TABLE IF NOT EXISTS users (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
     username VARCHAR(50) NOT NULL UNIQUE,
     email VARCHAR(255) NOT NULL UNIQUE,
+    email_verified_at TIMESTAMPTZ, -- NULL until the address is confirmed
+    pending_email VARCHAR(255), -- a new address waiting to be confirmed
     password_hash VARCHAR(255) NOT NULL,
+    role VARCHAR(20) NOT NULL DEFAULT 'user' CONSTRAINT users_role_check
+        CHECK (role IN ('user', 'support', 'admin', 'auditor')),
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
@@ -22,6 +26,21 @@ CREATE TABLE IF NOT EXISTS accounts (
     UNIQUE(user_id, currency)
 );
 
+-- Create FX quotes table: a conversion rate locked for a short time
+CREATE TABLE IF NOT EXISTS fx_quotes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    from_currency VARCHAR(3) NOT NULL,
+    to_currency VARCHAR(3) NOT NULL,
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0), -- in from_currency
+    rate NUMERIC(24, 10) NOT NULL CHECK (rate > 0),
+    fee NUMERIC(19, 4) NOT NULL CHECK (fee >= 0), -- spread kept, in from_currency
+    converted_amount NUMERIC(19, 4) NOT NULL CHECK (converted_amount > 0), -- in to_currency
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ, -- set once a transfer pays with the quote
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
 -- Create transactions table
 CREATE TABLE IF NOT EXISTS transactions (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
@@ -30,11 +49,248 @@ CREATE TABLE IF NOT EXISTS transactions (
     amount NUMERIC(19, 4) NOT NULL,
     currency VARCHAR(3) NOT NULL,
     description TEXT DEFAULT '',
-    status INTEGER NOT NULL DEFAULT 0, -- 0: pending, 1: completed, 2: failed
+    status INTEGER NOT NULL DEFAULT 0, -- 0: pending, 1: completed, 2: failed, 3: voided, 4: expired
+    failure_reason TEXT,
+    refund_of UUID REFERENCES transactions(id), -- set on refunds
+    refunded_amount NUMERIC(19, 4) NOT NULL DEFAULT 0, -- total of completed refunds
+    authorized_amount NUMERIC(19, 4), -- set on holds placed with capture: false
+    hold_expires_at TIMESTAMPTZ, -- pending holds stop reserving funds after this
+    fx_quote_id UUID REFERENCES fx_quotes(id), -- the quote a conversion used, if any
+    fx_rate NUMERIC(24, 10), -- set on transfers converted between currencies
+    fx_fee NUMERIC(19, 4), -- spread kept, in the transaction currency
+    converted_amount NUMERIC(19, 4), -- credited to the recipient
+    converted_currency VARCHAR(3),
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
 
+-- Create ledger entries table: the double-entry journal behind accounts.balance
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    journal_id UUID NOT NULL, -- groups the balanced lines of one posting
+    transaction_id UUID REFERENCES transactions(id),
+    account_id UUID REFERENCES accounts(id),
+    system_account VARCHAR(50), -- e.g. 'funding' for money entering the platform
+    entry_type VARCHAR(6) NOT NULL CHECK (entry_type IN ('debit', 'credit')),
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    currency VARCHAR(3) NOT NULL,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((account_id IS NULL) <> (system_account IS NULL))
+);
+
+-- Create idempotency keys table
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    user_id UUID NOT NULL, -- nil UUID for unauthenticated callers
+    idempotency_key VARCHAR(255) NOT NULL,
+    request_hash VARCHAR(64) NOT NULL,
+    response_status INTEGER, -- NULL while the first request is in flight
+    response_content_type VARCHAR(255),
+    response_body BYTEA,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, idempotency_key)
+);
+
+-- Create refresh tokens table: only a hash of each token is kept
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    family_id UUID NOT NULL, -- shared by every token rotated from one login
+    token_hash VARCHAR(64) NOT NULL UNIQUE, -- SHA-256 of the token
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ, -- set once rotated; reuse after this revokes the family
+    revoked_at TIMESTAMPTZ, -- set on logout or detected reuse
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create sessions table: one per login, shared by the refresh token family it started
+CREATE TABLE IF NOT EXISTS sessions (
+    id UUID PRIMARY KEY, -- the refresh token family id, carried in access tokens as `sid`
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    user_agent VARCHAR(512),
+    ip_address VARCHAR(45),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMPTZ -- set on logout; access and refresh tokens of the session stop working
+);
+
+-- Create revoked tokens table: access tokens ended early by logout
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL, -- purged once the token would have expired anyway
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create admin audit log table: one row per privileged action
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    actor_id UUID REFERENCES users(id), -- the admin, support or auditor user acting; NULL for automatic actions such as lockouts
+    action VARCHAR(50) NOT NULL, -- e.g. 'fund_account', 'refund_transaction'
+    target_id UUID, -- the user or transaction acted on
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create MFA table: a user's TOTP authenticator and step-up settings
+CREATE TABLE IF NOT EXISTS user_mfa (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret VARCHAR(64) NOT NULL, -- base32 TOTP secret shared with the authenticator
+    enabled_at TIMESTAMPTZ, -- NULL until enrollment is verified with a code
+    last_used_step BIGINT, -- last accepted TOTP time step, so codes cannot be replayed
+    step_up_threshold NUMERIC(19, 4) CHECK (step_up_threshold >= 0), -- NULL uses the default
+    step_up_currency VARCHAR(3),
+    failed_attempts INTEGER NOT NULL DEFAULT 0, -- wrong codes since the last right one or lockout
+    locked_until TIMESTAMPTZ, -- codes are refused until then after too many wrong ones
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((step_up_threshold IS NULL) = (step_up_currency IS NULL))
+);
+
+-- Create MFA recovery codes table: one-time codes, only hashes are kept
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL, -- SHA-256 of the normalized code
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create MFA challenges table: a password login waiting for its second factor
+CREATE TABLE IF NOT EXISTS mfa_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE, -- SHA-256 of the challenge token
+    attempts INTEGER NOT NULL DEFAULT 0, -- wrong codes tried against this challenge
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create password reset tokens table: single-use, only a hash of each token is kept
+CREATE TABLE IF NOT EXISTS password_reset_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE, -- SHA-256 of the emailed token
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ, -- set once the password is reset, or when a newer token replaces it
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create email verification tokens table: single-use, only a hash of each token is kept
+CREATE TABLE IF NOT EXISTS email_verification_tokens (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL, -- the address the token confirms
+    token_hash VARCHAR(64) NOT NULL UNIQUE, -- SHA-256 of the emailed token
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ, -- set once confirmed, or when a newer token replaces it
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create login attempts table: recent failed logins per username, for backoff and lockout
+CREATE TABLE IF NOT EXISTS login_attempts (
+    username VARCHAR(50) PRIMARY KEY, -- as given at login, whether or not such a user exists
+    failed_count INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    blocked_until TIMESTAMPTZ -- logins are refused until then, without checking the password
+);
+
+-- Create rate limit counters table: sliding window request counts shared by every app instance
+CREATE TABLE IF NOT EXISTS rate_limit_counters (
+    key VARCHAR(255) PRIMARY KEY,
+    window_index BIGINT NOT NULL, -- seconds since the epoch divided by the window length
+    count BIGINT NOT NULL DEFAULT 0,
+    previous_count BIGINT NOT NULL DEFAULT 0, -- requests in the window before, weighted into the sliding estimate
+    expires_at TIMESTAMPTZ NOT NULL -- once both windows have passed the row no longer matters
+);
+
+-- Create API keys table: scoped keys used by other services instead of a login
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    prefix VARCHAR(16) NOT NULL UNIQUE, -- shown in listings so a key can be recognised
+    key_hash VARCHAR(64) NOT NULL UNIQUE, -- the key itself is only shown once, when created
+    scopes TEXT[] NOT NULL,
+    allowed_ips TEXT[], -- addresses and CIDR ranges the key may be used from; NULL allows any
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create scheduled transfers table: one-off future-dated and recurring transfers
+CREATE TABLE IF NOT EXISTS scheduled_transfers (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- the sender
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    currency VARCHAR(3) NOT NULL,
+    description VARCHAR(200),
+    frequency VARCHAR(10) NOT NULL CHECK (frequency IN ('once', 'daily', 'weekly', 'monthly')),
+    start_at TIMESTAMPTZ NOT NULL, -- the first occurrence; later ones are counted from it
+    end_at TIMESTAMPTZ, -- no occurrences after this
+    max_occurrences INTEGER CHECK (max_occurrences > 0),
+    on_insufficient_funds VARCHAR(10) NOT NULL DEFAULT 'skip' CHECK (on_insufficient_funds IN ('skip', 'retry')),
+    status VARCHAR(10) NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'paused', 'cancelled', 'completed')),
+    occurrence INTEGER NOT NULL DEFAULT 0, -- index of the occurrence due at next_run_at
+    attempt INTEGER NOT NULL DEFAULT 0, -- retries already made for that occurrence
+    next_run_at TIMESTAMPTZ, -- NULL once the schedule is cancelled or completed
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create scheduled transfer runs table: the outcome of each attempt at an occurrence
+CREATE TABLE IF NOT EXISTS scheduled_transfer_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    scheduled_transfer_id UUID NOT NULL REFERENCES scheduled_transfers(id) ON DELETE CASCADE,
+    occurrence INTEGER NOT NULL,
+    attempt INTEGER NOT NULL,
+    status VARCHAR(10) NOT NULL CHECK (status IN ('succeeded', 'failed', 'skipped', 'retrying')),
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS payment_requests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    requester_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- receives the money
+    payer_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- asked to send it
+    amount NUMERIC(19, 4) NOT NULL CHECK (amount > 0),
+    currency VARCHAR(3) NOT NULL,
+    note VARCHAR(200),
+    status VARCHAR(10) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'declined', 'cancelled', 'expired')),
+    transaction_id UUID REFERENCES transactions(id), -- the transfer made on acceptance
+    expires_at TIMESTAMPTZ NOT NULL,
+    responded_at TIMESTAMPTZ, -- when it left pending
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS transfer_batches (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE, -- the sender of every item
+    mode VARCHAR(20) NOT NULL CHECK (mode IN ('all_or_nothing', 'best_effort')),
+    status VARCHAR(20) NOT NULL CHECK (status IN ('processing', 'completed', 'partially_completed', 'failed')),
+    currency VARCHAR(3) NOT NULL,
+    total_amount NUMERIC(19, 4) NOT NULL,
+    item_count INTEGER NOT NULL,
+    succeeded_count INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS transfer_batch_items (
+    batch_id UUID NOT NULL REFERENCES transfer_batches(id) ON DELETE CASCADE,
+    item_index INTEGER NOT NULL, -- position in the submitted batch, from 0
+    recipient_id UUID NOT NULL,
+    amount NUMERIC(19, 4) NOT NULL,
+    description VARCHAR(200),
+    status VARCHAR(10) NOT NULL CHECK (status IN ('pending', 'succeeded', 'failed', 'aborted')),
+    transaction_id UUID REFERENCES transactions(id),
+    error TEXT,
+    PRIMARY KEY (batch_id, item_index)
+);
+
 -- Create trigger to update the updated_at timestamp automatically
 CREATE OR REPLACE FUNCTION update_updated_at_column()
 RETURNS TRIGGER AS $$
@@ -62,8 +318,57 @@ BEFORE UPDATE ON transactions
 FOR EACH ROW
 EXECUTE FUNCTION update_updated_at_column();
 
+-- Apply trigger to user_mfa table
+CREATE TRIGGER update_user_mfa_updated_at
+BEFORE UPDATE ON user_mfa
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at_column();
+
+-- Apply trigger to scheduled_transfers table
+CREATE TRIGGER update_scheduled_transfers_updated_at
+BEFORE UPDATE ON scheduled_transfers
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at_column();
+
+-- Apply trigger to payment_requests table
+CREATE TRIGGER update_payment_requests_updated_at
+BEFORE UPDATE ON payment_requests
+FOR EACH ROW
+EXECUTE FUNCTION update_updated_at_column();
+
 -- Create indexes for faster lookups
 CREATE INDEX IF NOT EXISTS idx_accounts_user_id ON accounts(user_id);
 CREATE INDEX IF NOT EXISTS idx_transactions_sender_id ON transactions(sender_id);
 CREATE INDEX IF NOT EXISTS idx_transactions_recipient_id ON transactions(recipient_id);
 CREATE INDEX IF NOT EXISTS idx_transactions_created_at ON transactions(created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_transactions_refund_of ON transactions(refund_of);
+CREATE INDEX IF NOT EXISTS idx_transactions_active_holds ON transactions(sender_id, hold_expires_at)
+    WHERE status = 0 AND hold_expires_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_fx_quotes_user_id ON fx_quotes(user_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_account_id ON ledger_entries(account_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_journal_id ON ledger_entries(journal_id);
+CREATE INDEX IF NOT EXISTS idx_ledger_entries_transaction_id ON ledger_entries(transaction_id);
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys(created_at);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_family_id ON refresh_tokens(family_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id ON refresh_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_expires_at ON refresh_tokens(expires_at);
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_revoked_tokens_expires_at ON revoked_tokens(expires_at);
+CREATE INDEX IF NOT EXISTS idx_admin_audit_log_created_at ON admin_audit_log(created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_admin_audit_log_actor_id ON admin_audit_log(actor_id);
+CREATE INDEX IF NOT EXISTS idx_mfa_recovery_codes_user_id ON mfa_recovery_codes(user_id);
+CREATE INDEX IF NOT EXISTS idx_mfa_challenges_expires_at ON mfa_challenges(expires_at);
+CREATE INDEX IF NOT EXISTS idx_password_reset_tokens_user_id ON password_reset_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_password_reset_tokens_expires_at ON password_reset_tokens(expires_at);
+CREATE INDEX IF NOT EXISTS idx_email_verification_tokens_user_id ON email_verification_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_email_verification_tokens_expires_at ON email_verification_tokens(expires_at);
+CREATE INDEX IF NOT EXISTS idx_login_attempts_last_failed_at ON login_attempts(last_failed_at);
+CREATE INDEX IF NOT EXISTS idx_rate_limit_counters_expires_at ON rate_limit_counters(expires_at);
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
+CREATE INDEX IF NOT EXISTS idx_scheduled_transfers_user_id ON scheduled_transfers(user_id);
+CREATE INDEX IF NOT EXISTS idx_scheduled_transfers_due ON scheduled_transfers(next_run_at) WHERE status = 'active';
+CREATE INDEX IF NOT EXISTS idx_scheduled_transfer_runs_scheduled_transfer_id ON scheduled_transfer_runs(scheduled_transfer_id);
+CREATE INDEX IF NOT EXISTS idx_payment_requests_requester_id ON payment_requests(requester_id);
+CREATE INDEX IF NOT EXISTS idx_payment_requests_payer_id ON payment_requests(payer_id);
+CREATE INDEX IF NOT EXISTS idx_payment_requests_pending_expiry ON payment_requests(expires_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_transfer_batches_user_id ON transfer_batches(user_id);
@@ -2,22 +2,104 @@ use std::process::Command;
 use std::io::Write;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // With --rotate the current key is kept for verifying existing tokens
+    let rotate = args.iter().any(|arg| arg == "--rotate");
+
+    // With --algorithm RS256, ES256 or EdDSA a key pair is generated instead of a secret
+    let algorithm = args
+        .iter()
+        .position(|arg| arg == "--algorithm")
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+        .unwrap_or("HS256");
+
+    match algorithm {
+        "HS256" => generate_secret(rotate),
+        "RS256" | "ES256" | "EdDSA" => generate_key_pair(algorithm, rotate),
+        _ => Err(format!("Unsupported algorithm {}; use HS256, RS256, ES256 or EdDSA", algorithm).into()),
+    }?;
+
+    if rotate {
+        println!("Send SIGHUP to the server or call POST /admin/keys/reload to start using it");
+    }
+
+    Ok(())
+}
+
+fn generate_secret(rotate: bool) -> Result<(), Box<dyn std::error::Error>> {
     // Generate a random 32-byte key for JWT signing
     let output = Command::new("openssl")
-        .args(&["rand", "-base64", "32"])
+        .args(["rand", "-base64", "32"])
         .output()?;
-    
+
     if !output.status.success() {
         return Err("Failed to generate JWT secret".into());
     }
-    
+
     let jwt_secret = String::from_utf8(output.stdout)?;
-    
+
+    if rotate {
+        if let Ok(current) = std::fs::read_to_string("jwt_secret.txt") {
+            let previous_file = std::env::var("JWT_PREVIOUS_SECRETS_FILE")
+                .unwrap_or_else(|_| "jwt_secrets_previous.txt".to_string());
+            let previous = std::fs::read_to_string(&previous_file).unwrap_or_default();
+
+            // Newest first, so the list can be trimmed from the bottom
+            std::fs::write(&previous_file, format!("{}\n{}", current.trim(), previous))?;
+            println!("Moved the current JWT secret to {}", previous_file);
+        }
+    }
+
     // Write to file
     let mut file = std::fs::File::create("jwt_secret.txt")?;
     file.write_all(jwt_secret.trim().as_bytes())?;
-    
+
     println!("Created JWT secret and saved to jwt_secret.txt");
-    
+    Ok(())
+}
+
+fn generate_key_pair(algorithm: &str, rotate: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let private_file = std::env::var("JWT_PRIVATE_KEY_FILE").unwrap_or_else(|_| "jwt_private_key.pem".to_string());
+    let public_file = std::env::var("JWT_PUBLIC_KEY_FILE").unwrap_or_else(|_| "jwt_public_key.pem".to_string());
+
+    if rotate {
+        if let Ok(current) = std::fs::read_to_string(&public_file) {
+            let previous_file = std::env::var("JWT_PREVIOUS_PUBLIC_KEYS_FILE")
+                .unwrap_or_else(|_| "jwt_previous_public_keys.pem".to_string());
+            let previous = std::fs::read_to_string(&previous_file).unwrap_or_default();
+
+            // Newest first, so the list can be trimmed from the bottom
+            std::fs::write(&previous_file, format!("{}\n{}", current.trim(), previous))?;
+            println!("Moved the current JWT public key to {}", previous_file);
+        }
+    }
+
+    let key_args: &[&str] = match algorithm {
+        "RS256" => &["-algorithm", "RSA", "-pkeyopt", "rsa_keygen_bits:2048"],
+        "ES256" => &["-algorithm", "EC", "-pkeyopt", "ec_paramgen_curve:P-256"],
+        _ => &["-algorithm", "ED25519"],
+    };
+
+    // PKCS#8, which is what the server expects for every algorithm
+    let status = Command::new("openssl")
+        .arg("genpkey")
+        .args(key_args)
+        .args(["-out", &private_file])
+        .status()?;
+    if !status.success() {
+        return Err("Failed to generate JWT private key".into());
+    }
+
+    let status = Command::new("openssl")
+        .args(["pkey", "-in", &private_file, "-pubout", "-out", &public_file])
+        .status()?;
+    if !status.success() {
+        return Err("Failed to extract JWT public key".into());
+    }
+
+    println!("Created {} key pair in {} and {}", algorithm, private_file, public_file);
+    println!("Set JWT_ALGORITHM={} for the server to sign with it", algorithm);
     Ok(())
 }
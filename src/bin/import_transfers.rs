@@ -0,0 +1,97 @@
+use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+
+use dodo_payments::config::{BatchConfig, TransferConfig};
+use dodo_payments::handlers::batch::{load_batch, submit_batch};
+use dodo_payments::handlers::import::check_with_accounts;
+use dodo_payments::models::{BatchItemStatus, BatchMode, ImportFormat};
+use dodo_payments::utils::import::parse_transfers;
+
+const USAGE: &str = "Usage: import_transfers <file> [--format csv|pain001] [--sender <user id>] \
+                     [--mode all_or_nothing|best_effort] [--execute]";
+
+// Check a CSV or pain.001 file of transfers and, with --execute, make them as
+// a batch from --sender. Without --sender only the file itself is checked and
+// no database is needed. Transfers made here skip MFA step-up, as this is an
+// operator tool with direct database access.
+#[actix_web::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenv::dotenv().ok();
+    let args: Vec<String> = std::env::args().collect();
+
+    let option = |name: &str| args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1));
+    let path = args.get(1).filter(|path| !path.starts_with("--")).ok_or(USAGE)?;
+    let execute = args.iter().any(|arg| arg == "--execute");
+
+    // The format follows the file extension unless --format is given
+    let format = match option("--format").map(String::as_str) {
+        Some("csv") => ImportFormat::Csv,
+        Some("pain001") => ImportFormat::Pain001,
+        Some(format) => return Err(format!("Unsupported format {}; use csv or pain001", format).into()),
+        None if path.ends_with(".csv") => ImportFormat::Csv,
+        None if path.ends_with(".xml") => ImportFormat::Pain001,
+        None => return Err("Cannot tell the format from the file name; pass --format csv or pain001".into()),
+    };
+    let mode = match option("--mode").map(String::as_str) {
+        None | Some("all_or_nothing") => BatchMode::AllOrNothing,
+        Some("best_effort") => BatchMode::BestEffort,
+        Some(mode) => return Err(format!("Unsupported mode {}; use all_or_nothing or best_effort", mode).into()),
+    };
+    let sender = option("--sender")
+        .map(|sender| Uuid::parse_str(sender).map_err(|_| format!("--sender {} is not a valid user id", sender)))
+        .transpose()?;
+    if execute && sender.is_none() {
+        return Err("--execute needs --sender <user id>".into());
+    }
+
+    let input = String::from_utf8(std::fs::read(path)?).map_err(|_| "The file must be UTF-8 encoded")?;
+    let mut parsed = parse_transfers(format, &input, BatchConfig::from_env().max_items);
+
+    let pool = match sender {
+        Some(sender) => {
+            let database_url = std::env::var("DATABASE_URL").map_err(|_| "--sender needs DATABASE_URL to be set")?;
+            let pool = PgPoolOptions::new().max_connections(2).connect(&database_url).await?;
+            check_with_accounts(&pool, sender, &mut parsed).await.map_err(|err| err.to_string())?;
+            Some(pool)
+        }
+        None => None,
+    };
+
+    let report = parsed.report(format, !execute);
+    for error in &report.errors {
+        match error.line {
+            Some(line) => println!("{}:{}: {}", path, line, error.message),
+            None => println!("{}: {}", path, error.message),
+        }
+    }
+    if !report.valid {
+        let problems = if report.errors.len() == 1 { "problem" } else { "problems" };
+        return Err(format!("Found {} {}; nothing was imported", report.errors.len(), problems).into());
+    }
+
+    let total = parsed.total().ok_or("The file holds no transfers")?;
+    println!("{} transfers totalling {} {}", report.item_count, total, total.currency());
+
+    let (pool, sender) = match (pool, sender) {
+        (Some(pool), Some(sender)) if execute => (pool, sender),
+        _ => {
+            println!("Dry run; pass --sender <user id> --execute to make these transfers");
+            return Ok(());
+        }
+    };
+
+    let batch_id = submit_batch(&pool, &TransferConfig::from_env(), sender, mode, &parsed.requests(), &total)
+        .await
+        .map_err(|err| err.to_string())?;
+    let batch = load_batch(&pool, batch_id, sender).await.map_err(|err| err.to_string())?;
+
+    println!("Batch {} {}: {} of {} transfers made", batch.id, batch.status.as_str(), batch.succeeded_count, batch.item_count);
+    for (item, transfer) in batch.items.iter().zip(&parsed.transfers) {
+        if item.status != BatchItemStatus::Succeeded {
+            let error = item.error.as_deref().unwrap_or("not made because another transfer failed");
+            println!("{}:{}: {}", path, transfer.line, error);
+        }
+    }
+
+    Ok(())
+}
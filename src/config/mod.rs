@@ -1,12 +1,21 @@
 use std::env;
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
+use bigdecimal::BigDecimal;
 use dotenv::dotenv;
-use log::info;
+use log::{info, warn};
+
+use crate::middleware::rate_limit::{Quota, RateLimitKey, RateLimitPolicy, TrustedProxies};
+use crate::models::{Currency, Money};
+use crate::utils::fx::{FileFxRateProvider, FxRateProvider};
+use crate::utils::mailer::{FileMailer, Mailer};
 
 pub struct Config {
     pub database_url: String,
-    pub jwt_secret: String,
+    pub jwt_keys: JwtKeyConfig,
     pub server_addr: String,
     pub rust_log: String,
 }
@@ -18,8 +27,8 @@ impl Config {
         // Get database URL with potential Docker secrets
         let database_url = Self::get_database_url();
         
-        // Get JWT secret from Docker secrets or environment variable
-        let jwt_secret = Self::get_jwt_secret();
+        // Get JWT secrets from Docker secrets, local files or environment variable
+        let jwt_keys = JwtKeyConfig::load();
         
         let server_addr = env::var("SERVER_ADDR")
             .unwrap_or_else(|_| "127.0.0.1:8080".to_string());
@@ -29,7 +38,7 @@ impl Config {
         
         Self {
             database_url,
-            jwt_secret,
+            jwt_keys,
             server_addr,
             rust_log,
         }
@@ -91,7 +100,7 @@ impl Config {
         })
     }
     
-    fn get_jwt_secret() -> String {
+    pub fn get_jwt_secret() -> String {
         // First try to read from Docker secret file
         if let Ok(secret) = std::fs::read_to_string("/run/secrets/jwt_secret") {
             let secret = secret.trim();
@@ -121,6 +130,484 @@ impl Config {
 }
 
 
+// Defaults for JWT signing
+const DEFAULT_JWT_ALGORITHM: &str = "HS256";
+const DEFAULT_JWT_PREVIOUS_SECRETS_FILE: &str = "jwt_secrets_previous.txt";
+const DEFAULT_JWT_PRIVATE_KEY_FILE: &str = "jwt_private_key.pem";
+const DEFAULT_JWT_PUBLIC_KEY_FILE: &str = "jwt_public_key.pem";
+const DEFAULT_JWT_PREVIOUS_PUBLIC_KEYS_FILE: &str = "jwt_previous_public_keys.pem";
+const DEFAULT_JWT_ISSUER: &str = "dodo-payments";
+const DEFAULT_JWT_AUDIENCE: &str = "dodo-payments";
+
+/// The keys behind `utils::keys::KeyStore`. Loaded at startup and again
+/// whenever the key store is reloaded, so rotating a key only needs the
+/// files updated.
+#[derive(Clone)]
+pub struct JwtKeyConfig {
+    // HS256, RS256, ES256 or EdDSA
+    pub algorithm: String,
+    // Signs new tokens with HS256
+    pub secret: String,
+    // Retired HS256 secrets whose tokens are still accepted until they expire
+    pub previous_secrets: Vec<String>,
+    // PEM key pair that signs new tokens with the asymmetric algorithms
+    pub private_key_pem: Option<String>,
+    pub public_key_pem: Option<String>,
+    // Retired PEM public keys, concatenated, still accepted and published
+    pub previous_public_keys_pem: Option<String>,
+    // Expected `iss` and `aud` of every token
+    pub issuer: String,
+    pub audience: String,
+}
+
+impl JwtKeyConfig {
+    pub fn load() -> Self {
+        let previous_secrets = read_key_file("JWT_PREVIOUS_SECRETS_FILE", DEFAULT_JWT_PREVIOUS_SECRETS_FILE)
+            .map(|secrets| {
+                secrets
+                    .lines()
+                    .map(str::trim)
+                    .filter(|secret| !secret.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        
+        // Docker secrets take precedence over local files, as for the HS256 secret
+        let private_key_pem = std::fs::read_to_string("/run/secrets/jwt_private_key")
+            .ok()
+            .or_else(|| read_key_file("JWT_PRIVATE_KEY_FILE", DEFAULT_JWT_PRIVATE_KEY_FILE));
+        
+        Self {
+            algorithm: env::var("JWT_ALGORITHM").unwrap_or_else(|_| DEFAULT_JWT_ALGORITHM.to_string()),
+            secret: Config::get_jwt_secret(),
+            previous_secrets,
+            private_key_pem,
+            public_key_pem: read_key_file("JWT_PUBLIC_KEY_FILE", DEFAULT_JWT_PUBLIC_KEY_FILE),
+            previous_public_keys_pem: read_key_file("JWT_PREVIOUS_PUBLIC_KEYS_FILE", DEFAULT_JWT_PREVIOUS_PUBLIC_KEYS_FILE),
+            issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| DEFAULT_JWT_ISSUER.to_string()),
+            audience: env::var("JWT_AUDIENCE").unwrap_or_else(|_| DEFAULT_JWT_AUDIENCE.to_string()),
+        }
+    }
+    
+    /// HS256 with `secret`, for tools and tests
+    pub fn from_secret(secret: &str) -> Self {
+        Self {
+            algorithm: DEFAULT_JWT_ALGORITHM.to_string(),
+            secret: secret.to_string(),
+            previous_secrets: Vec::new(),
+            private_key_pem: None,
+            public_key_pem: None,
+            previous_public_keys_pem: None,
+            issuer: DEFAULT_JWT_ISSUER.to_string(),
+            audience: DEFAULT_JWT_AUDIENCE.to_string(),
+        }
+    }
+}
+
+// Read the file named by `name`, or `default` when unset; None if it does not exist
+fn read_key_file(name: &str, default: &str) -> Option<String> {
+    let path = env::var(name).unwrap_or_else(|_| default.to_string());
+    std::fs::read_to_string(path).ok()
+}
+
+// Defaults for issued tokens
+const DEFAULT_ACCESS_TOKEN_TTL_SECS: u64 = 15 * 60;
+const DEFAULT_REFRESH_TOKEN_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+const DEFAULT_PASSWORD_RESET_TTL_SECS: u64 = 60 * 60;
+const DEFAULT_EMAIL_VERIFICATION_TTL_SECS: u64 = 24 * 60 * 60;
+const DEFAULT_API_KEY_TTL_SECS: u64 = 90 * 24 * 60 * 60;
+
+/// Lifetimes of the tokens handed out at login and for account recovery, and
+/// of API keys created without an expiry
+#[derive(Clone)]
+pub struct TokenConfig {
+    // How long a JWT access token is accepted
+    pub access_token_ttl: Duration,
+    // How long a refresh token can be exchanged for a new pair
+    pub refresh_token_ttl: Duration,
+    // How long an emailed password reset token can be used
+    pub password_reset_ttl: Duration,
+    // How long an emailed address verification token can be used
+    pub email_verification_ttl: Duration,
+    // How long an API key lasts when it is created without `expires_at`
+    pub api_key_ttl: Duration,
+}
+
+impl TokenConfig {
+    pub fn from_env() -> Self {
+        Self {
+            access_token_ttl: Duration::from_secs(get_secs("ACCESS_TOKEN_TTL_SECS", DEFAULT_ACCESS_TOKEN_TTL_SECS)),
+            refresh_token_ttl: Duration::from_secs(get_secs("REFRESH_TOKEN_TTL_SECS", DEFAULT_REFRESH_TOKEN_TTL_SECS)),
+            password_reset_ttl: Duration::from_secs(get_secs("PASSWORD_RESET_TTL_SECS", DEFAULT_PASSWORD_RESET_TTL_SECS)),
+            email_verification_ttl: Duration::from_secs(
+                get_secs("EMAIL_VERIFICATION_TTL_SECS", DEFAULT_EMAIL_VERIFICATION_TTL_SECS),
+            ),
+            api_key_ttl: Duration::from_secs(get_secs("API_KEY_TTL_SECS", DEFAULT_API_KEY_TTL_SECS)),
+        }
+    }
+}
+
+// Defaults for outgoing email
+const DEFAULT_MAIL_OUTBOX_FILE: &str = "mail_outbox.log";
+const DEFAULT_MAIL_FROM: &str = "Dodo Payments <no-reply@dodopayments.local>";
+const DEFAULT_PUBLIC_URL: &str = "http://localhost:8080";
+
+/// How emails to users are delivered
+#[derive(Clone)]
+pub struct MailConfig {
+    pub mailer: Arc<dyn Mailer>,
+    // Base URL of the app, used to build links in emails
+    pub public_url: String,
+}
+
+impl MailConfig {
+    pub fn from_env() -> Self {
+        let outbox = env::var("MAIL_OUTBOX_FILE").unwrap_or_else(|_| DEFAULT_MAIL_OUTBOX_FILE.to_string());
+        let from = env::var("MAIL_FROM").unwrap_or_else(|_| DEFAULT_MAIL_FROM.to_string());
+        let public_url = env::var("PUBLIC_URL").unwrap_or_else(|_| DEFAULT_PUBLIC_URL.to_string());
+        
+        Self {
+            mailer: Arc::new(FileMailer::new(outbox, &from)),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+// Defaults for two-factor authentication
+const DEFAULT_MFA_ISSUER: &str = "Dodo Payments";
+const DEFAULT_MFA_CHALLENGE_TTL_SECS: u64 = 5 * 60;
+const DEFAULT_MFA_STEP_UP_THRESHOLD: &str = "1000";
+const DEFAULT_MFA_STEP_UP_CURRENCY: &str = "USD";
+const DEFAULT_MFA_MAX_FAILED_CODES: u32 = 5;
+const DEFAULT_MFA_LOCKOUT_SECS: u64 = 15 * 60;
+
+/// Settings for TOTP enrollment, MFA logins and step-up checks
+#[derive(Clone)]
+pub struct MfaConfig {
+    // Shown as the account's issuer in authenticator apps
+    pub issuer: String,
+    // How long a password login can wait for its second factor
+    pub challenge_ttl: Duration,
+    // Transfers above this need a fresh code, unless a user sets their own threshold
+    pub step_up_threshold: Money,
+    // Wrong codes in a row, from any endpoint, before a user's codes are refused
+    pub max_failed_codes: u32,
+    // How long codes are refused for once `max_failed_codes` is reached
+    pub lockout: Duration,
+}
+
+impl MfaConfig {
+    pub fn from_env() -> Self {
+        let default_threshold = || {
+            Money::parse(
+                BigDecimal::from_str(DEFAULT_MFA_STEP_UP_THRESHOLD).expect("valid default threshold"),
+                DEFAULT_MFA_STEP_UP_CURRENCY,
+            )
+            .expect("valid default threshold")
+        };
+        let step_up_threshold = env::var("MFA_STEP_UP_THRESHOLD")
+            .ok()
+            .and_then(|amount| BigDecimal::from_str(&amount).ok())
+            .filter(|amount| *amount >= BigDecimal::from(0))
+            .and_then(|amount| {
+                let currency = env::var("MFA_STEP_UP_CURRENCY")
+                    .unwrap_or_else(|_| DEFAULT_MFA_STEP_UP_CURRENCY.to_string());
+                Money::parse(amount, &currency).ok()
+            })
+            .unwrap_or_else(default_threshold);
+        
+        Self {
+            issuer: env::var("MFA_ISSUER").unwrap_or_else(|_| DEFAULT_MFA_ISSUER.to_string()),
+            challenge_ttl: Duration::from_secs(get_secs("MFA_CHALLENGE_TTL_SECS", DEFAULT_MFA_CHALLENGE_TTL_SECS)),
+            step_up_threshold,
+            max_failed_codes: get_count("MFA_MAX_FAILED_CODES", DEFAULT_MFA_MAX_FAILED_CODES).max(1),
+            lockout: Duration::from_secs(get_secs("MFA_LOCKOUT_SECS", DEFAULT_MFA_LOCKOUT_SECS)),
+        }
+    }
+}
+
+// Defaults for login brute-force protection
+const DEFAULT_LOGIN_BACKOFF_AFTER: u32 = 3;
+const DEFAULT_LOGIN_BACKOFF_BASE_SECS: u64 = 1;
+const DEFAULT_LOGIN_BACKOFF_MAX_SECS: u64 = 60;
+const DEFAULT_LOGIN_LOCKOUT_THRESHOLD: u32 = 10;
+const DEFAULT_LOGIN_LOCKOUT_SECS: u64 = 15 * 60;
+const DEFAULT_LOGIN_FAILURE_WINDOW_SECS: u64 = 60 * 60;
+
+/// How repeated failed logins for a username are slowed down and locked out
+#[derive(Clone)]
+pub struct LoginConfig {
+    // Failed logins allowed before each further one adds a delay
+    pub backoff_after: u32,
+    // Delay after the first failure past `backoff_after`, doubled for each one after it
+    pub backoff_base: Duration,
+    // Longest delay before the account is locked
+    pub backoff_max: Duration,
+    // Failed logins that lock the username
+    pub lockout_threshold: u32,
+    // How long a locked username refuses logins
+    pub lockout_duration: Duration,
+    // Failures further apart than this start the count again
+    pub failure_window: Duration,
+}
+
+impl LoginConfig {
+    pub fn from_env() -> Self {
+        Self {
+            backoff_after: get_count("LOGIN_BACKOFF_AFTER", DEFAULT_LOGIN_BACKOFF_AFTER),
+            backoff_base: Duration::from_secs(get_secs("LOGIN_BACKOFF_BASE_SECS", DEFAULT_LOGIN_BACKOFF_BASE_SECS)),
+            backoff_max: Duration::from_secs(get_secs("LOGIN_BACKOFF_MAX_SECS", DEFAULT_LOGIN_BACKOFF_MAX_SECS)),
+            lockout_threshold: get_count("LOGIN_LOCKOUT_THRESHOLD", DEFAULT_LOGIN_LOCKOUT_THRESHOLD).max(1),
+            lockout_duration: Duration::from_secs(get_secs("LOGIN_LOCKOUT_SECS", DEFAULT_LOGIN_LOCKOUT_SECS)),
+            failure_window: Duration::from_secs(
+                get_secs("LOGIN_FAILURE_WINDOW_SECS", DEFAULT_LOGIN_FAILURE_WINDOW_SECS),
+            ),
+        }
+    }
+    
+    /// How long logins are refused after `failures` consecutive failed ones,
+    /// or `None` if the next attempt may be made straight away
+    pub fn block_after(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.lockout_threshold {
+            return Some(self.lockout_duration);
+        }
+        if failures <= self.backoff_after {
+            return None;
+        }
+        
+        let doublings = (failures - self.backoff_after - 1).min(31);
+        Some(self.backoff_base.saturating_mul(1 << doublings).min(self.backoff_max))
+    }
+    
+    /// Whether `failures` consecutive failed logins lock the username
+    pub fn is_lockout(&self, failures: u32) -> bool {
+        failures >= self.lockout_threshold
+    }
+}
+
+// Defaults for uncaptured authorization holds
+const DEFAULT_HOLD_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_HOLD_SWEEP_INTERVAL_SECS: u64 = 60;
+
+// Defaults for currency conversion
+const DEFAULT_FX_RATES_FILE: &str = "fx_rates.json";
+const DEFAULT_FX_SPREAD: &str = "0.005";
+const DEFAULT_FX_QUOTE_TTL_SECS: u64 = 30;
+
+/// Settings that shape how transfers are processed
+#[derive(Clone)]
+pub struct TransferConfig {
+    // How long an uncaptured hold reserves funds before it expires
+    pub hold_ttl: Duration,
+    // How often expired holds are swept and marked expired
+    pub hold_sweep_interval: Duration,
+    // Where exchange rates for cross-currency transfers come from
+    pub fx_rates: Arc<dyn FxRateProvider>,
+    // Fraction of a converted amount kept as a fee, e.g. 0.005 for 0.5%
+    pub fx_spread: BigDecimal,
+    // How long a quoted exchange rate can be used
+    pub fx_quote_ttl: Duration,
+    // Whether senders must have confirmed their email address
+    pub require_verified_email: bool,
+}
+
+impl TransferConfig {
+    pub fn from_env() -> Self {
+        let rates_file = env::var("FX_RATES_FILE").unwrap_or_else(|_| DEFAULT_FX_RATES_FILE.to_string());
+        let fx_rates = FileFxRateProvider::from_file(&rates_file).unwrap_or_else(|err| {
+            warn!("{}; only same-currency transfers are possible", err);
+            FileFxRateProvider::empty(Currency::from_code("USD").expect("USD is supported"))
+        });
+        
+        let fx_spread = env::var("FX_SPREAD")
+            .ok()
+            .and_then(|spread| BigDecimal::from_str(&spread).ok())
+            .filter(|spread| *spread >= BigDecimal::from(0) && *spread < BigDecimal::from(1))
+            .unwrap_or_else(|| BigDecimal::from_str(DEFAULT_FX_SPREAD).expect("valid default spread"));
+        
+        Self {
+            hold_ttl: Duration::from_secs(get_secs("HOLD_TTL_SECS", DEFAULT_HOLD_TTL_SECS)),
+            hold_sweep_interval: Duration::from_secs(
+                get_secs("HOLD_SWEEP_INTERVAL_SECS", DEFAULT_HOLD_SWEEP_INTERVAL_SECS),
+            ),
+            fx_rates: Arc::new(fx_rates),
+            fx_spread,
+            fx_quote_ttl: Duration::from_secs(get_secs("FX_QUOTE_TTL_SECS", DEFAULT_FX_QUOTE_TTL_SECS)),
+            require_verified_email: env::var("REQUIRE_VERIFIED_EMAIL")
+                .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false),
+        }
+    }
+}
+
+// Defaults for scheduled transfers
+const DEFAULT_SCHEDULER_POLL_INTERVAL_SECS: u64 = 30;
+const DEFAULT_SCHEDULED_RETRY_INTERVAL_SECS: u64 = 60 * 60;
+const DEFAULT_SCHEDULED_MAX_RETRIES: u32 = 3;
+
+/// How the in-process scheduler runs scheduled transfers
+#[derive(Clone)]
+pub struct ScheduleConfig {
+    // How often the scheduler looks for transfers that are due
+    pub poll_interval: Duration,
+    // Wait before retrying a transfer that failed for lack of funds
+    pub retry_interval: Duration,
+    // Retries of one occurrence before it is given up as failed
+    pub max_retries: u32,
+}
+
+impl ScheduleConfig {
+    pub fn from_env() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(
+                get_secs("SCHEDULER_POLL_INTERVAL_SECS", DEFAULT_SCHEDULER_POLL_INTERVAL_SECS).max(1),
+            ),
+            retry_interval: Duration::from_secs(
+                get_secs("SCHEDULED_TRANSFER_RETRY_SECS", DEFAULT_SCHEDULED_RETRY_INTERVAL_SECS),
+            ),
+            max_retries: get_count("SCHEDULED_TRANSFER_MAX_RETRIES", DEFAULT_SCHEDULED_MAX_RETRIES),
+        }
+    }
+}
+
+// Defaults for payment requests
+const DEFAULT_PAYMENT_REQUEST_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_PAYMENT_REQUEST_MAX_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// How long payment requests wait for the payer
+#[derive(Clone)]
+pub struct PaymentRequestConfig {
+    // Lifetime of a request that does not give its own expiry
+    pub ttl: Duration,
+    // Latest expiry a request may ask for
+    pub max_ttl: Duration,
+}
+
+impl PaymentRequestConfig {
+    pub fn from_env() -> Self {
+        Self {
+            ttl: Duration::from_secs(get_secs("PAYMENT_REQUEST_TTL_SECS", DEFAULT_PAYMENT_REQUEST_TTL_SECS)),
+            max_ttl: Duration::from_secs(get_secs("PAYMENT_REQUEST_MAX_TTL_SECS", DEFAULT_PAYMENT_REQUEST_MAX_TTL_SECS)),
+        }
+    }
+}
+
+// Defaults for batch transfers
+const DEFAULT_BATCH_MAX_ITEMS: u32 = 500;
+// Room in a request body for each transfer in a batch
+const BATCH_ITEM_BODY_BYTES: usize = 1024;
+// Room in an imported file for each transfer; pain.001 is wordier than JSON
+const IMPORT_ITEM_BODY_BYTES: usize = 2048;
+
+/// Limits on batches of transfers
+#[derive(Clone)]
+pub struct BatchConfig {
+    // Most transfers one batch may hold
+    pub max_items: u32,
+}
+
+impl BatchConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_items: get_count("BATCH_MAX_ITEMS", DEFAULT_BATCH_MAX_ITEMS).max(1),
+        }
+    }
+
+    /// Largest request body a batch of `max_items` needs
+    pub fn max_body_bytes(&self) -> usize {
+        (self.max_items as usize).saturating_mul(BATCH_ITEM_BODY_BYTES)
+    }
+
+    /// Largest file of `max_items` transfers that can be imported
+    pub fn max_import_bytes(&self) -> usize {
+        (self.max_items as usize).saturating_mul(IMPORT_ITEM_BODY_BYTES)
+    }
+}
+
+// Defaults for rate limiting
+const DEFAULT_RATE_LIMITS_FILE: &str = "rate_limits.json";
+
+/// Rate limit policies for each route scope, and the proxies trusted to
+/// report the client IP
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    pub policies: HashMap<String, RateLimitPolicy>,
+    pub trusted_proxies: Arc<TrustedProxies>,
+}
+
+impl RateLimitConfig {
+    /// Read policies from `RATE_LIMITS_FILE`, a JSON object mapping scope names
+    /// to policies. Scopes missing from the file keep their default policy.
+    pub fn from_env() -> Self {
+        let mut policies = Self::default_policies();
+        let path = env::var("RATE_LIMITS_FILE").unwrap_or_else(|_| DEFAULT_RATE_LIMITS_FILE.to_string());
+        match std::fs::read_to_string(&path) {
+            Ok(json) => match serde_json::from_str::<HashMap<String, RateLimitPolicy>>(&json) {
+                Ok(configured) => {
+                    info!("Loaded rate limit policies for {} scopes from {}", configured.len(), path);
+                    policies.extend(configured);
+                }
+                Err(err) => warn!("Invalid rate limit policies in {}: {}; using the defaults", path, err),
+            },
+            Err(_) => info!("No rate limit policies at {}; using the defaults", path),
+        }
+        
+        let trusted_proxies = env::var("TRUSTED_PROXIES")
+            .ok()
+            .and_then(|list| {
+                TrustedProxies::parse(&list)
+                    .map_err(|err| warn!("{}; trusting no proxies", err))
+                    .ok()
+            })
+            .unwrap_or_default();
+        
+        Self {
+            policies,
+            trusted_proxies: Arc::new(trusted_proxies),
+        }
+    }
+    
+    /// The policy for `scope`, or the sign-in policy for unknown scopes
+    pub fn policy(&self, scope: &str) -> RateLimitPolicy {
+        self.policies
+            .get(scope)
+            .or_else(|| self.policies.get("users"))
+            .cloned()
+            .unwrap_or(RateLimitPolicy {
+                key: RateLimitKey::Ip,
+                burst: None,
+                sustained: Quota::new(100, 60),
+            })
+    }
+    
+    fn default_policies() -> HashMap<String, RateLimitPolicy> {
+        let policy = |key, burst, sustained| RateLimitPolicy { key, burst: Some(burst), sustained };
+        HashMap::from([
+            ("users".to_string(), policy(RateLimitKey::Ip, Quota::new(10, 1), Quota::new(100, 60))),
+            ("clients".to_string(), policy(RateLimitKey::Ip, Quota::new(50, 1), Quota::new(1000, 60))),
+            ("accounts".to_string(), policy(RateLimitKey::User, Quota::new(20, 1), Quota::new(300, 60))),
+            ("fx".to_string(), policy(RateLimitKey::User, Quota::new(10, 1), Quota::new(100, 60))),
+            ("transactions".to_string(), policy(RateLimitKey::ApiKey, Quota::new(10, 1), Quota::new(100, 60))),
+            ("admin".to_string(), policy(RateLimitKey::User, Quota::new(20, 1), Quota::new(300, 60))),
+        ])
+    }
+}
+
+fn get_secs(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(default)
+}
+
+fn get_count(name: &str, default: u32) -> u32 {
+    env::var(name)
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(default)
+}
+
 
 // Function to set up the database pool
 pub async fn setup_database_pool(database_url: &str) -> PgPool {
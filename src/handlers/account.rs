@@ -1,34 +1,124 @@
 use actix_web::{web, HttpResponse, Responder};
 use sqlx::PgPool;
 use sqlx::Row; // Explicitly import Row trait
+use sqlx::postgres::PgRow;
 use uuid::Uuid;
+use validator::Validate;
 
-use crate::models::{Account, AccountBalanceResponse, AppError};
+use crate::models::{
+    Account, AccountBalanceResponse, AccountListResponse, AccountResponse, AppError, Currency,
+    OpenAccountRequest, TransactionStatus,
+};
 
+// Account columns plus the funds reserved by the wallet's active holds
+const ACCOUNT_WITH_HELD_COLUMNS: &str = r#"
+        SELECT a.id, a.user_id, a.balance, a.currency, a.created_at, a.updated_at,
+               COALESCE((
+                   SELECT SUM(t.amount) FROM transactions t
+                   WHERE t.sender_id = a.user_id AND t.currency = a.currency
+                     AND t.status = $2 AND t.hold_expires_at > NOW()
+               ), 0) AS held
+        FROM accounts a
+"#;
+
+/// Get the balance of one wallet: the one in `currency` if given, otherwise
+/// the user's first wallet
 pub async fn get_balance(
     user_id: web::ReqData<Uuid>,
     pool: web::Data<PgPool>,
+    query: web::Query<BalanceQuery>,
 ) -> Result<impl Responder, AppError> {
     let user_id = user_id.into_inner();
     
+    let sql = format!(
+        "{} WHERE a.user_id = $1 AND ($3::VARCHAR IS NULL OR a.currency = $3) ORDER BY a.created_at, a.id LIMIT 1",
+        ACCOUNT_WITH_HELD_COLUMNS
+    );
+    let currency = match &query.currency {
+        Some(code) => Some(Currency::from_code(code)?.code()),
+        None => None,
+    };
+    
+    let row = sqlx::query(&sql)
+        .bind(user_id)
+        .bind(TransactionStatus::Pending as i32)
+        .bind(currency)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("Account not found".to_string()))?;
+    
+    let account = account_from_row(&row)?;
+    
+    Ok(HttpResponse::Ok().json(AccountBalanceResponse::new(account, row.try_get("held")?)?))
+}
+
+/// List all of the user's currency wallets
+pub async fn list_accounts(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let sql = format!(
+        "{} WHERE a.user_id = $1 ORDER BY a.created_at, a.id",
+        ACCOUNT_WITH_HELD_COLUMNS
+    );
+    
+    let rows = sqlx::query(&sql)
+        .bind(user_id.into_inner())
+        .bind(TransactionStatus::Pending as i32)
+        .fetch_all(pool.get_ref())
+        .await?;
+    
+    let mut accounts = Vec::with_capacity(rows.len());
+    for row in rows {
+        let held = row.try_get("held")?;
+        accounts.push(AccountResponse::new(account_from_row(&row)?, held)?);
+    }
+    
+    Ok(HttpResponse::Ok().json(AccountListResponse { accounts }))
+}
+
+/// Open an empty wallet in another currency
+pub async fn open_account(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    account_data: web::Json<OpenAccountRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    account_data.validate()?;
+    
+    let currency = Currency::from_code(&account_data.currency)?;
+    
     let row = sqlx::query(
         r#"
-        SELECT id, user_id, balance, currency, created_at, updated_at
-        FROM accounts
-        WHERE user_id = $1
+        INSERT INTO accounts (user_id, balance, currency)
+        VALUES ($1, 0, $2)
+        ON CONFLICT (user_id, currency) DO NOTHING
+        RETURNING id, user_id, balance, currency, created_at, updated_at
         "#
     )
-    .bind(user_id)
-    .fetch_one(pool.get_ref())
-    .await?;
-      let account = Account {
-        id: row.get("id"),
-        user_id: row.get("user_id"),
-        balance: row.get("balance"),
-        currency: row.get("currency"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-    };
+    .bind(user_id.into_inner())
+    .bind(currency.code())
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::ConflictError(format!("A {} wallet is already open", currency)))?;
+    
+    let account = account_from_row(&row)?;
     
-    Ok(HttpResponse::Ok().json(AccountBalanceResponse::from(account)))
+    Ok(HttpResponse::Created().json(AccountResponse::new(account, 0.into())?))
+}
+
+fn account_from_row(row: &PgRow) -> Result<Account, AppError> {
+    Ok(Account {
+        id: row.try_get("id")?,
+        user_id: row.try_get("user_id")?,
+        balance: row.try_get("balance")?,
+        currency: row.try_get("currency")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct BalanceQuery {
+    currency: Option<String>,
 }
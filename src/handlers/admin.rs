@@ -1,50 +1,260 @@
 use actix_web::{web, HttpResponse, Responder};
-use sqlx::PgPool;
+use validator::Validate;
+use sqlx::{PgPool, Row};
 use uuid::Uuid;
 use serde::Deserialize;
+use bigdecimal::BigDecimal;
+
+use crate::handlers::audit::record_admin_action;
+use crate::handlers::ledger::post_journal;
+use crate::handlers::token::revoke_user_sessions;
+use crate::handlers::transaction::{process_refund, RefundInitiator};
+use crate::models::{AppError, Currency, Money, RefundTransactionRequest, TransactionResponse, UpdateRoleRequest, User, UserResponse};
+use crate::models::audit::{CHANGE_ROLE, FUND_ACCOUNT, RELOAD_KEYS, UNLOCK_ACCOUNT};
+use crate::utils::keys::KeyStore;
+use crate::models::ledger::{LedgerAccount, LedgerLine, FUNDING_ACCOUNT};
+use crate::models::money::decimal;
 
 #[derive(Deserialize)]
 pub struct FundAmount {
-    pub amount: f64,
+    #[serde(with = "decimal")]
+    pub amount: BigDecimal,
+    // Funds the user's first wallet when omitted
+    pub currency: Option<String>,
 }
 
 pub async fn fund_user_balance(
+    actor_id: web::ReqData<Uuid>,
     pool: web::Data<PgPool>,
     path: web::Path<Uuid>,
     data: web::Json<FundAmount>,
 ) -> impl Responder {
     let user_id = path.into_inner();
-    let amount = data.amount;
 
-    if amount <= 0.0 {
+    if data.amount <= BigDecimal::from(0) {
         return HttpResponse::BadRequest().json({
             serde_json::json!({ "error": "Amount must be greater than 0" })
         });
     }
 
-   let result = sqlx::query(
-    r#"
-    UPDATE accounts
-    SET balance = balance + $1
-    WHERE user_id = $2
-    "#
-)
-.bind(amount)
-.bind(user_id)
-.execute(pool.get_ref())
-.await;
-
-    match result {
-        Ok(res) if res.rows_affected() > 0 => {
+    match credit_account(pool.get_ref(), actor_id.into_inner(), user_id, &data.amount, data.currency.as_deref()).await {
+        Ok(Some(credited)) => {
             HttpResponse::Ok().json({
-                serde_json::json!({ "status": "success", "user_id": user_id, "credited": amount })
+                serde_json::json!({ "status": "success", "user_id": user_id, "credited": credited })
             })
         }
-        Ok(_) => HttpResponse::NotFound().json({
-            serde_json::json!({ "error": "User not found or has no account" })
+        Ok(None) => HttpResponse::NotFound().json({
+            serde_json::json!({ "error": "User not found or has no account in that currency" })
+        }),
+        Err(AppError::BadRequestError(msg)) => HttpResponse::BadRequest().json({
+            serde_json::json!({ "error": msg })
         }),
         Err(err) => HttpResponse::InternalServerError().json({
             serde_json::json!({ "error": format!("DB error: {}", err) })
         }),
     }
-}
\ No newline at end of file
+}
+
+// Post the funding to the ledger, returning the amount credited or None if
+// the user has no matching wallet
+async fn credit_account(
+    pool: &PgPool,
+    actor_id: Uuid,
+    user_id: Uuid,
+    amount: &BigDecimal,
+    currency: Option<&str>,
+) -> Result<Option<Money>, AppError> {
+    let currency = currency.map(Currency::from_code).transpose()?;
+
+    let mut tx = pool.begin().await?;
+
+    let account = sqlx::query(
+        r#"
+        SELECT id, currency FROM accounts
+        WHERE user_id = $1 AND ($2::VARCHAR IS NULL OR currency = $2)
+        ORDER BY created_at, id
+        LIMIT 1
+        FOR UPDATE
+        "#
+    )
+    .bind(user_id)
+    .bind(currency.map(|currency| currency.code()))
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let account = match account {
+        Some(account) => account,
+        None => return Ok(None),
+    };
+    let account_id: Uuid = account.try_get("id")?;
+    let currency: String = account.try_get("currency")?;
+    let amount = Money::positive(amount.clone(), &currency)?;
+
+    post_journal(
+        &mut tx,
+        None,
+        &currency,
+        "Admin funding",
+        &[
+            LedgerLine::debit(LedgerAccount::System(FUNDING_ACCOUNT), amount.amount().clone()),
+            LedgerLine::credit(LedgerAccount::User(account_id), amount.amount().clone()),
+        ],
+    )
+    .await?;
+
+    record_admin_action(
+        &mut tx,
+        actor_id,
+        FUND_ACCOUNT,
+        Some(user_id),
+        serde_json::json!({ "account_id": account_id, "amount": amount, "currency": currency }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Some(amount))
+}
+
+/// Refund a transaction on behalf of its recipient
+pub async fn refund_transaction(
+    actor_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+    data: web::Json<RefundTransactionRequest>,
+) -> Result<impl Responder, AppError> {
+    data.validate()?;
+
+    let initiator = RefundInitiator::Admin(actor_id.into_inner());
+    let refund = process_refund(pool.get_ref(), path.into_inner(), initiator, &data).await?;
+
+    Ok(HttpResponse::Created().json(TransactionResponse::try_from(refund)?))
+}
+
+/// Change what a user is allowed to do. Their sessions are revoked with the
+/// change, since tokens carry the role, so it takes effect at once and they
+/// must log in again.
+pub async fn update_user_role(
+    actor_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+    data: web::Json<UpdateRoleRequest>,
+) -> Result<impl Responder, AppError> {
+    let actor_id = actor_id.into_inner();
+    let user_id = path.into_inner();
+
+    // Keeps the last admin from locking everyone out
+    if actor_id == user_id {
+        return Err(AppError::BadRequestError("You cannot change your own role".to_string()));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let previous: String = sqlx::query("SELECT role FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+        .try_get("role")?;
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        UPDATE users
+        SET role = $1, updated_at = NOW()
+        WHERE id = $2
+        RETURNING id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
+        "#
+    )
+    .bind(data.role.as_str())
+    .bind(user_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    record_admin_action(
+        &mut tx,
+        actor_id,
+        CHANGE_ROLE,
+        Some(user_id),
+        serde_json::json!({ "from": previous, "to": data.role }),
+    )
+    .await?;
+    revoke_user_sessions(&mut tx, user_id, None).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::from(user)))
+}
+
+/// Clear a user's failed logins, lifting any backoff or lockout on their username
+pub async fn unlock_user(
+    actor_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let user_id = path.into_inner();
+
+    let mut tx = pool.begin().await?;
+
+    let username: String = sqlx::query("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+        .try_get("username")?;
+
+    let cleared = sqlx::query(
+        "DELETE FROM login_attempts WHERE username = $1 RETURNING failed_count, blocked_until"
+    )
+    .bind(&username)
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let (failed_attempts, blocked_until) = match cleared {
+        Some(row) => (
+            row.try_get::<i32, _>("failed_count")?,
+            row.try_get::<Option<chrono::DateTime<chrono::Utc>>, _>("blocked_until")?,
+        ),
+        None => (0, None),
+    };
+
+    record_admin_action(
+        &mut tx,
+        actor_id.into_inner(),
+        UNLOCK_ACCOUNT,
+        Some(user_id),
+        serde_json::json!({ "username": username, "failed_attempts": failed_attempts, "blocked_until": blocked_until }),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user_id": user_id,
+        "username": username,
+        "failed_attempts": failed_attempts,
+        "was_blocked": blocked_until.is_some_and(|until| until > chrono::Utc::now()),
+    })))
+}
+
+/// Re-read the JWT signing keys, e.g. after rotating the secret
+pub async fn reload_keys(
+    actor_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    keys: web::Data<KeyStore>,
+) -> Result<impl Responder, AppError> {
+    keys.reload()?;
+
+    let active_kid = keys.active_kid()?;
+    let kids = keys.kids()?;
+
+    record_admin_action(
+        pool.get_ref(),
+        actor_id.into_inner(),
+        RELOAD_KEYS,
+        None,
+        serde_json::json!({ "active_kid": active_kid, "kids": kids }),
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "active_kid": active_kid, "kids": kids })))
+}
@@ -0,0 +1,197 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, RngCore};
+use sqlx::{postgres::PgRow, PgPool, Postgres, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{MfaConfig, TokenConfig};
+use crate::handlers::mfa::{require_fresh_code, MFA_CODE_HEADER};
+use crate::middleware::rate_limit::IpNetworks;
+use crate::models::{
+    ApiKeyListResponse, ApiKeyResponse, ApiScope, AppError, CreateApiKeyRequest, CreatedApiKeyResponse,
+};
+use crate::utils::auth::{generate_refresh_token, hash_token, verify_password};
+
+/// Start of every API key, so they are easy to spot in code and logs
+pub const API_KEY_PREFIX: &str = "dodo_";
+
+const API_KEY_COLUMNS: &str =
+    "id, name, prefix, scopes, allowed_ips, expires_at, last_used_at, revoked_at, created_at";
+
+/// Create an API key with the requested scopes. The key is in the response
+/// and cannot be retrieved again.
+///
+/// A key outlives the access token used to create it, so the user must also
+/// give their current password or a code from their authenticator.
+pub async fn create_api_key(
+    req: HttpRequest,
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    key_data: web::Json<CreateApiKeyRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    key_data.validate()?;
+
+    let user_id = user_id.into_inner();
+
+    if key_data.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(AppError::BadRequestError("expires_at must be in the future".to_string()));
+    }
+
+    let code = req.headers().get(MFA_CODE_HEADER).and_then(|value| value.to_str().ok());
+    match (code, key_data.current_password.as_deref()) {
+        (Some(code), _) => require_fresh_code(pool.get_ref(), mfa_config.get_ref(), user_id, code).await?,
+        (None, Some(password)) => require_password(pool.get_ref(), user_id, password).await?,
+        (None, None) => {
+            return Err(AppError::ForbiddenError(format!(
+                "Creating an API key needs current_password or a code from your authenticator in the {} header",
+                MFA_CODE_HEADER
+            )));
+        }
+    }
+
+    let ttl = chrono::Duration::from_std(config.api_key_ttl)
+        .map_err(|_| AppError::InternalServerError("API key TTL is out of range".to_string()))?;
+    let expires_at = key_data.expires_at.unwrap_or_else(|| Utc::now() + ttl);
+
+    let allowed_ips = key_data
+        .allowed_ips
+        .as_ref()
+        .map(|entries| {
+            let entries: Vec<String> = entries.iter().map(|entry| entry.trim().to_string()).collect();
+            IpNetworks::parse(entries.iter().map(String::as_str))
+                .map_err(|entry| AppError::BadRequestError(format!("Invalid IP address or range: {}", entry)))?;
+            Ok::<_, AppError>(entries)
+        })
+        .transpose()?;
+
+    let mut scopes: Vec<&str> = key_data.scopes.iter().map(ApiScope::as_str).collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+
+    let (prefix, key) = generate_api_key();
+
+    let row = sqlx::query(&format!(
+        r#"
+        INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, allowed_ips, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING {}
+        "#,
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(key_data.name.trim())
+    .bind(&prefix)
+    .bind(hash_token(&key))
+    .bind(&scopes)
+    .bind(&allowed_ips)
+    .bind(expires_at)
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Created().json(CreatedApiKeyResponse {
+        key,
+        api_key: api_key_from_row(&row)?,
+    }))
+}
+
+/// The user's API keys, newest first, including revoked and expired ones
+pub async fn list_api_keys(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+        API_KEY_COLUMNS
+    ))
+    .bind(user_id.into_inner())
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let api_keys = rows.iter().map(api_key_from_row).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(HttpResponse::Ok().json(ApiKeyListResponse { api_keys }))
+}
+
+/// Revoke one of the user's API keys, which stops it working at once
+pub async fn revoke_api_key(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let revoked_at: Option<DateTime<Utc>> = sqlx::query(
+        r#"
+        UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+        WHERE id = $1 AND user_id = $2
+        RETURNING revoked_at
+        "#
+    )
+    .bind(path.into_inner())
+    .bind(user_id.into_inner())
+    .fetch_optional(pool.get_ref())
+    .await?
+    .map(|row| row.try_get("revoked_at"))
+    .transpose()?;
+
+    match revoked_at {
+        Some(_) => Ok(HttpResponse::NoContent().finish()),
+        None => Err(AppError::NotFoundError("API key not found".to_string())),
+    }
+}
+
+/// Revoke every API key of the user, for when their account may have been
+/// taken over. Returns how many keys were revoked.
+pub async fn revoke_user_api_keys(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<u64, AppError> {
+    let revoked = sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    Ok(revoked)
+}
+
+async fn require_password(pool: &PgPool, user_id: Uuid, password: &str) -> Result<(), AppError> {
+    let password_hash: String = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+        .try_get("password_hash")?;
+
+    if !verify_password(password, &password_hash)? {
+        return Err(AppError::ForbiddenError("Current password is incorrect".to_string()));
+    }
+    Ok(())
+}
+
+// A new key and its visible prefix, which is the start of the key
+fn generate_api_key() -> (String, String) {
+    let mut id = [0u8; 4];
+    OsRng.fill_bytes(&mut id);
+    let prefix = format!("{}{}", API_KEY_PREFIX, hex::encode(id));
+    let key = format!("{}_{}", prefix, generate_refresh_token());
+    (prefix, key)
+}
+
+fn api_key_from_row(row: &PgRow) -> Result<ApiKeyResponse, AppError> {
+    let scopes: Vec<String> = row.try_get("scopes")?;
+
+    Ok(ApiKeyResponse {
+        id: row.try_get("id")?,
+        name: row.try_get("name")?,
+        prefix: row.try_get("prefix")?,
+        scopes: scopes.iter().map(|scope| scope.parse()).collect::<Result<_, _>>()?,
+        allowed_ips: row.try_get("allowed_ips")?,
+        expires_at: row.try_get("expires_at")?,
+        last_used_at: row.try_get("last_used_at")?,
+        revoked_at: row.try_get("revoked_at")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
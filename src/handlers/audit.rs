@@ -0,0 +1,114 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::{PgPool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::models::{AppError, AuditLogEntry, AuditLogListResponse};
+
+/// Record a privileged action taken by `actor_id`.
+///
+/// Pass the transaction that performs the action where there is one, so the
+/// action and its audit record commit or roll back together.
+pub async fn record_admin_action<'e, E>(
+    executor: E,
+    actor_id: Uuid,
+    action: &str,
+    target_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> Result<(), AppError>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    insert_audit_record(executor, Some(actor_id), action, target_id, details).await?;
+
+    log::info!("Admin action {} by {} on {:?}", action, actor_id, target_id);
+
+    Ok(())
+}
+
+/// Record a security action the system took on its own, such as locking an
+/// account after repeated failed logins
+pub async fn record_system_action<'e, E>(
+    executor: E,
+    action: &str,
+    target_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> Result<(), AppError>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    log::warn!("System action {} on {:?}: {}", action, target_id, details);
+
+    insert_audit_record(executor, None, action, target_id, details).await
+}
+
+async fn insert_audit_record<'e, E>(
+    executor: E,
+    actor_id: Option<Uuid>,
+    action: &str,
+    target_id: Option<Uuid>,
+    details: serde_json::Value,
+) -> Result<(), AppError>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO admin_audit_log (actor_id, action, target_id, details)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(actor_id)
+    .bind(action)
+    .bind(target_id)
+    .bind(details)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// List recorded admin actions, newest first
+pub async fn list_audit_log(
+    pool: web::Data<PgPool>,
+    query: web::Query<ListAuditLogQuery>,
+) -> Result<impl Responder, AppError> {
+    let limit = query.limit.unwrap_or(10).clamp(1, 100) as i64;
+    let offset = query.offset.unwrap_or(0) as i64;
+
+    let entries = sqlx::query_as::<_, AuditLogEntry>(
+        r#"
+        SELECT id, actor_id, action, target_id, details, created_at
+        FROM admin_audit_log
+        WHERE ($1::UUID IS NULL OR actor_id = $1)
+        ORDER BY created_at DESC, id
+        LIMIT $2 OFFSET $3
+        "#
+    )
+    .bind(query.actor_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let total: i64 = sqlx::query(
+        "SELECT COUNT(*) AS count FROM admin_audit_log WHERE ($1::UUID IS NULL OR actor_id = $1)"
+    )
+    .bind(query.actor_id)
+    .fetch_one(pool.get_ref())
+    .await?
+    .try_get("count")?;
+
+    Ok(HttpResponse::Ok().json(AuditLogListResponse {
+        entries,
+        total,
+        page: offset / limit,
+        per_page: limit,
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListAuditLogQuery {
+    actor_id: Option<Uuid>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
@@ -0,0 +1,523 @@
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use bigdecimal::BigDecimal;
+use sqlx::{Connection, PgPool, Postgres, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{BatchConfig, MfaConfig, TransferConfig};
+use crate::handlers::mfa::{require_step_up, MFA_CODE_HEADER};
+use crate::handlers::transaction::{process_transfer_in, INSUFFICIENT_FUNDS};
+use crate::middleware::KeepIdempotencyKey;
+use crate::models::{
+    AppError, BatchItemResponse, BatchItemStatus, BatchMode, BatchResponse, BatchStatus, CreateBatchRequest,
+    CreateTransactionRequest, Currency, Money, TransactionStatus,
+};
+
+// What became of one transfer in a batch
+struct ItemOutcome {
+    status: BatchItemStatus,
+    transaction_id: Option<Uuid>,
+    error: Option<String>,
+}
+
+impl ItemOutcome {
+    fn pending() -> Self {
+        ItemOutcome { status: BatchItemStatus::Pending, transaction_id: None, error: None }
+    }
+
+    fn succeeded(transaction_id: Uuid) -> Self {
+        ItemOutcome { status: BatchItemStatus::Succeeded, transaction_id: Some(transaction_id), error: None }
+    }
+
+    fn failed(err: AppError) -> Self {
+        ItemOutcome { status: BatchItemStatus::Failed, transaction_id: None, error: Some(err.to_string()) }
+    }
+
+    fn aborted() -> Self {
+        ItemOutcome { status: BatchItemStatus::Aborted, transaction_id: None, error: None }
+    }
+}
+
+// Given to the transfers of a best-effort batch that were never tried
+const BATCH_STOPPED: &str = "Not made: the batch stopped after an internal error";
+
+/// Make many transfers from the signed-in user in one request.
+///
+/// The batch is checked as a whole before any transfer is made: it must fit
+/// `BatchConfig::max_items`, be in one currency, and the sender's available
+/// balance must cover its total. The batch and the outcome of each transfer
+/// are then recorded, whether or not they went through.
+pub async fn create_batch(
+    req: HttpRequest,
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TransferConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    batch_config: web::Data<BatchConfig>,
+    batch_data: web::Json<CreateBatchRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    batch_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let items = &batch_data.items;
+
+    if items.is_empty() {
+        return Err(AppError::BadRequestError("A batch needs at least one transfer".to_string()));
+    }
+    if items.len() > batch_config.max_items as usize {
+        return Err(AppError::BadRequestError(format!(
+            "A batch can hold at most {} transfers",
+            batch_config.max_items
+        )));
+    }
+
+    let total = batch_total(items)?;
+
+    // One code covers the whole batch
+    let code = req.headers().get(MFA_CODE_HEADER).and_then(|value| value.to_str().ok());
+    require_step_up(pool.get_ref(), mfa_config.get_ref(), config.get_ref(), user_id, &total, code).await?;
+
+    let batch_id = submit_batch(pool.get_ref(), config.get_ref(), user_id, batch_data.mode, items, &total).await?;
+
+    batch_submitted(&req, pool.get_ref(), batch_id, user_id).await
+}
+
+/// Make a checked batch of transfers from `user_id` and record the outcome,
+/// returning the id of the batch.
+///
+/// `items` must already have passed the checks `create_batch` makes, and
+/// `total` be their sum. The batch is refused whole if the sender's available
+/// balance cannot cover it; otherwise it is recorded whatever happens to its
+/// transfers. Once a best-effort batch is recorded, an error part way through
+/// stops it without failing the call, and the transfers not yet tried are
+/// recorded as aborted.
+pub async fn submit_batch(
+    pool: &PgPool,
+    config: &TransferConfig,
+    user_id: Uuid,
+    mode: BatchMode,
+    items: &[CreateTransactionRequest],
+    total: &Money,
+) -> Result<Uuid, AppError> {
+    // Each transfer checks funds again as it is made; this spares making
+    // some of them from a balance that cannot cover the rest
+    let available = available_balance(pool, user_id, total.currency()).await?;
+    if available < *total.amount() {
+        return Err(AppError::BadRequestError(format!(
+            "{}: the batch totals {} {} but {} is available",
+            INSUFFICIENT_FUNDS,
+            total,
+            total.currency(),
+            Money::parse(available, total.currency().code())?
+        )));
+    }
+
+    match mode {
+        BatchMode::AllOrNothing => run_all_or_nothing(pool, config, user_id, items, total).await,
+        BatchMode::BestEffort => run_best_effort(pool, config, user_id, items, total).await,
+    }
+}
+
+/// The response to a batch `submit_batch` recorded. Its transfers may have
+/// been made, so from here on a retry with the same idempotency key is given
+/// this response rather than submitting the batch again.
+pub async fn batch_submitted(
+    req: &HttpRequest,
+    pool: &PgPool,
+    batch_id: Uuid,
+    user_id: Uuid,
+) -> Result<HttpResponse, AppError> {
+    req.extensions_mut().insert(KeepIdempotencyKey);
+
+    let batch = load_batch(pool, batch_id, user_id).await.map_err(|err| {
+        log::error!("Failed to load batch {}: {}", batch_id, err);
+        AppError::InternalServerError(format!("Batch {} was submitted; fetch it to see its outcome", batch_id))
+    })?;
+
+    Ok(batch_created(batch))
+}
+
+// A batch in which nothing went through is still recorded, but not created
+fn batch_created(batch: BatchResponse) -> HttpResponse {
+    if batch.status == BatchStatus::Failed {
+        return HttpResponse::UnprocessableEntity().json(batch);
+    }
+    HttpResponse::Created().json(batch)
+}
+
+/// A batch the user submitted, with the outcome of each transfer
+pub async fn get_batch(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let batch = load_batch(pool.get_ref(), path.into_inner(), user_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(batch))
+}
+
+// The sum of the batch's transfers, which must all be in one currency and
+// settle straight away
+fn batch_total(items: &[CreateTransactionRequest]) -> Result<Money, AppError> {
+    let mut currency: Option<Currency> = None;
+    let mut total = BigDecimal::from(0);
+
+    for (index, item) in items.iter().enumerate() {
+        let amount = Money::positive(item.amount.clone(), &item.currency).map_err(|err| item_error(index, err))?;
+        if item.capture == Some(false) {
+            return Err(item_error(index, AppError::BadRequestError("Batch transfers cannot be holds".to_string())));
+        }
+        match currency {
+            Some(currency) if currency != amount.currency() => {
+                return Err(item_error(
+                    index,
+                    AppError::BadRequestError("Every transfer in a batch must be in the same currency".to_string()),
+                ));
+            }
+            _ => currency = Some(amount.currency()),
+        }
+        total += amount.amount();
+    }
+
+    match currency {
+        Some(currency) => Money::new(total, currency),
+        None => Err(AppError::BadRequestError("A batch needs at least one transfer".to_string())),
+    }
+}
+
+// Point an error in a batch at the transfer that caused it
+fn item_error(index: usize, err: AppError) -> AppError {
+    match err {
+        AppError::BadRequestError(msg) => AppError::BadRequestError(format!("items[{}]: {}", index, msg)),
+        err => err,
+    }
+}
+
+/// The sender's balance in `currency` less the funds held for pending transfers
+pub async fn available_balance(pool: &PgPool, user_id: Uuid, currency: Currency) -> Result<BigDecimal, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT a.balance - COALESCE((
+                   SELECT SUM(t.amount) FROM transactions t
+                   WHERE t.sender_id = a.user_id AND t.currency = a.currency
+                     AND t.status = $3 AND t.hold_expires_at > NOW()
+               ), 0) AS available
+        FROM accounts a
+        WHERE a.user_id = $1 AND a.currency = $2
+        "#
+    )
+    .bind(user_id)
+    .bind(currency.code())
+    .bind(TransactionStatus::Pending as i32)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::BadRequestError(format!("Sender has no {} wallet", currency)))?;
+
+    Ok(row.try_get("available")?)
+}
+
+// Make every transfer in one database transaction, along with the record of
+// the batch. If one fails the rest are rolled back, so no transaction is
+// recorded for any of them, but the batch still is.
+async fn run_all_or_nothing(
+    pool: &PgPool,
+    config: &TransferConfig,
+    sender_id: Uuid,
+    items: &[CreateTransactionRequest],
+    total: &Money,
+) -> Result<Uuid, AppError> {
+    let mut tx = pool.begin().await?;
+
+    // Take every account the batch touches up front and in id order, so the
+    // batch cannot deadlock with transfers that lock them one pair at a time
+    let mut user_ids: Vec<Uuid> = items.iter().map(|item| item.recipient_id).collect();
+    user_ids.push(sender_id);
+    sqlx::query("SELECT id FROM accounts WHERE user_id = ANY($1) ORDER BY id FOR UPDATE")
+        .bind(&user_ids)
+        .fetch_all(&mut tx)
+        .await?;
+
+    // The transfers go in a savepoint, which a failure rolls back alone
+    let mut transfers = tx.begin().await?;
+    let mut transaction_ids = Vec::with_capacity(items.len());
+    let mut failure = None;
+    for (index, item) in items.iter().enumerate() {
+        match process_transfer_in(&mut transfers, config, sender_id, item).await {
+            Ok(transaction) => transaction_ids.push(transaction.id),
+            Err(err) => {
+                failure = Some((index, err));
+                break;
+            }
+        }
+    }
+
+    let outcomes: Vec<ItemOutcome> = match failure {
+        None => {
+            transfers.commit().await?;
+            transaction_ids.into_iter().map(ItemOutcome::succeeded).collect()
+        }
+        Some((index, err)) => {
+            transfers.rollback().await?;
+            let mut outcomes: Vec<ItemOutcome> = (0..items.len()).map(|_| ItemOutcome::aborted()).collect();
+            outcomes[index] = ItemOutcome::failed(err);
+            outcomes
+        }
+    };
+
+    let succeeded = outcomes.iter().filter(|outcome| outcome.status == BatchItemStatus::Succeeded).count();
+    let status = BatchStatus::from_counts(succeeded, outcomes.len());
+    let batch_id = record_batch(&mut tx, sender_id, BatchMode::AllOrNothing, status, total, items, &outcomes).await?;
+
+    tx.commit().await?;
+
+    Ok(batch_id)
+}
+
+// Record the batch, then make each transfer on its own, updating its item in
+// the same database transaction as the transfer. Failed transfers leave no
+// transaction behind, only the error on their item. Money may have moved once
+// the batch is recorded, so an error after that stops the batch rather than
+// failing it: the transfers not yet tried are aborted and the batch settles
+// on what went through.
+async fn run_best_effort(
+    pool: &PgPool,
+    config: &TransferConfig,
+    sender_id: Uuid,
+    items: &[CreateTransactionRequest],
+    total: &Money,
+) -> Result<Uuid, AppError> {
+    let mut tx = pool.begin().await?;
+    let pending: Vec<ItemOutcome> = items.iter().map(|_| ItemOutcome::pending()).collect();
+    let batch_id = record_batch(
+        &mut tx,
+        sender_id,
+        BatchMode::BestEffort,
+        BatchStatus::Processing,
+        total,
+        items,
+        &pending,
+    )
+    .await?;
+    tx.commit().await?;
+
+    let mut succeeded = 0;
+    let mut stopped = false;
+    for (index, item) in items.iter().enumerate() {
+        match run_item(pool, config, sender_id, batch_id, index, item).await {
+            Ok(BatchItemStatus::Succeeded) => succeeded += 1,
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("Batch {} stopped at item {}: {}", batch_id, index, err);
+                stopped = true;
+                break;
+            }
+        }
+    }
+
+    let status = BatchStatus::from_counts(succeeded, items.len());
+    if let Err(err) = finish_batch(pool, batch_id, status, stopped).await {
+        log::error!("Failed to finish batch {}: {}", batch_id, err);
+    }
+
+    Ok(batch_id)
+}
+
+// Make one transfer of a best-effort batch and record its outcome with it
+async fn run_item(
+    pool: &PgPool,
+    config: &TransferConfig,
+    sender_id: Uuid,
+    batch_id: Uuid,
+    index: usize,
+    item: &CreateTransactionRequest,
+) -> Result<BatchItemStatus, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let mut transfer = tx.begin().await?;
+    let outcome = match process_transfer_in(&mut transfer, config, sender_id, item).await {
+        Ok(transaction) => {
+            transfer.commit().await?;
+            ItemOutcome::succeeded(transaction.id)
+        }
+        Err(err) => {
+            transfer.rollback().await?;
+            ItemOutcome::failed(err)
+        }
+    };
+
+    settle_item(&mut tx, batch_id, index, &outcome).await?;
+    tx.commit().await?;
+
+    Ok(outcome.status)
+}
+
+// Give a best-effort batch its final status, aborting any items a stop left
+// untried
+async fn finish_batch(pool: &PgPool, batch_id: Uuid, status: BatchStatus, stopped: bool) -> Result<(), AppError> {
+    let mut tx = pool.begin().await?;
+
+    if stopped {
+        sqlx::query(
+            r#"
+            UPDATE transfer_batch_items SET status = $1, error = $2
+            WHERE batch_id = $3 AND status = $4
+            "#
+        )
+        .bind(BatchItemStatus::Aborted.as_str())
+        .bind(BATCH_STOPPED)
+        .bind(batch_id)
+        .bind(BatchItemStatus::Pending.as_str())
+        .execute(&mut tx)
+        .await?;
+    }
+
+    sqlx::query("UPDATE transfer_batches SET status = $1 WHERE id = $2")
+        .bind(status.as_str())
+        .bind(batch_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// Store the batch and the outcome of each of its transfers
+async fn record_batch(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    user_id: Uuid,
+    mode: BatchMode,
+    status: BatchStatus,
+    total: &Money,
+    items: &[CreateTransactionRequest],
+    outcomes: &[ItemOutcome],
+) -> Result<Uuid, AppError> {
+    let succeeded = outcomes.iter().filter(|outcome| outcome.status == BatchItemStatus::Succeeded).count();
+
+    let batch_id: Uuid = sqlx::query(
+        r#"
+        INSERT INTO transfer_batches (user_id, mode, status, currency, total_amount, item_count, succeeded_count)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#
+    )
+    .bind(user_id)
+    .bind(mode.as_str())
+    .bind(status.as_str())
+    .bind(total.currency().code())
+    .bind(total.amount())
+    .bind(items.len() as i32)
+    .bind(succeeded as i32)
+    .fetch_one(&mut *tx)
+    .await?
+    .try_get("id")?;
+
+    for (index, (item, outcome)) in items.iter().zip(outcomes).enumerate() {
+        sqlx::query(
+            r#"
+            INSERT INTO transfer_batch_items
+                (batch_id, item_index, recipient_id, amount, description, status, transaction_id, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#
+        )
+        .bind(batch_id)
+        .bind(index as i32)
+        .bind(item.recipient_id)
+        .bind(&item.amount)
+        .bind(&item.description)
+        .bind(outcome.status.as_str())
+        .bind(outcome.transaction_id)
+        .bind(&outcome.error)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    Ok(batch_id)
+}
+
+// Record what became of one pending item of a batch
+async fn settle_item(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    batch_id: Uuid,
+    index: usize,
+    outcome: &ItemOutcome,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE transfer_batch_items SET status = $1, transaction_id = $2, error = $3
+        WHERE batch_id = $4 AND item_index = $5
+        "#
+    )
+    .bind(outcome.status.as_str())
+    .bind(outcome.transaction_id)
+    .bind(&outcome.error)
+    .bind(batch_id)
+    .bind(index as i32)
+    .execute(&mut *tx)
+    .await?;
+
+    if outcome.status == BatchItemStatus::Succeeded {
+        sqlx::query("UPDATE transfer_batches SET succeeded_count = succeeded_count + 1 WHERE id = $1")
+            .bind(batch_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// A batch `user_id` submitted, with the outcome of each transfer
+pub async fn load_batch(pool: &PgPool, batch_id: Uuid, user_id: Uuid) -> Result<BatchResponse, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, mode, status, currency, total_amount, item_count, succeeded_count, created_at
+        FROM transfer_batches
+        WHERE id = $1 AND user_id = $2
+        "#
+    )
+    .bind(batch_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Batch not found".to_string()))?;
+
+    let currency: String = row.try_get("currency")?;
+
+    let items = sqlx::query(
+        r#"
+        SELECT item_index, recipient_id, amount, description, status, transaction_id, error
+        FROM transfer_batch_items
+        WHERE batch_id = $1
+        ORDER BY item_index
+        "#
+    )
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|item| {
+        Ok(BatchItemResponse {
+            index: item.try_get("item_index")?,
+            recipient_id: item.try_get("recipient_id")?,
+            amount: Money::stored(item.try_get("amount")?, Currency::stored(&currency)?),
+            description: item.try_get("description")?,
+            status: item.try_get::<String, _>("status")?.parse()?,
+            transaction_id: item.try_get("transaction_id")?,
+            error: item.try_get("error")?,
+        })
+    })
+    .collect::<Result<Vec<_>, AppError>>()?;
+
+    Ok(BatchResponse {
+        id: row.try_get("id")?,
+        mode: row.try_get::<String, _>("mode")?.parse()?,
+        status: row.try_get::<String, _>("status")?.parse()?,
+        total_amount: Money::stored(row.try_get("total_amount")?, Currency::stored(&currency)?),
+        currency,
+        item_count: row.try_get("item_count")?,
+        succeeded_count: row.try_get("succeeded_count")?,
+        created_at: row.try_get("created_at")?,
+        items,
+    })
+}
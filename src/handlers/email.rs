@@ -0,0 +1,222 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{MailConfig, TokenConfig};
+use crate::models::{AppError, User, UserResponse, VerifyEmailRequest};
+use crate::utils::auth::{generate_refresh_token, hash_token};
+use crate::utils::mailer::Email;
+
+/// Confirm an email address with an emailed verification token.
+///
+/// A token for the current address marks it verified; a token for a pending
+/// address replaces the current one with it.
+pub async fn verify_email(
+    pool: web::Data<PgPool>,
+    verify_data: web::Json<VerifyEmailRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    verify_data.validate()?;
+
+    let invalid = || AppError::BadRequestError("Invalid or expired verification token".to_string());
+
+    let mut tx = pool.begin().await?;
+
+    let verification = sqlx::query(
+        r#"
+        SELECT id, user_id, email, expires_at, used_at
+        FROM email_verification_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#
+    )
+    .bind(hash_token(&verify_data.token))
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(invalid)?;
+
+    let verification_id: Uuid = verification.try_get("id")?;
+    let user_id: Uuid = verification.try_get("user_id")?;
+    let email: String = verification.try_get("email")?;
+    let expires_at: DateTime<Utc> = verification.try_get("expires_at")?;
+    let used_at: Option<DateTime<Utc>> = verification.try_get("used_at")?;
+
+    if used_at.is_some() || expires_at <= Utc::now() {
+        return Err(invalid());
+    }
+
+    sqlx::query("UPDATE email_verification_tokens SET used_at = NOW() WHERE id = $1")
+        .bind(verification_id)
+        .execute(&mut tx)
+        .await?;
+
+    let user = sqlx::query("SELECT email, pending_email FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+    let current_email: String = user.try_get("email")?;
+    let pending_email: Option<String> = user.try_get("pending_email")?;
+
+    if pending_email.as_deref() == Some(email.as_str()) {
+        // Someone else may have registered the address while it was pending
+        let taken = sqlx::query("SELECT id FROM users WHERE email = $1 AND id != $2")
+            .bind(&email)
+            .bind(user_id)
+            .fetch_optional(&mut tx)
+            .await?
+            .is_some();
+
+        if taken {
+            return Err(AppError::ConflictError("Email already registered by another user".to_string()));
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET email = pending_email, pending_email = NULL, email_verified_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            "#
+        )
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+        info!("User {} changed email address from {} to {}", user_id, current_email, email);
+    } else if current_email == email {
+        sqlx::query("UPDATE users SET email_verified_at = COALESCE(email_verified_at, NOW()), updated_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+    } else {
+        // The address was changed again after this token was sent
+        return Err(invalid());
+    }
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
+        FROM users
+        WHERE id = $1
+        "#
+    )
+    .bind(user_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(UserResponse::from(user)))
+}
+
+/// Send a new verification link for the pending address, or for the current
+/// one if it has not been verified yet
+pub async fn resend_verification(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mail: web::Data<MailConfig>,
+) -> Result<impl Responder, AppError> {
+    let user_id = user_id.into_inner();
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
+        FROM users
+        WHERE id = $1
+        "#
+    )
+    .bind(user_id)
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+    let email = match (&user.pending_email, user.email_verified_at) {
+        (Some(pending), _) => pending.clone(),
+        (None, None) => user.email.clone(),
+        (None, Some(_)) => return Err(AppError::ConflictError("Email address is already verified".to_string())),
+    };
+
+    send_verification(pool.get_ref(), config.get_ref(), mail.get_ref(), &user, &email).await?;
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "status": "accepted",
+        "message": format!("A verification link has been sent to {}", email)
+    })))
+}
+
+/// Email the user a link that confirms `email` belongs to them
+pub async fn send_verification(
+    pool: &PgPool,
+    config: &TokenConfig,
+    mail: &MailConfig,
+    user: &User,
+    email: &str,
+) -> Result<(), AppError> {
+    let token = issue_verification_token(pool, config, user.id, email).await?;
+    let message = Email {
+        to: email.to_string(),
+        subject: "Confirm your Dodo Payments email address".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             To confirm that this is your email address, open\n\n\
+             {}/verify-email?token={}\n\n\
+             or send this token to POST /api/users/email/verify:\n\n{}\n\n\
+             The link expires in {} hours. If you did not ask for this, you can ignore this email.",
+            user.username,
+            mail.public_url,
+            token,
+            token,
+            config.email_verification_ttl.as_secs() / 3600
+        ),
+    };
+
+    mail.mailer.send(&message).await
+}
+
+/// Like `send_verification`, but only logs delivery failures so the change
+/// that triggered the email still succeeds; the user can ask for a new link.
+pub async fn try_send_verification(pool: &PgPool, config: &TokenConfig, mail: &MailConfig, user: &User, email: &str) {
+    if let Err(err) = send_verification(pool, config, mail, user, email).await {
+        error!("Failed to send verification email to user {}: {}", user.id, err);
+    }
+}
+
+/// Create a verification token for `email`, replacing any earlier unused one.
+/// Only its hash is stored; the token itself is returned to be emailed.
+pub async fn issue_verification_token(
+    pool: &PgPool,
+    config: &TokenConfig,
+    user_id: Uuid,
+    email: &str,
+) -> Result<String, AppError> {
+    let token = generate_refresh_token();
+    let ttl = chrono::Duration::from_std(config.email_verification_ttl)
+        .map_err(|_| AppError::InternalServerError("Email verification TTL is out of range".to_string()))?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE email_verification_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO email_verification_tokens (user_id, email, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(user_id)
+    .bind(email)
+    .bind(hash_token(&token))
+    .bind(Utc::now() + ttl)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(token)
+}
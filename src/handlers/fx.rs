@@ -0,0 +1,148 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::Utc;
+use sqlx::{PgExecutor, PgPool, Postgres};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::TransferConfig;
+use crate::models::{AppError, Conversion, CreateQuoteRequest, Currency, FxQuote, FxQuoteResponse, Money};
+
+pub async fn create_quote(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TransferConfig>,
+    quote_data: web::Json<CreateQuoteRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    quote_data.validate()?;
+
+    let quote = issue_quote(pool.get_ref(), config.get_ref(), user_id.into_inner(), &quote_data).await?;
+
+    Ok(HttpResponse::Created().json(FxQuoteResponse::try_from(quote)?))
+}
+
+/// Quote a conversion for `user_id` and lock its rate for `fx_quote_ttl`
+pub async fn issue_quote(
+    pool: &PgPool,
+    config: &TransferConfig,
+    user_id: Uuid,
+    quote_data: &CreateQuoteRequest,
+) -> Result<FxQuote, AppError> {
+    let amount = Money::positive(quote_data.amount.clone(), &quote_data.from_currency)?;
+    let to = Currency::from_code(&quote_data.to_currency)?;
+    if amount.currency() == to {
+        return Err(AppError::BadRequestError("Cannot quote a conversion to the same currency".to_string()));
+    }
+
+    let conversion = convert(config, amount, to).await?;
+    let ttl = chrono::Duration::from_std(config.fx_quote_ttl)
+        .map_err(|_| AppError::InternalServerError("Quote TTL is out of range".to_string()))?;
+
+    let quote = sqlx::query_as::<_, FxQuote>(
+        r#"
+        INSERT INTO fx_quotes (user_id, from_currency, to_currency, amount, rate, fee, converted_amount, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, user_id, from_currency, to_currency, amount, rate, fee, converted_amount, expires_at, used_at, created_at
+        "#
+    )
+    .bind(user_id)
+    .bind(conversion.amount.currency().code())
+    .bind(to.code())
+    .bind(conversion.amount.amount())
+    .bind(&conversion.rate)
+    .bind(conversion.fee.amount())
+    .bind(conversion.converted.amount())
+    .bind(Utc::now() + ttl)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(quote)
+}
+
+pub async fn get_quote(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    quote_id: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let quote = find_quote(pool.get_ref(), quote_id.into_inner(), user_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(FxQuoteResponse::try_from(quote)?))
+}
+
+/// Convert `amount` into `to` at the provider's current rate less the spread
+pub async fn convert(config: &TransferConfig, amount: Money, to: Currency) -> Result<Conversion, AppError> {
+    let rate = config.fx_rates.rate(amount.currency(), to).await?;
+
+    Conversion::compute(amount, to, rate, &config.fx_spread)
+}
+
+/// Look up a quote belonging to `user_id`
+pub async fn find_quote<'c>(executor: impl PgExecutor<'c>, quote_id: Uuid, user_id: Uuid) -> Result<FxQuote, AppError> {
+    sqlx::query_as::<_, FxQuote>(
+        r#"
+        SELECT id, user_id, from_currency, to_currency, amount, rate, fee, converted_amount, expires_at, used_at, created_at
+        FROM fx_quotes
+        WHERE id = $1 AND user_id = $2
+        "#
+    )
+    .bind(quote_id)
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Quote not found".to_string()))
+}
+
+/// Check a quote can pay for `amount` and mark it used inside `tx`, so each
+/// quote funds at most one transfer
+pub async fn claim_quote(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    quote_id: Uuid,
+    user_id: Uuid,
+    amount: &Money,
+) -> Result<Conversion, AppError> {
+    let quote = sqlx::query_as::<_, FxQuote>(
+        r#"
+        SELECT id, user_id, from_currency, to_currency, amount, rate, fee, converted_amount, expires_at, used_at, created_at
+        FROM fx_quotes
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE
+        "#
+    )
+    .bind(quote_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Quote not found".to_string()))?;
+
+    let conversion = check_quote(&quote, amount)?;
+
+    sqlx::query("UPDATE fx_quotes SET used_at = NOW() WHERE id = $1")
+        .bind(quote_id)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(conversion)
+}
+
+/// Ensure a quote is unused, unexpired and for exactly `amount`
+pub fn check_quote(quote: &FxQuote, amount: &Money) -> Result<Conversion, AppError> {
+    if quote.used_at.is_some() {
+        return Err(AppError::BadRequestError("Quote has already been used".to_string()));
+    }
+    if quote.expires_at <= Utc::now() {
+        return Err(AppError::BadRequestError("Quote has expired".to_string()));
+    }
+
+    let conversion = quote.to_conversion()?;
+    if conversion.amount != *amount {
+        return Err(AppError::BadRequestError(format!(
+            "Quote is for {} {}, not {} {}",
+            conversion.amount,
+            conversion.amount.currency(),
+            amount,
+            amount.currency()
+        )));
+    }
+
+    Ok(conversion)
+}
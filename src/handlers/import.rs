@@ -0,0 +1,112 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Row};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::config::{BatchConfig, MfaConfig, TransferConfig};
+use crate::handlers::batch::{available_balance, batch_submitted, submit_batch};
+use crate::handlers::mfa::{require_step_up, MFA_CODE_HEADER};
+use crate::handlers::transaction::INSUFFICIENT_FUNDS;
+use crate::models::{AppError, BatchMode, ImportFormat, ImportLineError, Money};
+use crate::utils::import::{parse_transfers, ParsedImport};
+
+/// Import a batch of transfers from a CSV or ISO 20022 pain.001 file sent as
+/// the request body.
+///
+/// Every transfer in the file is read and checked before any is made, and
+/// each problem is reported against the line it was found on. A dry run
+/// returns that report and makes nothing. Otherwise a file with any problem
+/// is refused with the report, and a clean one is submitted as a batch.
+#[allow(clippy::too_many_arguments)]
+pub async fn import_transfers(
+    req: HttpRequest,
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TransferConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    batch_config: web::Data<BatchConfig>,
+    query: web::Query<ImportTransfersQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, AppError> {
+    let user_id = user_id.into_inner();
+    let input = std::str::from_utf8(&body)
+        .map_err(|_| AppError::BadRequestError("The file must be UTF-8 encoded".to_string()))?;
+
+    let mut parsed = parse_transfers(query.format, input, batch_config.max_items);
+    check_with_accounts(pool.get_ref(), user_id, &mut parsed).await?;
+
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(parsed.report(query.format, true)));
+    }
+    let total = match parsed.total() {
+        Some(total) if parsed.is_valid() => total,
+        _ => return Ok(HttpResponse::UnprocessableEntity().json(parsed.report(query.format, false))),
+    };
+
+    // One code covers the whole file
+    let code = req.headers().get(MFA_CODE_HEADER).and_then(|value| value.to_str().ok());
+    require_step_up(pool.get_ref(), mfa_config.get_ref(), config.get_ref(), user_id, &total, code).await?;
+
+    let mode = query.mode.unwrap_or(BatchMode::AllOrNothing);
+    let batch_id = submit_batch(pool.get_ref(), config.get_ref(), user_id, mode, &parsed.requests(), &total).await?;
+
+    batch_submitted(&req, pool.get_ref(), batch_id, user_id).await
+}
+
+/// Add the problems only the database can find to an import from `user_id`:
+/// transfers to themselves or to users who do not exist, and a total their
+/// available balance cannot cover. These would otherwise only be found when
+/// the batch is run.
+pub async fn check_with_accounts(pool: &PgPool, user_id: Uuid, parsed: &mut ParsedImport) -> Result<(), AppError> {
+    let recipient_ids: Vec<Uuid> = parsed.transfers.iter().map(|transfer| transfer.request.recipient_id).collect();
+    let known = sqlx::query("SELECT id FROM users WHERE id = ANY($1)")
+        .bind(&recipient_ids)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| row.try_get("id"))
+        .collect::<Result<HashSet<Uuid>, _>>()?;
+
+    for transfer in &parsed.transfers {
+        let message = match transfer.request.recipient_id {
+            recipient_id if recipient_id == user_id => "Cannot send money to yourself",
+            recipient_id if !known.contains(&recipient_id) => "Recipient not found",
+            _ => continue,
+        };
+        parsed.errors.push(ImportLineError { line: Some(transfer.line), message: message.to_string() });
+    }
+
+    // Funds only matter once every transfer can be made
+    let total = match parsed.total() {
+        Some(total) if parsed.is_valid() => total,
+        _ => return Ok(()),
+    };
+    match available_balance(pool, user_id, total.currency()).await {
+        Ok(available) if available < *total.amount() => parsed.errors.push(ImportLineError {
+            line: None,
+            message: format!(
+                "{}: the file totals {} {} but {} is available",
+                INSUFFICIENT_FUNDS,
+                total,
+                total.currency(),
+                Money::parse(available, total.currency().code())?
+            ),
+        }),
+        Ok(_) => {}
+        // The sender has no wallet in the file's currency
+        Err(AppError::BadRequestError(message)) => parsed.errors.push(ImportLineError { line: None, message }),
+        Err(err) => return Err(err),
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportTransfersQuery {
+    format: ImportFormat,
+    // all_or_nothing unless given
+    mode: Option<BatchMode>,
+    // Check the file and report on it without making any transfer
+    #[serde(default)]
+    dry_run: bool,
+}
@@ -0,0 +1,245 @@
+use actix_web::{web, HttpResponse, Responder};
+use bigdecimal::{BigDecimal, Zero};
+use sqlx::{PgPool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::models::AppError;
+use crate::models::ledger::{
+    AccountDrift, EntryType, LedgerAccount, LedgerCheckResponse, LedgerEntryListResponse,
+    LedgerEntryResponse, LedgerLine, UnbalancedJournal,
+};
+
+/// Post a balanced set of journal lines and apply them to the cached
+/// `accounts.balance` of every user account involved.
+///
+/// Callers must already hold row locks on the affected accounts. Returns the
+/// id shared by all lines of the journal.
+pub async fn post_journal(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    transaction_id: Option<Uuid>,
+    currency: &str,
+    description: &str,
+    lines: &[LedgerLine],
+) -> Result<Uuid, AppError> {
+    let mut debits = BigDecimal::zero();
+    let mut credits = BigDecimal::zero();
+    for line in lines {
+        if line.amount <= BigDecimal::zero() {
+            return Err(AppError::InternalServerError("Ledger amounts must be positive".to_string()));
+        }
+        match line.entry_type {
+            EntryType::Debit => debits += &line.amount,
+            EntryType::Credit => credits += &line.amount,
+        }
+    }
+
+    if lines.is_empty() || debits != credits {
+        return Err(AppError::InternalServerError(format!(
+            "Unbalanced journal: debits {} and credits {}",
+            debits, credits
+        )));
+    }
+
+    let journal_id = Uuid::new_v4();
+
+    for line in lines {
+        let (account_id, system_account) = match &line.account {
+            LedgerAccount::User(account_id) => (Some(*account_id), None),
+            LedgerAccount::System(name) => (None, Some(*name)),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO ledger_entries
+                (journal_id, transaction_id, account_id, system_account, entry_type, amount, currency, description)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#
+        )
+        .bind(journal_id)
+        .bind(transaction_id)
+        .bind(account_id)
+        .bind(system_account)
+        .bind(line.entry_type.as_str())
+        .bind(&line.amount)
+        .bind(currency)
+        .bind(description)
+        .execute(&mut *tx)
+        .await?;
+
+        // User accounts are liabilities of the platform: credits raise the balance
+        if let Some(account_id) = account_id {
+            let delta = match line.entry_type {
+                EntryType::Credit => line.amount.clone(),
+                EntryType::Debit => -line.amount.clone(),
+            };
+
+            sqlx::query(
+                r#"
+                UPDATE accounts
+                SET balance = balance + $1, updated_at = NOW()
+                WHERE id = $2
+                "#
+            )
+            .bind(&delta)
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    Ok(journal_id)
+}
+
+/// Recompute every account balance from the journal and report any drift
+/// from the cached balance, along with journals whose lines do not balance.
+pub async fn check_ledger(pool: &PgPool) -> Result<LedgerCheckResponse, AppError> {
+    let accounts_checked: i64 = sqlx::query("SELECT COUNT(*) AS count FROM accounts")
+        .fetch_one(pool)
+        .await?
+        .try_get("count")?;
+
+    let drift_rows = sqlx::query(
+        r#"
+        SELECT a.id, a.user_id, a.currency, a.balance,
+               COALESCE(SUM(CASE WHEN l.entry_type = 'credit' THEN l.amount ELSE -l.amount END), 0) AS ledger_balance
+        FROM accounts a
+        LEFT JOIN ledger_entries l ON l.account_id = a.id
+        GROUP BY a.id, a.user_id, a.currency, a.balance
+        HAVING a.balance <> COALESCE(SUM(CASE WHEN l.entry_type = 'credit' THEN l.amount ELSE -l.amount END), 0)
+        ORDER BY a.id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut account_drift = Vec::with_capacity(drift_rows.len());
+    for row in drift_rows {
+        account_drift.push(AccountDrift {
+            account_id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            currency: row.try_get("currency")?,
+            cached_balance: row.try_get::<BigDecimal, _>("balance")?.to_string(),
+            ledger_balance: row.try_get::<BigDecimal, _>("ledger_balance")?.to_string(),
+        });
+    }
+
+    let journal_rows = sqlx::query(
+        r#"
+        SELECT journal_id, currency,
+               SUM(CASE WHEN entry_type = 'debit' THEN amount ELSE 0 END) AS debits,
+               SUM(CASE WHEN entry_type = 'credit' THEN amount ELSE 0 END) AS credits
+        FROM ledger_entries
+        GROUP BY journal_id, currency
+        HAVING SUM(CASE WHEN entry_type = 'debit' THEN amount ELSE -amount END) <> 0
+        ORDER BY journal_id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut unbalanced_journals = Vec::with_capacity(journal_rows.len());
+    for row in journal_rows {
+        unbalanced_journals.push(UnbalancedJournal {
+            journal_id: row.try_get("journal_id")?,
+            currency: row.try_get("currency")?,
+            debits: row.try_get::<BigDecimal, _>("debits")?.to_string(),
+            credits: row.try_get::<BigDecimal, _>("credits")?.to_string(),
+        });
+    }
+
+    Ok(LedgerCheckResponse {
+        consistent: account_drift.is_empty() && unbalanced_journals.is_empty(),
+        accounts_checked,
+        account_drift,
+        unbalanced_journals,
+    })
+}
+
+/// Run the ledger invariant check
+pub async fn verify_ledger(
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let report = check_ledger(pool.get_ref()).await?;
+
+    if !report.consistent {
+        log::error!(
+            "Ledger check found {} drifted accounts and {} unbalanced journals",
+            report.account_drift.len(),
+            report.unbalanced_journals.len()
+        );
+    }
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// List the journal lines behind the current user's balance
+pub async fn list_entries(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    query: web::Query<ListEntriesQuery>,
+) -> Result<impl Responder, AppError> {
+    let user_id = user_id.into_inner();
+    let limit = query.limit.unwrap_or(10).clamp(1, 100) as i64;
+    let offset = query.offset.unwrap_or(0) as i64;
+
+    let rows = sqlx::query(
+        r#"
+        SELECT l.id, l.journal_id, l.transaction_id, l.entry_type, l.amount, l.currency, l.description, l.created_at
+        FROM ledger_entries l
+        JOIN accounts a ON a.id = l.account_id
+        WHERE a.user_id = $1
+        ORDER BY l.created_at DESC, l.id
+        LIMIT $2 OFFSET $3
+        "#
+    )
+    .bind(user_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let mut entries = Vec::with_capacity(rows.len());
+    for row in rows {
+        let entry_type = match row.try_get::<String, _>("entry_type")?.as_str() {
+            "debit" => EntryType::Debit,
+            _ => EntryType::Credit,
+        };
+
+        entries.push(LedgerEntryResponse {
+            id: row.try_get("id")?,
+            journal_id: row.try_get("journal_id")?,
+            transaction_id: row.try_get("transaction_id")?,
+            entry_type,
+            amount: row.try_get::<BigDecimal, _>("amount")?.to_string(),
+            currency: row.try_get("currency")?,
+            description: row.try_get("description")?,
+            created_at: row.try_get("created_at")?,
+        });
+    }
+
+    let total: i64 = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS count
+        FROM ledger_entries l
+        JOIN accounts a ON a.id = l.account_id
+        WHERE a.user_id = $1
+        "#
+    )
+    .bind(user_id)
+    .fetch_one(pool.get_ref())
+    .await?
+    .try_get("count")?;
+
+    Ok(HttpResponse::Ok().json(LedgerEntryListResponse {
+        entries,
+        total,
+        page: offset / limit,
+        per_page: limit,
+    }))
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListEntriesQuery {
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
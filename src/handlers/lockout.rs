@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use crate::config::LoginConfig;
+use crate::handlers::audit::record_system_action;
+use crate::models::audit::LOCK_ACCOUNT;
+use crate::models::AppError;
+
+/// A login attempt counted by `claim_login_attempt`
+pub struct LoginAttempt {
+    // Failed attempts in a row, this one included
+    pub failures: u32,
+    // Set when this attempt locks the username, should it fail
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// Count a login attempt for `username` before its password is checked,
+/// refusing it while the username is backing off or locked out.
+///
+/// The attempt counts as failed from the start, and the check and the count
+/// are one statement on a locked row, so parallel guesses cannot all get past
+/// the check before any of them is counted. A successful login then clears
+/// the count with `clear_failed_logins`. This runs before the user is looked
+/// up, so unknown usernames are throttled exactly like real ones.
+pub async fn claim_login_attempt(
+    pool: &PgPool,
+    config: &LoginConfig,
+    username: &str,
+) -> Result<LoginAttempt, AppError> {
+    let mut tx = pool.begin().await?;
+
+    // A blocked username is left as it is; otherwise failures older than
+    // the window no longer count
+    let attempt = sqlx::query(
+        r#"
+        INSERT INTO login_attempts (username, failed_count, last_failed_at)
+        VALUES ($1, 1, NOW())
+        ON CONFLICT (username) DO UPDATE
+        SET failed_count = CASE
+                WHEN login_attempts.blocked_until > NOW() THEN login_attempts.failed_count
+                WHEN login_attempts.last_failed_at < NOW() - $2 * INTERVAL '1 second' THEN 1
+                ELSE login_attempts.failed_count + 1
+            END,
+            last_failed_at = CASE
+                WHEN login_attempts.blocked_until > NOW() THEN login_attempts.last_failed_at
+                ELSE NOW()
+            END
+        RETURNING failed_count, blocked_until, COALESCE(blocked_until > NOW(), FALSE) AS blocked
+        "#
+    )
+    .bind(username)
+    .bind(config.failure_window.as_secs() as i64)
+    .fetch_one(&mut tx)
+    .await?;
+
+    if attempt.try_get("blocked")? {
+        let blocked_until: DateTime<Utc> = attempt.try_get("blocked_until")?;
+        let retry_after = (blocked_until - Utc::now()).num_seconds().max(1) as u64;
+        return Err(AppError::TooManyRequestsError(
+            "Too many failed login attempts; try again later".to_string(),
+            retry_after,
+        ));
+    }
+
+    let failures = attempt.try_get::<i32, _>("failed_count")?.max(0) as u32;
+    let mut locked_until = None;
+    if let Some(delay) = config.block_after(failures) {
+        let delay = chrono::Duration::from_std(delay)
+            .map_err(|_| AppError::InternalServerError("Login backoff is out of range".to_string()))?;
+        let blocked_until = Utc::now() + delay;
+
+        sqlx::query("UPDATE login_attempts SET blocked_until = $1 WHERE username = $2")
+            .bind(blocked_until)
+            .bind(username)
+            .execute(&mut tx)
+            .await?;
+
+        if config.is_lockout(failures) {
+            locked_until = Some(blocked_until);
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(LoginAttempt { failures, locked_until })
+}
+
+/// Finish a login attempt whose password was wrong. The attempt was already
+/// counted; if it locked the username, the lockout is recorded in the audit
+/// log with `user_id`, the matching user if there is one, as its target.
+pub async fn record_failed_login(
+    pool: &PgPool,
+    username: &str,
+    user_id: Option<Uuid>,
+    attempt: &LoginAttempt,
+) -> Result<(), AppError> {
+    if let Some(locked_until) = attempt.locked_until {
+        record_system_action(
+            pool,
+            LOCK_ACCOUNT,
+            user_id,
+            serde_json::json!({
+                "username": username,
+                "failed_attempts": attempt.failures,
+                "locked_until": locked_until,
+            }),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Forget the failed logins of `username` after a successful one
+pub async fn clear_failed_logins(pool: &PgPool, username: &str) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM login_attempts WHERE username = $1")
+        .bind(username)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Delete failed login counts that are past their window and no longer block
+/// anything. Returns the number of rows removed.
+pub async fn purge_stale_login_attempts(pool: &PgPool, config: &LoginConfig) -> Result<u64, AppError> {
+    let purged = sqlx::query(
+        r#"
+        DELETE FROM login_attempts
+        WHERE last_failed_at < NOW() - $1 * INTERVAL '1 second'
+          AND (blocked_until IS NULL OR blocked_until <= NOW())
+        "#
+    )
+    .bind(config.failure_window.as_secs() as i64)
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(purged)
+}
@@ -0,0 +1,571 @@
+use actix_web::{web, HttpResponse, Responder};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, RngCore};
+use sqlx::{PgPool, Postgres, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{MfaConfig, TokenConfig, TransferConfig};
+use crate::handlers::session::{start_session, ClientInfo};
+use crate::handlers::token::issue_tokens;
+use crate::models::{
+    AppError, MfaChallengeResponse, MfaCodeRequest, MfaEnrollmentResponse, MfaLoginRequest, MfaStatusResponse,
+    Money, RecoveryCodesResponse, Role, StepUpThresholdRequest,
+};
+use crate::utils::auth::{generate_refresh_token, hash_token};
+use crate::utils::keys::KeyStore;
+use crate::utils::totp;
+
+/// Header carrying the step-up code for transfers above the user's threshold
+pub const MFA_CODE_HEADER: &str = "X-MFA-Code";
+
+// Recovery codes handed out when MFA is enabled or the codes are regenerated
+const RECOVERY_CODE_COUNT: usize = 10;
+// Wrong codes a login challenge tolerates before it is thrown away
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// Whether MFA is on for the user and the threshold above which transfers
+/// need a step-up code
+pub async fn get_status(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<MfaConfig>,
+) -> Result<impl Responder, AppError> {
+    let status = load_status(pool.get_ref(), config.get_ref(), user_id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Start enrolling an authenticator: generate a secret and its provisioning
+/// URI. MFA stays off until `confirm_enrollment` sees a valid code.
+pub async fn enroll(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<MfaConfig>,
+) -> Result<impl Responder, AppError> {
+    let user_id = user_id.into_inner();
+
+    let username: String = sqlx::query("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+        .try_get("username")?;
+
+    let secret = totp::generate_secret();
+
+    // Replaces an unconfirmed enrollment, but never an active authenticator
+    let enrolled = sqlx::query(
+        r#"
+        INSERT INTO user_mfa (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, last_used_step = NULL
+        WHERE user_mfa.enabled_at IS NULL
+        "#
+    )
+    .bind(user_id)
+    .bind(&secret)
+    .execute(pool.get_ref())
+    .await?
+    .rows_affected();
+
+    if enrolled == 0 {
+        return Err(AppError::ConflictError("MFA is already enabled".to_string()));
+    }
+
+    Ok(HttpResponse::Created().json(MfaEnrollmentResponse {
+        provisioning_uri: totp::provisioning_uri(&secret, &username, &config.issuer),
+        secret,
+    }))
+}
+
+/// Turn MFA on once the authenticator produces a valid code, and hand out the
+/// recovery codes
+pub async fn confirm_enrollment(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    code_data: web::Json<MfaCodeRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    code_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let mut tx = pool.begin().await?;
+
+    let enrollment = sqlx::query("SELECT secret, enabled_at FROM user_mfa WHERE user_id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::BadRequestError("No MFA enrollment in progress".to_string()))?;
+
+    let enabled_at: Option<DateTime<Utc>> = enrollment.try_get("enabled_at")?;
+    if enabled_at.is_some() {
+        return Err(AppError::ConflictError("MFA is already enabled".to_string()));
+    }
+
+    let secret: String = enrollment.try_get("secret")?;
+    let step = totp::verify(&secret, &normalize_code(&code_data.code), unix_now(), None)?
+        .ok_or_else(|| AppError::BadRequestError("Invalid MFA code".to_string()))?;
+
+    sqlx::query("UPDATE user_mfa SET enabled_at = NOW(), last_used_step = $1 WHERE user_id = $2")
+        .bind(step as i64)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+    let recovery_codes = replace_recovery_codes(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turn MFA off, which needs a current code or a recovery code
+pub async fn disable(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<MfaConfig>,
+    code_data: web::Json<MfaCodeRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    code_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let mut tx = require_code(pool.get_ref(), config.get_ref(), user_id, &code_data.code).await?;
+
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("DELETE FROM user_mfa WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Replace every recovery code, used or not, with a fresh set
+pub async fn regenerate_recovery_codes(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<MfaConfig>,
+    code_data: web::Json<MfaCodeRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    code_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let mut tx = require_code(pool.get_ref(), config.get_ref(), user_id, &code_data.code).await?;
+    let recovery_codes = replace_recovery_codes(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Set the amount above which the user's transfers need a step-up code
+pub async fn set_step_up_threshold(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<MfaConfig>,
+    threshold_data: web::Json<StepUpThresholdRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    threshold_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let threshold = Money::parse(threshold_data.amount.clone(), &threshold_data.currency)?;
+    if threshold.amount() < &BigDecimal::from(0) {
+        return Err(AppError::BadRequestError("amount cannot be negative".to_string()));
+    }
+
+    let mut tx = require_code(pool.get_ref(), config.get_ref(), user_id, &threshold_data.code).await?;
+
+    sqlx::query("UPDATE user_mfa SET step_up_threshold = $1, step_up_currency = $2 WHERE user_id = $3")
+        .bind(threshold.amount())
+        .bind(threshold.currency().code())
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    let status = load_status(pool.get_ref(), config.get_ref(), user_id).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Finish a login for a user with MFA: exchange the challenge token from
+/// `login` and a valid code for an access token and refresh token
+pub async fn complete_login(
+    pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    keys: web::Data<KeyStore>,
+    client: ClientInfo,
+    login_data: web::Json<MfaLoginRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    login_data.validate()?;
+
+    let mut tx = pool.begin().await?;
+
+    let challenge = sqlx::query(
+        r#"
+        SELECT c.id, c.user_id, c.attempts, c.expires_at, u.role
+        FROM mfa_challenges c
+        JOIN users u ON u.id = c.user_id
+        WHERE c.token_hash = $1
+        FOR UPDATE OF c
+        "#
+    )
+    .bind(hash_token(&login_data.mfa_token))
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::AuthenticationError("Invalid MFA token".to_string()))?;
+
+    let challenge_id: Uuid = challenge.try_get("id")?;
+    let user_id: Uuid = challenge.try_get("user_id")?;
+    let attempts: i32 = challenge.try_get("attempts")?;
+    let expires_at: DateTime<Utc> = challenge.try_get("expires_at")?;
+    let role: Role = challenge.try_get::<String, _>("role")?.parse()?;
+
+    if expires_at <= Utc::now() {
+        return Err(AppError::AuthenticationError("MFA token has expired".to_string()));
+    }
+
+    if !verify_code(&mut tx, mfa_config.get_ref(), user_id, &login_data.code).await? {
+        // A challenge only survives a few wrong guesses
+        if attempts + 1 >= MAX_CHALLENGE_ATTEMPTS {
+            sqlx::query("DELETE FROM mfa_challenges WHERE id = $1")
+                .bind(challenge_id)
+                .execute(&mut tx)
+                .await?;
+        } else {
+            sqlx::query("UPDATE mfa_challenges SET attempts = attempts + 1 WHERE id = $1")
+                .bind(challenge_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        return Err(AppError::AuthenticationError("Invalid MFA code".to_string()));
+    }
+
+    sqlx::query("DELETE FROM mfa_challenges WHERE id = $1")
+        .bind(challenge_id)
+        .execute(&mut tx)
+        .await?;
+
+    let session_id = start_session(&mut tx, user_id, &client).await?;
+    let tokens = issue_tokens(&mut tx, config.get_ref(), keys.get_ref(), user_id, role, session_id).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Whether the user has a confirmed authenticator
+pub async fn is_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, AppError> {
+    let enabled = sqlx::query("SELECT 1 FROM user_mfa WHERE user_id = $1 AND enabled_at IS NOT NULL")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+
+    Ok(enabled)
+}
+
+/// Record a login challenge for a user whose password checked out, to be
+/// completed with `complete_login` within `config.challenge_ttl`
+pub async fn start_challenge(pool: &PgPool, config: &MfaConfig, user_id: Uuid) -> Result<MfaChallengeResponse, AppError> {
+    let mfa_token = generate_refresh_token();
+    let ttl = chrono::Duration::from_std(config.challenge_ttl)
+        .map_err(|_| AppError::InternalServerError("MFA challenge TTL is out of range".to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO mfa_challenges (user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3)
+        "#
+    )
+    .bind(user_id)
+    .bind(hash_token(&mfa_token))
+    .bind(Utc::now() + ttl)
+    .execute(pool)
+    .await?;
+
+    Ok(MfaChallengeResponse {
+        mfa_required: true,
+        mfa_token,
+        expires_in: config.challenge_ttl.as_secs(),
+    })
+}
+
+/// Check `code` against the user's authenticator or unused recovery codes,
+/// consuming it if it matches. Fails if MFA is not enabled.
+///
+/// Wrong codes are counted per user whichever endpoint they come through, and
+/// after `config.max_failed_codes` in a row every code is refused for
+/// `config.lockout`. The count is written in `tx`, so callers must commit it
+/// when the code is wrong too. A right code resets it.
+pub async fn verify_code(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    config: &MfaConfig,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, AppError> {
+    let mfa = sqlx::query(
+        r#"
+        SELECT secret, last_used_step, locked_until
+        FROM user_mfa
+        WHERE user_id = $1 AND enabled_at IS NOT NULL
+        FOR UPDATE
+        "#
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::BadRequestError("MFA is not enabled".to_string()))?;
+
+    let locked_until: Option<DateTime<Utc>> = mfa.try_get("locked_until")?;
+    if let Some(locked_until) = locked_until.filter(|until| *until > Utc::now()) {
+        let retry_after = (locked_until - Utc::now()).num_seconds().max(1) as u64;
+        return Err(AppError::TooManyRequestsError(
+            "Too many wrong MFA codes; try again later".to_string(),
+            retry_after,
+        ));
+    }
+
+    let code = normalize_code(code);
+
+    let matched = if code.len() == totp::DIGITS as usize && code.chars().all(|c| c.is_ascii_digit()) {
+        let secret: String = mfa.try_get("secret")?;
+        let last_used_step: Option<i64> = mfa.try_get("last_used_step")?;
+
+        match totp::verify(&secret, &code, unix_now(), last_used_step.map(|step| step as u64))? {
+            Some(step) => {
+                sqlx::query("UPDATE user_mfa SET last_used_step = $1 WHERE user_id = $2")
+                    .bind(step as i64)
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                true
+            }
+            None => false,
+        }
+    } else {
+        use_recovery_code(tx, user_id, &code).await?
+    };
+
+    if matched {
+        sqlx::query("UPDATE user_mfa SET failed_attempts = 0, locked_until = NULL WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        record_failed_code(tx, config, user_id).await?;
+    }
+
+    Ok(matched)
+}
+
+// Count a wrong code, locking the user's codes once there are too many
+async fn record_failed_code(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    config: &MfaConfig,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    let lockout = chrono::Duration::from_std(config.lockout)
+        .map_err(|_| AppError::InternalServerError("MFA lockout is out of range".to_string()))?;
+
+    sqlx::query(
+        r#"
+        UPDATE user_mfa
+        SET failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END,
+            locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $3 ELSE locked_until END
+        WHERE user_id = $1
+        "#
+    )
+    .bind(user_id)
+    .bind(config.max_failed_codes as i32)
+    .bind(Utc::now() + lockout)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+// Mark an unused recovery code as used if `code` is one
+async fn use_recovery_code(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, AppError> {
+    let used = sqlx::query(
+        r#"
+        UPDATE mfa_recovery_codes
+        SET used_at = NOW()
+        WHERE id = (
+            SELECT id FROM mfa_recovery_codes
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            LIMIT 1
+        )
+        "#
+    )
+    .bind(user_id)
+    .bind(hash_token(code))
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    Ok(used > 0)
+}
+
+/// Require a fresh code for a transfer of `amount` if it is above the user's
+/// step-up threshold. Users without MFA are not asked for one.
+///
+/// Amounts in another currency than the threshold are compared at the current
+/// rate; if there is no rate the code is required.
+pub async fn require_step_up(
+    pool: &PgPool,
+    mfa_config: &MfaConfig,
+    transfer_config: &TransferConfig,
+    user_id: Uuid,
+    amount: &Money,
+    code: Option<&str>,
+) -> Result<(), AppError> {
+    let status = load_status(pool, mfa_config, user_id).await?;
+    if !status.enabled {
+        return Ok(());
+    }
+
+    let threshold = status.step_up_threshold;
+    let above_threshold = if amount.currency() == threshold.currency() {
+        amount.amount() > threshold.amount()
+    } else {
+        match transfer_config.fx_rates.rate(amount.currency(), threshold.currency()).await {
+            Ok(rate) => amount.amount() * rate > *threshold.amount(),
+            Err(_) => true,
+        }
+    };
+    if !above_threshold {
+        return Ok(());
+    }
+
+    let code = code.ok_or_else(|| {
+        AppError::ForbiddenError(format!(
+            "Transfers above {} {} need a code from your authenticator in the {} header",
+            threshold,
+            threshold.currency(),
+            MFA_CODE_HEADER
+        ))
+    })?;
+
+    require_fresh_code(pool, mfa_config, user_id, code).await
+}
+
+/// Check a code from the user's authenticator before a sensitive action
+pub async fn require_fresh_code(pool: &PgPool, config: &MfaConfig, user_id: Uuid, code: &str) -> Result<(), AppError> {
+    require_code(pool, config, user_id, code).await?.commit().await?;
+
+    Ok(())
+}
+
+// Check `code` in a new database transaction, returned still open so that
+// the caller's changes commit along with the code being used up. A wrong code
+// is committed as a failed attempt before failing.
+async fn require_code(
+    pool: &PgPool,
+    config: &MfaConfig,
+    user_id: Uuid,
+    code: &str,
+) -> Result<sqlx::Transaction<'static, Postgres>, AppError> {
+    let mut tx = pool.begin().await?;
+    if !verify_code(&mut tx, config, user_id, code).await? {
+        tx.commit().await?;
+        return Err(AppError::ForbiddenError("Invalid MFA code".to_string()));
+    }
+    Ok(tx)
+}
+
+async fn load_status(pool: &PgPool, config: &MfaConfig, user_id: Uuid) -> Result<MfaStatusResponse, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT m.enabled_at, m.step_up_threshold, m.step_up_currency,
+               (SELECT COUNT(*) FROM mfa_recovery_codes r WHERE r.user_id = m.user_id AND r.used_at IS NULL) AS remaining
+        FROM user_mfa m
+        WHERE m.user_id = $1
+        "#
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(MfaStatusResponse {
+            enabled: false,
+            step_up_threshold: config.step_up_threshold.clone(),
+            recovery_codes_remaining: 0,
+        });
+    };
+
+    let enabled_at: Option<DateTime<Utc>> = row.try_get("enabled_at")?;
+    let amount: Option<BigDecimal> = row.try_get("step_up_threshold")?;
+    let currency: Option<String> = row.try_get("step_up_currency")?;
+    let step_up_threshold = match (amount, currency) {
+        (Some(amount), Some(currency)) => Money::parse(amount, &currency)?,
+        _ => config.step_up_threshold.clone(),
+    };
+
+    Ok(MfaStatusResponse {
+        enabled: enabled_at.is_some(),
+        step_up_threshold,
+        recovery_codes_remaining: row.try_get("remaining")?,
+    })
+}
+
+// Delete the user's recovery codes and store hashes of a new set, returning the codes
+async fn replace_recovery_codes(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<Vec<String>, AppError> {
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let mut bytes = [0u8; 5];
+        OsRng.fill_bytes(&mut bytes);
+        let code = hex::encode(bytes);
+
+        sqlx::query("INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(hash_token(&code))
+            .execute(&mut *tx)
+            .await?;
+
+        // Grouped for reading; the dash is ignored when the code is entered
+        codes.push(format!("{}-{}", &code[..5], &code[5..]));
+    }
+
+    Ok(codes)
+}
+
+// Codes are accepted with spaces, dashes and either case
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
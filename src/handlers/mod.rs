@@ -3,30 +3,75 @@ pub mod account;
 pub mod transaction;
 pub mod health;
 pub mod admin;
+pub mod ledger;
+pub mod fx;
+pub mod audit;
+pub mod token;
+pub mod mfa;
+pub mod password;
+pub mod email;
+pub mod lockout;
+pub mod api_key;
+pub mod session;
+pub mod scheduled_transfer;
+pub mod payment_request;
+pub mod batch;
+pub mod import;
+pub mod statement;
 
 use actix_web::web;
-use actix_extensible_rate_limit::{
-    backend::SimpleInputFunctionBuilder,
-    backend::memory::InMemoryBackend,
-    RateLimiter,
-};
+use std::env;
 use std::time::Duration;
 use log::info;
 
-use crate::middleware::Auth;
+use crate::config::{BatchConfig, LoginConfig, MailConfig, MfaConfig, PaymentRequestConfig, RateLimitConfig, ScheduleConfig, TokenConfig, TransferConfig};
+use crate::middleware::rate_limit::policy_limiter;
+use crate::middleware::rate_limit_backend::RateLimitBackend;
+use crate::middleware::{AcceptApiKeys, Auth, Authorize, Idempotency};
+use crate::models::{ApiScope, Permission};
 
-// Configure routes
-pub fn config_routes(cfg: &mut web::ServiceConfig) {    // Set up rate limiter
-    let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 100)
-        .peer_ip_key()
-        .build();
-    let backend = InMemoryBackend::builder().build();
-    let rate_limit = RateLimiter::builder(backend, input)
-        .add_headers()
-        .build();
+// Default window in which a repeated Idempotency-Key replays the stored response
+const DEFAULT_IDEMPOTENCY_KEY_TTL_SECS: u64 = 24 * 60 * 60;
+
+// Configure routes, counting rate limits in this process only
+pub fn config_routes(cfg: &mut web::ServiceConfig) {
+    configure_routes(cfg, RateLimitBackend::memory());
+}
+
+// Configure routes, counting rate limits in `backend`, which is cloned into
+// every worker so they all share its counters
+pub fn configure_routes(cfg: &mut web::ServiceConfig, backend: RateLimitBackend) {    // Set up rate limiters, one per route scope sharing a backend; those
+    // that count requests per user sit inside `Auth` so the user is known
+    let rate_limits = RateLimitConfig::from_env();
+    let rate_limit = |scope: &str| {
+        policy_limiter(scope, &rate_limits.policy(scope), rate_limits.trusted_proxies.clone(), backend.clone())
+    };
+    
+    let idempotency_ttl = env::var("IDEMPOTENCY_KEY_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_IDEMPOTENCY_KEY_TTL_SECS);
+    let idempotency = Idempotency::new(Duration::from_secs(idempotency_ttl));
+    
+    cfg.app_data(web::Data::new(TransferConfig::from_env()));
+    cfg.app_data(web::Data::new(TokenConfig::from_env()));
+    cfg.app_data(web::Data::new(MfaConfig::from_env()));
+    cfg.app_data(web::Data::new(MailConfig::from_env()));
+    cfg.app_data(web::Data::new(LoginConfig::from_env()));
+    cfg.app_data(web::Data::new(ScheduleConfig::from_env()));
+    cfg.app_data(web::Data::new(PaymentRequestConfig::from_env()));
+    let batch_config = BatchConfig::from_env();
+    let batch_body_limit = batch_config.max_body_bytes();
+    let import_body_limit = batch_config.max_import_bytes();
+    cfg.app_data(web::Data::new(batch_config));
+    // `Auth` checks API key allow-lists against the client IP behind these
+    cfg.app_data(web::Data::from(rate_limits.trusted_proxies.clone()));
         
     // Health check route - no auth required
     cfg.route("/health", web::get().to(health::health_check));
+    
+    // Public keys for verifying access tokens - no auth required
+    cfg.route("/.well-known/jwks.json", web::get().to(token::jwks));
       // API routes with authentication where needed
     cfg.service(
         web::scope("/api")
@@ -35,7 +80,51 @@ pub fn config_routes(cfg: &mut web::ServiceConfig) {    // Set up rate limiter
                 web::scope("/users")
                     .route("/register", web::post().to(user::register))
                     .route("/login", web::post().to(user::login))
-                    .wrap(rate_limit.clone())
+                    .route("/login/mfa", web::post().to(mfa::complete_login))
+                    .route("/token/refresh", web::post().to(token::refresh_token))
+                    .route("/password/forgot", web::post().to(password::forgot_password))
+                    .route("/password/reset", web::post().to(password::reset_password))
+                    .route("/email/verify", web::post().to(email::verify_email))
+                    .wrap(rate_limit("users"))
+                    .service(
+                        web::scope("/logout")
+                            .wrap(Auth)
+                            .route("", web::post().to(token::logout))
+                    )
+                    .service(
+                        web::resource("/password")
+                            .wrap(Auth)
+                            .route(web::post().to(password::change_password))
+                    )
+                    .service(
+                        web::resource("/email/resend")
+                            .wrap(Auth)
+                            .route(web::post().to(email::resend_verification))
+                    )
+                    .service(
+                        web::scope("/mfa")
+                            .wrap(Auth)
+                            .route("", web::get().to(mfa::get_status))
+                            .route("/enroll", web::post().to(mfa::enroll))
+                            .route("/verify", web::post().to(mfa::confirm_enrollment))
+                            .route("/disable", web::post().to(mfa::disable))
+                            .route("/recovery-codes", web::post().to(mfa::regenerate_recovery_codes))
+                            .route("/step-up-threshold", web::put().to(mfa::set_step_up_threshold))
+                    )
+                    .service(
+                        web::scope("/api-keys")
+                            .wrap(Auth)
+                            .route("", web::get().to(api_key::list_api_keys))
+                            .route("", web::post().to(api_key::create_api_key))
+                            .route("/{key_id}", web::delete().to(api_key::revoke_api_key))
+                    )
+                    .service(
+                        web::scope("/sessions")
+                            .wrap(Auth)
+                            .route("", web::get().to(session::list_sessions))
+                            .route("", web::delete().to(session::revoke_all_sessions))
+                            .route("/{session_id}", web::delete().to(session::revoke_session))
+                    )
                     .service(
                         web::scope("/profile")
                             .wrap(Auth)
@@ -46,23 +135,123 @@ pub fn config_routes(cfg: &mut web::ServiceConfig) {    // Set up rate limiter
             // Account routes
             .service(
                 web::scope("/accounts")
+                    .wrap(rate_limit("accounts"))
                     .wrap(Auth)
+                    .wrap(AcceptApiKeys::read_only(ApiScope::BalanceRead))
+                    .wrap(rate_limit("clients"))
+                    .route("", web::get().to(account::list_accounts))
+                    .route("", web::post().to(account::open_account))
                     .route("/balance", web::get().to(account::get_balance))
+                    .route("/ledger", web::get().to(ledger::list_entries))
+                    .route("/statement", web::get().to(statement::get_statement))
+            )
+            // Currency conversion routes
+            .service(
+                web::scope("/fx")
+                    .wrap(rate_limit("fx"))
+                    .wrap(Auth)
+                    .wrap(AcceptApiKeys::new(ApiScope::FxRead, ApiScope::FxWrite))
+                    .wrap(rate_limit("clients"))
+                    .route("/quotes", web::post().to(fx::create_quote))
+                    .route("/quotes/{quote_id}", web::get().to(fx::get_quote))
             )
             // Transaction routes
             .service(
                 web::scope("/transactions")
+                    .wrap(idempotency.clone())
+                    .wrap(rate_limit("transactions"))
                     .wrap(Auth)
-                    .wrap(rate_limit.clone())
+                    .wrap(AcceptApiKeys::new(ApiScope::TransactionsRead, ApiScope::TransactionsWrite))
+                    .wrap(rate_limit("clients"))
                     .route("", web::post().to(transaction::create_transaction))
                     .route("", web::get().to(transaction::list_transactions))
+                    .service(
+                        web::resource("/batch")
+                            .app_data(web::JsonConfig::default().limit(batch_body_limit))
+                            .route(web::post().to(batch::create_batch))
+                    )
+                    .route("/batch/{batch_id}", web::get().to(batch::get_batch))
+                    .service(
+                        web::resource("/import")
+                            .app_data(web::PayloadConfig::default().limit(import_body_limit))
+                            .route(web::post().to(import::import_transfers))
+                    )
                     .route("/{transaction_id}", web::get().to(transaction::get_transaction))
+                    .route("/{transaction_id}/refund", web::post().to(transaction::refund_transaction))
+                    .route("/{transaction_id}/capture", web::post().to(transaction::capture_transaction))
+                    .route("/{transaction_id}/void", web::post().to(transaction::void_transaction))
             )
-    );    // Admin routes
+            // Payment request routes
+            .service(
+                web::scope("/payment-requests")
+                    .wrap(idempotency.clone())
+                    .wrap(rate_limit("transactions"))
+                    .wrap(Auth)
+                    .wrap(AcceptApiKeys::new(ApiScope::TransactionsRead, ApiScope::TransactionsWrite))
+                    .wrap(rate_limit("clients"))
+                    .route("", web::post().to(payment_request::create_payment_request))
+                    .route("", web::get().to(payment_request::list_payment_requests))
+                    .route("/{request_id}", web::get().to(payment_request::get_payment_request))
+                    .route("/{request_id}/accept", web::post().to(payment_request::accept_payment_request))
+                    .route("/{request_id}/decline", web::post().to(payment_request::decline_payment_request))
+                    .route("/{request_id}/cancel", web::post().to(payment_request::cancel_payment_request))
+            )
+            // Scheduled and recurring transfer routes
+            .service(
+                web::scope("/scheduled-transfers")
+                    .wrap(rate_limit("transactions"))
+                    .wrap(Auth)
+                    .wrap(AcceptApiKeys::new(ApiScope::TransactionsRead, ApiScope::TransactionsWrite))
+                    .wrap(rate_limit("clients"))
+                    .route("", web::post().to(scheduled_transfer::create_scheduled_transfer))
+                    .route("", web::get().to(scheduled_transfer::list_scheduled_transfers))
+                    .route("/{scheduled_id}", web::get().to(scheduled_transfer::get_scheduled_transfer))
+                    .route("/{scheduled_id}/pause", web::post().to(scheduled_transfer::pause_scheduled_transfer))
+                    .route("/{scheduled_id}/resume", web::post().to(scheduled_transfer::resume_scheduled_transfer))
+                    .route("/{scheduled_id}/cancel", web::post().to(scheduled_transfer::cancel_scheduled_transfer))
+            )
+    );    // Admin routes, each limited to the roles granted its permission
     cfg.service(
         web::scope("/admin")
-            .wrap(rate_limit.clone())
-            .route("/fund/{user_id}", web::post().to(admin::fund_user_balance))
+            .wrap(idempotency)
+            .wrap(rate_limit("admin"))
+            .wrap(Auth)
+            .wrap(rate_limit("clients"))
+            .service(
+                web::resource("/fund/{user_id}")
+                    .wrap(Authorize::new(Permission::FundAccounts))
+                    .route(web::post().to(admin::fund_user_balance))
+            )
+            .service(
+                web::resource("/ledger/check")
+                    .wrap(Authorize::new(Permission::CheckLedger))
+                    .route(web::get().to(ledger::verify_ledger))
+            )
+            .service(
+                web::resource("/transactions/{transaction_id}/refund")
+                    .wrap(Authorize::new(Permission::RefundTransactions))
+                    .route(web::post().to(admin::refund_transaction))
+            )
+            .service(
+                web::resource("/users/{user_id}/role")
+                    .wrap(Authorize::new(Permission::ManageRoles))
+                    .route(web::put().to(admin::update_user_role))
+            )
+            .service(
+                web::resource("/users/{user_id}/unlock")
+                    .wrap(Authorize::new(Permission::UnlockAccounts))
+                    .route(web::post().to(admin::unlock_user))
+            )
+            .service(
+                web::resource("/keys/reload")
+                    .wrap(Authorize::new(Permission::ManageKeys))
+                    .route(web::post().to(admin::reload_keys))
+            )
+            .service(
+                web::resource("/audit-log")
+                    .wrap(Authorize::new(Permission::ViewAuditLog))
+                    .route(web::get().to(audit::list_audit_log))
+            )
     );
     
     info!("Routes configured with rate limiting");
@@ -0,0 +1,221 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{MailConfig, TokenConfig};
+use crate::handlers::api_key::revoke_user_api_keys;
+use crate::handlers::token::revoke_user_sessions;
+use crate::models::{AppError, ChangePasswordRequest, ForgotPasswordRequest, ResetPasswordRequest};
+use crate::utils::auth::{generate_refresh_token, hash_password, hash_token, verify_password, Claims};
+use crate::utils::mailer::Email;
+
+/// Change the password of the signed-in user, who must know the current one.
+///
+/// Other sessions are signed out; the one making the request stays signed in.
+pub async fn change_password(
+    claims: web::ReqData<Claims>,
+    pool: web::Data<PgPool>,
+    password_data: web::Json<ChangePasswordRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    password_data.validate()?;
+
+    let claims = claims.into_inner();
+    let user_id: Uuid = claims
+        .sub
+        .parse()
+        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
+
+    let mut tx = pool.begin().await?;
+
+    let password_hash: String = sqlx::query("SELECT password_hash FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?
+        .try_get("password_hash")?;
+
+    if !verify_password(&password_data.current_password, &password_hash)? {
+        return Err(AppError::ForbiddenError("Current password is incorrect".to_string()));
+    }
+
+    set_password(&mut tx, user_id, &password_data.new_password).await?;
+    revoke_user_sessions(&mut tx, user_id, Some(claims.family_id()?)).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Email a password reset link to the account with this address, if any.
+///
+/// The response is the same whether or not the address is registered, so it
+/// cannot be used to find out who has an account. The lookup and email happen
+/// after responding, so response times do not give it away either.
+pub async fn forgot_password(
+    pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mail: web::Data<MailConfig>,
+    forgot_data: web::Json<ForgotPasswordRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    forgot_data.validate()?;
+
+    let email = forgot_data.into_inner().email;
+    actix_web::rt::spawn(async move {
+        if let Err(err) = send_reset_link(&pool, &config, &mail, &email).await {
+            error!("Failed to send a password reset email: {}", err);
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "status": "accepted",
+        "message": "If an account uses that email address, a reset link has been sent to it"
+    })))
+}
+
+/// Email a reset link to the account using `email`, if there is one.
+///
+/// The token is generated and hashed before the lookup, so an address with no
+/// account costs about as much as one with an account.
+pub async fn send_reset_link(
+    pool: &PgPool,
+    config: &TokenConfig,
+    mail: &MailConfig,
+    email: &str,
+) -> Result<(), AppError> {
+    let token = generate_refresh_token();
+    let token_hash = hash_token(&token);
+
+    let user = sqlx::query("SELECT id, username, email FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await?;
+    let Some(user) = user else {
+        return Ok(());
+    };
+
+    let user_id: Uuid = user.try_get("id")?;
+    let username: String = user.try_get("username")?;
+    store_reset_token(pool, config, user_id, &token_hash).await?;
+
+    let email = Email {
+        to: user.try_get("email")?,
+        subject: "Reset your Dodo Payments password".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             Someone asked to reset the password for your account. To choose a new one, open\n\n\
+             {}/reset-password?token={}\n\n\
+             or send this token to POST /api/users/password/reset:\n\n{}\n\n\
+             The link expires in {} minutes and works once. If you did not ask for this, you can ignore this email.",
+            username,
+            mail.public_url,
+            token,
+            token,
+            config.password_reset_ttl.as_secs() / 60
+        ),
+    };
+
+    mail.mailer.send(&email).await
+}
+
+/// Set a new password with an emailed reset token. The token is used up and
+/// every session of the user is signed out.
+pub async fn reset_password(
+    pool: web::Data<PgPool>,
+    reset_data: web::Json<ResetPasswordRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    reset_data.validate()?;
+
+    let mut tx = pool.begin().await?;
+
+    let reset = sqlx::query(
+        r#"
+        SELECT id, user_id, expires_at, used_at
+        FROM password_reset_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#
+    )
+    .bind(hash_token(&reset_data.token))
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::BadRequestError("Invalid or expired reset token".to_string()))?;
+
+    let reset_id: Uuid = reset.try_get("id")?;
+    let user_id: Uuid = reset.try_get("user_id")?;
+    let expires_at: DateTime<Utc> = reset.try_get("expires_at")?;
+    let used_at: Option<DateTime<Utc>> = reset.try_get("used_at")?;
+
+    if used_at.is_some() || expires_at <= Utc::now() {
+        return Err(AppError::BadRequestError("Invalid or expired reset token".to_string()));
+    }
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = NOW() WHERE id = $1")
+        .bind(reset_id)
+        .execute(&mut tx)
+        .await?;
+
+    set_password(&mut tx, user_id, &reset_data.new_password).await?;
+
+    // Whoever knew the old password is signed out everywhere, including any
+    // login still waiting for its MFA code, and loses any API keys they made
+    let revoked = revoke_user_sessions(&mut tx, user_id, None).await?;
+    let revoked_keys = revoke_user_api_keys(&mut tx, user_id).await?;
+    sqlx::query("DELETE FROM mfa_challenges WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    info!("Password reset for user {}; revoked {} sessions and {} API keys", user_id, revoked, revoked_keys);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Store the hash of a reset token for the user, replacing any earlier unused one
+async fn store_reset_token(pool: &PgPool, config: &TokenConfig, user_id: Uuid, token_hash: &str) -> Result<(), AppError> {
+    let ttl = chrono::Duration::from_std(config.password_reset_ttl)
+        .map_err(|_| AppError::InternalServerError("Password reset TTL is out of range".to_string()))?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = NOW() WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3)
+        "#
+    )
+    .bind(user_id)
+    .bind(token_hash)
+    .bind(Utc::now() + ttl)
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn set_password(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+    password: &str,
+) -> Result<(), AppError> {
+    sqlx::query("UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2")
+        .bind(hash_password(password)?)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,371 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, Utc};
+use sqlx::postgres::PgRow;
+use sqlx::{PgExecutor, PgPool, Postgres, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{MfaConfig, PaymentRequestConfig, TransferConfig};
+use crate::handlers::mfa::{require_step_up, MFA_CODE_HEADER};
+use crate::handlers::transaction::process_transfer_in;
+use crate::models::{
+    AppError, CreatePaymentRequestRequest, CreateTransactionRequest, Currency, Money, PaymentRequestListResponse,
+    PaymentRequestResponse, PaymentRequestStatus,
+};
+
+// A pending request past its expiry reads as expired before the sweep marks it
+const PAYMENT_REQUEST_STATUS: &str =
+    "CASE WHEN status = 'pending' AND expires_at <= NOW() THEN 'expired' ELSE status END";
+
+const DEFAULT_LIST_LIMIT: u32 = 50;
+const MAX_LIST_LIMIT: u32 = 100;
+
+fn payment_request_columns() -> String {
+    format!(
+        "id, requester_id, payer_id, amount, currency, note, {} AS status, transaction_id, expires_at, responded_at, created_at",
+        PAYMENT_REQUEST_STATUS
+    )
+}
+
+/// Ask another user to pay the signed-in user
+pub async fn create_payment_request(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<PaymentRequestConfig>,
+    request_data: web::Json<CreatePaymentRequestRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    request_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let amount = Money::positive(request_data.amount.clone(), &request_data.currency)?;
+
+    if request_data.payer_id == user_id {
+        return Err(AppError::BadRequestError("Cannot request money from yourself".to_string()));
+    }
+
+    let now = Utc::now();
+    let max_ttl = ChronoDuration::from_std(config.max_ttl)
+        .map_err(|_| AppError::InternalServerError("Payment request max TTL is out of range".to_string()))?;
+    let expires_at = match request_data.expires_at {
+        Some(expires_at) => expires_at,
+        None => now + ChronoDuration::from_std(config.ttl)
+            .map_err(|_| AppError::InternalServerError("Payment request TTL is out of range".to_string()))?,
+    };
+    if expires_at <= now {
+        return Err(AppError::BadRequestError("expires_at must be in the future".to_string()));
+    }
+    if expires_at > now + max_ttl {
+        return Err(AppError::BadRequestError(format!(
+            "expires_at cannot be more than {} seconds away",
+            config.max_ttl.as_secs()
+        )));
+    }
+
+    let payer_exists = sqlx::query("SELECT id FROM users WHERE id = $1")
+        .bind(request_data.payer_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .is_some();
+    if !payer_exists {
+        return Err(AppError::NotFoundError("Payer not found".to_string()));
+    }
+
+    let row = sqlx::query(&format!(
+        r#"
+        INSERT INTO payment_requests (requester_id, payer_id, amount, currency, note, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING {}
+        "#,
+        payment_request_columns()
+    ))
+    .bind(user_id)
+    .bind(request_data.payer_id)
+    .bind(amount.amount())
+    .bind(amount.currency().code())
+    .bind(&request_data.note)
+    .bind(expires_at)
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Created().json(payment_request_from_row(&row)?))
+}
+
+/// Payment requests the user has made or been sent, newest first
+pub async fn list_payment_requests(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    query: web::Query<ListPaymentRequestsQuery>,
+) -> Result<impl Responder, AppError> {
+    let party = match query.direction.as_deref() {
+        None => "(requester_id = $1 OR payer_id = $1)",
+        Some("incoming") => "payer_id = $1",
+        Some("outgoing") => "requester_id = $1",
+        Some(_) => return Err(AppError::BadRequestError("direction must be incoming or outgoing".to_string())),
+    };
+    let status = query.status.as_deref().map(str::parse::<PaymentRequestStatus>).transpose()?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT {} FROM payment_requests
+        WHERE {} AND ($2::text IS NULL OR {} = $2)
+        ORDER BY created_at DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        payment_request_columns(),
+        party,
+        PAYMENT_REQUEST_STATUS
+    ))
+    .bind(user_id.into_inner())
+    .bind(status.map(|status| status.as_str()))
+    .bind(limit as i64)
+    .bind(query.offset.unwrap_or(0) as i64)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let payment_requests = rows.iter().map(payment_request_from_row).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(HttpResponse::Ok().json(PaymentRequestListResponse { payment_requests }))
+}
+
+/// A payment request the user made or was sent
+pub async fn get_payment_request(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let row = sqlx::query(&format!(
+        "SELECT {} FROM payment_requests WHERE id = $1 AND (requester_id = $2 OR payer_id = $2)",
+        payment_request_columns()
+    ))
+    .bind(path.into_inner())
+    .bind(user_id.into_inner())
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Payment request not found".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(payment_request_from_row(&row)?))
+}
+
+/// Pay a pending request sent to the user. The transfer goes through the
+/// same path as `POST /api/transactions`, step-up code included; if it
+/// fails the request stays pending.
+pub async fn accept_payment_request(
+    req: HttpRequest,
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TransferConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let user_id = user_id.into_inner();
+    let request_id = path.into_inner();
+
+    // The step-up check uses connections of its own, so it is made before
+    // anything is locked
+    let amount = find_pending(pool.get_ref(), request_id, user_id, Party::Payer, false).await?.amount;
+    let code = req.headers().get(MFA_CODE_HEADER).and_then(|value| value.to_str().ok());
+    require_step_up(pool.get_ref(), mfa_config.get_ref(), config.get_ref(), user_id, &amount, code).await?;
+
+    // The transfer and the request's new status commit together, so the
+    // request is paid once and never left pending after being paid
+    let mut tx = pool.begin().await?;
+    let pending = lock_pending(&mut tx, request_id, user_id, Party::Payer).await?;
+
+    let request = CreateTransactionRequest {
+        recipient_id: pending.requester_id,
+        amount: pending.amount.amount().clone(),
+        currency: pending.currency.clone(),
+        description: pending.note.clone(),
+        capture: Some(true),
+        quote_id: None,
+    };
+    let transaction = process_transfer_in(&mut tx, config.get_ref(), user_id, &request).await?;
+
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE payment_requests
+        SET status = 'accepted', transaction_id = $1, responded_at = NOW()
+        WHERE id = $2
+        RETURNING {}
+        "#,
+        payment_request_columns()
+    ))
+    .bind(transaction.id)
+    .bind(pending.id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(payment_request_from_row(&row)?))
+}
+
+/// Turn down a pending request sent to the user
+pub async fn decline_payment_request(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let payment_request = close_pending(
+        pool.get_ref(),
+        path.into_inner(),
+        user_id.into_inner(),
+        Party::Payer,
+        PaymentRequestStatus::Declined,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(payment_request))
+}
+
+/// Withdraw a pending request the user made
+pub async fn cancel_payment_request(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let payment_request = close_pending(
+        pool.get_ref(),
+        path.into_inner(),
+        user_id.into_inner(),
+        Party::Requester,
+        PaymentRequestStatus::Cancelled,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(payment_request))
+}
+
+/// Mark pending requests past their expiry as expired. Returns how many were.
+pub async fn expire_payment_requests(pool: &PgPool) -> Result<u64, AppError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE payment_requests
+        SET status = 'expired', responded_at = expires_at
+        WHERE status = 'pending' AND expires_at <= NOW()
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Which side of a request the user must be on to act on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Party {
+    Requester,
+    Payer,
+}
+
+// Lock a request the user is party to and check it is still pending and
+// theirs to act on
+async fn lock_pending(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    request_id: Uuid,
+    user_id: Uuid,
+    party: Party,
+) -> Result<PaymentRequestResponse, AppError> {
+    find_pending(&mut *tx, request_id, user_id, party, true).await
+}
+
+// Find a request as `lock_pending` does, locking it only if `lock` is set
+async fn find_pending<'c>(
+    executor: impl PgExecutor<'c>,
+    request_id: Uuid,
+    user_id: Uuid,
+    party: Party,
+    lock: bool,
+) -> Result<PaymentRequestResponse, AppError> {
+    let row = sqlx::query(&format!(
+        r#"
+        SELECT {} FROM payment_requests
+        WHERE id = $1 AND (requester_id = $2 OR payer_id = $2)
+        {}
+        "#,
+        payment_request_columns(),
+        if lock { "FOR UPDATE" } else { "" }
+    ))
+    .bind(request_id)
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Payment request not found".to_string()))?;
+
+    let payment_request = payment_request_from_row(&row)?;
+
+    let allowed = match party {
+        Party::Requester => payment_request.requester_id == user_id,
+        Party::Payer => payment_request.payer_id == user_id,
+    };
+    if !allowed {
+        return Err(AppError::ForbiddenError(match party {
+            Party::Requester => "Only the requester can cancel a payment request".to_string(),
+            Party::Payer => "Only the payer can respond to a payment request".to_string(),
+        }));
+    }
+    if payment_request.status != PaymentRequestStatus::Pending {
+        return Err(AppError::ConflictError(format!("Payment request is {}", payment_request.status)));
+    }
+
+    Ok(payment_request)
+}
+
+// Close a pending request with `status` without paying it
+async fn close_pending(
+    pool: &PgPool,
+    request_id: Uuid,
+    user_id: Uuid,
+    party: Party,
+    status: PaymentRequestStatus,
+) -> Result<PaymentRequestResponse, AppError> {
+    let mut tx = pool.begin().await?;
+    lock_pending(&mut tx, request_id, user_id, party).await?;
+
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE payment_requests
+        SET status = $1, responded_at = NOW()
+        WHERE id = $2
+        RETURNING {}
+        "#,
+        payment_request_columns()
+    ))
+    .bind(status.as_str())
+    .bind(request_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    payment_request_from_row(&row)
+}
+
+fn payment_request_from_row(row: &PgRow) -> Result<PaymentRequestResponse, AppError> {
+    let currency: String = row.try_get("currency")?;
+
+    Ok(PaymentRequestResponse {
+        id: row.try_get("id")?,
+        requester_id: row.try_get("requester_id")?,
+        payer_id: row.try_get("payer_id")?,
+        amount: Money::stored(row.try_get("amount")?, Currency::stored(&currency)?),
+        currency,
+        note: row.try_get("note")?,
+        status: row.try_get::<String, _>("status")?.parse()?,
+        transaction_id: row.try_get("transaction_id")?,
+        expires_at: row.try_get("expires_at")?,
+        responded_at: row.try_get("responded_at")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListPaymentRequestsQuery {
+    // incoming (sent to the user to pay) or outgoing (made by the user)
+    direction: Option<String>,
+    status: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
@@ -0,0 +1,486 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use bigdecimal::BigDecimal;
+use chrono::{Duration as ChronoDuration, Utc};
+use log::{error, info};
+use sqlx::postgres::PgRow;
+use sqlx::{Connection, PgPool, Postgres, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::{MfaConfig, ScheduleConfig, TransferConfig};
+use crate::handlers::mfa::{require_step_up, MFA_CODE_HEADER};
+use crate::handlers::transaction::{process_transfer_in, INSUFFICIENT_FUNDS};
+use crate::models::scheduled_transfer::{
+    CreateScheduledTransferRequest, Frequency, InsufficientFundsPolicy, RunOutcome, Schedule, ScheduleStatus,
+    ScheduledTransferListResponse, ScheduledTransferResponse, ScheduledTransferRunResponse,
+};
+use crate::models::{AppError, CreateTransactionRequest, Currency, Money};
+
+const SCHEDULED_TRANSFER_COLUMNS: &str = "id, user_id, recipient_id, amount, currency, description, frequency, \
+     start_at, end_at, max_occurrences, on_insufficient_funds, status, occurrence, attempt, next_run_at, created_at";
+
+// Runs shown with a single schedule
+const RECENT_RUNS: i64 = 50;
+// Due transfers run in one pass of the scheduler, so a backlog cannot hold it up
+const MAX_RUNS_PER_PASS: u64 = 100;
+
+/// Schedule a future-dated or recurring transfer.
+///
+/// The step-up code for large transfers is checked now, as the transfers are
+/// made later without the user.
+pub async fn create_scheduled_transfer(
+    req: HttpRequest,
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    config: web::Data<TransferConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    schedule_data: web::Json<CreateScheduledTransferRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    schedule_data.validate()?;
+
+    let user_id = user_id.into_inner();
+    let amount = Money::positive(schedule_data.amount.clone(), &schedule_data.currency)?;
+
+    if schedule_data.recipient_id == user_id {
+        return Err(AppError::BadRequestError("Cannot send money to yourself".to_string()));
+    }
+    if schedule_data.frequency == Frequency::Once
+        && (schedule_data.end_at.is_some() || schedule_data.max_occurrences.is_some())
+    {
+        return Err(AppError::BadRequestError(
+            "end_at and max_occurrences only apply to recurring transfers".to_string(),
+        ));
+    }
+    // A little slack for clocks and requests in flight
+    if schedule_data.start_at < Utc::now() - ChronoDuration::minutes(1) {
+        return Err(AppError::BadRequestError("start_at cannot be in the past".to_string()));
+    }
+    if schedule_data.end_at.is_some_and(|end_at| end_at < schedule_data.start_at) {
+        return Err(AppError::BadRequestError("end_at cannot be before start_at".to_string()));
+    }
+
+    let recipient_exists = sqlx::query("SELECT id FROM users WHERE id = $1")
+        .bind(schedule_data.recipient_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .is_some();
+    if !recipient_exists {
+        return Err(AppError::NotFoundError("Recipient not found".to_string()));
+    }
+
+    let code = req.headers().get(MFA_CODE_HEADER).and_then(|value| value.to_str().ok());
+    require_step_up(pool.get_ref(), mfa_config.get_ref(), config.get_ref(), user_id, &amount, code).await?;
+
+    let row = sqlx::query(&format!(
+        r#"
+        INSERT INTO scheduled_transfers
+            (user_id, recipient_id, amount, currency, description, frequency, start_at, end_at,
+             max_occurrences, on_insufficient_funds, next_run_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $7)
+        RETURNING {}
+        "#,
+        SCHEDULED_TRANSFER_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(schedule_data.recipient_id)
+    .bind(amount.amount())
+    .bind(amount.currency().code())
+    .bind(&schedule_data.description)
+    .bind(schedule_data.frequency.as_str())
+    .bind(schedule_data.start_at)
+    .bind(schedule_data.end_at)
+    .bind(schedule_data.max_occurrences.map(|max| max.min(i32::MAX as u32) as i32))
+    .bind(schedule_data.on_insufficient_funds.as_str())
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    let mut scheduled = scheduled_transfer_from_row(&row)?;
+    scheduled.runs = Some(Vec::new());
+
+    Ok(HttpResponse::Created().json(scheduled))
+}
+
+/// The user's scheduled transfers, newest first
+pub async fn list_scheduled_transfers(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let rows = sqlx::query(&format!(
+        "SELECT {} FROM scheduled_transfers WHERE user_id = $1 ORDER BY created_at DESC",
+        SCHEDULED_TRANSFER_COLUMNS
+    ))
+    .bind(user_id.into_inner())
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let scheduled_transfers = rows.iter().map(scheduled_transfer_from_row).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(HttpResponse::Ok().json(ScheduledTransferListResponse { scheduled_transfers }))
+}
+
+/// One scheduled transfer with the outcomes of its recent runs
+pub async fn get_scheduled_transfer(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let scheduled_id = path.into_inner();
+
+    let row = sqlx::query(&format!(
+        "SELECT {} FROM scheduled_transfers WHERE id = $1 AND user_id = $2",
+        SCHEDULED_TRANSFER_COLUMNS
+    ))
+    .bind(scheduled_id)
+    .bind(user_id.into_inner())
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Scheduled transfer not found".to_string()))?;
+
+    let runs = sqlx::query(
+        r#"
+        SELECT id, occurrence, attempt, status, transaction_id, error, created_at
+        FROM scheduled_transfer_runs
+        WHERE scheduled_transfer_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#
+    )
+    .bind(scheduled_id)
+    .bind(RECENT_RUNS)
+    .fetch_all(pool.get_ref())
+    .await?
+    .iter()
+    .map(|run| {
+        Ok(ScheduledTransferRunResponse {
+            id: run.try_get("id")?,
+            occurrence: run.try_get("occurrence")?,
+            attempt: run.try_get("attempt")?,
+            status: run.try_get("status")?,
+            transaction_id: run.try_get("transaction_id")?,
+            error: run.try_get("error")?,
+            created_at: run.try_get("created_at")?,
+        })
+    })
+    .collect::<Result<Vec<_>, AppError>>()?;
+
+    let mut scheduled = scheduled_transfer_from_row(&row)?;
+    scheduled.runs = Some(runs);
+
+    Ok(HttpResponse::Ok().json(scheduled))
+}
+
+/// Stop running an active schedule until it is resumed
+pub async fn pause_scheduled_transfer(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let scheduled = change_status(
+        pool.get_ref(),
+        path.into_inner(),
+        user_id.into_inner(),
+        &[ScheduleStatus::Active],
+        ScheduleStatus::Paused,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(scheduled))
+}
+
+/// Run a paused schedule again. If occurrences fell due while it was paused,
+/// the first runs straight away and the rest are passed over.
+pub async fn resume_scheduled_transfer(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let scheduled = change_status(
+        pool.get_ref(),
+        path.into_inner(),
+        user_id.into_inner(),
+        &[ScheduleStatus::Paused],
+        ScheduleStatus::Active,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(scheduled))
+}
+
+/// Stop a schedule for good
+pub async fn cancel_scheduled_transfer(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let scheduled = change_status(
+        pool.get_ref(),
+        path.into_inner(),
+        user_id.into_inner(),
+        &[ScheduleStatus::Active, ScheduleStatus::Paused],
+        ScheduleStatus::Cancelled,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(scheduled))
+}
+
+// Move one of the user's schedules from one of `from` to `to`
+async fn change_status(
+    pool: &PgPool,
+    scheduled_id: Uuid,
+    user_id: Uuid,
+    from: &[ScheduleStatus],
+    to: ScheduleStatus,
+) -> Result<ScheduledTransferResponse, AppError> {
+    let mut tx = pool.begin().await?;
+
+    // Waits for a run in progress, so the change applies to what it leaves
+    let status: ScheduleStatus = sqlx::query(
+        "SELECT status FROM scheduled_transfers WHERE id = $1 AND user_id = $2 FOR UPDATE"
+    )
+    .bind(scheduled_id)
+    .bind(user_id)
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Scheduled transfer not found".to_string()))?
+    .try_get::<String, _>("status")?
+    .parse()?;
+
+    if !from.contains(&status) {
+        return Err(AppError::ConflictError(format!("Scheduled transfer is {}", status)));
+    }
+
+    let row = sqlx::query(&format!(
+        r#"
+        UPDATE scheduled_transfers
+        SET status = $1, next_run_at = CASE WHEN $1 = 'cancelled' THEN NULL ELSE next_run_at END
+        WHERE id = $2
+        RETURNING {}
+        "#,
+        SCHEDULED_TRANSFER_COLUMNS
+    ))
+    .bind(to.as_str())
+    .bind(scheduled_id)
+    .fetch_one(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    scheduled_transfer_from_row(&row)
+}
+
+/// Make every scheduled transfer that is due, up to a limit per pass.
+/// Returns the number of runs recorded.
+///
+/// Each due schedule is locked while its transfer is made, so instances of
+/// the app sharing the database never run the same occurrence twice.
+pub async fn run_due_transfers(
+    pool: &PgPool,
+    transfer_config: &TransferConfig,
+    schedule_config: &ScheduleConfig,
+) -> Result<u64, AppError> {
+    let mut runs = 0;
+    while runs < MAX_RUNS_PER_PASS {
+        if !run_next_due(pool, transfer_config, schedule_config).await? {
+            break;
+        }
+        runs += 1;
+    }
+
+    Ok(runs)
+}
+
+// Run the schedule that has been due the longest, if any. Returns whether
+// there was one.
+async fn run_next_due(
+    pool: &PgPool,
+    transfer_config: &TransferConfig,
+    schedule_config: &ScheduleConfig,
+) -> Result<bool, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query(&format!(
+        r#"
+        SELECT {} FROM scheduled_transfers
+        WHERE status = 'active' AND next_run_at <= NOW()
+        ORDER BY next_run_at
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+        "#,
+        SCHEDULED_TRANSFER_COLUMNS
+    ))
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(false),
+    };
+
+    let scheduled_id: Uuid = row.try_get("id")?;
+    let sender_id: Uuid = row.try_get("user_id")?;
+    let occurrence: i32 = row.try_get("occurrence")?;
+    let attempt: i32 = row.try_get("attempt")?;
+    let policy: InsufficientFundsPolicy = row.try_get::<String, _>("on_insufficient_funds")?.parse()?;
+
+    // The same path as a transfer made through the API
+    let request = CreateTransactionRequest {
+        recipient_id: row.try_get("recipient_id")?,
+        amount: row.try_get::<BigDecimal, _>("amount")?,
+        currency: row.try_get("currency")?,
+        description: row.try_get("description")?,
+        capture: Some(true),
+        quote_id: None,
+    };
+    // Made in a savepoint of the transaction that records the run, so the
+    // money only moves if the schedule moves on too, and a failed transfer
+    // can be rolled back while its run is still recorded
+    let mut savepoint = tx.begin().await?;
+    let result = process_transfer_in(&mut savepoint, transfer_config, sender_id, &request).await;
+    match result {
+        Ok(_) => savepoint.commit().await?,
+        Err(_) => savepoint.rollback().await?,
+    }
+
+    let retries_left = (attempt as u32) < schedule_config.max_retries;
+    let (outcome, transaction_id, reason) = match result {
+        Ok(transaction) => (RunOutcome::Succeeded, Some(transaction.id), None),
+        Err(err) => {
+            let short_of_funds = matches!(&err, AppError::BadRequestError(msg) if msg == INSUFFICIENT_FUNDS);
+            // Errors on our side may clear up, so they are retried whatever the policy
+            let transient = matches!(&err, AppError::InternalServerError(_));
+            let outcome = if (transient || (short_of_funds && policy == InsufficientFundsPolicy::Retry)) && retries_left {
+                RunOutcome::Retrying
+            } else if short_of_funds && policy == InsufficientFundsPolicy::Skip {
+                RunOutcome::Skipped
+            } else {
+                RunOutcome::Failed
+            };
+            (outcome, None, Some(err.to_string()))
+        }
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO scheduled_transfer_runs (scheduled_transfer_id, occurrence, attempt, status, transaction_id, error)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#
+    )
+    .bind(scheduled_id)
+    .bind(occurrence)
+    .bind(attempt)
+    .bind(outcome.as_str())
+    .bind(transaction_id)
+    .bind(&reason)
+    .execute(&mut tx)
+    .await?;
+
+    if outcome == RunOutcome::Retrying {
+        let retry_interval = ChronoDuration::from_std(schedule_config.retry_interval)
+            .map_err(|_| AppError::InternalServerError("Scheduled transfer retry interval is out of range".to_string()))?;
+        sqlx::query("UPDATE scheduled_transfers SET attempt = attempt + 1, next_run_at = $1 WHERE id = $2")
+            .bind(Utc::now() + retry_interval)
+            .bind(scheduled_id)
+            .execute(&mut tx)
+            .await?;
+    } else {
+        advance(&mut tx, &row, occurrence).await?;
+    }
+
+    tx.commit().await?;
+
+    match outcome {
+        RunOutcome::Succeeded => info!("Ran scheduled transfer {} occurrence {}", scheduled_id, occurrence),
+        _ => info!(
+            "Scheduled transfer {} occurrence {} {}: {}",
+            scheduled_id,
+            occurrence,
+            outcome.as_str(),
+            reason.unwrap_or_default()
+        ),
+    }
+
+    Ok(true)
+}
+
+// Move a schedule past `occurrence` to its next occurrence still to come,
+// passing over any that were missed, or complete it if there are none
+async fn advance(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    row: &PgRow,
+    occurrence: i32,
+) -> Result<(), AppError> {
+    let schedule = Schedule {
+        frequency: row.try_get::<String, _>("frequency")?.parse()?,
+        start_at: row.try_get("start_at")?,
+        end_at: row.try_get("end_at")?,
+        max_occurrences: row.try_get::<Option<i32>, _>("max_occurrences")?.map(|max| max.max(0) as u32),
+    };
+    let scheduled_id: Uuid = row.try_get("id")?;
+    let done = occurrence.max(0) as u32 + 1;
+
+    match schedule.next_due(done, Utc::now()) {
+        Some((next, next_run_at)) => {
+            sqlx::query(
+                "UPDATE scheduled_transfers SET occurrence = $1, attempt = 0, next_run_at = $2 WHERE id = $3"
+            )
+            .bind(next.min(i32::MAX as u32) as i32)
+            .bind(next_run_at)
+            .bind(scheduled_id)
+            .execute(&mut **tx)
+            .await?;
+        }
+        None => {
+            sqlx::query(
+                r#"
+                UPDATE scheduled_transfers
+                SET status = 'completed', occurrence = $1, attempt = 0, next_run_at = NULL
+                WHERE id = $2
+                "#
+            )
+            .bind(done.min(i32::MAX as u32) as i32)
+            .bind(scheduled_id)
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn scheduled_transfer_from_row(row: &PgRow) -> Result<ScheduledTransferResponse, AppError> {
+    let currency: String = row.try_get("currency")?;
+
+    Ok(ScheduledTransferResponse {
+        id: row.try_get("id")?,
+        recipient_id: row.try_get("recipient_id")?,
+        amount: Money::stored(row.try_get("amount")?, Currency::stored(&currency)?),
+        currency,
+        description: row.try_get("description")?,
+        frequency: row.try_get::<String, _>("frequency")?.parse()?,
+        start_at: row.try_get("start_at")?,
+        end_at: row.try_get("end_at")?,
+        max_occurrences: row.try_get("max_occurrences")?,
+        on_insufficient_funds: row.try_get::<String, _>("on_insufficient_funds")?.parse()?,
+        status: row.try_get::<String, _>("status")?.parse()?,
+        occurrences_done: row.try_get("occurrence")?,
+        next_run_at: row.try_get("next_run_at")?,
+        created_at: row.try_get("created_at")?,
+        runs: None,
+    })
+}
+
+/// Run due scheduled transfers every `config.poll_interval`, for as long as
+/// the process lives
+pub async fn run_scheduler(pool: PgPool, transfer_config: TransferConfig, config: ScheduleConfig) {
+    let mut interval = actix_web::rt::time::interval(config.poll_interval);
+    loop {
+        interval.tick().await;
+        match run_due_transfers(&pool, &transfer_config, &config).await {
+            Ok(0) => {}
+            Ok(runs) => info!("Ran {} scheduled transfers", runs),
+            Err(err) => error!("Failed to run scheduled transfers: {}", err),
+        }
+    }
+}
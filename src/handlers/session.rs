@@ -0,0 +1,156 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest, HttpResponse, Responder};
+use futures::future::{ready, Ready};
+use sqlx::{PgPool, Postgres, Row};
+use uuid::Uuid;
+
+use crate::handlers::api_key::revoke_user_api_keys;
+use crate::handlers::token::{revoke_family, revoke_user_sessions};
+use crate::middleware::rate_limit::TrustedProxies;
+use crate::models::{AppError, RevokedSessionsResponse, SessionListResponse, SessionResponse};
+use crate::utils::auth::Claims;
+
+// Longest user agent kept with a session
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// The device a request comes from, recorded when a session starts
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl FromRequest for ClientInfo {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user_agent = req
+            .headers()
+            .get("User-Agent")
+            .and_then(|value| value.to_str().ok())
+            .map(|agent| agent.chars().take(MAX_USER_AGENT_LEN).collect());
+
+        // The client behind any trusted proxies, as for rate limiting
+        let forwarded_for = req.headers().get("X-Forwarded-For").and_then(|value| value.to_str().ok());
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let ip_address = match req.app_data::<web::Data<TrustedProxies>>() {
+            Some(proxies) => proxies.client_ip(peer, forwarded_for),
+            None => peer,
+        };
+
+        ready(Ok(ClientInfo {
+            user_agent,
+            ip_address: ip_address.map(|ip| ip.to_string()),
+        }))
+    }
+}
+
+/// Record a new session for a login from `client`. Returns its id, which is
+/// also the family id of the refresh tokens issued for it.
+pub async fn start_session(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> Result<Uuid, AppError> {
+    let session_id = Uuid::new_v4();
+
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, user_agent, ip_address)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .bind(&client.user_agent)
+    .bind(&client.ip_address)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(session_id)
+}
+
+/// The user's active sessions, most recently used first
+pub async fn list_sessions(
+    claims: web::ReqData<Claims>,
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let current = claims.family_id()?;
+
+    // A session is active while it has a refresh token left to use
+    let rows = sqlx::query(
+        r#"
+        SELECT s.id, s.user_agent, s.ip_address, s.created_at, s.last_seen_at
+        FROM sessions s
+        WHERE s.user_id = $1 AND s.revoked_at IS NULL
+          AND EXISTS (
+              SELECT 1 FROM refresh_tokens t
+              WHERE t.family_id = s.id AND t.revoked_at IS NULL AND t.expires_at > NOW()
+          )
+        ORDER BY s.last_seen_at DESC
+        "#
+    )
+    .bind(user_id.into_inner())
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let sessions = rows
+        .iter()
+        .map(|row| {
+            let id: Uuid = row.try_get("id")?;
+            Ok(SessionResponse {
+                id,
+                user_agent: row.try_get("user_agent")?,
+                ip_address: row.try_get("ip_address")?,
+                created_at: row.try_get("created_at")?,
+                last_seen_at: row.try_get("last_seen_at")?,
+                current: id == current,
+            })
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    Ok(HttpResponse::Ok().json(SessionListResponse { sessions }))
+}
+
+/// Log one of the user's sessions out, on whichever device it is
+pub async fn revoke_session(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let session_id = path.into_inner();
+    let mut tx = pool.begin().await?;
+
+    let found = sqlx::query("SELECT 1 FROM sessions WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL")
+        .bind(session_id)
+        .bind(user_id.into_inner())
+        .fetch_optional(&mut tx)
+        .await?
+        .is_some();
+    if !found {
+        return Err(AppError::NotFoundError("Session not found".to_string()));
+    }
+
+    revoke_family(&mut tx, session_id).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Log out everywhere: revoke every session of the user, this one included,
+/// and every API key they created
+pub async fn revoke_all_sessions(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let user_id = user_id.into_inner();
+
+    let mut tx = pool.begin().await?;
+    let revoked_sessions = revoke_user_sessions(&mut tx, user_id, None).await?;
+    let revoked_api_keys = revoke_user_api_keys(&mut tx, user_id).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(RevokedSessionsResponse { revoked_sessions, revoked_api_keys }))
+}
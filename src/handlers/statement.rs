@@ -0,0 +1,214 @@
+use actix_web::{http::header, web, HttpResponse};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use futures::stream::{self, StreamExt};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use crate::models::ledger::EntryType;
+use crate::models::{AppError, Currency, Money, Statement, StatementEntry, StatementFormat};
+use crate::utils::statement::{self, start_of};
+
+// Ledger lines read from the database, and written out, at a time
+const STATEMENT_PAGE_SIZE: i64 = 500;
+
+// Longest period one statement can cover, in days
+const MAX_STATEMENT_DAYS: i64 = 366;
+
+// A user account's balance moves up with credits and down with debits
+const SIGNED_AMOUNT: &str = "CASE WHEN entry_type = 'credit' THEN amount ELSE -amount END";
+
+/// Download a statement of one wallet for whole days from `from` to `to`, as
+/// CSV, OFX or camt.053.
+///
+/// The statement gives the wallet's balance at the start and end of the
+/// period and every ledger line in between with the balance after it. The
+/// period is cut off when the statement is generated, so lines booked while it
+/// downloads are left out; each page of lines is then read with its own short
+/// query and streamed out as it is written.
+pub async fn get_statement(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    query: web::Query<StatementQuery>,
+) -> Result<HttpResponse, AppError> {
+    let user_id = user_id.into_inner();
+    if query.from > query.to {
+        return Err(AppError::BadRequestError("from must not be after to".to_string()));
+    }
+    if (query.to - query.from).num_days() >= MAX_STATEMENT_DAYS {
+        return Err(AppError::BadRequestError(format!(
+            "A statement can cover at most {} days",
+            MAX_STATEMENT_DAYS
+        )));
+    }
+    let currency = match &query.currency {
+        Some(code) => Some(Currency::from_code(code)?.code()),
+        None => None,
+    };
+
+    // The wallet in `currency` if given, otherwise the user's first wallet
+    let account = sqlx::query(
+        r#"
+        SELECT id, currency FROM accounts
+        WHERE user_id = $1 AND ($2::VARCHAR IS NULL OR currency = $2)
+        ORDER BY created_at, id
+        LIMIT 1
+        "#
+    )
+    .bind(user_id)
+    .bind(currency)
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Account not found".to_string()))?;
+    let account_id: Uuid = account.try_get("id")?;
+    let currency = Currency::stored(account.try_get("currency")?)?;
+
+    let start = start_of(query.from);
+    let end = start_of(query.to) + Duration::days(1);
+    let balances = sqlx::query(&format!(
+        r#"
+        SELECT NOW() AS generated_at, LEAST($3, NOW()) AS cutoff,
+               COALESCE(SUM({signed}) FILTER (WHERE created_at < $2), 0) AS opening,
+               COALESCE(SUM({signed}) FILTER (WHERE created_at < LEAST($3, NOW())), 0) AS closing
+        FROM ledger_entries
+        WHERE account_id = $1
+        "#,
+        signed = SIGNED_AMOUNT
+    ))
+    .bind(account_id)
+    .bind(start)
+    .bind(end)
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    let opening_balance: BigDecimal = balances.try_get("opening")?;
+    let statement = Statement {
+        id: Uuid::new_v4(),
+        account_id,
+        from: query.from,
+        to: query.to,
+        opening_balance: Money::stored(opening_balance.clone(), currency),
+        closing_balance: Money::stored(balances.try_get("closing")?, currency),
+        generated_at: balances.try_get("generated_at")?,
+    };
+
+    let format = query.format;
+    let file_name = statement::file_name(format, &statement);
+    let header = statement::header(format, &statement);
+    let pages = Pages {
+        pool: pool.get_ref().clone(),
+        format,
+        statement,
+        cutoff: balances.try_get("cutoff")?,
+        after: (start, Uuid::nil()),
+        balance: opening_balance,
+        finished: false,
+    };
+    let body = stream::once(async move { Ok(web::Bytes::from(header)) }).chain(stream::unfold(pages, next_page));
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)))
+        .streaming(body))
+}
+
+// Where a statement being streamed has got to
+struct Pages {
+    pool: PgPool,
+    format: StatementFormat,
+    statement: Statement,
+    // End of the period or when the statement was generated, whichever is
+    // first, exclusive
+    cutoff: DateTime<Utc>,
+    // The last line written, by time then id
+    after: (DateTime<Utc>, Uuid),
+    // Balance after the last line written, unrounded so that lines with
+    // more digits than the currency allows do not drift it
+    balance: BigDecimal,
+    finished: bool,
+}
+
+// Write out the next page of lines, or the footer once they run out
+async fn next_page(mut pages: Pages) -> Option<(Result<web::Bytes, actix_web::Error>, Pages)> {
+    if pages.finished {
+        return None;
+    }
+
+    let chunk = match read_page(&mut pages).await {
+        Ok(entries) if entries.is_empty() => {
+            pages.finished = true;
+            statement::footer(pages.format, &pages.statement)
+        }
+        Ok(entries) => entries.iter().map(|entry| statement::entry(pages.format, entry)).collect(),
+        // Headers are long gone, so the download is cut short
+        Err(err) => {
+            log::error!("Failed to read statement {}: {}", pages.statement.id, err);
+            pages.finished = true;
+            return Some((Err(err.into()), pages));
+        }
+    };
+
+    Some((Ok(web::Bytes::from(chunk)), pages))
+}
+
+async fn read_page(pages: &mut Pages) -> Result<Vec<StatementEntry>, AppError> {
+    let rows = sqlx::query(
+        r#"
+        SELECT l.id, l.transaction_id, l.entry_type, l.amount, l.created_at,
+               COALESCE(t.description, l.description) AS description,
+               CASE WHEN t.sender_id = a.user_id THEN t.recipient_id ELSE t.sender_id END AS counterparty_id
+        FROM ledger_entries l
+        JOIN accounts a ON a.id = l.account_id
+        LEFT JOIN transactions t ON t.id = l.transaction_id
+        WHERE l.account_id = $1 AND l.created_at < $2 AND (l.created_at, l.id) > ($3, $4)
+        ORDER BY l.created_at, l.id
+        LIMIT $5
+        "#
+    )
+    .bind(pages.statement.account_id)
+    .bind(pages.cutoff)
+    .bind(pages.after.0)
+    .bind(pages.after.1)
+    .bind(STATEMENT_PAGE_SIZE)
+    .fetch_all(&pages.pool)
+    .await?;
+
+    let currency = pages.statement.opening_balance.currency();
+    let mut entries = Vec::with_capacity(rows.len());
+    for row in rows {
+        let entry_type = match row.try_get::<String, _>("entry_type")?.as_str() {
+            "debit" => EntryType::Debit,
+            _ => EntryType::Credit,
+        };
+        let amount: BigDecimal = row.try_get("amount")?;
+        pages.balance = match entry_type {
+            EntryType::Credit => &pages.balance + &amount,
+            EntryType::Debit => &pages.balance - &amount,
+        };
+
+        let entry = StatementEntry {
+            id: row.try_get("id")?,
+            transaction_id: row.try_get("transaction_id")?,
+            counterparty_id: row.try_get("counterparty_id")?,
+            entry_type,
+            amount: Money::stored(amount, currency),
+            description: row.try_get("description")?,
+            booked_at: row.try_get("created_at")?,
+            balance: Money::stored(pages.balance.clone(), currency),
+        };
+        pages.after = (entry.booked_at, entry.id);
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+#[derive(serde::Deserialize)]
+pub struct StatementQuery {
+    // First and last days of the statement, in UTC
+    from: NaiveDate,
+    to: NaiveDate,
+    format: StatementFormat,
+    // The user's first wallet unless given
+    currency: Option<String>,
+}
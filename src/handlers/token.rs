@@ -0,0 +1,269 @@
+use actix_web::{web, HttpResponse, Responder};
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::{PgPool, Postgres, Row};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::config::TokenConfig;
+use crate::models::{AppError, RefreshTokenRequest, Role, TokenResponse};
+use crate::utils::auth::{generate_jwt, generate_refresh_token, hash_token, Claims};
+use crate::utils::keys::KeyStore;
+
+/// Issue an access token and a refresh token in `family_id`.
+///
+/// A login starts a new family; every refresh token rotated from it stays in
+/// that family, so reuse of an old one can revoke them all.
+pub async fn issue_tokens(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    config: &TokenConfig,
+    keys: &KeyStore,
+    user_id: Uuid,
+    role: Role,
+    family_id: Uuid,
+) -> Result<TokenResponse, AppError> {
+    let claims = Claims::new(user_id, role, family_id, config.access_token_ttl);
+    let token = generate_jwt(&claims, keys)?;
+
+    let refresh_token = generate_refresh_token();
+    let refresh_ttl = chrono::Duration::from_std(config.refresh_token_ttl)
+        .map_err(|_| AppError::InternalServerError("Refresh token TTL is out of range".to_string()))?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#
+    )
+    .bind(user_id)
+    .bind(family_id)
+    .bind(hash_token(&refresh_token))
+    .bind(Utc::now() + refresh_ttl)
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(TokenResponse::new(token, config.access_token_ttl.as_secs(), refresh_token))
+}
+
+/// Exchange a refresh token for a new access and refresh token pair
+pub async fn refresh_token(
+    pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    keys: web::Data<KeyStore>,
+    refresh_data: web::Json<RefreshTokenRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    refresh_data.validate()?;
+
+    let tokens = rotate_refresh_token(pool.get_ref(), config.get_ref(), keys.get_ref(), &refresh_data.refresh_token).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Mark `refresh_token` used and issue its successor in the same family.
+///
+/// A token that was already used or revoked is being replayed, most likely by
+/// whoever stole it, so its whole family is revoked along with every access
+/// token issued from it.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    config: &TokenConfig,
+    keys: &KeyStore,
+    refresh_token: &str,
+) -> Result<TokenResponse, AppError> {
+    let mut tx = pool.begin().await?;
+
+    let stored = sqlx::query(
+        r#"
+        SELECT t.id, t.user_id, t.family_id, t.expires_at, t.used_at, t.revoked_at, u.role
+        FROM refresh_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1
+        FOR UPDATE OF t
+        "#
+    )
+    .bind(hash_token(refresh_token))
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or_else(|| AppError::AuthenticationError("Invalid refresh token".to_string()))?;
+
+    let token_id: Uuid = stored.try_get("id")?;
+    let user_id: Uuid = stored.try_get("user_id")?;
+    let family_id: Uuid = stored.try_get("family_id")?;
+    let expires_at: DateTime<Utc> = stored.try_get("expires_at")?;
+    let used_at: Option<DateTime<Utc>> = stored.try_get("used_at")?;
+    let revoked_at: Option<DateTime<Utc>> = stored.try_get("revoked_at")?;
+    let role: Role = stored.try_get::<String, _>("role")?.parse()?;
+
+    if used_at.is_some() || revoked_at.is_some() {
+        revoke_family(&mut tx, family_id).await?;
+        tx.commit().await?;
+
+        log::warn!("Refresh token reuse for user {}; revoked token family {}", user_id, family_id);
+        return Err(AppError::AuthenticationError("Refresh token has been revoked".to_string()));
+    }
+    if expires_at <= Utc::now() {
+        return Err(AppError::AuthenticationError("Refresh token has expired".to_string()));
+    }
+
+    sqlx::query("UPDATE refresh_tokens SET used_at = NOW() WHERE id = $1")
+        .bind(token_id)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query("UPDATE sessions SET last_seen_at = NOW() WHERE id = $1")
+        .bind(family_id)
+        .execute(&mut tx)
+        .await?;
+
+    // Picks up any role change since the family was issued
+    let tokens = issue_tokens(&mut tx, config, keys, user_id, role, family_id).await?;
+
+    tx.commit().await?;
+
+    Ok(tokens)
+}
+
+/// Revoke the current access token and the refresh token family it came from
+pub async fn logout(
+    claims: web::ReqData<Claims>,
+    pool: web::Data<PgPool>,
+) -> Result<impl Responder, AppError> {
+    let claims = claims.into_inner();
+    let user_id: Uuid = claims
+        .sub
+        .parse()
+        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
+    let expires_at = Utc
+        .timestamp_opt(claims.exp as i64, 0)
+        .single()
+        .ok_or_else(|| AppError::AuthenticationError("Invalid expiry in token".to_string()))?;
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_tokens (jti, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING
+        "#
+    )
+    .bind(claims.token_id()?)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(&mut tx)
+    .await?;
+
+    revoke_family(&mut tx, claims.family_id()?).await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Publish the public keys tokens are verified with, so other services can
+/// check them without sharing a secret
+pub async fn jwks(keys: web::Data<KeyStore>) -> Result<impl Responder, AppError> {
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(keys.jwks()?))
+}
+
+/// Revoke every session of the user, and with it every refresh token
+/// family, except `keep_family` if given. Access tokens from a revoked
+/// session stop working with it. Returns the number of sessions revoked.
+pub async fn revoke_user_sessions(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    user_id: Uuid,
+    keep_family: Option<Uuid>,
+) -> Result<u64, AppError> {
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL
+          AND ($2::UUID IS NULL OR family_id <> $2)
+        "#
+    )
+    .bind(user_id)
+    .bind(keep_family)
+    .execute(&mut *tx)
+    .await?;
+
+    let revoked = sqlx::query(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL
+          AND ($2::UUID IS NULL OR id <> $2)
+        "#
+    )
+    .bind(user_id)
+    .bind(keep_family)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    Ok(revoked)
+}
+
+/// Revoke the session `family_id` and every refresh token issued for it
+pub async fn revoke_family(tx: &mut sqlx::Transaction<'_, Postgres>, family_id: Uuid) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE family_id = $1 AND revoked_at IS NULL
+        "#
+    )
+    .bind(family_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(family_id)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Delete revocation entries, refresh tokens, MFA login challenges, password
+/// reset tokens and email verification tokens that have expired anyway, and
+/// the sessions left without refresh tokens. Returns the number of rows
+/// removed.
+pub async fn purge_expired_tokens(pool: &PgPool) -> Result<u64, AppError> {
+    let revoked = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    let refresh = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    let challenges = sqlx::query("DELETE FROM mfa_challenges WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    let resets = sqlx::query("DELETE FROM password_reset_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    let verifications = sqlx::query("DELETE FROM email_verification_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    // Sessions end with the last of their refresh tokens
+    let sessions = sqlx::query(
+        "DELETE FROM sessions s WHERE NOT EXISTS (SELECT 1 FROM refresh_tokens t WHERE t.family_id = s.id)"
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    Ok(revoked + refresh + challenges + resets + verifications + sessions)
+}
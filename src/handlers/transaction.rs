@@ -1,26 +1,195 @@
-use actix_web::{web, HttpResponse, Responder};
-use sqlx::{PgPool, Row}; // Import Row trait explicitly for try_get method
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use sqlx::{PgConnection, PgPool, Postgres, Row}; // Import Row trait explicitly for try_get method
+use sqlx::postgres::PgRow;
 use uuid::Uuid;
 use validator::Validate;
 use bigdecimal::BigDecimal;
-use std::str::FromStr;
+use chrono::Utc;
+use std::time::Duration;
 
+use crate::config::{MfaConfig, TransferConfig};
 use crate::models::transaction_fixed::{
-    Transaction, TransactionResponse, CreateTransactionRequest
+    Transaction, TransactionResponse, CreateTransactionRequest, CaptureTransactionRequest, RefundTransactionRequest
 };
 use crate::models::transaction_fixed::TransactionStatus;
 use crate::models::transaction_fixed::TransactionListResponse;
-use crate::models::AppError;
+use crate::models::{AppError, Conversion, Currency, Money};
+use crate::models::ledger::{LedgerAccount, LedgerLine, FX_ACCOUNT, FX_FEES_ACCOUNT};
+use crate::handlers::fx;
+use crate::handlers::mfa::{require_step_up, MFA_CODE_HEADER};
+use crate::handlers::ledger::post_journal;
+use crate::handlers::audit::record_admin_action;
+use crate::models::audit::REFUND_TRANSACTION;
+
+/// Reason a transfer is refused when the sender's available balance is short
+pub const INSUFFICIENT_FUNDS: &str = "Insufficient funds";
 
 pub async fn create_transaction(
+    req: HttpRequest,
     user_id: web::ReqData<Uuid>,
     pool: web::Data<PgPool>,
+    config: web::Data<TransferConfig>,
+    mfa_config: web::Data<MfaConfig>,
     transaction_data: web::Json<CreateTransactionRequest>,
 ) -> Result<impl Responder, AppError> {
     // Validate request data
     transaction_data.validate()?;
     
-    let sender_id = user_id.into_inner();
+    let user_id = user_id.into_inner();
+    
+    // Large transfers need a fresh code from users with MFA enabled
+    let amount = Money::positive(transaction_data.amount.clone(), &transaction_data.currency)?;
+    let code = req.headers().get(MFA_CODE_HEADER).and_then(|value| value.to_str().ok());
+    require_step_up(pool.get_ref(), mfa_config.get_ref(), config.get_ref(), user_id, &amount, code).await?;
+    
+    let transaction = process_transfer(pool.get_ref(), config.get_ref(), user_id, &transaction_data).await?;
+    
+    Ok(HttpResponse::Created().json(TransactionResponse::try_from(transaction)?))
+}
+
+pub async fn capture_transaction(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    transaction_id: web::Path<Uuid>,
+    capture_data: web::Json<CaptureTransactionRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    capture_data.validate()?;
+    
+    let transaction = process_capture(
+        pool.get_ref(),
+        transaction_id.into_inner(),
+        user_id.into_inner(),
+        &capture_data,
+    )
+    .await?;
+    
+    Ok(HttpResponse::Ok().json(TransactionResponse::try_from(transaction)?))
+}
+
+pub async fn void_transaction(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    transaction_id: web::Path<Uuid>,
+) -> Result<impl Responder, AppError> {
+    let transaction = process_void(pool.get_ref(), transaction_id.into_inner(), user_id.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(TransactionResponse::try_from(transaction)?))
+}
+
+pub async fn refund_transaction(
+    user_id: web::ReqData<Uuid>,
+    pool: web::Data<PgPool>,
+    transaction_id: web::Path<Uuid>,
+    refund_data: web::Json<RefundTransactionRequest>,
+) -> Result<impl Responder, AppError> {
+    // Validate request data
+    refund_data.validate()?;
+    
+    let refund = process_refund(
+        pool.get_ref(),
+        transaction_id.into_inner(),
+        RefundInitiator::Recipient(user_id.into_inner()),
+        &refund_data,
+    )
+    .await?;
+    
+    Ok(HttpResponse::Created().json(TransactionResponse::try_from(refund)?))
+}
+
+/// Who asked for a refund
+#[derive(Debug, Clone, Copy)]
+pub enum RefundInitiator {
+    /// The recipient of the original transfer
+    Recipient(Uuid),
+    /// An admin acting for the recipient
+    Admin(Uuid),
+}
+
+/// Record a transfer and move the funds between the two accounts.
+///
+/// The transaction row is inserted as pending first so that a failed transfer
+/// leaves an audit trail; the balance changes then run in a single database
+/// transaction and the row is marked failed with the reason if anything goes wrong.
+///
+/// With `capture: false` the funds are only held: the row stays pending and
+/// reduces the sender's available balance until it is captured, voided or
+/// expires after `config.hold_ttl`.
+///
+/// If the recipient has no wallet in the transfer's currency the amount is
+/// converted into their first wallet's currency, at the rate locked by
+/// `quote_id` if given or else at the current rate.
+///
+/// When `config.require_verified_email` is set, senders who have not verified
+/// their email address are refused.
+pub async fn process_transfer(
+    pool: &PgPool,
+    config: &TransferConfig,
+    sender_id: Uuid,
+    transaction_data: &CreateTransactionRequest,
+) -> Result<Transaction, AppError> {
+    let prepared = prepare_transfer(&mut *pool.acquire().await?, config, sender_id, transaction_data).await?;
+    
+    let transaction_id = insert_pending_transaction(
+        pool,
+        sender_id,
+        prepared.recipient_id,
+        &prepared.amount,
+        transaction_data.description.as_deref(),
+        None,
+    )
+    .await?;
+    
+    let result = async {
+        let mut tx = pool.begin().await?;
+        let transaction = execute_transfer(&mut tx, config, transaction_id, sender_id, transaction_data, prepared).await?;
+        tx.commit().await?;
+        Ok(transaction)
+    }
+    .await;
+    
+    fail_on_error(pool, transaction_id, result).await
+}
+
+/// Make a transfer as `process_transfer` does, but inside the caller's `tx`
+/// so that it commits or rolls back with the caller's other changes. Nothing
+/// is recorded for a transfer that fails; rolling back `tx` undoes the rest.
+pub async fn process_transfer_in(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    config: &TransferConfig,
+    sender_id: Uuid,
+    transaction_data: &CreateTransactionRequest,
+) -> Result<Transaction, AppError> {
+    let prepared = prepare_transfer(tx, config, sender_id, transaction_data).await?;
+    
+    let transaction_id = insert_pending_transaction(
+        &mut *tx,
+        sender_id,
+        prepared.recipient_id,
+        &prepared.amount,
+        transaction_data.description.as_deref(),
+        None,
+    )
+    .await?;
+    
+    execute_transfer(tx, config, transaction_id, sender_id, transaction_data, prepared).await
+}
+
+// A transfer that has passed the checks made before anything is recorded
+struct PreparedTransfer {
+    recipient_id: Uuid,
+    amount: Money,
+    capture: bool,
+    conversion: Option<Conversion>,
+}
+
+// Check a transfer can be attempted and work out any currency conversion
+async fn prepare_transfer(
+    conn: &mut PgConnection,
+    config: &TransferConfig,
+    sender_id: Uuid,
+    transaction_data: &CreateTransactionRequest,
+) -> Result<PreparedTransfer, AppError> {
     let recipient_id = transaction_data.recipient_id;
     
     // Ensure sender and recipient are different
@@ -28,10 +197,24 @@ pub async fn create_transaction(
         return Err(AppError::BadRequestError("Cannot send money to yourself".to_string()));
     }
     
+    if config.require_verified_email {
+        let verified = sqlx::query("SELECT email_verified_at IS NOT NULL AS verified FROM users WHERE id = $1")
+            .bind(sender_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .map(|row| row.try_get::<bool, _>("verified"))
+            .transpose()?
+            .unwrap_or(false);
+        
+        if !verified {
+            return Err(AppError::ForbiddenError("Verify your email address before sending money".to_string()));
+        }
+    }
+    
     // Ensure recipient exists
     let recipient_exists = sqlx::query("SELECT id FROM users WHERE id = $1")
         .bind(recipient_id)
-        .fetch_optional(pool.get_ref())
+        .fetch_optional(&mut *conn)
         .await?
         .is_some();
     
@@ -39,105 +222,594 @@ pub async fn create_transaction(
         return Err(AppError::NotFoundError("Recipient not found".to_string()));
     }
     
-    // Convert f64 to BigDecimal
-    let amount_decimal = BigDecimal::from_str(&transaction_data.amount.to_string())
-        .map_err(|_| AppError::BadRequestError("Invalid amount".to_string()))?;
+    let amount = Money::positive(transaction_data.amount.clone(), &transaction_data.currency)?;
+    let capture = transaction_data.capture.unwrap_or(true);
+    
+    let conversion = match transaction_data.quote_id {
+        Some(quote_id) => {
+            // Checked again when the quote is claimed below
+            let quote = fx::find_quote(&mut *conn, quote_id, sender_id).await?;
+            Some(fx::check_quote(&quote, &amount)?)
+        }
+        None => {
+            let recipient_currency = recipient_wallet_currency(conn, recipient_id, amount.currency()).await?;
+            if recipient_currency == amount.currency() {
+                None
+            } else {
+                Some(fx::convert(config, amount.clone(), recipient_currency).await?)
+            }
+        }
+    };
+    
+    if conversion.is_some() && !capture {
+        return Err(AppError::BadRequestError("Holds cannot be placed on transfers that need currency conversion".to_string()));
+    }
+    
+    Ok(PreparedTransfer { recipient_id, amount, capture, conversion })
+}
 
-    // Check sender balance
-    let sender_account = sqlx::query(
+// Settle or hold a prepared transfer inside `tx`
+async fn execute_transfer(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    config: &TransferConfig,
+    transaction_id: Uuid,
+    sender_id: Uuid,
+    transaction_data: &CreateTransactionRequest,
+    prepared: PreparedTransfer,
+) -> Result<Transaction, AppError> {
+    let PreparedTransfer { recipient_id, amount, capture, conversion } = prepared;
+    
+    if capture {
+        let conversion = match (transaction_data.quote_id, conversion) {
+            (Some(quote_id), Some(_)) => Some(fx::claim_quote(tx, quote_id, sender_id, &amount).await?),
+            (_, conversion) => conversion,
+        };
+        settle_transfer(tx, transaction_id, sender_id, recipient_id, &amount, conversion.as_ref()).await
+    } else {
+        authorize_transfer(tx, transaction_id, sender_id, recipient_id, &amount, config.hold_ttl).await
+    }
+}
+
+/// Settle all or part of a held transfer.
+///
+/// Either party to the transfer may capture it. A partial capture settles the
+/// given amount and releases the rest of the hold; the transaction's `amount`
+/// becomes the captured amount while `authorized_amount` keeps the original.
+pub async fn process_capture(
+    pool: &PgPool,
+    transaction_id: Uuid,
+    user_id: Uuid,
+    capture_data: &CaptureTransactionRequest,
+) -> Result<Transaction, AppError> {
+    let mut tx = pool.begin().await?;
+    
+    let held = lock_active_hold(&mut tx, transaction_id, user_id).await?;
+    let authorized = Money::parse(
+        held.authorized_amount.clone().unwrap_or_else(|| held.amount.clone()),
+        &held.currency,
+    )?;
+    
+    let amount = match &capture_data.amount {
+        Some(amount) => Money::positive(amount.clone(), &held.currency)?,
+        None => authorized.clone(),
+    };
+    if amount.amount() > authorized.amount() {
+        return Err(AppError::BadRequestError(format!(
+            "Capture exceeds the authorized amount of {}",
+            authorized
+        )));
+    }
+    
+    sqlx::query(
         r#"
-        SELECT balance, currency FROM accounts
-        WHERE user_id = $1
+        UPDATE transactions
+        SET amount = $1, updated_at = NOW()
+        WHERE id = $2
         "#
     )
-    .bind(sender_id)
-    .fetch_one(pool.get_ref())
+    .bind(amount.amount())
+    .bind(transaction_id)
+    .execute(&mut tx)
     .await?;
     
-    let balance: BigDecimal = sender_account.try_get("balance")?;
-    let currency: String = sender_account.try_get("currency")?;
+    // A failed capture rolls back and leaves the hold in place
+    let transaction = settle_transfer(&mut tx, transaction_id, held.sender_id, held.recipient_id, &amount, None).await?;
     
-    // Ensure sender has enough funds
-    if balance < amount_decimal {
-        return Err(AppError::BadRequestError("Insufficient funds".to_string()));
+    tx.commit().await?;
+    
+    Ok(transaction)
+}
+
+/// Release a held transfer without moving any money.
+///
+/// Either party to the transfer may void it.
+pub async fn process_void(
+    pool: &PgPool,
+    transaction_id: Uuid,
+    user_id: Uuid,
+) -> Result<Transaction, AppError> {
+    let mut tx = pool.begin().await?;
+    
+    lock_active_hold(&mut tx, transaction_id, user_id).await?;
+    
+    let voided = sqlx::query(
+        r#"
+        UPDATE transactions
+        SET status = $1, updated_at = NOW()
+        WHERE id = $2
+        RETURNING id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
+        "#
+    )
+    .bind(TransactionStatus::Voided as i32)
+    .bind(transaction_id)
+    .fetch_one(&mut tx)
+    .await?;
+    
+    tx.commit().await?;
+    
+    transaction_from_row(&voided)
+}
+
+/// Mark every hold whose TTL has passed as expired, returning how many were.
+///
+/// Expired holds already stop counting against the available balance once
+/// their TTL passes; this only brings their status up to date.
+pub async fn expire_holds(pool: &PgPool) -> Result<u64, AppError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE transactions
+        SET status = $1, updated_at = NOW()
+        WHERE status = $2 AND hold_expires_at <= NOW()
+        "#
+    )
+    .bind(TransactionStatus::Expired as i32)
+    .bind(TransactionStatus::Pending as i32)
+    .execute(pool)
+    .await?;
+    
+    Ok(result.rows_affected())
+}
+
+// Lock a held transfer the user is party to and check it can still be captured or voided
+async fn lock_active_hold(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    transaction_id: Uuid,
+    user_id: Uuid,
+) -> Result<Transaction, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
+        FROM transactions
+        WHERE id = $1 AND (sender_id = $2 OR recipient_id = $2)
+        FOR UPDATE
+        "#
+    )
+    .bind(transaction_id)
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Transaction not found".to_string()))?;
+    let transaction = transaction_from_row(&row)?;
+    
+    let expires_at = transaction
+        .hold_expires_at
+        .ok_or_else(|| AppError::BadRequestError("Transaction is not a hold".to_string()))?;
+    if !matches!(transaction.status, TransactionStatus::Pending) {
+        return Err(AppError::BadRequestError("Hold has already been captured, voided or expired".to_string()));
+    }
+    if expires_at <= Utc::now() {
+        return Err(AppError::BadRequestError("Hold has expired".to_string()));
     }
     
-    // Ensure currency matches
-    if currency != transaction_data.currency {
-        return Err(AppError::BadRequestError(format!(
-            "Currency mismatch: account is in {}, transaction is in {}",
-            currency, transaction_data.currency
-        )));
+    Ok(transaction)
+}
+
+/// Refund all or part of a completed transfer back to its sender.
+///
+/// A recipient asking for a refund must be the recipient of the original
+/// transfer; an admin's refund is audited in the same database transaction
+/// as the money movement, so it never happens unrecorded. The refund is a new transaction
+/// in the opposite direction linked through `refund_of`, and the original row
+/// is locked while checking that refunds never exceed its amount.
+pub async fn process_refund(
+    pool: &PgPool,
+    original_id: Uuid,
+    initiator: RefundInitiator,
+    refund_data: &RefundTransactionRequest,
+) -> Result<Transaction, AppError> {
+    let original = sqlx::query(
+        r#"
+        SELECT id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
+        FROM transactions
+        WHERE id = $1
+        "#
+    )
+    .bind(original_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::NotFoundError("Transaction not found".to_string()))?;
+    let original = transaction_from_row(&original)?;
+    
+    if let RefundInitiator::Recipient(user_id) = initiator {
+        if user_id != original.recipient_id {
+            // Don't reveal transactions the user has no part in
+            if user_id != original.sender_id {
+                return Err(AppError::NotFoundError("Transaction not found".to_string()));
+            }
+            return Err(AppError::BadRequestError("Only the recipient can refund a transaction".to_string()));
+        }
+    }
+    
+    if original.refund_of.is_some() {
+        return Err(AppError::BadRequestError("A refund cannot be refunded".to_string()));
+    }
+    
+    if original.converted_currency.is_some() {
+        return Err(AppError::BadRequestError("Transactions with a currency conversion cannot be refunded".to_string()));
+    }
+    
+    // Checked again under the row lock below; this avoids recording hopeless refunds
+    if !matches!(original.status, TransactionStatus::Completed) {
+        return Err(AppError::BadRequestError("Only completed transactions can be refunded".to_string()));
+    }
+    if original.refunded_amount >= original.amount {
+        return Err(AppError::BadRequestError("Transaction has already been fully refunded".to_string()));
+    }
+    
+    let amount = match &refund_data.amount {
+        Some(amount) => Money::positive(amount.clone(), &original.currency)?,
+        // Default to refunding whatever is left
+        None => Money::parse(&original.amount - &original.refunded_amount, &original.currency)?,
+    };
+    
+    let description = refund_data
+        .description
+        .clone()
+        .unwrap_or_else(|| format!("Refund of {}", original.id));
+    
+    let refund_id = insert_pending_transaction(
+        pool,
+        original.recipient_id,
+        original.sender_id,
+        &amount,
+        Some(&description),
+        Some(original.id),
+    )
+    .await?;
+    
+    let result = async {
+        let mut tx = pool.begin().await?;
+        
+        // Lock the original so concurrent refunds see each other's totals
+        let locked = sqlx::query(
+            r#"
+            SELECT amount, refunded_amount, status FROM transactions
+            WHERE id = $1
+            FOR UPDATE
+            "#
+        )
+        .bind(original.id)
+        .fetch_one(&mut tx)
+        .await?;
+        
+        let status: i32 = locked.try_get("status")?;
+        if status != TransactionStatus::Completed as i32 {
+            return Err(AppError::BadRequestError("Only completed transactions can be refunded".to_string()));
+        }
+        
+        let original_amount: BigDecimal = locked.try_get("amount")?;
+        let refunded_amount: BigDecimal = locked.try_get("refunded_amount")?;
+        if &refunded_amount + amount.amount() > original_amount {
+            return Err(AppError::BadRequestError(format!(
+                "Refund exceeds the refundable amount of {}",
+                Money::new(original_amount - refunded_amount, amount.currency())?
+            )));
+        }
+        
+        let refund = settle_transfer(&mut tx, refund_id, original.recipient_id, original.sender_id, &amount, None).await?;
+        
+        sqlx::query(
+            r#"
+            UPDATE transactions
+            SET refunded_amount = refunded_amount + $1, updated_at = NOW()
+            WHERE id = $2
+            "#
+        )
+        .bind(amount.amount())
+        .bind(original.id)
+        .execute(&mut tx)
+        .await?;
+        
+        if let RefundInitiator::Admin(actor_id) = initiator {
+            record_admin_action(
+                &mut tx,
+                actor_id,
+                REFUND_TRANSACTION,
+                Some(original.id),
+                serde_json::json!({ "refund_id": refund.id, "amount": refund.amount, "currency": refund.currency }),
+            )
+            .await?;
+        }
+        
+        tx.commit().await?;
+        Ok(refund)
     }
-      // Create transaction record
+    .await;
+    
+    fail_on_error(pool, refund_id, result).await
+}
+
+// Insert the record of a transfer before any money moves
+async fn insert_pending_transaction<'e, E>(
+    executor: E,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    amount: &Money,
+    description: Option<&str>,
+    refund_of: Option<Uuid>,
+) -> Result<Uuid, AppError>
+where
+    E: sqlx::Executor<'e, Database = Postgres>,
+{
     let transaction_id = sqlx::query(
         r#"
-        INSERT INTO transactions (sender_id, recipient_id, amount, currency, description, status)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO transactions (sender_id, recipient_id, amount, currency, description, status, refund_of)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         RETURNING id
         "#
     )
     .bind(sender_id)
     .bind(recipient_id)
-    .bind(&amount_decimal)
-    .bind(&transaction_data.currency)
-    .bind(&transaction_data.description)
+    .bind(amount.amount())
+    .bind(amount.currency().code())
+    .bind(description)
     .bind(TransactionStatus::Pending as i32)
-    .fetch_one(pool.get_ref())
+    .bind(refund_of)
+    .fetch_one(executor)
     .await?
     .try_get::<Uuid, _>("id")?;
     
-    // Update sender's balance
-    sqlx::query(
+    Ok(transaction_id)
+}
+
+// The currency of the recipient's wallet a transfer in `currency` lands in:
+// `currency` itself if they hold it, otherwise their first wallet's
+async fn recipient_wallet_currency(
+    conn: &mut PgConnection,
+    recipient_id: Uuid,
+    currency: Currency,
+) -> Result<Currency, AppError> {
+    let code: String = sqlx::query(
         r#"
-        UPDATE accounts
-        SET balance = balance - $1, updated_at = NOW()
-        WHERE user_id = $2
+        SELECT currency FROM accounts
+        WHERE user_id = $1
+        ORDER BY currency = $2 DESC, created_at, id
+        LIMIT 1
         "#
     )
-    .bind(&amount_decimal)
-    .bind(sender_id)
-    .execute(pool.get_ref())
-    .await?;
+    .bind(recipient_id)
+    .bind(currency.code())
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| AppError::BadRequestError("Recipient has no wallet".to_string()))?
+    .try_get("currency")?;
     
-    // Update recipient's balance
+    Currency::from_code(&code)
+}
+
+// Mark the record failed with the reason if settlement did not go through
+async fn fail_on_error(
+    pool: &PgPool,
+    transaction_id: Uuid,
+    result: Result<Transaction, AppError>,
+) -> Result<Transaction, AppError> {
+    let err = match result {
+        Ok(transaction) => return Ok(transaction),
+        Err(err) => err,
+    };
+    
+    // The settlement rolled back, so only the status of the record changes
     sqlx::query(
         r#"
-        UPDATE accounts
-        SET balance = balance + $1, updated_at = NOW()
-        WHERE user_id = $2
+        UPDATE transactions
+        SET status = $1, failure_reason = $2, updated_at = NOW()
+        WHERE id = $3
         "#
     )
-    .bind(&amount_decimal)
-    .bind(recipient_id)
-    .execute(pool.get_ref())
+    .bind(TransactionStatus::Failed as i32)
+    .bind(err.to_string())
+    .bind(transaction_id)
+    .execute(pool)
     .await?;
-      // Mark transaction as completed
+    
+    Err(err)
+}
+
+// Debit the sender, credit the recipient and complete the record inside `tx`.
+// With a conversion the recipient's wallet in the converted currency is
+// credited and the applied rate and fee are recorded on the transaction.
+async fn settle_transfer(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    transaction_id: Uuid,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    amount: &Money,
+    conversion: Option<&Conversion>,
+) -> Result<Transaction, AppError> {
+    let recipient_currency = conversion.map_or(amount.currency(), |conversion| conversion.converted.currency());
+    let (sender_account_id, recipient_account_id) =
+        lock_transfer_accounts(tx, transaction_id, sender_id, recipient_id, amount, recipient_currency).await?;
+    
+    match conversion {
+        // Debit the sender and credit the recipient through the ledger
+        None => {
+            post_journal(
+                tx,
+                Some(transaction_id),
+                amount.currency().code(),
+                "Transfer",
+                &[
+                    LedgerLine::debit(LedgerAccount::User(sender_account_id), amount.amount().clone()),
+                    LedgerLine::credit(LedgerAccount::User(recipient_account_id), amount.amount().clone()),
+                ],
+            )
+            .await?;
+        }
+        // Each currency balances on its own: the FX account buys the sender's
+        // currency less the fee and sells the recipient's
+        Some(conversion) => {
+            let mut lines = vec![
+                LedgerLine::debit(LedgerAccount::User(sender_account_id), amount.amount().clone()),
+                LedgerLine::credit(
+                    LedgerAccount::System(FX_ACCOUNT),
+                    amount.amount() - conversion.fee.amount(),
+                ),
+            ];
+            if conversion.fee.is_positive() {
+                lines.push(LedgerLine::credit(LedgerAccount::System(FX_FEES_ACCOUNT), conversion.fee.amount().clone()));
+            }
+            post_journal(tx, Some(transaction_id), amount.currency().code(), "Currency conversion", &lines).await?;
+            
+            post_journal(
+                tx,
+                Some(transaction_id),
+                recipient_currency.code(),
+                "Currency conversion",
+                &[
+                    LedgerLine::debit(LedgerAccount::System(FX_ACCOUNT), conversion.converted.amount().clone()),
+                    LedgerLine::credit(LedgerAccount::User(recipient_account_id), conversion.converted.amount().clone()),
+                ],
+            )
+            .await?;
+        }
+    }
+    
+    // Mark transaction as completed
     let completed_transaction = sqlx::query(
         r#"
         UPDATE transactions
-        SET status = $1, updated_at = NOW()
+        SET status = $1, fx_quote_id = $3, fx_rate = $4, fx_fee = $5,
+            converted_amount = $6, converted_currency = $7, updated_at = NOW()
         WHERE id = $2
-        RETURNING id, sender_id, recipient_id, amount, currency, description, status, created_at, updated_at
+        RETURNING id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
         "#
     )
     .bind(TransactionStatus::Completed as i32)
     .bind(transaction_id)
-    .fetch_one(pool.get_ref())
-    .await?;    // Extract data from the row
-    let transaction = Transaction {
-        id: completed_transaction.try_get("id")?,
-        sender_id: completed_transaction.try_get("sender_id")?,
-        recipient_id: completed_transaction.try_get("recipient_id")?,
-        amount: completed_transaction.try_get("amount")?,
-        currency: completed_transaction.try_get("currency")?,
-        description: completed_transaction.try_get("description").ok(),
-        status: TransactionStatus::Completed, // We know it's completed because we just set it
-        created_at: completed_transaction.try_get("created_at")?,
-        updated_at: completed_transaction.try_get("updated_at")?,
-    };
+    .bind(conversion.and_then(|conversion| conversion.quote_id))
+    .bind(conversion.map(|conversion| &conversion.rate))
+    .bind(conversion.map(|conversion| conversion.fee.amount()))
+    .bind(conversion.map(|conversion| conversion.converted.amount()))
+    .bind(conversion.map(|conversion| conversion.converted.currency().code()))
+    .fetch_one(&mut *tx)
+    .await?;
+    
+    transaction_from_row(&completed_transaction)
+}
+
+// Reserve the amount against the sender's available balance inside `tx`
+async fn authorize_transfer(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    transaction_id: Uuid,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    amount: &Money,
+    hold_ttl: Duration,
+) -> Result<Transaction, AppError> {
+    lock_transfer_accounts(tx, transaction_id, sender_id, recipient_id, amount, amount.currency()).await?;
+    
+    let ttl = chrono::Duration::from_std(hold_ttl)
+        .map_err(|_| AppError::InternalServerError("Hold TTL is out of range".to_string()))?;
+    
+    // The row stays pending; its amount counts as held until the expiry
+    let authorized_transaction = sqlx::query(
+        r#"
+        UPDATE transactions
+        SET authorized_amount = amount, hold_expires_at = $1, updated_at = NOW()
+        WHERE id = $2
+        RETURNING id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
+        "#
+    )
+    .bind(Utc::now() + ttl)
+    .bind(transaction_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    
+    transaction_from_row(&authorized_transaction)
+}
+
+// Lock the sender's wallet in the currency of `amount` and the recipient's in
+// `recipient_currency`, and check the sender can cover `amount` from their
+// available balance, returning the sender and recipient account ids
+async fn lock_transfer_accounts(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    transaction_id: Uuid,
+    sender_id: Uuid,
+    recipient_id: Uuid,
+    amount: &Money,
+    recipient_currency: Currency,
+) -> Result<(Uuid, Uuid), AppError> {
+    let currency = amount.currency();
+    
+    // Lock both accounts in id order so concurrent transfers between the same
+    // pair of users cannot deadlock
+    let accounts = sqlx::query(
+        r#"
+        SELECT id, user_id, balance FROM accounts
+        WHERE (user_id = $1 AND currency = $3) OR (user_id = $2 AND currency = $4)
+        ORDER BY id
+        FOR UPDATE
+        "#
+    )
+    .bind(sender_id)
+    .bind(recipient_id)
+    .bind(currency.code())
+    .bind(recipient_currency.code())
+    .fetch_all(&mut *tx)
+    .await?;
+    
+    let mut sender_account = None;
+    let mut recipient_account_id = None;
+    for account in &accounts {
+        let account_user_id: Uuid = account.try_get("user_id")?;
+        if account_user_id == sender_id {
+            sender_account = Some(account);
+        } else if account_user_id == recipient_id {
+            recipient_account_id = Some(account.try_get::<Uuid, _>("id")?);
+        }
+    }
+    
+    let sender_account = sender_account
+        .ok_or_else(|| AppError::BadRequestError(format!("Sender has no {} wallet", currency)))?;
+    let recipient_account_id = recipient_account_id
+        .ok_or_else(|| AppError::BadRequestError(format!("Recipient has no {} wallet", recipient_currency)))?;
+    
+    let sender_account_id: Uuid = sender_account.try_get("id")?;
+    let balance: BigDecimal = sender_account.try_get("balance")?;
+    
+    // Funds held for other pending transfers are not available; a hold being
+    // captured is excluded so it does not count against itself
+    let held: BigDecimal = sqlx::query(
+        r#"
+        SELECT COALESCE(SUM(amount), 0) AS held FROM transactions
+        WHERE sender_id = $1 AND currency = $2 AND status = $3
+          AND hold_expires_at > NOW() AND id <> $4
+        "#
+    )
+    .bind(sender_id)
+    .bind(currency.code())
+    .bind(TransactionStatus::Pending as i32)
+    .bind(transaction_id)
+    .fetch_one(&mut *tx)
+    .await?
+    .try_get("held")?;
     
-    Ok(HttpResponse::Created().json(TransactionResponse::from(transaction)))
+    // Ensure sender has enough funds
+    if balance - held < *amount.amount() {
+        return Err(AppError::BadRequestError(INSUFFICIENT_FUNDS.to_string()));
+    }
+    
+    Ok((sender_account_id, recipient_account_id))
 }
 
 pub async fn get_transaction(
@@ -149,7 +821,7 @@ pub async fn get_transaction(
     let transaction_id = transaction_id.into_inner();
       let row = sqlx::query(
         r#"
-        SELECT id, sender_id, recipient_id, amount, currency, description, status, created_at, updated_at
+        SELECT id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
         FROM transactions
         WHERE id = $1 AND (sender_id = $2 OR recipient_id = $2)
         "#
@@ -159,24 +831,10 @@ pub async fn get_transaction(
     .fetch_optional(pool.get_ref())
     .await?
     .ok_or_else(|| AppError::NotFoundError("Transaction not found".to_string()))?;
-      let transaction = Transaction {
-        id: row.try_get("id")?,
-        sender_id: row.try_get("sender_id")?,
-        recipient_id: row.try_get("recipient_id")?,
-        amount: row.try_get("amount")?,
-        currency: row.try_get("currency")?,
-        description: row.try_get("description").ok(),
-        status: match row.try_get::<i32, _>("status")? {
-            0 => TransactionStatus::Pending,
-            1 => TransactionStatus::Completed,
-            2 => TransactionStatus::Failed,
-            _ => TransactionStatus::Pending, // Default case
-        },
-        created_at: row.try_get("created_at")?,
-        updated_at: row.try_get("updated_at")?,
-    };
     
-    Ok(HttpResponse::Ok().json(TransactionResponse::from(transaction)))
+    let transaction = transaction_from_row(&row)?;
+    
+    Ok(HttpResponse::Ok().json(TransactionResponse::try_from(transaction)?))
 }
 
 pub async fn list_transactions(
@@ -189,7 +847,7 @@ pub async fn list_transactions(
     let offset = query.offset.unwrap_or(0);
       let mut sql = String::from(
         r#"
-        SELECT id, sender_id, recipient_id, amount, currency, description, status, created_at, updated_at
+        SELECT id, sender_id, recipient_id, amount, currency, description, status, failure_reason, refund_of, refunded_amount, authorized_amount, hold_expires_at, fx_quote_id, fx_rate, fx_fee, converted_amount, converted_currency, created_at, updated_at
         FROM transactions
         WHERE (sender_id = $1 OR recipient_id = $1)
         "#
@@ -204,6 +862,8 @@ pub async fn list_transactions(
             "pending" => 0,
             "completed" => 1,
             "failed" => 2,
+            "voided" => 3,
+            "expired" => 4,
             _ => return Err(AppError::BadRequestError("Invalid status".to_string())),
         };
         sql.push_str(&format!("AND status = ${} ", param_index));
@@ -228,23 +888,8 @@ pub async fn list_transactions(
     
     // Map rows to Transaction objects
     let mut transactions = Vec::with_capacity(rows.len());
-    for row in rows {        let transaction = Transaction {
-            id: row.try_get("id")?,
-            sender_id: row.try_get("sender_id")?,
-            recipient_id: row.try_get("recipient_id")?,
-            amount: row.try_get("amount")?,
-            currency: row.try_get("currency")?,
-            description: row.try_get("description").ok(),
-            status: match row.try_get::<i32, _>("status")? {
-                0 => TransactionStatus::Pending,
-                1 => TransactionStatus::Completed,
-                2 => TransactionStatus::Failed,
-                _ => TransactionStatus::Pending,
-            },
-            created_at: row.try_get("created_at")?,
-            updated_at: row.try_get("updated_at")?,
-        };
-        transactions.push(transaction);
+    for row in rows {
+        transactions.push(transaction_from_row(&row)?);
     }
     
     // Count total transactions
@@ -261,6 +906,8 @@ pub async fn list_transactions(
             "pending" => 0,
             "completed" => 1,
             "failed" => 2,
+            "voided" => 3,
+            "expired" => 4,
             _ => return Err(AppError::BadRequestError("Invalid status".to_string())),
         };
         count_sql.push_str("AND status = $2");
@@ -274,8 +921,8 @@ pub async fn list_transactions(
             
         let transaction_responses = transactions
             .into_iter()
-            .map(TransactionResponse::from)
-            .collect::<Vec<_>>();
+            .map(TransactionResponse::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
         
         Ok(HttpResponse::Ok().json(TransactionListResponse {
             transactions: transaction_responses,
@@ -292,8 +939,8 @@ pub async fn list_transactions(
             
         let transaction_responses = transactions
             .into_iter()
-            .map(TransactionResponse::from)
-            .collect::<Vec<_>>();
+            .map(TransactionResponse::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
         
         Ok(HttpResponse::Ok().json(TransactionListResponse {
             transactions: transaction_responses,
@@ -310,3 +957,35 @@ pub struct ListTransactionsQuery {
     offset: Option<u32>,
     status: Option<String>,
 }
+
+// Map a transactions row, whose status is stored as an integer, to the model
+fn transaction_from_row(row: &PgRow) -> Result<Transaction, AppError> {
+    Ok(Transaction {
+        id: row.try_get("id")?,
+        sender_id: row.try_get("sender_id")?,
+        recipient_id: row.try_get("recipient_id")?,
+        amount: row.try_get("amount")?,
+        currency: row.try_get("currency")?,
+        description: row.try_get("description").ok(),
+        status: match row.try_get::<i32, _>("status")? {
+            0 => TransactionStatus::Pending,
+            1 => TransactionStatus::Completed,
+            2 => TransactionStatus::Failed,
+            3 => TransactionStatus::Voided,
+            4 => TransactionStatus::Expired,
+            _ => TransactionStatus::Pending, // Default case
+        },
+        failure_reason: row.try_get("failure_reason")?,
+        refund_of: row.try_get("refund_of")?,
+        refunded_amount: row.try_get("refunded_amount")?,
+        authorized_amount: row.try_get("authorized_amount")?,
+        hold_expires_at: row.try_get("hold_expires_at")?,
+        fx_quote_id: row.try_get("fx_quote_id")?,
+        fx_rate: row.try_get("fx_rate")?,
+        fx_fee: row.try_get("fx_fee")?,
+        converted_amount: row.try_get("converted_amount")?,
+        converted_currency: row.try_get("converted_currency")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+    })
+}
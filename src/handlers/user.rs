@@ -6,12 +6,19 @@ use serde::{Serialize, Deserialize};
 use bigdecimal::BigDecimal;
 use std::str::FromStr;
 
-use crate::utils::auth::{hash_password, verify_password, generate_jwt};
-use crate::models::{AppError, User, UserResponse, LoginUserRequest, RegisterUserRequest, TokenResponse};
+use crate::config::{LoginConfig, MailConfig, MfaConfig, TokenConfig};
+use crate::handlers::{email, lockout, mfa};
+use crate::handlers::session::{start_session, ClientInfo};
+use crate::handlers::token::issue_tokens;
+use crate::utils::auth::{hash_password, verify_dummy_password, verify_password};
+use crate::utils::keys::KeyStore;
+use crate::models::{AppError, Role, User, UserResponse, LoginUserRequest, RegisterUserRequest};
 
-/// Register a new user
+/// Register a new user and email them a link to verify their address
 pub async fn register(
     pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mail: web::Data<MailConfig>,
     user_data: web::Json<RegisterUserRequest>,
 ) -> Result<impl Responder, AppError> {
     // Validate request data
@@ -45,7 +52,7 @@ pub async fn register(
         r#"
         INSERT INTO users (username, email, password_hash)
         VALUES ($1, $2, $3)
-        RETURNING id, username, email, password_hash, created_at, updated_at
+        RETURNING id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
         "#
     )
     .bind(&user_data.username)
@@ -69,47 +76,75 @@ pub async fn register(
     .execute(pool.get_ref())
     .await?;
     
+    email::try_send_verification(pool.get_ref(), config.get_ref(), mail.get_ref(), &user, &user.email).await;
+    
     // Convert to user response
     let user_response = UserResponse::from(user);
     
     Ok(HttpResponse::Created().json(user_response))
 }
 
-/// Login user and return JWT token
+/// Login user and return JWT token, or an MFA challenge if the user has
+/// enabled two-factor authentication.
+///
+/// Failed logins are counted per username, known or not, and repeated ones
+/// are slowed down and then locked out as set in `LoginConfig`.
 pub async fn login(
     pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mfa_config: web::Data<MfaConfig>,
+    login_config: web::Data<LoginConfig>,
+    keys: web::Data<KeyStore>,
+    client: ClientInfo,
     login_data: web::Json<LoginUserRequest>,
 ) -> Result<impl Responder, AppError> {
     // Validate request data
     login_data.validate()?;
     
+    let attempt = lockout::claim_login_attempt(pool.get_ref(), login_config.get_ref(), &login_data.username).await?;
+    
     // Find user by username
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, email, password_hash, created_at, updated_at
+        SELECT id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
         FROM users
         WHERE username = $1
         "#
     )
     .bind(&login_data.username)
     .fetch_optional(pool.get_ref())
-    .await?
-    .ok_or_else(|| AppError::AuthenticationError("Invalid username or password".to_string()))?;
+    .await?;
     
-    // Verify password
-    if !verify_password(&login_data.password, &user.password_hash)? {
-        return Err(AppError::AuthenticationError("Invalid username or password".to_string()));
-    }
+    // Verify password, taking as long for unknown users as for real ones
+    let user = match user {
+        Some(user) if verify_password(&login_data.password, &user.password_hash)? => user,
+        user => {
+            if user.is_none() {
+                verify_dummy_password(&login_data.password)?;
+            }
+            lockout::record_failed_login(pool.get_ref(), &login_data.username, user.map(|user| user.id), &attempt)
+                .await?;
+            return Err(AppError::AuthenticationError("Invalid username or password".to_string()));
+        }
+    };
+    
+    lockout::clear_failed_logins(pool.get_ref(), &login_data.username).await?;
     
-    // Read JWT secret
-    let jwt_secret = std::fs::read_to_string("jwt_secret.txt")
-        .map_err(|e| AppError::InternalServerError(format!("Failed to read JWT secret: {}", e)))?;
+    // The tokens are only issued once the second factor is checked
+    if mfa::is_enabled(pool.get_ref(), user.id).await? {
+        let challenge = mfa::start_challenge(pool.get_ref(), mfa_config.get_ref(), user.id).await?;
+        return Ok(HttpResponse::Ok().json(challenge));
+    }
     
-    // Generate JWT token
-    let token = generate_jwt(user.id, &jwt_secret)?;
+    // Start a session, whose id is the family of the refresh tokens issued for it
+    let role: Role = user.role.parse()?;
+    let mut tx = pool.begin().await?;
+    let session_id = start_session(&mut tx, user.id, &client).await?;
+    let tokens = issue_tokens(&mut tx, config.get_ref(), keys.get_ref(), user.id, role, session_id).await?;
+    tx.commit().await?;
     
-    // Return token
-    Ok(HttpResponse::Ok().json(TokenResponse::new(token)))
+    // Return tokens
+    Ok(HttpResponse::Ok().json(tokens))
 }
 
 /// Get user profile
@@ -122,7 +157,7 @@ pub async fn get_profile(
     // Get user data
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, email, password_hash, created_at, updated_at
+        SELECT id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
         FROM users
         WHERE id = $1
         "#
@@ -136,10 +171,15 @@ pub async fn get_profile(
     Ok(HttpResponse::Ok().json(UserResponse::from(user)))
 }
 
-/// Update user profile
+/// Update user profile.
+///
+/// A new email address is kept as pending and only replaces the current one
+/// once it has been confirmed through the link emailed to it.
 pub async fn update_profile(
     user_id: web::ReqData<Uuid>,
     pool: web::Data<PgPool>,
+    config: web::Data<TokenConfig>,
+    mail: web::Data<MailConfig>,
     update_data: web::Json<UpdateProfileRequest>,
 ) -> Result<impl Responder, AppError> {
     // Validate request data
@@ -161,20 +201,30 @@ pub async fn update_profile(
             return Err(AppError::ConflictError("Email already registered by another user".to_string()));
         }
         
-        // Update email
-        sqlx::query(
-            "UPDATE users SET email = $1, updated_at = NOW() WHERE id = $2"
+        // Hold the new address until it is confirmed; changing back to the
+        // current address cancels a pending change
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET pending_email = NULLIF($1, email), updated_at = NOW()
+            WHERE id = $2
+            RETURNING id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
+            "#
         )
         .bind(email)
         .bind(user_id)
-        .execute(pool.get_ref())
+        .fetch_one(pool.get_ref())
         .await?;
+        
+        if let Some(ref pending_email) = user.pending_email {
+            email::try_send_verification(pool.get_ref(), config.get_ref(), mail.get_ref(), &user, pending_email).await;
+        }
     }
     
     // Get updated user profile
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, email, password_hash, created_at, updated_at
+        SELECT id, username, email, email_verified_at, pending_email, password_hash, role, created_at, updated_at
         FROM users
         WHERE id = $1
         "#
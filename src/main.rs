@@ -1,12 +1,21 @@
 use std::env;
 use std::io::Result;
-use std::fs;
 
 use actix_cors::Cors;
 use actix_web::{middleware as actix_middleware, web, App, HttpServer};
 use dotenv::dotenv;
-use log::info;
+use log::{error, info};
 use sqlx::postgres::PgPoolOptions;
+use std::time::Duration;
+
+use dodo_payments::config::{Config, LoginConfig, ScheduleConfig, TransferConfig};
+use dodo_payments::handlers::lockout::purge_stale_login_attempts;
+use dodo_payments::handlers::payment_request::expire_payment_requests;
+use dodo_payments::handlers::scheduled_transfer::run_scheduler;
+use dodo_payments::handlers::token::purge_expired_tokens;
+use dodo_payments::handlers::transaction::expire_holds;
+use dodo_payments::middleware::rate_limit_backend::RateLimitBackend;
+use dodo_payments::utils::keys::KeyStore;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
@@ -30,20 +39,72 @@ async fn main() -> Result<()> {
         .connect_lazy(&database_url)
         .expect("Failed to create database connection pool");
     
-    // Read JWT secret from file or environment variable
-    let jwt_secret = env::var("JWT_SECRET").ok().unwrap_or_else(|| {
-        fs::read_to_string("jwt_secret.txt")
-            .unwrap_or_else(|_| {
-                info!("JWT secret not found, using default value");
-                "dodo_payments_default_secret".to_string()
-            })
+    // Load the JWT signing keys once; every worker shares the same store
+    let config = Config::from_env();
+    let key_store = web::Data::new(
+        KeyStore::new(&config.jwt_keys).expect("Failed to load JWT signing keys")
+    );
+    info!("JWT signing key {} loaded", key_store.active_kid().unwrap_or_default());
+    
+    // Reload the keys on SIGHUP so secrets can be rotated without a restart
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        
+        let mut hangup = signal(SignalKind::hangup())?;
+        let reload_keys = key_store.clone();
+        actix_web::rt::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match reload_keys.reload().and_then(|_| reload_keys.active_kid()) {
+                    Ok(kid) => info!("Reloaded JWT keys; signing with {}", kid),
+                    Err(err) => error!("Failed to reload JWT keys, keeping the current ones: {}", err),
+                }
+            }
+        });
+    }
+    
+    // One set of rate limit counters for every worker
+    let rate_limit_backend = RateLimitBackend::from_env(&pool);
+    
+    // Periodically mark uncaptured holds and payment requests past their TTL
+    // as expired and drop refresh and revoked tokens that can no longer be
+    // used, along with failed login counts and rate limit counters that have
+    // run out
+    let sweep_interval = TransferConfig::from_env().hold_sweep_interval.max(Duration::from_secs(1));
+    let sweep_pool = pool.clone();
+    let login_config = LoginConfig::from_env();
+    let sweep_rate_limits = rate_limit_backend.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(sweep_interval);
+        loop {
+            interval.tick().await;
+            match expire_holds(&sweep_pool).await {
+                Ok(0) => {}
+                Ok(expired) => info!("Expired {} uncaptured holds", expired),
+                Err(err) => error!("Failed to expire holds: {}", err),
+            }
+            match expire_payment_requests(&sweep_pool).await {
+                Ok(0) => {}
+                Ok(expired) => info!("Expired {} payment requests", expired),
+                Err(err) => error!("Failed to expire payment requests: {}", err),
+            }
+            if let Err(err) = purge_expired_tokens(&sweep_pool).await {
+                error!("Failed to purge expired tokens: {}", err);
+            }
+            if let Err(err) = purge_stale_login_attempts(&sweep_pool, &login_config).await {
+                error!("Failed to purge stale login attempts: {}", err);
+            }
+            if let Err(err) = sweep_rate_limits.purge_expired().await {
+                error!("Failed to purge expired rate limit counters: {}", err);
+            }
+        }
     });
     
-    info!("JWT secret loaded (length: {})", jwt_secret.len());
+    // Make scheduled and recurring transfers as they fall due
+    actix_web::rt::spawn(run_scheduler(pool.clone(), TransferConfig::from_env(), ScheduleConfig::from_env()));
     
     // Create data that will be shared across requests
     let pool_data = web::Data::new(pool);
-    let jwt_secret_data = web::Data::new(jwt_secret);
       // Run the server
     HttpServer::new(move || {
         // Set up CORS
@@ -57,8 +118,8 @@ async fn main() -> Result<()> {
     .wrap(cors)
     .wrap(actix_middleware::Logger::default())
     .app_data(pool_data.clone())
-    .app_data(jwt_secret_data.clone())
-    .configure(dodo_payments::handlers::config_routes)
+    .app_data(key_store.clone())
+    .configure(|cfg| dodo_payments::handlers::configure_routes(cfg, rate_limit_backend.clone()))
 
     })
     .bind(server_addr)?
@@ -0,0 +1,154 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error, HttpMessage,
+};
+use chrono::{DateTime, Utc};
+use futures::future::{ready, Ready};
+use sqlx::{PgPool, Row};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::middleware::rate_limit::IpNetworks;
+use crate::models::{ApiKeyIdentity, ApiScope, AppError, Role};
+use crate::utils::auth::hash_token;
+
+/// Lets `Auth` accept API keys on the routes it wraps, as long as the key
+/// has the scope for the request: `read` for GET and HEAD requests, `write`
+/// for anything else. Requests that need a missing `write` scope cannot be
+/// made with an API key at all.
+///
+/// Routes without it only take access tokens. It must be wrapped outside
+/// `Auth`, so the scope is known by the time the key is checked.
+#[derive(Clone, Copy)]
+pub struct AcceptApiKeys {
+    read: ApiScope,
+    write: Option<ApiScope>,
+}
+
+impl AcceptApiKeys {
+    pub fn new(read: ApiScope, write: ApiScope) -> Self {
+        Self { read, write: Some(write) }
+    }
+
+    pub fn read_only(read: ApiScope) -> Self {
+        Self { read, write: None }
+    }
+}
+
+/// The scope an API key needs for the current request, or `None` if API
+/// keys cannot make it. Placed in the request extensions by `AcceptApiKeys`.
+#[derive(Debug, Clone, Copy)]
+pub struct RequiredApiScope(pub Option<ApiScope>);
+
+impl<S, B> Transform<S, ServiceRequest> for AcceptApiKeys
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AcceptApiKeysMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AcceptApiKeysMiddleware {
+            service,
+            scopes: *self,
+        }))
+    }
+}
+
+pub struct AcceptApiKeysMiddleware<S> {
+    service: S,
+    scopes: AcceptApiKeys,
+}
+
+impl<S, B> Service<ServiceRequest> for AcceptApiKeysMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let scope = match *req.method() {
+            Method::GET | Method::HEAD => Some(self.scopes.read),
+            _ => self.scopes.write,
+        };
+        req.extensions_mut().insert(RequiredApiScope(scope));
+
+        self.service.call(req)
+    }
+}
+
+/// Look up an API key, check it is live and allowed from `client_ip`, and
+/// record that it was used. Returns the owner, their current role and the
+/// key's scopes.
+pub async fn authenticate_api_key(
+    pool: &PgPool,
+    key: &str,
+    client_ip: Option<IpAddr>,
+) -> Result<(Uuid, Role, ApiKeyIdentity), AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT k.id, k.user_id, k.scopes, k.allowed_ips, k.expires_at, k.revoked_at, u.role
+        FROM api_keys k
+        JOIN users u ON u.id = k.user_id
+        WHERE k.key_hash = $1
+        "#
+    )
+    .bind(hash_token(key))
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::AuthenticationError("Invalid API key".to_string()))?;
+
+    let expires_at: Option<DateTime<Utc>> = row.try_get("expires_at")?;
+    let revoked_at: Option<DateTime<Utc>> = row.try_get("revoked_at")?;
+    if revoked_at.is_some() {
+        return Err(AppError::AuthenticationError("API key has been revoked".to_string()));
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        return Err(AppError::AuthenticationError("API key has expired".to_string()));
+    }
+
+    let allowed_ips: Option<Vec<String>> = row.try_get("allowed_ips")?;
+    if let Some(allowed_ips) = allowed_ips {
+        let networks = IpNetworks::parse(allowed_ips.iter().map(String::as_str))
+            .map_err(|entry| AppError::InternalServerError(format!("Invalid allowed IP on API key: {}", entry)))?;
+        if !client_ip.is_some_and(|ip| networks.contains(ip)) {
+            return Err(AppError::ForbiddenError("API key cannot be used from this address".to_string()));
+        }
+    }
+
+    let key_id: Uuid = row.try_get("id")?;
+    let scopes: Vec<String> = row.try_get("scopes")?;
+    let scopes = scopes.iter().map(|scope| scope.parse()).collect::<Result<Vec<ApiScope>, _>>()?;
+    let role: String = row.try_get("role")?;
+
+    sqlx::query("UPDATE api_keys SET last_used_at = NOW() WHERE id = $1")
+        .bind(key_id)
+        .execute(pool)
+        .await?;
+
+    Ok((row.try_get("user_id")?, role.parse()?, ApiKeyIdentity { key_id, scopes }))
+}
+
+/// Refuse a request made with an API key unless the route accepts API keys
+/// and the key has the scope the request needs
+pub fn check_api_scope(identity: &ApiKeyIdentity, required: Option<RequiredApiScope>) -> Result<(), AppError> {
+    match required.and_then(|RequiredApiScope(scope)| scope) {
+        None => Err(AppError::ForbiddenError("API keys cannot be used for this request".to_string())),
+        Some(scope) if !identity.has_scope(scope) => {
+            Err(AppError::ForbiddenError(format!("API key is missing the '{}' scope", scope)))
+        }
+        Some(_) => Ok(()),
+    }
+}
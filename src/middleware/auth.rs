@@ -1,13 +1,13 @@
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
     http::header::HeaderValue,
-    Error, HttpMessage,
+    web, Error, HttpMessage,
 };
 use futures::future::{ready, LocalBoxFuture, Ready};
 use std::rc::Rc;
-use std::fs;
 
 use crate::utils::auth::validate_jwt;
+use crate::utils::keys::KeyStore;
 
 pub struct Auth;
 
@@ -49,18 +49,17 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = Rc::clone(&self.service);
         
-        // Read JWT secret from file directly instead of app data
-        let jwt_secret_result = fs::read_to_string("jwt_secret.txt");
+        // Keys are loaded once at startup and shared as app data
+        let keys = req.app_data::<web::Data<KeyStore>>().cloned();
         
         // Get authorization header
         let auth_header = req.headers().get("Authorization").cloned();
         
         Box::pin(async move {
-            // Handle JWT secret read result
-            let jwt_secret = match jwt_secret_result {
-                Ok(secret) => secret,
-                Err(_) => {
-                    return Err(actix_web::error::ErrorInternalServerError("Failed to read JWT secret"));
+            let keys = match keys {
+                Some(keys) => keys,
+                None => {
+                    return Err(actix_web::error::ErrorInternalServerError("JWT keys not configured"));
                 }
             };
             
@@ -73,7 +72,7 @@ where
                 }
             };
             
-            match validate_jwt(&token, &jwt_secret) {
+            match validate_jwt(&token, &keys) {
                 Ok(user_id) => {
                     // Add user_id to request extensions
                     req.extensions_mut().insert(user_id);
@@ -90,10 +89,6 @@ where
 fn extract_token_from_header(header: Option<&HeaderValue>) -> Option<String> {
     header.and_then(|h| {
         let header_str = h.to_str().ok()?;
-        if header_str.starts_with("Bearer ") {
-            Some(header_str[7..].to_string())
-        } else {
-            None
-        }
+        header_str.strip_prefix("Bearer ").map(|token| token.to_string())
     })
 }
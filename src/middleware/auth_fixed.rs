@@ -4,12 +4,18 @@ use actix_web::{
     web, Error, HttpMessage,
 };
 use futures::future::{ready, LocalBoxFuture, Ready};
+use sqlx::{PgPool, Row};
 use std::rc::Rc;
-use std::fs;
 use uuid::Uuid;
 
-use crate::utils::auth::validate_jwt;
+use crate::middleware::api_key::{authenticate_api_key, check_api_scope, RequiredApiScope};
+use crate::middleware::rate_limit::{TrustedProxies, API_KEY_HEADER};
+use crate::models::AppError;
+use crate::utils::auth::{decode_jwt, Claims};
+use crate::utils::keys::KeyStore;
 
+/// Authenticates a request by its bearer access token, or by an API key in
+/// `X-API-Key` on routes wrapped in `AcceptApiKeys`
 pub struct Auth;
 
 impl<S, B> Transform<S, ServiceRequest> for Auth
@@ -50,33 +56,45 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = Rc::clone(&self.service);
         
-        // Read JWT secret from file directly
-        let jwt_secret_result = fs::read_to_string("jwt_secret.txt");
+        // Keys are loaded once at startup and shared as app data
+        let keys = req.app_data::<web::Data<KeyStore>>().cloned();
         
         // Get authorization header
         let auth_header = req.headers().get("Authorization").cloned();
         
         Box::pin(async move {
-            // Handle JWT secret read result
-            let jwt_secret = match jwt_secret_result {
-                Ok(secret) => secret.trim().to_string(), // Trim any whitespace
-                Err(_) => {
-                    return Err(actix_web::error::ErrorInternalServerError("Failed to read JWT secret"));
+            let keys = match keys {
+                Some(keys) => keys,
+                None => {
+                    return Err(actix_web::error::ErrorInternalServerError("JWT keys not configured"));
                 }
             };
             
             let token = match extract_token_from_header(auth_header.as_ref()) {
                 Some(t) => t,
                 None => {
+                    // Other services may send an API key instead of a bearer token
+                    let api_key = req.headers().get(API_KEY_HEADER).and_then(|value| value.to_str().ok());
+                    if let Some(api_key) = api_key.map(str::to_string) {
+                        authenticate_with_api_key(&req, &api_key).await?;
+                        return service.call(req).await;
+                    }
+                    
                     return Err(actix_web::error::ErrorUnauthorized(
                         "Authorization header missing or invalid"
                     ));
                 }
             };
             
-            // Validate and extract user ID from token
-            match validate_jwt(&token, &jwt_secret) {
-                Ok(user_id) => {
+            // Validate and extract user ID and role from token
+            match decode_jwt(&token, &keys) {
+                Ok((user_id, claims)) => {
+                    let pool = database_pool(&req)?;
+                    if is_revoked(pool.get_ref(), &claims).await? {
+                        return Err(actix_web::error::ErrorUnauthorized("Token has been revoked"));
+                    }
+                    touch_session(pool.get_ref(), &claims).await?;
+                    
                     // Insert user_id as an app_data for handlers
                     req.app_data::<web::Data<Uuid>>()
                         .map(|current| {
@@ -85,6 +103,8 @@ where
                     
                     // Add user_id to request extensions
                     req.extensions_mut().insert(user_id);
+                    req.extensions_mut().insert(claims.role);
+                    req.extensions_mut().insert(claims);
                     
                     service.call(req).await
                 },
@@ -96,13 +116,67 @@ where
     }
 }
 
+fn database_pool(req: &ServiceRequest) -> Result<web::Data<PgPool>, AppError> {
+    req.app_data::<web::Data<PgPool>>()
+        .cloned()
+        .ok_or_else(|| AppError::InternalServerError("Database pool not configured".to_string()))
+}
+
+// Check an API key and the scope the route needs, then put its owner in the
+// request just as an access token would, along with the key's identity.
+// There are no `Claims`, so routes that need them cannot take API keys.
+async fn authenticate_with_api_key(req: &ServiceRequest, api_key: &str) -> Result<(), AppError> {
+    let pool = database_pool(req)?;
+    let client_ip = match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.request_client_ip(req),
+        None => req.peer_addr().map(|addr| addr.ip()),
+    };
+    
+    let (user_id, role, identity) = authenticate_api_key(pool.get_ref(), api_key, client_ip).await?;
+    check_api_scope(&identity, req.extensions().get::<RequiredApiScope>().copied())?;
+    
+    req.extensions_mut().insert(user_id);
+    req.extensions_mut().insert(role);
+    req.extensions_mut().insert(identity);
+    
+    Ok(())
+}
+
+// A token is revoked by logout of its own jti, or with its whole session
+async fn is_revoked(pool: &PgPool, claims: &Claims) -> Result<bool, AppError> {
+    let revoked: bool = sqlx::query(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)
+            OR EXISTS (SELECT 1 FROM refresh_tokens WHERE family_id = $2 AND revoked_at IS NOT NULL)
+            OR EXISTS (SELECT 1 FROM sessions WHERE id = $2 AND revoked_at IS NOT NULL)
+            AS revoked
+        "#
+    )
+    .bind(claims.token_id()?)
+    .bind(claims.family_id()?)
+    .fetch_one(pool)
+    .await?
+    .try_get("revoked")?;
+    
+    Ok(revoked)
+}
+
+// Note that the session is in use, at most once a minute so requests do not
+// all write to it
+async fn touch_session(pool: &PgPool, claims: &Claims) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE sessions SET last_seen_at = NOW() WHERE id = $1 AND last_seen_at < NOW() - INTERVAL '1 minute'"
+    )
+    .bind(claims.family_id()?)
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
+
 fn extract_token_from_header(header: Option<&HeaderValue>) -> Option<String> {
     header.and_then(|h| {
         let header_str = h.to_str().ok()?;
-        if header_str.starts_with("Bearer ") {
-            Some(header_str[7..].to_string())
-        } else {
-            None
-        }
+        header_str.strip_prefix("Bearer ").map(|token| token.to_string())
     })
 }
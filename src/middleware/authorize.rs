@@ -0,0 +1,79 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::rc::Rc;
+
+use crate::models::{AppError, Permission, Role};
+
+/// Only lets a request through if the caller's role grants `permission`.
+///
+/// Reads the `Role` that `Auth` places in the request extensions, so this must
+/// be wrapped inside `Auth`.
+#[derive(Clone, Copy)]
+pub struct Authorize {
+    permission: Permission,
+}
+
+impl Authorize {
+    pub fn new(permission: Permission) -> Self {
+        Self { permission }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authorize
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AuthorizeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthorizeMiddleware {
+            service: Rc::new(service),
+            permission: self.permission,
+        }))
+    }
+}
+
+pub struct AuthorizeMiddleware<S> {
+    service: Rc<S>,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthorizeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let role = req.extensions().get::<Role>().copied();
+        let permission = self.permission;
+
+        Box::pin(async move {
+            match role {
+                None => Err(AppError::AuthenticationError("Authentication required".to_string()).into()),
+                Some(role) if !role.has_permission(permission) => Err(AppError::ForbiddenError(format!(
+                    "Role '{}' is not allowed to perform this action",
+                    role
+                ))
+                .into()),
+                Some(_) => service.call(req).await,
+            }
+        })
+    }
+}
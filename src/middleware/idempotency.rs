@@ -0,0 +1,288 @@
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method, StatusCode},
+    web, Error, HttpMessage, HttpResponse, ResponseError,
+};
+use chrono::Utc;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row};
+use std::rc::Rc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::models::AppError;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const IDEMPOTENCY_REPLAYED_HEADER: &str = "Idempotency-Replayed";
+
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Placed in the request extensions by a handler once it has made changes a
+/// retry must not repeat. A server error after that point is stored and
+/// replayed like any other response instead of releasing the key.
+pub struct KeepIdempotencyKey;
+
+/// Stores the first response to a mutating request sent with an `Idempotency-Key`
+/// header and replays it for retries of the same request within `ttl`.
+///
+/// Keys are scoped to the user id placed in the request extensions by `Auth`, so
+/// this must be wrapped inside `Auth` on authenticated scopes. Unauthenticated
+/// callers share the nil user id.
+#[derive(Clone)]
+pub struct Idempotency {
+    ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            ttl: self.ttl,
+        }))
+    }
+}
+
+pub struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    ttl: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let ttl = self.ttl;
+
+        Box::pin(async move {
+            // Safe methods and requests without a key pass straight through
+            let is_safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+            let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+                Some(value) if !is_safe => value
+                    .to_str()
+                    .ok()
+                    .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH)
+                    .map(|key| key.to_string()),
+                _ => return service.call(req).await.map(|res| res.map_into_boxed_body()),
+            };
+            let key = match key {
+                Some(key) => key,
+                None => {
+                    let err = AppError::BadRequestError(format!(
+                        "{} must be between 1 and {} visible characters",
+                        IDEMPOTENCY_KEY_HEADER, MAX_KEY_LENGTH
+                    ));
+                    return Ok(req.into_response(err.error_response()));
+                }
+            };
+
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .cloned()
+                .ok_or_else(|| AppError::InternalServerError("Database pool not configured".to_string()))?;
+            let user_id = req.extensions().get::<Uuid>().copied().unwrap_or_else(Uuid::nil);
+
+            // Buffer the body so it can be fingerprinted and handed on to the handler
+            let request_body = req.extract::<web::Bytes>().await?;
+            let request_hash = fingerprint(req.method(), req.path(), &request_body);
+            req.set_payload(Payload::from(request_body));
+
+            if !claim_key(pool.get_ref(), user_id, &key, &request_hash, ttl).await? {
+                let replay = stored_response(pool.get_ref(), user_id, &key, &request_hash)
+                    .await
+                    .unwrap_or_else(|err| err.error_response());
+                return Ok(req.into_response(replay));
+            }
+
+            let res = match service.call(req).await {
+                Ok(res) => res,
+                Err(err) => {
+                    release_key(pool.get_ref(), user_id, &key).await?;
+                    return Err(err);
+                }
+            };
+
+            let (req, res) = res.into_parts();
+            let status = res.status();
+            let content_type = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+            let (res, response_body) = res.into_parts();
+            let response_body = body::to_bytes(response_body)
+                .await
+                .map_err(|_| AppError::InternalServerError("Failed to read response body".to_string()))?;
+
+            // Server errors are not stored so the client can retry with the same key,
+            // unless the handler had already made changes, nor are auth failures
+            // such as a missing step-up code, where nothing ran
+            let committed = req.extensions().get::<KeepIdempotencyKey>().is_some();
+            if (status.is_server_error() && !committed)
+                || status == StatusCode::UNAUTHORIZED
+                || status == StatusCode::FORBIDDEN
+            {
+                release_key(pool.get_ref(), user_id, &key).await?;
+            } else {
+                sqlx::query(
+                    r#"
+                    UPDATE idempotency_keys
+                    SET response_status = $1, response_content_type = $2, response_body = $3
+                    WHERE user_id = $4 AND idempotency_key = $5
+                    "#
+                )
+                .bind(status.as_u16() as i32)
+                .bind(&content_type)
+                .bind(response_body.as_ref())
+                .bind(user_id)
+                .bind(&key)
+                .execute(pool.get_ref())
+                .await
+                .map_err(AppError::from)?;
+            }
+
+            let res = res.set_body(BoxBody::new(response_body));
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
+
+fn fingerprint(method: &Method, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+// Insert a placeholder row for the key, returning false if it is already taken
+async fn claim_key(
+    pool: &PgPool,
+    user_id: Uuid,
+    key: &str,
+    request_hash: &str,
+    ttl: Duration,
+) -> Result<bool, AppError> {
+    let ttl = chrono::Duration::from_std(ttl)
+        .map_err(|_| AppError::InternalServerError("Invalid idempotency key TTL".to_string()))?;
+
+    // Expired keys can be reused
+    sqlx::query(
+        r#"
+        DELETE FROM idempotency_keys
+        WHERE user_id = $1 AND idempotency_key = $2 AND created_at < $3
+        "#
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(Utc::now() - ttl)
+    .execute(pool)
+    .await?;
+
+    let claimed = sqlx::query(
+        r#"
+        INSERT INTO idempotency_keys (user_id, idempotency_key, request_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, idempotency_key) DO NOTHING
+        RETURNING user_id
+        "#
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(request_hash)
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+
+    Ok(claimed)
+}
+
+// Build the replay of a previously stored response for a key that is already taken
+async fn stored_response(
+    pool: &PgPool,
+    user_id: Uuid,
+    key: &str,
+    request_hash: &str,
+) -> Result<HttpResponse, AppError> {
+    let row = sqlx::query(
+        r#"
+        SELECT request_hash, response_status, response_content_type, response_body
+        FROM idempotency_keys
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#
+    )
+    .bind(user_id)
+    .bind(key)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| AppError::ConflictError("Idempotency key was released, retry the request".to_string()))?;
+
+    let stored_hash: String = row.try_get("request_hash")?;
+    if stored_hash != request_hash {
+        return Err(AppError::UnprocessableEntityError(format!(
+            "{} was already used with a different request",
+            IDEMPOTENCY_KEY_HEADER
+        )));
+    }
+
+    let status: Option<i32> = row.try_get("response_status")?;
+    let status = match status {
+        Some(status) => StatusCode::from_u16(status as u16)
+            .map_err(|_| AppError::InternalServerError("Invalid stored response status".to_string()))?,
+        None => {
+            return Err(AppError::ConflictError(
+                "A request with this idempotency key is still being processed".to_string(),
+            ))
+        }
+    };
+    let content_type: Option<String> = row.try_get("response_content_type")?;
+    let response_body: Vec<u8> = row.try_get::<Option<Vec<u8>>, _>("response_body")?.unwrap_or_default();
+
+    let mut response = HttpResponse::build(status);
+    response.insert_header((IDEMPOTENCY_REPLAYED_HEADER, "true"));
+    if let Some(content_type) = content_type {
+        response.content_type(content_type);
+    }
+
+    Ok(response.body(response_body))
+}
+
+async fn release_key(pool: &PgPool, user_id: Uuid, key: &str) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1 AND idempotency_key = $2")
+        .bind(user_id)
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
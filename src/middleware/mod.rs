@@ -1,7 +1,15 @@
+pub mod api_key;
 pub mod auth;
 pub mod auth_fixed;
+pub mod authorize;
+pub mod idempotency;
+pub mod rate_limit;
+pub mod rate_limit_backend;
 
 // Use the fixed auth middleware by default
 pub use auth_fixed::Auth;
+pub use api_key::AcceptApiKeys;
+pub use authorize::Authorize;
+pub use idempotency::{Idempotency, KeepIdempotencyKey};
 
 // Other modules should import Auth directly from middleware module
@@ -0,0 +1,302 @@
+use actix_extensible_rate_limit::{
+    backend::{Backend, SimpleInput, SimpleOutput},
+    RateLimiter,
+};
+use actix_web::{dev::ServiceRequest, HttpMessage};
+use async_trait::async_trait;
+use futures::future::{ready, Ready};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::models::{ApiKeyIdentity, AppError};
+
+/// Header carrying an API key
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// What a rate limit policy counts requests by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The user id `Auth` puts in the request, or the client IP before login
+    User,
+    /// The API key `Auth` authenticated, then the user, then the client IP
+    ApiKey,
+    /// The client IP, looked through trusted proxies
+    Ip,
+}
+
+/// At most `requests` requests every `per_secs` seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Quota {
+    pub requests: u64,
+    pub per_secs: u64,
+}
+
+impl Quota {
+    pub fn new(requests: u64, per_secs: u64) -> Self {
+        Self { requests, per_secs }
+    }
+}
+
+/// The quotas of one route scope. `burst` caps short spikes while
+/// `sustained` caps the rate over a longer window; both must allow a request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RateLimitPolicy {
+    pub key: RateLimitKey,
+    #[serde(default)]
+    pub burst: Option<Quota>,
+    pub sustained: Quota,
+}
+
+impl RateLimitPolicy {
+    // Each quota is counted in its own window, labelled so they do not share a counter
+    fn windows(&self) -> Vec<(&'static str, Quota)> {
+        let mut windows = vec![("sustained", self.sustained)];
+        if let Some(burst) = self.burst {
+            windows.push(("burst", burst));
+        }
+        windows
+    }
+}
+
+/// A list of single addresses and CIDR ranges such as `10.0.0.0/8`
+#[derive(Debug, Clone, Default)]
+pub struct IpNetworks {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl IpNetworks {
+    /// Parse a list of addresses and CIDR ranges, or return the first entry
+    /// that is neither
+    pub fn parse<'a>(entries: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut networks = Vec::new();
+        for entry in entries.into_iter().map(str::trim).filter(|entry| !entry.is_empty()) {
+            networks.push(parse_network(entry).ok_or_else(|| entry.to_string())?);
+        }
+        Ok(Self { networks })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        self.networks.iter().any(|(network, prefix)| match (canonical(*network), ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` header is believed, as single
+/// addresses or CIDR ranges such as `10.0.0.0/8`
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: IpNetworks,
+}
+
+impl TrustedProxies {
+    /// Parse a comma-separated list of addresses and CIDR ranges
+    pub fn parse(list: &str) -> Result<Self, AppError> {
+        let networks = IpNetworks::parse(list.split(','))
+            .map_err(|entry| AppError::InternalServerError(format!("Invalid trusted proxy: {}", entry)))?;
+        Ok(Self { networks })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.contains(ip)
+    }
+
+    /// The address of the client behind any trusted proxies.
+    ///
+    /// `X-Forwarded-For` is only read when the peer is a trusted proxy, and
+    /// then from the right, stopping at the first address that is not
+    /// trusted, so a client cannot choose its own key by sending the header.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let mut client = peer.map(canonical)?;
+        if !self.contains(client) {
+            return Some(client);
+        }
+
+        let hops = forwarded_for.unwrap_or_default().split(',').map(str::trim).rev();
+        for hop in hops {
+            match IpAddr::from_str(hop) {
+                Ok(hop) => {
+                    client = canonical(hop);
+                    if !self.contains(client) {
+                        break;
+                    }
+                }
+                // Anything left of a malformed entry cannot be trusted
+                Err(_) => break,
+            }
+        }
+        Some(client)
+    }
+
+    /// The client address of `req`, from its peer and `X-Forwarded-For`
+    pub fn request_client_ip(&self, req: &ServiceRequest) -> Option<IpAddr> {
+        let forwarded_for = req.headers().get("X-Forwarded-For").and_then(|value| value.to_str().ok());
+        self.client_ip(req.peer_addr().map(|addr| addr.ip()), forwarded_for)
+    }
+}
+
+fn parse_network(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry, None),
+    };
+    let addr = IpAddr::from_str(addr).ok()?;
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max_prefix)?,
+        None => max_prefix,
+    };
+    Some((addr, prefix))
+}
+
+// Compare IPv4-mapped IPv6 addresses as the IPv4 address they carry
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// Input for `PolicyBackend`: one request checked against several windows
+#[derive(Debug, Clone)]
+pub struct PolicyInput {
+    pub windows: Vec<SimpleInput>,
+}
+
+/// Checks a request against every quota of a policy, using `B` to count each
+/// window. A request is allowed only if all windows allow it; the counts of
+/// the windows that did allow a denied request are rolled back.
+#[derive(Clone)]
+pub struct PolicyBackend<B> {
+    inner: B,
+}
+
+impl<B> PolicyBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait(?Send)]
+impl<B> Backend<PolicyInput> for PolicyBackend<B>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput> + 'static,
+{
+    type Output = SimpleOutput;
+    type RollbackToken = Vec<B::RollbackToken>;
+    type Error = B::Error;
+
+    async fn request(&self, input: PolicyInput) -> Result<(bool, SimpleOutput, Self::RollbackToken), B::Error> {
+        let mut allowed_tokens = Vec::new();
+        let mut tightest: Option<SimpleOutput> = None;
+        let mut denied: Option<SimpleOutput> = None;
+
+        for window in input.windows {
+            let (allow, output, token) = self.inner.request(window).await?;
+            if allow {
+                allowed_tokens.push(token);
+                if tightest.as_ref().is_none_or(|tightest| output.remaining < tightest.remaining) {
+                    tightest = Some(output);
+                }
+            } else if denied.as_ref().is_none_or(|denied| output.reset > denied.reset) {
+                // Report the window that stays closed longest
+                denied = Some(output);
+            }
+        }
+
+        if let Some(denied) = denied {
+            for token in allowed_tokens {
+                self.inner.rollback(token).await?;
+            }
+            return Ok((false, denied, Vec::new()));
+        }
+
+        let output = tightest.expect("a rate limit policy has at least one quota");
+        Ok((true, output, allowed_tokens))
+    }
+
+    async fn rollback(&self, tokens: Self::RollbackToken) -> Result<(), B::Error> {
+        for token in tokens {
+            self.inner.rollback(token).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Turns a request into the windows it is counted in
+pub trait PolicyInputFn: Fn(&ServiceRequest) -> Ready<Result<PolicyInput, actix_web::Error>> + 'static {}
+
+impl<F> PolicyInputFn for F where F: Fn(&ServiceRequest) -> Ready<Result<PolicyInput, actix_web::Error>> + 'static {}
+
+/// Build the rate limiter for the route scope `scope` from its policy,
+/// counting requests in `backend`.
+///
+/// Scopes keyed by `User` must wrap the limiter inside `Auth`, so the user
+/// id is in the request by the time it is read.
+pub fn policy_limiter<B>(
+    scope: &str,
+    policy: &RateLimitPolicy,
+    proxies: Arc<TrustedProxies>,
+    backend: B,
+) -> RateLimiter<PolicyBackend<B>, SimpleOutput, impl PolicyInputFn>
+where
+    B: Backend<SimpleInput, Output = SimpleOutput> + 'static,
+{
+    let scope = scope.to_string();
+    let policy = policy.clone();
+
+    let input = move |req: &ServiceRequest| {
+        let identity = request_identity(req, policy.key, &proxies);
+        let windows = policy
+            .windows()
+            .into_iter()
+            .map(|(label, quota)| SimpleInput {
+                interval: Duration::from_secs(quota.per_secs.max(1)),
+                max_requests: quota.requests,
+                key: format!("{}:{}:{}", scope, label, identity),
+            })
+            .collect();
+        ready(Ok(PolicyInput { windows }))
+    };
+
+    // A counter store that cannot be reached should not take the API down with it
+    RateLimiter::builder(PolicyBackend::new(backend), input)
+        .add_headers()
+        .fail_open(true)
+        .build()
+}
+
+// Who a request is counted against, falling back to the client IP
+fn request_identity(req: &ServiceRequest, key: RateLimitKey, proxies: &TrustedProxies) -> String {
+    if key == RateLimitKey::ApiKey {
+        // Only a key `Auth` has checked; a header it ignored must not pick the bucket
+        if let Some(identity) = req.extensions().get::<ApiKeyIdentity>() {
+            return format!("key:{}", identity.key_id);
+        }
+    }
+
+    if key != RateLimitKey::Ip {
+        if let Some(user_id) = req.extensions().get::<Uuid>() {
+            return format!("user:{}", user_id);
+        }
+    }
+
+    match proxies.request_client_ip(req) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:unknown".to_string(),
+    }
+}
@@ -0,0 +1,196 @@
+use actix_extensible_rate_limit::backend::{memory::InMemoryBackend, Backend, SimpleInput, SimpleOutput};
+use actix_web::rt::time::Instant;
+use async_trait::async_trait;
+use log::info;
+use sqlx::{PgPool, Row};
+use std::env;
+use std::time::Duration;
+
+use crate::models::AppError;
+
+/// A sliding window rate limit [Backend] that keeps its counters in Postgres,
+/// so every instance of the app sharing the database shares the limits.
+///
+/// Each key counts requests in fixed windows of the input's interval. A
+/// request is allowed while the current window's count plus the previous
+/// window's count, weighted by how much of it still overlaps the sliding
+/// window, stays within the limit. Counting is a single upsert timed by the
+/// database clock, so instances never race or disagree about the window.
+#[derive(Clone)]
+pub struct PgBackend {
+    pool: PgPool,
+}
+
+/// Identifies the counter a request was added to, so it can be taken back
+#[derive(Debug, Clone)]
+pub struct PgRollbackToken {
+    key: String,
+    window_index: i64,
+}
+
+impl PgBackend {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Delete counters whose windows have both passed. Returns the number of
+    /// rows removed.
+    pub async fn purge_expired(&self) -> Result<u64, AppError> {
+        let purged = sqlx::query("DELETE FROM rate_limit_counters WHERE expires_at <= NOW()")
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        Ok(purged)
+    }
+}
+
+#[async_trait(?Send)]
+impl Backend<SimpleInput> for PgBackend {
+    type Output = SimpleOutput;
+    type RollbackToken = PgRollbackToken;
+    type Error = AppError;
+
+    async fn request(&self, input: SimpleInput) -> Result<(bool, SimpleOutput, PgRollbackToken), AppError> {
+        let window_secs = input.interval.as_secs().max(1) as i64;
+        // Changing a window's length starts its counts again
+        let key = format!("{}:{}", input.key, window_secs);
+
+        let row = sqlx::query(
+            r#"
+            WITH clock AS (
+                SELECT EXTRACT(EPOCH FROM clock_timestamp())::DOUBLE PRECISION AS now_secs
+            ), slot AS (
+                SELECT now_secs, FLOOR(now_secs / $2)::BIGINT AS window_index FROM clock
+            )
+            INSERT INTO rate_limit_counters AS counter (key, window_index, count, previous_count, expires_at)
+            SELECT $1, window_index, 1, 0, to_timestamp(((window_index + 2) * $2)::DOUBLE PRECISION) FROM slot
+            ON CONFLICT (key) DO UPDATE
+            SET previous_count = CASE
+                    WHEN counter.window_index = EXCLUDED.window_index THEN counter.previous_count
+                    WHEN counter.window_index = EXCLUDED.window_index - 1 THEN counter.count
+                    ELSE 0
+                END,
+                count = CASE
+                    WHEN counter.window_index = EXCLUDED.window_index THEN counter.count + 1
+                    ELSE 1
+                END,
+                window_index = EXCLUDED.window_index,
+                expires_at = EXCLUDED.expires_at
+            RETURNING count, previous_count, window_index, (SELECT now_secs FROM slot) AS now_secs
+            "#
+        )
+        .bind(&key)
+        .bind(window_secs)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let count: i64 = row.try_get("count")?;
+        let previous_count: i64 = row.try_get("previous_count")?;
+        let window_index: i64 = row.try_get("window_index")?;
+        let now_secs: f64 = row.try_get("now_secs")?;
+
+        let window_start = (window_index * window_secs) as f64;
+        let elapsed = ((now_secs - window_start) / window_secs as f64).clamp(0.0, 1.0);
+        let estimate = previous_count as f64 * (1.0 - elapsed) + count as f64;
+
+        let limit = input.max_requests;
+        let allow = estimate <= limit as f64;
+        let remaining = (limit as f64 - estimate).floor().max(0.0) as u64;
+        let until_reset = (window_start + window_secs as f64 - now_secs).max(0.0);
+
+        let output = SimpleOutput {
+            limit,
+            remaining,
+            reset: Instant::now() + Duration::from_secs_f64(until_reset),
+        };
+        Ok((allow, output, PgRollbackToken { key, window_index }))
+    }
+
+    async fn rollback(&self, token: PgRollbackToken) -> Result<(), AppError> {
+        sqlx::query(
+            "UPDATE rate_limit_counters SET count = count - 1 WHERE key = $1 AND window_index = $2 AND count > 0"
+        )
+        .bind(&token.key)
+        .bind(token.window_index)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Where rate limit counters are kept, chosen with `RATE_LIMIT_BACKEND`
+#[derive(Clone)]
+pub enum RateLimitBackend {
+    /// In this process only; each instance of the app counts separately
+    Memory(InMemoryBackend),
+    /// In Postgres, shared by every instance using the database
+    Postgres(PgBackend),
+}
+
+#[derive(Debug, Clone)]
+pub enum RateLimitRollbackToken {
+    Memory(String),
+    Postgres(PgRollbackToken),
+}
+
+impl RateLimitBackend {
+    pub fn memory() -> Self {
+        RateLimitBackend::Memory(InMemoryBackend::builder().build())
+    }
+
+    /// `postgres` keeps counters in `pool`; anything else keeps them in memory
+    pub fn from_env(pool: &PgPool) -> Self {
+        let kind = env::var("RATE_LIMIT_BACKEND").unwrap_or_default().to_lowercase();
+        if kind == "postgres" {
+            info!("Keeping rate limit counters in Postgres");
+            RateLimitBackend::Postgres(PgBackend::new(pool.clone()))
+        } else {
+            info!("Keeping rate limit counters in memory");
+            Self::memory()
+        }
+    }
+
+    /// Delete counters that no longer matter, where they are not cleaned up
+    /// on their own. Returns the number removed.
+    pub async fn purge_expired(&self) -> Result<u64, AppError> {
+        match self {
+            RateLimitBackend::Memory(_) => Ok(0),
+            RateLimitBackend::Postgres(backend) => backend.purge_expired().await,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Backend<SimpleInput> for RateLimitBackend {
+    type Output = SimpleOutput;
+    type RollbackToken = RateLimitRollbackToken;
+    type Error = AppError;
+
+    async fn request(&self, input: SimpleInput) -> Result<(bool, SimpleOutput, RateLimitRollbackToken), AppError> {
+        match self {
+            RateLimitBackend::Memory(backend) => {
+                let Ok((allow, output, token)) = backend.request(input).await;
+                Ok((allow, output, RateLimitRollbackToken::Memory(token)))
+            }
+            RateLimitBackend::Postgres(backend) => {
+                let (allow, output, token) = backend.request(input).await?;
+                Ok((allow, output, RateLimitRollbackToken::Postgres(token)))
+            }
+        }
+    }
+
+    async fn rollback(&self, token: RateLimitRollbackToken) -> Result<(), AppError> {
+        match (self, token) {
+            (RateLimitBackend::Memory(backend), RateLimitRollbackToken::Memory(token)) => {
+                let Ok(()) = backend.rollback(token).await;
+                Ok(())
+            }
+            (RateLimitBackend::Postgres(backend), RateLimitRollbackToken::Postgres(token)) => {
+                backend.rollback(token).await
+            }
+            _ => Err(AppError::InternalServerError("Rate limit token from another backend".to_string())),
+        }
+    }
+}
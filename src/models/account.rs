@@ -1,8 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
+use validator::Validate;
 use bigdecimal::BigDecimal;
-use std::str::FromStr;
+
+use crate::models::AppError;
+use crate::models::money::{Currency, Money};
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Account {
@@ -14,26 +17,61 @@ pub struct Account {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 pub struct AccountBalanceResponse {
-    pub balance: f64,
+    // Same as `ledger`, kept for existing clients
+    pub balance: Money,
+    // Ledger balance less the funds reserved by active holds
+    pub available: Money,
+    pub ledger: Money,
+    pub currency: String,
+}
+
+impl AccountBalanceResponse {
+    pub fn new(account: Account, held: BigDecimal) -> Result<Self, AppError> {
+        let currency = Currency::stored(&account.currency)?;
+        let ledger = Money::stored(account.balance.clone(), currency);
+        
+        Ok(Self {
+            balance: ledger.clone(),
+            available: Money::stored(account.balance - held, currency),
+            ledger,
+            currency: account.currency,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct OpenAccountRequest {
+    #[validate(length(min = 3, max = 3, message = "currency must be a 3-letter code"))]
     pub currency: String,
 }
 
-impl From<Account> for AccountBalanceResponse {
-    fn from(account: Account) -> Self {
-        // Convert BigDecimal to f64 for JSON serialization - safely parse the string value
-        let balance_f64 = account.balance.to_string().parse::<f64>().unwrap_or(0.0);
+/// One of a user's currency wallets
+#[derive(Debug, Serialize)]
+pub struct AccountResponse {
+    pub id: Uuid,
+    pub currency: String,
+    pub available: Money,
+    pub ledger: Money,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AccountResponse {
+    pub fn new(account: Account, held: BigDecimal) -> Result<Self, AppError> {
+        let currency = Currency::stored(&account.currency)?;
         
-        Self {
-            balance: balance_f64,
+        Ok(Self {
+            id: account.id,
+            available: Money::stored(&account.balance - held, currency),
+            ledger: Money::stored(account.balance, currency),
             currency: account.currency,
-        }
+            created_at: account.created_at,
+        })
     }
 }
 
-// Helper function to convert f64 to BigDecimal
-pub fn f64_to_bigdecimal(value: f64) -> Result<BigDecimal, String> {
-    BigDecimal::from_str(&value.to_string())
-        .map_err(|e| format!("Failed to convert to BigDecimal: {}", e))
+#[derive(Debug, Serialize)]
+pub struct AccountListResponse {
+    pub accounts: Vec<AccountResponse>,
 }
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::models::AppError;
+
+/// What an API key may be used for. Routes that take API keys name the scope
+/// they need with `middleware::AcceptApiKeys`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ApiScope {
+    #[serde(rename = "balance:read")]
+    BalanceRead,
+    #[serde(rename = "transactions:read")]
+    TransactionsRead,
+    #[serde(rename = "transactions:write")]
+    TransactionsWrite,
+    #[serde(rename = "fx:read")]
+    FxRead,
+    #[serde(rename = "fx:write")]
+    FxWrite,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::BalanceRead => "balance:read",
+            ApiScope::TransactionsRead => "transactions:read",
+            ApiScope::TransactionsWrite => "transactions:write",
+            ApiScope::FxRead => "fx:read",
+            ApiScope::FxWrite => "fx:write",
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = AppError;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "balance:read" => Ok(ApiScope::BalanceRead),
+            "transactions:read" => Ok(ApiScope::TransactionsRead),
+            "transactions:write" => Ok(ApiScope::TransactionsWrite),
+            "fx:read" => Ok(ApiScope::FxRead),
+            "fx:write" => Ok(ApiScope::FxWrite),
+            _ => Err(AppError::BadRequestError(format!("Unknown API key scope: {}", scope))),
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Placed in the request extensions by `Auth` when a request is made with an
+/// API key rather than an access token
+#[derive(Debug, Clone)]
+pub struct ApiKeyIdentity {
+    pub key_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+}
+
+impl ApiKeyIdentity {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 100, message = "name must be between 1 and 100 characters"))]
+    pub name: String,
+
+    #[validate(length(min = 1, message = "at least one scope is required"))]
+    pub scopes: Vec<ApiScope>,
+
+    // The key stops working after this; `API_KEY_TTL_SECS` from now when left out
+    pub expires_at: Option<DateTime<Utc>>,
+
+    // Addresses and CIDR ranges the key may be used from; any when left out
+    #[validate(length(min = 1, max = 50, message = "allowed_ips must list between 1 and 50 entries"))]
+    pub allowed_ips: Option<Vec<String>>,
+
+    // Needed unless a code from the user's authenticator is sent in `X-MFA-Code`
+    #[serde(default, skip_serializing)]
+    pub current_password: Option<String>,
+}
+
+/// An API key as listed to its owner; the key itself is never shown again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub allowed_ips: Option<Vec<String>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedApiKeyResponse {
+    // Shown once; only a hash is stored
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyListResponse {
+    pub api_keys: Vec<ApiKeyResponse>,
+}
@@ -0,0 +1,31 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+// Actions recorded in the admin audit log
+pub const FUND_ACCOUNT: &str = "fund_account";
+pub const REFUND_TRANSACTION: &str = "refund_transaction";
+pub const CHANGE_ROLE: &str = "change_role";
+pub const RELOAD_KEYS: &str = "reload_keys";
+pub const LOCK_ACCOUNT: &str = "lock_account";
+pub const UNLOCK_ACCOUNT: &str = "unlock_account";
+
+/// A privileged action taken by an admin, support or auditor user, or a
+/// security action the system took on its own, which has no `actor_id`
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuditLogEntry {
+    pub id: Uuid,
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub target_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditLogListResponse {
+    pub entries: Vec<AuditLogEntry>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::models::AppError;
+use crate::models::money::Money;
+use crate::models::transaction_fixed::CreateTransactionRequest;
+
+/// How a batch treats transfers that cannot be made
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    /// Every transfer is made in one database transaction, or none is
+    AllOrNothing,
+    /// Each transfer is made on its own, whatever happens to the others
+    BestEffort,
+}
+
+impl BatchMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchMode::AllOrNothing => "all_or_nothing",
+            BatchMode::BestEffort => "best_effort",
+        }
+    }
+}
+
+impl FromStr for BatchMode {
+    type Err = AppError;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "all_or_nothing" => Ok(BatchMode::AllOrNothing),
+            "best_effort" => Ok(BatchMode::BestEffort),
+            _ => Err(AppError::InternalServerError(format!("Unknown batch mode: {}", mode))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    // A best-effort batch whose transfers are still being made
+    Processing,
+    Completed,
+    PartiallyCompleted,
+    // Nothing was transferred
+    Failed,
+}
+
+impl BatchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchStatus::Processing => "processing",
+            BatchStatus::Completed => "completed",
+            BatchStatus::PartiallyCompleted => "partially_completed",
+            BatchStatus::Failed => "failed",
+        }
+    }
+
+    /// The status of a batch in which `succeeded` of `total` transfers went through
+    pub fn from_counts(succeeded: usize, total: usize) -> Self {
+        if succeeded == total {
+            BatchStatus::Completed
+        } else if succeeded == 0 {
+            BatchStatus::Failed
+        } else {
+            BatchStatus::PartiallyCompleted
+        }
+    }
+}
+
+impl FromStr for BatchStatus {
+    type Err = AppError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "processing" => Ok(BatchStatus::Processing),
+            "completed" => Ok(BatchStatus::Completed),
+            "partially_completed" => Ok(BatchStatus::PartiallyCompleted),
+            "failed" => Ok(BatchStatus::Failed),
+            _ => Err(AppError::InternalServerError(format!("Unknown batch status: {}", status))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchItemStatus {
+    /// Not made yet
+    Pending,
+    Succeeded,
+    Failed,
+    /// Not made because another transfer in an all-or-nothing batch failed
+    Aborted,
+}
+
+impl BatchItemStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchItemStatus::Pending => "pending",
+            BatchItemStatus::Succeeded => "succeeded",
+            BatchItemStatus::Failed => "failed",
+            BatchItemStatus::Aborted => "aborted",
+        }
+    }
+}
+
+impl FromStr for BatchItemStatus {
+    type Err = AppError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(BatchItemStatus::Pending),
+            "succeeded" => Ok(BatchItemStatus::Succeeded),
+            "failed" => Ok(BatchItemStatus::Failed),
+            "aborted" => Ok(BatchItemStatus::Aborted),
+            _ => Err(AppError::InternalServerError(format!("Unknown batch item status: {}", status))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateBatchRequest {
+    pub mode: BatchMode,
+
+    // Transfers from the signed-in user, all in one currency
+    #[validate]
+    pub items: Vec<CreateTransactionRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchItemResponse {
+    // Position of the transfer in the submitted batch, from 0
+    pub index: i32,
+    pub recipient_id: Uuid,
+    pub amount: Money,
+    pub description: Option<String>,
+    pub status: BatchItemStatus,
+    pub transaction_id: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub id: Uuid,
+    pub mode: BatchMode,
+    pub status: BatchStatus,
+    pub currency: String,
+    pub total_amount: Money,
+    pub item_count: i32,
+    pub succeeded_count: i32,
+    pub created_at: DateTime<Utc>,
+    pub items: Vec<BatchItemResponse>,
+}
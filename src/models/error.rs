@@ -8,9 +8,13 @@ pub enum AppError {
     InternalServerError(String),
     ValidationError(ValidationErrors),
     AuthenticationError(String),
+    ForbiddenError(String),
     NotFoundError(String),
     ConflictError(String),
     BadRequestError(String),
+    UnprocessableEntityError(String),
+    // Message and the number of seconds to wait before retrying
+    TooManyRequestsError(String, u64),
 }
 
 #[derive(Serialize)]
@@ -25,9 +29,12 @@ impl fmt::Display for AppError {
             AppError::InternalServerError(msg) => write!(f, "Internal server error: {}", msg),
             AppError::ValidationError(_) => write!(f, "Validation error"),
             AppError::AuthenticationError(msg) => write!(f, "Authentication error: {}", msg),
+            AppError::ForbiddenError(msg) => write!(f, "Forbidden: {}", msg),
             AppError::NotFoundError(msg) => write!(f, "Not found: {}", msg),
             AppError::ConflictError(msg) => write!(f, "Conflict: {}", msg),
             AppError::BadRequestError(msg) => write!(f, "Bad request: {}", msg),
+            AppError::UnprocessableEntityError(msg) => write!(f, "Unprocessable entity: {}", msg),
+            AppError::TooManyRequestsError(msg, _) => write!(f, "Too many requests: {}", msg),
         }
     }
 }
@@ -42,22 +49,9 @@ impl ResponseError for AppError {
                 })
             }
             AppError::ValidationError(errors) => {
-                // Convert validation errors to a more user-friendly format
-                let error_messages: Vec<String> = errors
-                    .field_errors()
-                    .iter()
-                    .map(|(field, errors)| {
-                        let messages: Vec<String> = errors
-                            .iter()
-                            .map(|error| error.message.as_ref().unwrap_or(&error.code).to_string())
-                            .collect();
-                        format!("{}: {}", field, messages.join(", "))
-                    })
-                    .collect();
-
                 HttpResponse::BadRequest().json(ErrorResponse {
                     status: "error".into(),
-                    message: error_messages.join("; "),
+                    message: describe_validation_errors(errors),
                 })
             }
             AppError::AuthenticationError(msg) => {
@@ -66,6 +60,12 @@ impl ResponseError for AppError {
                     message: msg.clone(),
                 })
             }
+            AppError::ForbiddenError(msg) => {
+                HttpResponse::Forbidden().json(ErrorResponse {
+                    status: "error".into(),
+                    message: msg.clone(),
+                })
+            }
             AppError::NotFoundError(msg) => {
                 HttpResponse::NotFound().json(ErrorResponse {
                     status: "error".into(),
@@ -84,6 +84,20 @@ impl ResponseError for AppError {
                     message: msg.clone(),
                 })
             }
+            AppError::UnprocessableEntityError(msg) => {
+                HttpResponse::UnprocessableEntity().json(ErrorResponse {
+                    status: "error".into(),
+                    message: msg.clone(),
+                })
+            }
+            AppError::TooManyRequestsError(msg, retry_after) => {
+                HttpResponse::TooManyRequests()
+                    .insert_header(("Retry-After", retry_after.to_string()))
+                    .json(ErrorResponse {
+                        status: "error".into(),
+                        message: msg.clone(),
+                    })
+            }
         }
     }
 }
@@ -111,3 +125,21 @@ impl From<ValidationErrors> for AppError {
         AppError::ValidationError(errors)
     }
 }
+
+/// Validation errors as one user-friendly message, `field: message` for each
+/// field that failed
+pub fn describe_validation_errors(errors: &ValidationErrors) -> String {
+    let error_messages: Vec<String> = errors
+        .field_errors()
+        .iter()
+        .map(|(field, errors)| {
+            let messages: Vec<String> = errors
+                .iter()
+                .map(|error| error.message.as_ref().unwrap_or(&error.code).to_string())
+                .collect();
+            format!("{}: {}", field, messages.join(", "))
+        })
+        .collect();
+
+    error_messages.join("; ")
+}
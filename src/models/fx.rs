@@ -0,0 +1,129 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use validator::Validate;
+use bigdecimal::{BigDecimal, Zero};
+
+use crate::models::AppError;
+use crate::models::money::{decimal, Currency, Money};
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateQuoteRequest {
+    #[validate(length(min = 3, max = 3, message = "from_currency must be a 3-letter code"))]
+    pub from_currency: String,
+
+    #[validate(length(min = 3, max = 3, message = "to_currency must be a 3-letter code"))]
+    pub to_currency: String,
+
+    // Amount to send, in `from_currency`
+    #[serde(with = "decimal")]
+    pub amount: BigDecimal,
+}
+
+/// The outcome of converting an amount: what the sender pays, the fee kept
+/// from it and what the recipient receives at the applied rate
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub quote_id: Option<Uuid>,
+    pub amount: Money,
+    pub rate: BigDecimal,
+    pub fee: Money,
+    pub converted: Money,
+}
+
+impl Conversion {
+    /// Take the spread off `amount` and convert the rest at `rate`.
+    ///
+    /// Both the fee and the converted amount are truncated to their
+    /// currency's minor units.
+    pub fn compute(amount: Money, to: Currency, rate: BigDecimal, spread: &BigDecimal) -> Result<Self, AppError> {
+        let from = amount.currency();
+
+        let fee = (amount.amount() * spread).with_scale(from.minor_units() as i64);
+        let net = amount.amount() - &fee;
+        let converted = (net * &rate).with_scale(to.minor_units() as i64);
+
+        if converted <= BigDecimal::zero() {
+            return Err(AppError::BadRequestError("Amount is too small to convert".to_string()));
+        }
+
+        Ok(Self {
+            quote_id: None,
+            fee: Money::new(fee, from)?,
+            converted: Money::new(converted, to)?,
+            amount,
+            rate,
+        })
+    }
+}
+
+/// A stored quote that locks a rate until `expires_at`
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct FxQuote {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub amount: BigDecimal,
+    pub rate: BigDecimal,
+    pub fee: BigDecimal,
+    pub converted_amount: BigDecimal,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FxQuote {
+    pub fn to_conversion(&self) -> Result<Conversion, AppError> {
+        Ok(Conversion {
+            quote_id: Some(self.id),
+            amount: Money::parse(self.amount.clone(), &self.from_currency)?,
+            rate: self.rate.clone(),
+            fee: Money::parse(self.fee.clone(), &self.from_currency)?,
+            converted: Money::parse(self.converted_amount.clone(), &self.to_currency)?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FxQuoteResponse {
+    pub id: Uuid,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub amount: Money,
+    pub rate: String,
+    pub fee: Money,
+    pub converted_amount: Money,
+    pub expires_at: DateTime<Utc>,
+    pub used: bool,
+}
+
+impl TryFrom<FxQuote> for FxQuoteResponse {
+    type Error = AppError;
+
+    fn try_from(quote: FxQuote) -> Result<Self, Self::Error> {
+        let conversion = quote.to_conversion()?;
+
+        Ok(Self {
+            id: quote.id,
+            from_currency: quote.from_currency,
+            to_currency: quote.to_currency,
+            amount: conversion.amount,
+            rate: conversion.rate.to_string(),
+            fee: conversion.fee,
+            converted_amount: conversion.converted,
+            expires_at: quote.expires_at,
+            used: quote.used_at.is_some(),
+        })
+    }
+}
+
+/// The conversion applied to a cross-currency transaction
+#[derive(Debug, Serialize)]
+pub struct ConversionResponse {
+    pub quote_id: Option<Uuid>,
+    pub rate: String,
+    pub fee: Money,
+    pub converted_amount: Money,
+    pub converted_currency: String,
+}
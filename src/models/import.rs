@@ -0,0 +1,55 @@
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::models::money::Money;
+
+/// File formats transfers can be imported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// ISO 20022 customer credit transfer initiation
+    Pain001,
+}
+
+impl ImportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv",
+            ImportFormat::Pain001 => "pain001",
+        }
+    }
+}
+
+/// Why part of an import file cannot be used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportLineError {
+    // Line of the file the problem starts on, or None if it is the whole file
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportedTransferResponse {
+    // Line of the file the transfer starts on, from 1
+    pub line: usize,
+    pub recipient_id: Uuid,
+    pub amount: Money,
+    pub description: Option<String>,
+}
+
+/// What an import file holds and whether it can be submitted as a batch
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub format: ImportFormat,
+    pub dry_run: bool,
+    // True when there are no errors
+    pub valid: bool,
+    pub item_count: usize,
+    pub currency: Option<String>,
+    // Sum of the transfers that could be read
+    pub total_amount: Option<Money>,
+    pub items: Vec<ImportedTransferResponse>,
+    pub errors: Vec<ImportLineError>,
+}
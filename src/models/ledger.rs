@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use bigdecimal::BigDecimal;
+
+// System account that admin funding is drawn from
+pub const FUNDING_ACCOUNT: &str = "funding";
+// System account holding the platform's position in each currency from conversions
+pub const FX_ACCOUNT: &str = "fx";
+// System account collecting the spread kept on conversions
+pub const FX_FEES_ACCOUNT: &str = "fx_fees";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryType {
+    Debit,
+    Credit,
+}
+
+impl EntryType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryType::Debit => "debit",
+            EntryType::Credit => "credit",
+        }
+    }
+}
+
+/// The side of a journal line: a user's account or a named system account
+/// standing in for money entering or leaving the platform.
+#[derive(Debug, Clone)]
+pub enum LedgerAccount {
+    User(Uuid),
+    System(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct LedgerLine {
+    pub account: LedgerAccount,
+    pub entry_type: EntryType,
+    pub amount: BigDecimal,
+}
+
+impl LedgerLine {
+    pub fn debit(account: LedgerAccount, amount: BigDecimal) -> Self {
+        Self { account, entry_type: EntryType::Debit, amount }
+    }
+
+    pub fn credit(account: LedgerAccount, amount: BigDecimal) -> Self {
+        Self { account, entry_type: EntryType::Credit, amount }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LedgerEntryResponse {
+    pub id: Uuid,
+    pub journal_id: Uuid,
+    pub transaction_id: Option<Uuid>,
+    pub entry_type: EntryType,
+    pub amount: String,
+    pub currency: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LedgerEntryListResponse {
+    pub entries: Vec<LedgerEntryResponse>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountDrift {
+    pub account_id: Uuid,
+    pub user_id: Uuid,
+    pub currency: String,
+    pub cached_balance: String,
+    pub ledger_balance: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnbalancedJournal {
+    pub journal_id: Uuid,
+    pub currency: String,
+    pub debits: String,
+    pub credits: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LedgerCheckResponse {
+    pub consistent: bool,
+    pub accounts_checked: i64,
+    pub account_drift: Vec<AccountDrift>,
+    pub unbalanced_journals: Vec<UnbalancedJournal>,
+}
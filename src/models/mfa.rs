@@ -0,0 +1,64 @@
+use serde::{Serialize, Deserialize};
+use validator::Validate;
+use bigdecimal::BigDecimal;
+
+use crate::models::money::{decimal, Money};
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MfaCodeRequest {
+    // A TOTP code from the authenticator, or an unused recovery code
+    #[validate(length(min = 6, max = 32, message = "code must be between 6 and 32 characters"))]
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct MfaLoginRequest {
+    #[validate(length(min = 1, message = "mfa_token cannot be empty"))]
+    pub mfa_token: String,
+
+    #[validate(length(min = 6, max = 32, message = "code must be between 6 and 32 characters"))]
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct StepUpThresholdRequest {
+    #[serde(with = "decimal")]
+    pub amount: BigDecimal,
+
+    #[validate(length(min = 3, max = 3, message = "currency must be a 3-letter code"))]
+    pub currency: String,
+
+    // Changing the threshold needs a code, so a stolen token cannot raise it
+    #[validate(length(min = 6, max = 32, message = "code must be between 6 and 32 characters"))]
+    pub code: String,
+}
+
+/// A new authenticator secret, not active until a code from it is verified
+#[derive(Debug, Serialize)]
+pub struct MfaEnrollmentResponse {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    // Shown once; only hashes are stored
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaStatusResponse {
+    pub enabled: bool,
+    // Transfers above this need a step-up code while MFA is enabled
+    pub step_up_threshold: Money,
+    pub recovery_codes_remaining: i64,
+}
+
+/// Returned by login instead of tokens when the user has MFA enabled
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    // Seconds until `mfa_token` expires
+    pub expires_in: u64,
+}
@@ -2,9 +2,32 @@ pub mod user;
 pub mod transaction_fixed;
 pub mod account;
 pub mod error;
+pub mod ledger;
+pub mod fx;
+pub mod money;
+pub mod audit;
+pub mod mfa;
+pub mod api_key;
+pub mod session;
+pub mod scheduled_transfer;
+pub mod payment_request;
+pub mod batch;
+pub mod import;
+pub mod statement;
 
 // Re-exports - explicit to avoid ambiguity
-pub use user::{User, UserResponse, LoginUserRequest, RegisterUserRequest, TokenResponse};
-pub use transaction_fixed::{Transaction, TransactionResponse, TransactionListResponse, TransactionStatus, CreateTransactionRequest};
-pub use account::{Account, AccountBalanceResponse};
+pub use user::{User, UserResponse, LoginUserRequest, RegisterUserRequest, TokenResponse, RefreshTokenRequest, Role, Permission, UpdateRoleRequest, ChangePasswordRequest, ForgotPasswordRequest, ResetPasswordRequest, VerifyEmailRequest};
+pub use transaction_fixed::{Transaction, TransactionResponse, TransactionListResponse, TransactionStatus, CreateTransactionRequest, CaptureTransactionRequest, RefundTransactionRequest};
+pub use account::{Account, AccountBalanceResponse, AccountListResponse, AccountResponse, OpenAccountRequest};
+pub use money::{Currency, Money};
+pub use audit::{AuditLogEntry, AuditLogListResponse};
+pub use mfa::{MfaChallengeResponse, MfaCodeRequest, MfaEnrollmentResponse, MfaLoginRequest, MfaStatusResponse, RecoveryCodesResponse, StepUpThresholdRequest};
+pub use api_key::{ApiKeyIdentity, ApiKeyListResponse, ApiKeyResponse, ApiScope, CreateApiKeyRequest, CreatedApiKeyResponse};
+pub use session::{RevokedSessionsResponse, SessionListResponse, SessionResponse};
+pub use scheduled_transfer::{CreateScheduledTransferRequest, Frequency, InsufficientFundsPolicy, ScheduleStatus, ScheduledTransferListResponse, ScheduledTransferResponse, ScheduledTransferRunResponse};
+pub use payment_request::{CreatePaymentRequestRequest, PaymentRequestListResponse, PaymentRequestResponse, PaymentRequestStatus};
+pub use batch::{BatchItemResponse, BatchItemStatus, BatchMode, BatchResponse, BatchStatus, CreateBatchRequest};
+pub use import::{ImportFormat, ImportLineError, ImportReport, ImportedTransferResponse};
+pub use statement::{Statement, StatementEntry, StatementFormat};
+pub use fx::{Conversion, ConversionResponse, CreateQuoteRequest, FxQuote, FxQuoteResponse};
 pub use error::*;
@@ -0,0 +1,199 @@
+use bigdecimal::{BigDecimal, Zero};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::models::AppError;
+
+// Supported ISO 4217 codes and the number of minor units each allows
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AUD", 2),
+    ("BHD", 3),
+    ("BRL", 2),
+    ("CAD", 2),
+    ("CHF", 2),
+    ("CLP", 0),
+    ("CNY", 2),
+    ("DKK", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("HKD", 2),
+    ("INR", 2),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("MXN", 2),
+    ("NOK", 2),
+    ("NZD", 2),
+    ("OMR", 3),
+    ("SEK", 2),
+    ("SGD", 2),
+    ("TND", 3),
+    ("USD", 2),
+    ("VND", 0),
+    ("ZAR", 2),
+];
+
+/// An ISO 4217 currency the platform supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Currency {
+    code: &'static str,
+    minor_units: u32,
+}
+
+impl Currency {
+    pub fn from_code(code: &str) -> Result<Self, AppError> {
+        CURRENCIES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(code))
+            .map(|&(code, minor_units)| Self { code, minor_units })
+            .ok_or_else(|| AppError::BadRequestError(format!("Unsupported currency: {}", code)))
+    }
+
+    /// The currency of a row read back from the database. An unknown code
+    /// there means the row is corrupt, which is not the client's fault.
+    pub fn stored(code: &str) -> Result<Self, AppError> {
+        Self::from_code(code).map_err(|_| AppError::InternalServerError(format!("Stored currency {} is not supported", code)))
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    // Digits allowed after the decimal point, e.g. 2 for USD and 0 for JPY
+    pub fn minor_units(&self) -> u32 {
+        self.minor_units
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code)
+    }
+}
+
+/// An exact decimal amount in a currency.
+///
+/// Construction rejects amounts with more fractional digits than the currency
+/// allows, so a `Money` can always be stored and shown without rounding. It
+/// serializes as a string with exactly the currency's minor units, e.g. "10.00".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Money {
+    amount: BigDecimal,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: BigDecimal, currency: Currency) -> Result<Self, AppError> {
+        // with_scale truncates, so a change in value means digits were lost
+        if amount.with_scale(currency.minor_units() as i64) != amount {
+            return Err(AppError::BadRequestError(format!(
+                "{} allows at most {} decimal places",
+                currency,
+                currency.minor_units()
+            )));
+        }
+
+        Ok(Self { amount, currency })
+    }
+
+    /// An amount read back from the database.
+    ///
+    /// Rows written before amounts were checked can hold more digits than the
+    /// currency allows, such as 10.0050 USD, so they are rounded half away from
+    /// zero instead of rejected like client input.
+    pub fn stored(amount: BigDecimal, currency: Currency) -> Self {
+        Self { amount: amount.round(currency.minor_units() as i64), currency }
+    }
+
+    /// Parse an amount and currency code as received from a client
+    pub fn parse(amount: BigDecimal, currency: &str) -> Result<Self, AppError> {
+        Self::new(amount, Currency::from_code(currency)?)
+    }
+
+    /// Like `parse`, but also require the amount to be greater than zero
+    pub fn positive(amount: BigDecimal, currency: &str) -> Result<Self, AppError> {
+        let money = Self::parse(amount, currency)?;
+        if !money.is_positive() {
+            return Err(AppError::BadRequestError("amount must be greater than 0".to_string()));
+        }
+        Ok(money)
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self { amount: BigDecimal::zero(), currency }
+    }
+
+    pub fn amount(&self) -> &BigDecimal {
+        &self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.amount > BigDecimal::zero()
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.amount.with_scale(self.currency.minor_units() as i64))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Serde helpers for decimal amounts in requests.
+///
+/// Amounts should be sent as strings such as "10.50"; JSON numbers are still
+/// accepted for older clients. Use with `#[serde(with = "decimal")]`.
+pub mod decimal {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(amount: &BigDecimal, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(amount)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigDecimal, D::Error> {
+        match AmountInput::deserialize(deserializer)? {
+            AmountInput::Text(text) => BigDecimal::from_str(text.trim())
+                .map_err(|_| de::Error::custom(format!("invalid amount: {}", text))),
+            AmountInput::Number(number) => BigDecimal::from_str(&number.to_string())
+                .map_err(|_| de::Error::custom(format!("invalid amount: {}", number))),
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AmountInput {
+        Text(String),
+        Number(serde_json::Number),
+    }
+}
+
+/// `decimal` for optional amounts; pair with `#[serde(default)]`
+pub mod decimal_opt {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(amount: &Option<BigDecimal>, serializer: S) -> Result<S::Ok, S::Error> {
+        match amount {
+            Some(amount) => decimal::serialize(amount, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BigDecimal>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "decimal")] BigDecimal);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(amount)| amount))
+    }
+}
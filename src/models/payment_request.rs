@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::Validate;
+use bigdecimal::BigDecimal;
+
+use crate::models::AppError;
+use crate::models::money::{decimal, Money};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentRequestStatus {
+    Pending,
+    // Paid by the payer; the request links to the transfer
+    Accepted,
+    Declined,
+    // Withdrawn by the requester
+    Cancelled,
+    Expired,
+}
+
+impl PaymentRequestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentRequestStatus::Pending => "pending",
+            PaymentRequestStatus::Accepted => "accepted",
+            PaymentRequestStatus::Declined => "declined",
+            PaymentRequestStatus::Cancelled => "cancelled",
+            PaymentRequestStatus::Expired => "expired",
+        }
+    }
+}
+
+impl FromStr for PaymentRequestStatus {
+    type Err = AppError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(PaymentRequestStatus::Pending),
+            "accepted" => Ok(PaymentRequestStatus::Accepted),
+            "declined" => Ok(PaymentRequestStatus::Declined),
+            "cancelled" => Ok(PaymentRequestStatus::Cancelled),
+            "expired" => Ok(PaymentRequestStatus::Expired),
+            _ => Err(AppError::BadRequestError(format!("Unknown payment request status: {}", status))),
+        }
+    }
+}
+
+impl fmt::Display for PaymentRequestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreatePaymentRequestRequest {
+    // The user asked to pay
+    pub payer_id: Uuid,
+
+    // Must be positive and fit the currency's minor units
+    #[serde(with = "decimal")]
+    pub amount: BigDecimal,
+
+    #[validate(length(min = 3, max = 3, message = "currency must be a 3-letter code"))]
+    pub currency: String,
+
+    #[validate(length(max = 200, message = "note must be less than 200 characters"))]
+    pub note: Option<String>,
+
+    // Defaults to `PAYMENT_REQUEST_TTL_SECS` from now
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaymentRequestResponse {
+    pub id: Uuid,
+    pub requester_id: Uuid,
+    pub payer_id: Uuid,
+    pub amount: Money,
+    pub currency: String,
+    pub note: Option<String>,
+    pub status: PaymentRequestStatus,
+    // The transfer that paid the request, once accepted
+    pub transaction_id: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub responded_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaymentRequestListResponse {
+    pub payment_requests: Vec<PaymentRequestResponse>,
+}
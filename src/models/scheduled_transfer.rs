@@ -0,0 +1,251 @@
+use chrono::{DateTime, Duration, Months, Utc};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::Validate;
+use bigdecimal::BigDecimal;
+
+use crate::models::AppError;
+use crate::models::money::{decimal, Money};
+
+/// How often a scheduled transfer repeats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Once,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::Once => "once",
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+            Frequency::Monthly => "monthly",
+        }
+    }
+
+    /// When occurrence `n` of a schedule starting at `start` is due.
+    ///
+    /// Monthly occurrences keep the day of month of `start`, falling back to
+    /// the last day of shorter months, so a schedule starting on the 31st runs
+    /// on the 30th in April and on the 31st again in May.
+    pub fn occurrence(&self, start: DateTime<Utc>, n: u32) -> Option<DateTime<Utc>> {
+        match self {
+            Frequency::Once => (n == 0).then_some(start),
+            Frequency::Daily => start.checked_add_signed(Duration::days(n as i64)),
+            Frequency::Weekly => start.checked_add_signed(Duration::weeks(n as i64)),
+            Frequency::Monthly => start.checked_add_months(Months::new(n)),
+        }
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = AppError;
+
+    fn from_str(frequency: &str) -> Result<Self, Self::Err> {
+        match frequency {
+            "once" => Ok(Frequency::Once),
+            "daily" => Ok(Frequency::Daily),
+            "weekly" => Ok(Frequency::Weekly),
+            "monthly" => Ok(Frequency::Monthly),
+            _ => Err(AppError::InternalServerError(format!("Unknown frequency: {}", frequency))),
+        }
+    }
+}
+
+/// What the scheduler does when the sender cannot cover an occurrence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InsufficientFundsPolicy {
+    /// Give the occurrence up and wait for the next one
+    #[default]
+    Skip,
+    /// Try the occurrence again later, a limited number of times
+    Retry,
+}
+
+impl InsufficientFundsPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InsufficientFundsPolicy::Skip => "skip",
+            InsufficientFundsPolicy::Retry => "retry",
+        }
+    }
+}
+
+impl FromStr for InsufficientFundsPolicy {
+    type Err = AppError;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "skip" => Ok(InsufficientFundsPolicy::Skip),
+            "retry" => Ok(InsufficientFundsPolicy::Retry),
+            _ => Err(AppError::InternalServerError(format!("Unknown insufficient funds policy: {}", policy))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleStatus {
+    Active,
+    Paused,
+    Cancelled,
+    Completed,
+}
+
+impl ScheduleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleStatus::Active => "active",
+            ScheduleStatus::Paused => "paused",
+            ScheduleStatus::Cancelled => "cancelled",
+            ScheduleStatus::Completed => "completed",
+        }
+    }
+}
+
+impl FromStr for ScheduleStatus {
+    type Err = AppError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "active" => Ok(ScheduleStatus::Active),
+            "paused" => Ok(ScheduleStatus::Paused),
+            "cancelled" => Ok(ScheduleStatus::Cancelled),
+            "completed" => Ok(ScheduleStatus::Completed),
+            _ => Err(AppError::InternalServerError(format!("Unknown schedule status: {}", status))),
+        }
+    }
+}
+
+impl fmt::Display for ScheduleStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The outcome of one attempt at an occurrence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Succeeded,
+    Failed,
+    Skipped,
+    /// Failed for lack of funds, and will be tried again
+    Retrying,
+}
+
+impl RunOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Succeeded => "succeeded",
+            RunOutcome::Failed => "failed",
+            RunOutcome::Skipped => "skipped",
+            RunOutcome::Retrying => "retrying",
+        }
+    }
+}
+
+/// When a schedule's transfers are due
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    pub frequency: Frequency,
+    pub start_at: DateTime<Utc>,
+    pub end_at: Option<DateTime<Utc>>,
+    pub max_occurrences: Option<u32>,
+}
+
+impl Schedule {
+    /// The first occurrence from `from` on that is due no earlier than
+    /// `not_before`, or `None` if the schedule has run out. Occurrences that
+    /// are passed over still count towards `max_occurrences`.
+    pub fn next_due(&self, from: u32, not_before: DateTime<Utc>) -> Option<(u32, DateTime<Utc>)> {
+        let mut n = from;
+        loop {
+            if self.max_occurrences.is_some_and(|max| n >= max) {
+                return None;
+            }
+            let due = self.frequency.occurrence(self.start_at, n)?;
+            if self.end_at.is_some_and(|end_at| due > end_at) {
+                return None;
+            }
+            if due >= not_before {
+                return Some((n, due));
+            }
+            n = n.checked_add(1)?;
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateScheduledTransferRequest {
+    pub recipient_id: Uuid,
+
+    // Must be positive and fit the currency's minor units
+    #[serde(with = "decimal")]
+    pub amount: BigDecimal,
+
+    #[validate(length(min = 3, max = 3, message = "currency must be a 3-letter code"))]
+    pub currency: String,
+
+    #[validate(length(max = 200, message = "description must be less than 200 characters"))]
+    pub description: Option<String>,
+
+    pub frequency: Frequency,
+
+    // When the first transfer is made; later ones follow at the frequency
+    pub start_at: DateTime<Utc>,
+
+    // A recurring schedule stops after this time or this many occurrences,
+    // whichever comes first, and runs until cancelled without either
+    pub end_at: Option<DateTime<Utc>>,
+
+    #[validate(range(min = 1, message = "max_occurrences must be at least 1"))]
+    pub max_occurrences: Option<u32>,
+
+    #[serde(default)]
+    pub on_insufficient_funds: InsufficientFundsPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduledTransferRunResponse {
+    pub id: Uuid,
+    pub occurrence: i32,
+    pub attempt: i32,
+    pub status: String,
+    pub transaction_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledTransferResponse {
+    pub id: Uuid,
+    pub recipient_id: Uuid,
+    pub amount: Money,
+    pub currency: String,
+    pub description: Option<String>,
+    pub frequency: Frequency,
+    pub start_at: DateTime<Utc>,
+    pub end_at: Option<DateTime<Utc>>,
+    pub max_occurrences: Option<i32>,
+    pub on_insufficient_funds: InsufficientFundsPolicy,
+    pub status: ScheduleStatus,
+    // Occurrences already run, skipped or given up
+    pub occurrences_done: i32,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    // Most recent first; only included for a single schedule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runs: Option<Vec<ScheduledTransferRunResponse>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledTransferListResponse {
+    pub scheduled_transfers: Vec<ScheduledTransferResponse>,
+}
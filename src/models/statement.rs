@@ -0,0 +1,64 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+
+use crate::models::ledger::EntryType;
+use crate::models::money::Money;
+
+/// File formats statements can be downloaded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatementFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// Open Financial Exchange 2.2, read by most personal finance tools
+    Ofx,
+    /// ISO 20022 bank-to-customer statement
+    Camt053,
+}
+
+impl StatementFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "text/csv; charset=utf-8",
+            StatementFormat::Ofx => "application/x-ofx",
+            StatementFormat::Camt053 => "application/xml",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "csv",
+            StatementFormat::Ofx => "ofx",
+            StatementFormat::Camt053 => "xml",
+        }
+    }
+}
+
+/// The wallet and period a statement covers, with its balances
+#[derive(Debug, Clone)]
+pub struct Statement {
+    // Identifies this statement among others for the same wallet and period
+    pub id: Uuid,
+    pub account_id: Uuid,
+    // First and last days covered, both whole days in UTC
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub opening_balance: Money,
+    pub closing_balance: Money,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// One ledger line on a statement, with the wallet's balance after it
+#[derive(Debug, Clone)]
+pub struct StatementEntry {
+    pub id: Uuid,
+    pub transaction_id: Option<Uuid>,
+    // The other user in the transfer, if the entry belongs to one
+    pub counterparty_id: Option<Uuid>,
+    pub entry_type: EntryType,
+    pub amount: Money,
+    pub description: Option<String>,
+    pub booked_at: DateTime<Utc>,
+    pub balance: Money,
+}
@@ -3,7 +3,10 @@ use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use validator::Validate;
 use bigdecimal::BigDecimal;
-use std::str::FromStr;
+
+use crate::models::AppError;
+use crate::models::fx::ConversionResponse;
+use crate::models::money::{decimal, decimal_opt, Currency, Money};
 
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Transaction {
@@ -14,6 +17,16 @@ pub struct Transaction {
     pub currency: String,
     pub description: Option<String>,
     pub status: TransactionStatus,
+    pub failure_reason: Option<String>,
+    pub refund_of: Option<Uuid>,
+    pub refunded_amount: BigDecimal,
+    pub authorized_amount: Option<BigDecimal>,
+    pub hold_expires_at: Option<DateTime<Utc>>,
+    pub fx_quote_id: Option<Uuid>,
+    pub fx_rate: Option<BigDecimal>,
+    pub fx_fee: Option<BigDecimal>,
+    pub converted_amount: Option<BigDecimal>,
+    pub converted_currency: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -24,35 +37,68 @@ pub enum TransactionStatus {
     Pending,
     Completed,
     Failed,
+    Voided,
+    Expired,
 }
 
-#[derive(Debug, Serialize, Deserialize, Validate)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct CreateTransactionRequest {
     pub recipient_id: Uuid,
     
-    #[validate(range(min = 0.01, message = "amount must be greater than 0"))]
-    pub amount: f64,
+    // Must be positive and fit the currency's minor units
+    #[serde(with = "decimal")]
+    pub amount: BigDecimal,
     
     #[validate(length(min = 3, max = 3, message = "currency must be a 3-letter code"))]
     pub currency: String,
     
     #[validate(length(max = 200, message = "description must be less than 200 characters"))]
     pub description: Option<String>,
+    
+    // Places a hold to be captured or voided later when false
+    pub capture: Option<bool>,
+    
+    // Converts at a previously quoted rate when the recipient holds another currency
+    pub quote_id: Option<Uuid>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CaptureTransactionRequest {
+    // Captures the full authorized amount when omitted
+    #[serde(default, with = "decimal_opt")]
+    pub amount: Option<BigDecimal>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct RefundTransactionRequest {
+    // Refunds the remaining amount when omitted
+    #[serde(default, with = "decimal_opt")]
+    pub amount: Option<BigDecimal>,
+    
+    #[validate(length(max = 200, message = "description must be less than 200 characters"))]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
 pub struct TransactionResponse {
     pub id: Uuid,
     pub sender_id: Uuid,
     pub recipient_id: Uuid,
-    pub amount: f64,
+    pub amount: Money,
     pub currency: String,
     pub description: Option<String>,
     pub status: String,
+    pub failure_reason: Option<String>,
+    pub refund_of: Option<Uuid>,
+    pub refunded_amount: Money,
+    pub refund_status: Option<String>,
+    pub authorized_amount: Option<Money>,
+    pub hold_expires_at: Option<DateTime<Utc>>,
+    pub conversion: Option<ConversionResponse>,
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
 pub struct TransactionListResponse {
     pub transactions: Vec<TransactionResponse>,
     pub total: i64,
@@ -60,26 +106,49 @@ pub struct TransactionListResponse {
     pub per_page: i64,
 }
 
-impl From<Transaction> for TransactionResponse {
-    fn from(transaction: Transaction) -> Self {
-        // Safely convert BigDecimal to f64
-        let amount_f64 = transaction.amount.to_string().parse::<f64>().unwrap_or(0.0);
+impl TryFrom<Transaction> for TransactionResponse {
+    type Error = AppError;
+    
+    fn try_from(transaction: Transaction) -> Result<Self, Self::Error> {
+        let currency = Currency::stored(&transaction.currency)?;
         
-        Self {
+        let refund_status = if transaction.refunded_amount <= BigDecimal::from(0) {
+            None
+        } else if transaction.refunded_amount >= transaction.amount {
+            Some("refunded".to_string())
+        } else {
+            Some("partially_refunded".to_string())
+        };
+        
+        let conversion = match (transaction.fx_rate, transaction.converted_amount, transaction.converted_currency) {
+            (Some(rate), Some(converted_amount), Some(converted_currency)) => Some(ConversionResponse {
+                quote_id: transaction.fx_quote_id,
+                rate: rate.to_string(),
+                fee: Money::stored(transaction.fx_fee.unwrap_or_default(), currency),
+                converted_amount: Money::stored(converted_amount, Currency::stored(&converted_currency)?),
+                converted_currency,
+            }),
+            _ => None,
+        };
+        
+        Ok(Self {
             id: transaction.id,
             sender_id: transaction.sender_id,
             recipient_id: transaction.recipient_id,
-            amount: amount_f64,
+            amount: Money::stored(transaction.amount, currency),
             currency: transaction.currency,
             description: transaction.description,
             status: format!("{:?}", transaction.status).to_lowercase(),
+            failure_reason: transaction.failure_reason,
+            refund_of: transaction.refund_of,
+            refunded_amount: Money::stored(transaction.refunded_amount, currency),
+            refund_status,
+            authorized_amount: transaction
+                .authorized_amount
+                .map(|amount| Money::stored(amount, currency)),
+            hold_expires_at: transaction.hold_expires_at,
+            conversion,
             created_at: transaction.created_at,
-        }
+        })
     }
 }
-
-// Helper function to convert f64 to BigDecimal
-pub fn f64_to_bigdecimal(value: f64) -> Result<BigDecimal, String> {
-    BigDecimal::from_str(&value.to_string())
-        .map_err(|e| format!("Failed to convert to BigDecimal: {}", e))
-}
@@ -1,8 +1,12 @@
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::models::AppError;
+
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
     pub id: Uuid,
@@ -10,10 +14,77 @@ pub struct User {
     #[serde(skip_serializing)]
     pub password_hash: String,
     pub email: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    // A new address waiting to be confirmed before it replaces `email`
+    pub pending_email: Option<String>,
+    pub role: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// What a user is allowed to do beyond managing their own money
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Support,
+    Admin,
+    Auditor,
+}
+
+/// A privileged operation guarded by `middleware::Authorize`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    FundAccounts,
+    RefundTransactions,
+    CheckLedger,
+    ManageRoles,
+    UnlockAccounts,
+    ManageKeys,
+    ViewAuditLog,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Support => "support",
+            Role::Admin => "admin",
+            Role::Auditor => "auditor",
+        }
+    }
+    
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Support => matches!(permission, Permission::RefundTransactions | Permission::UnlockAccounts),
+            Role::Auditor => matches!(permission, Permission::CheckLedger | Permission::ViewAuditLog),
+            Role::User => false,
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = AppError;
+    
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "user" => Ok(Role::User),
+            "support" => Ok(Role::Support),
+            "admin" => Ok(Role::Admin),
+            "auditor" => Ok(Role::Auditor),
+            _ => Err(AppError::BadRequestError(format!("Unknown role: {}", role))),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct RegisterUserRequest {
     #[validate(length(min = 3, max = 50, message = "username must be between 3 and 50 characters"))]
@@ -35,11 +106,44 @@ pub struct LoginUserRequest {
     pub password: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1, message = "current_password cannot be empty"))]
+    pub current_password: String,
+    
+    #[validate(length(min = 8, message = "new_password must be at least 8 characters"))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(email(message = "email must be a valid email address"))]
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1, message = "token cannot be empty"))]
+    pub token: String,
+    
+    #[validate(length(min = 8, message = "new_password must be at least 8 characters"))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct VerifyEmailRequest {
+    #[validate(length(min = 1, message = "token cannot be empty"))]
+    pub token: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: Uuid,
     pub username: String,
     pub email: String,
+    pub email_verified: bool,
+    pub pending_email: Option<String>,
+    pub role: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -50,6 +154,9 @@ impl From<User> for UserResponse {
             id: user.id,
             username: user.username,
             email: user.email,
+            email_verified: user.email_verified_at.is_some(),
+            pending_email: user.pending_email,
+            role: user.role,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
@@ -60,13 +167,29 @@ impl From<User> for UserResponse {
 pub struct TokenResponse {
     pub token: String,
     pub token_type: String,
+    // Seconds until `token` expires
+    pub expires_in: u64,
+    pub refresh_token: String,
 }
 
 impl TokenResponse {
-    pub fn new(token: String) -> Self {
+    pub fn new(token: String, expires_in: u64, refresh_token: String) -> Self {
         Self {
             token,
             token_type: "Bearer".to_string(),
+            expires_in,
+            refresh_token,
         }
     }
 }
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct RefreshTokenRequest {
+    #[validate(length(min = 1, message = "refresh_token cannot be empty"))]
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateRoleRequest {
+    pub role: Role,
+}
@@ -2,18 +2,57 @@ use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use chrono::Utc;
+use rand::{rngs::OsRng as RandOsRng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use std::time::Duration;
 use uuid::Uuid;
 
-use crate::models::AppError;
+use crate::models::{AppError, Role};
+use crate::utils::keys::KeyStore;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,     // Subject (user ID)
     pub exp: usize,      // Expiration time (as UTC timestamp)
     pub iat: usize,      // Issued at (as UTC timestamp)
+    pub jti: String,     // Token ID, checked against the revocation list
+    pub sid: String,     // Refresh token family the token was issued from
+    #[serde(default)]
+    pub role: Role,      // Role at the time the token was issued
+    #[serde(default)]
+    pub iss: String,     // Issuer, set by the key store when signing
+    #[serde(default)]
+    pub aud: String,     // Audience, set by the key store when signing
+}
+
+impl Claims {
+    pub fn new(user_id: Uuid, role: Role, family_id: Uuid, ttl: Duration) -> Self {
+        let now = Utc::now().timestamp() as usize;
+        
+        Self {
+            sub: user_id.to_string(),
+            exp: now + ttl.as_secs() as usize,
+            iat: now,
+            jti: Uuid::new_v4().to_string(),
+            sid: family_id.to_string(),
+            role,
+            iss: String::new(),
+            aud: String::new(),
+        }
+    }
+    
+    pub fn token_id(&self) -> Result<Uuid, AppError> {
+        Uuid::parse_str(&self.jti)
+            .map_err(|_| AppError::AuthenticationError("Invalid token ID in token".to_string()))
+    }
+    
+    pub fn family_id(&self) -> Result<Uuid, AppError> {
+        Uuid::parse_str(&self.sid)
+            .map_err(|_| AppError::AuthenticationError("Invalid session in token".to_string()))
+    }
 }
 
 pub fn hash_password(password: &str) -> Result<String, AppError> {
@@ -34,42 +73,48 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
     Ok(result.is_ok())
 }
 
-pub fn generate_jwt(user_id: Uuid, secret: &str) -> Result<String, AppError> {
-    let now = Utc::now();
-    let iat = now.timestamp() as usize;
-    let exp = (now + Duration::hours(24)).timestamp() as usize;
+/// Spend as long as `verify_password` would, for logins with an unknown
+/// username, so response times do not reveal which usernames exist
+pub fn verify_dummy_password(password: &str) -> Result<(), AppError> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
     
-    let claims = Claims {
-        sub: user_id.to_string(),
-        exp,
-        iat,
+    let hash = match DUMMY_HASH.get() {
+        Some(hash) => hash,
+        None => {
+            let hash = hash_password(&generate_refresh_token())?;
+            DUMMY_HASH.get_or_init(|| hash)
+        }
     };
+    verify_password(password, hash)?;
     
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-    .map_err(|e| AppError::InternalServerError(format!("JWT generation error: {}", e)))
+    Ok(())
+}
+
+pub fn generate_jwt(claims: &Claims, keys: &KeyStore) -> Result<String, AppError> {
+    keys.sign(claims)
 }
 
 // Helper function used by middleware
-pub fn validate_jwt(token: &str, secret: &str) -> Result<Uuid, AppError> {
-    let decoded = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|e| AppError::AuthenticationError(format!("Invalid token: {}", e)))?;
+pub fn decode_jwt(token: &str, keys: &KeyStore) -> Result<(Uuid, Claims), AppError> {
+    let claims = keys.verify(token)?;
     
-    Uuid::parse_str(&decoded.claims.sub)
-        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::AuthenticationError("Invalid user ID in token".to_string()))?;
+    
+    Ok((user_id, claims))
 }
 
-// Helper function used by handlers where we don't have the JWT secret as an argument
-pub fn create_jwt(user_id: Uuid) -> Result<String, AppError> {
-    let jwt_secret = std::fs::read_to_string("jwt_secret.txt")
-        .map_err(|e| AppError::InternalServerError(format!("Failed to read JWT secret: {}", e)))?;
-    
-    generate_jwt(user_id, &jwt_secret)
+pub fn validate_jwt(token: &str, keys: &KeyStore) -> Result<Uuid, AppError> {
+    decode_jwt(token, keys).map(|(user_id, _)| user_id)
+}
+
+/// A random opaque refresh token; only its hash is stored
+pub fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; 32];
+    RandOsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use bigdecimal::{BigDecimal, One, Zero};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::models::{AppError, Currency};
+
+// Decimal places kept on exchange rates
+pub const RATE_SCALE: i64 = 10;
+
+/// Source of mid-market exchange rates
+#[async_trait]
+pub trait FxRateProvider: Send + Sync {
+    /// Units of `to` bought by one unit of `from`
+    async fn rate(&self, from: Currency, to: Currency) -> Result<BigDecimal, AppError>;
+}
+
+/// Rates read from a JSON file, so conversion works without an external feed.
+///
+/// The file lists what one unit of `base` buys in each currency, and any pair
+/// is derived from those:
+///
+/// ```json
+/// { "base": "USD", "rates": { "EUR": "0.92", "JPY": "155.20" } }
+/// ```
+pub struct FileFxRateProvider {
+    base: Currency,
+    rates: HashMap<&'static str, BigDecimal>,
+}
+
+#[derive(Deserialize)]
+struct RatesFile {
+    base: String,
+    rates: HashMap<String, String>,
+}
+
+impl FileFxRateProvider {
+    pub fn from_file(path: &str) -> Result<Self, AppError> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to read FX rates from {}: {}", path, e)))?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, AppError> {
+        let file: RatesFile = serde_json::from_str(json)
+            .map_err(|e| AppError::InternalServerError(format!("Invalid FX rates file: {}", e)))?;
+        let base = Currency::from_code(&file.base)?;
+
+        let mut rates = HashMap::new();
+        for (code, rate) in &file.rates {
+            let currency = Currency::from_code(code)?;
+            let rate = BigDecimal::from_str(rate)
+                .ok()
+                .filter(|rate| *rate > BigDecimal::zero())
+                .ok_or_else(|| AppError::InternalServerError(format!("Invalid FX rate for {}: {}", code, rate)))?;
+            rates.insert(currency.code(), rate);
+        }
+        rates.insert(base.code(), BigDecimal::one());
+
+        Ok(Self { base, rates })
+    }
+
+    /// A provider with no rates, which only allows same-currency "conversions"
+    pub fn empty(base: Currency) -> Self {
+        let mut rates = HashMap::new();
+        rates.insert(base.code(), BigDecimal::one());
+        Self { base, rates }
+    }
+
+    fn base_rate(&self, currency: Currency) -> Result<&BigDecimal, AppError> {
+        self.rates.get(currency.code()).ok_or_else(|| {
+            AppError::BadRequestError(format!("No exchange rate between {} and {}", self.base, currency))
+        })
+    }
+}
+
+#[async_trait]
+impl FxRateProvider for FileFxRateProvider {
+    async fn rate(&self, from: Currency, to: Currency) -> Result<BigDecimal, AppError> {
+        if from == to {
+            return Ok(BigDecimal::one());
+        }
+
+        let rate = self.base_rate(to)? / self.base_rate(from)?;
+        Ok(rate.with_scale(RATE_SCALE))
+    }
+}
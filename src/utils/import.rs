@@ -0,0 +1,302 @@
+use bigdecimal::BigDecimal;
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::models::{
+    describe_validation_errors, AppError, CreateTransactionRequest, Currency, ImportFormat, ImportLineError,
+    ImportReport, ImportedTransferResponse, Money,
+};
+use crate::utils::xml::{self, Element};
+
+// Columns a CSV file must have; `description` is optional and others are ignored
+const CSV_REQUIRED_COLUMNS: [&str; 3] = ["recipient_id", "amount", "currency"];
+
+/// A transfer read from an import file that passed every check
+pub struct ImportedTransfer {
+    // Line of the file the transfer starts on, from 1
+    pub line: usize,
+    pub request: CreateTransactionRequest,
+    pub amount: Money,
+}
+
+/// The transfers in an import file and the problems found in it
+pub struct ParsedImport {
+    // Transfers in the file, including those with errors
+    pub item_count: usize,
+    pub transfers: Vec<ImportedTransfer>,
+    pub errors: Vec<ImportLineError>,
+}
+
+impl ParsedImport {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The sum of the transfers that were read, which share one currency
+    pub fn total(&self) -> Option<Money> {
+        let currency = self.transfers.first()?.amount.currency();
+        let sum = self
+            .transfers
+            .iter()
+            .fold(BigDecimal::from(0), |sum, transfer| sum + transfer.amount.amount());
+        Money::new(sum, currency).ok()
+    }
+
+    /// The transfers as they would be submitted to a batch
+    pub fn requests(&self) -> Vec<CreateTransactionRequest> {
+        self.transfers.iter().map(|transfer| transfer.request.clone()).collect()
+    }
+
+    pub fn report(&self, format: ImportFormat, dry_run: bool) -> ImportReport {
+        let total = self.total();
+        let mut errors = self.errors.clone();
+        // Problems with the whole file come first, then the rest in file order
+        errors.sort_by_key(|error| error.line);
+
+        ImportReport {
+            format,
+            dry_run,
+            valid: errors.is_empty(),
+            item_count: self.item_count,
+            currency: total.as_ref().map(|total| total.currency().code().to_string()),
+            total_amount: total,
+            items: self
+                .transfers
+                .iter()
+                .map(|transfer| ImportedTransferResponse {
+                    line: transfer.line,
+                    recipient_id: transfer.request.recipient_id,
+                    amount: transfer.amount.clone(),
+                    description: transfer.request.description.clone(),
+                })
+                .collect(),
+            errors,
+        }
+    }
+}
+
+/// Read the transfers in an import file and check each as a batch would.
+///
+/// Reading goes on past bad lines so that every problem in the file is
+/// reported at once, each with the line it starts on. Only a file that
+/// cannot be read at all stops early.
+pub fn parse_transfers(format: ImportFormat, input: &str, max_items: u32) -> ParsedImport {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let mut parsed = ParsedImport { item_count: 0, transfers: Vec::new(), errors: Vec::new() };
+
+    let records = match format {
+        ImportFormat::Csv => read_csv(input),
+        ImportFormat::Pain001 => read_pain001(input),
+    };
+    let records = match records {
+        Ok(records) => records,
+        Err(err) => {
+            parsed.errors.push(err);
+            return parsed;
+        }
+    };
+
+    parsed.item_count = records.rows.len();
+    parsed.errors = records.errors;
+
+    let mut currency: Option<Currency> = None;
+    for (line, row) in records.rows {
+        match row.and_then(check_transfer) {
+            Ok((_, amount)) if currency.is_some_and(|currency| currency != amount.currency()) => {
+                parsed.errors.push(line_error(line, "Every transfer in a batch must be in the same currency"));
+            }
+            Ok((request, amount)) => {
+                currency = Some(amount.currency());
+                parsed.transfers.push(ImportedTransfer { line, request, amount });
+            }
+            Err(message) => parsed.errors.push(line_error(line, message)),
+        }
+    }
+
+    if parsed.item_count == 0 {
+        parsed.errors.push(file_error("The file holds no transfers"));
+    } else if parsed.item_count > max_items as usize {
+        parsed.errors.push(file_error(format!("A batch can hold at most {} transfers", max_items)));
+    }
+
+    parsed
+}
+
+// A record read from a file: the line it starts on, and the transfer it
+// describes or why it does not describe one
+type Row = (usize, Result<CreateTransactionRequest, String>);
+
+struct Records {
+    rows: Vec<Row>,
+    // Problems with the file as a whole that do not stop it being read
+    errors: Vec<ImportLineError>,
+}
+
+// Read a CSV file whose header row names its columns
+fn read_csv(input: &str) -> Result<Records, ImportLineError> {
+    let mut records = csv_records(input)?.into_iter();
+    let (header_line, header) = records.next().ok_or_else(|| file_error("The file holds no transfers"))?;
+    let header: Vec<String> = header.iter().map(|column| column.to_ascii_lowercase()).collect();
+
+    let column = |name: &str| header.iter().position(|column| column == name);
+    let missing: Vec<&str> = CSV_REQUIRED_COLUMNS.into_iter().filter(|name| column(name).is_none()).collect();
+    if !missing.is_empty() {
+        return Err(line_error(header_line, format!("The header row has no {} column", missing.join(", "))));
+    }
+    let [recipient, amount, currency] = CSV_REQUIRED_COLUMNS.map(|name| column(name).unwrap_or_default());
+    let description = column("description");
+
+    let rows = records
+        .map(|(line, fields)| {
+            if fields.len() != header.len() {
+                let message = format!("Expected {} fields but found {}", header.len(), fields.len());
+                return (line, Err(message));
+            }
+            let description = description.map(|index| fields[index].as_str());
+            (line, transfer_request(&fields[recipient], &fields[amount], &fields[currency], description))
+        })
+        .collect();
+
+    Ok(Records { rows, errors: Vec::new() })
+}
+
+// Split CSV text into records, each with the line it starts on. Fields are
+// trimmed and blank records are dropped.
+fn csv_records(input: &str) -> Result<Vec<(usize, Vec<String>)>, ImportLineError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input.as_bytes());
+
+    let mut records = Vec::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        // The reader's position is at the end of the previous record, before
+        // its line break and any blank lines after it
+        let position = reader.position().clone();
+        let rest = &input[position.byte() as usize..];
+        let skipped = &rest[..rest.len() - rest.trim_start_matches(['\r', '\n']).len()];
+        let line = position.line() as usize + skipped.matches('\n').count();
+
+        if !reader.read_record(&mut record).map_err(|err| line_error(line, err.to_string()))? {
+            break;
+        }
+        // The reader takes an unclosed quote to run to the end of the file
+        let end = reader.position().byte() as usize;
+        if end == input.len() && rest.matches('"').count() % 2 == 1 {
+            return Err(line_error(line, "A quoted field is never closed"));
+        }
+        if record.iter().any(|field| !field.is_empty()) {
+            records.push((line, record.iter().map(String::from).collect()));
+        }
+    }
+
+    Ok(records)
+}
+
+// Read the credit transfers of an ISO 20022 pain.001 file. The recipient's
+// user id goes in each creditor account's `Othr/Id`, since the service has no
+// IBANs of its own.
+fn read_pain001(input: &str) -> Result<Records, ImportLineError> {
+    let document =
+        xml::parse(input).map_err(|err| ImportLineError { line: Some(err.line), message: err.message })?;
+    let initiation = match document.name.as_str() {
+        "CstmrCdtTrfInitn" => &document,
+        _ => document.child("CstmrCdtTrfInitn").ok_or_else(|| {
+            line_error(document.line, "Not a pain.001 file: there is no CstmrCdtTrfInitn element")
+        })?,
+    };
+
+    let rows: Vec<Row> = initiation
+        .children_named("PmtInf")
+        .flat_map(|payment| payment.children_named("CdtTrfTxInf"))
+        .map(|transaction| (transaction.line, credit_transfer(transaction)))
+        .collect();
+
+    // The group header's count and control sum catch truncated files
+    let mut errors = Vec::new();
+    if let Some(count) = initiation.find(&["GrpHdr", "NbOfTxs"]) {
+        if count.trimmed_text().parse::<usize>().ok() != Some(rows.len()) {
+            errors.push(line_error(
+                count.line,
+                format!("NbOfTxs is {} but the file holds {} transfers", count.trimmed_text(), rows.len()),
+            ));
+        }
+    }
+    if let Some(control_sum) = initiation.find(&["GrpHdr", "CtrlSum"]) {
+        let amounts: Option<BigDecimal> =
+            rows.iter().map(|(_, row)| row.as_ref().ok().map(|request| request.amount.clone())).sum();
+        if let Some(sum) = amounts {
+            if BigDecimal::from_str(control_sum.trimmed_text()).ok() != Some(sum.clone()) {
+                errors.push(line_error(
+                    control_sum.line,
+                    format!("CtrlSum is {} but the transfers add up to {}", control_sum.trimmed_text(), sum),
+                ));
+            }
+        }
+    }
+
+    Ok(Records { rows, errors })
+}
+
+// The transfer described by a `CdtTrfTxInf` element
+fn credit_transfer(transaction: &Element) -> Result<CreateTransactionRequest, String> {
+    let amount = transaction
+        .find(&["Amt", "InstdAmt"])
+        .ok_or_else(|| "Amt/InstdAmt is missing".to_string())?;
+    let currency = amount
+        .attribute("Ccy")
+        .ok_or_else(|| "InstdAmt has no Ccy attribute".to_string())?;
+    let recipient = transaction
+        .find(&["CdtrAcct", "Id", "Othr", "Id"])
+        .ok_or_else(|| "CdtrAcct/Id/Othr/Id must hold the recipient's user id".to_string())?;
+    let description = transaction.find(&["RmtInf", "Ustrd"]).map(Element::trimmed_text);
+
+    transfer_request(recipient.trimmed_text(), amount.trimmed_text(), currency, description)
+}
+
+fn transfer_request(
+    recipient: &str,
+    amount: &str,
+    currency: &str,
+    description: Option<&str>,
+) -> Result<CreateTransactionRequest, String> {
+    let recipient_id = match recipient.trim() {
+        "" => return Err("recipient_id is missing".to_string()),
+        recipient => Uuid::parse_str(recipient).map_err(|_| format!("recipient_id {} is not a valid user id", recipient))?,
+    };
+    let amount = match amount.trim() {
+        "" => return Err("amount is missing".to_string()),
+        amount => BigDecimal::from_str(amount).map_err(|_| format!("amount {} is not a number", amount))?,
+    };
+
+    Ok(CreateTransactionRequest {
+        recipient_id,
+        amount,
+        currency: currency.trim().to_ascii_uppercase(),
+        description: description.map(str::trim).filter(|description| !description.is_empty()).map(String::from),
+        capture: None,
+        quote_id: None,
+    })
+}
+
+// Apply the checks a transfer in a batch goes through before any is made
+fn check_transfer(request: CreateTransactionRequest) -> Result<(CreateTransactionRequest, Money), String> {
+    request.validate().map_err(|errors| describe_validation_errors(&errors))?;
+    let amount = Money::positive(request.amount.clone(), &request.currency).map_err(|err| match err {
+        AppError::BadRequestError(msg) => msg,
+        err => err.to_string(),
+    })?;
+
+    Ok((request, amount))
+}
+
+fn line_error(line: usize, message: impl Into<String>) -> ImportLineError {
+    ImportLineError { line: Some(line), message: message.into() }
+}
+
+fn file_error(message: impl Into<String>) -> ImportLineError {
+    ImportLineError { line: None, message: message.into() }
+}
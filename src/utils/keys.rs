@@ -0,0 +1,360 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use simple_asn1::{oid, ASN1Block};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::config::JwtKeyConfig;
+use crate::models::{AppError, Role};
+use crate::utils::auth::Claims;
+
+/// A public key as published at `/.well-known/jwks.json` (RFC 7517)
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicJwk {
+    pub kty: &'static str,
+    pub kid: String,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<PublicJwk>,
+}
+
+struct VerifyingKey {
+    algorithm: Algorithm,
+    decoding: DecodingKey,
+    // None for HMAC secrets, which are never published
+    jwk: Option<PublicJwk>,
+}
+
+struct KeySet {
+    active_kid: String,
+    algorithm: Algorithm,
+    signing: EncodingKey,
+    verifying: HashMap<String, VerifyingKey>,
+    issuer: String,
+    audience: String,
+    // Tokens issued before this time, as a UTC timestamp, may lack `iss` and `aud`
+    legacy_before: usize,
+}
+
+impl KeySet {
+    fn new(config: &JwtKeyConfig, legacy_before: usize) -> Result<Self, AppError> {
+        let algorithm = match Algorithm::from_str(&config.algorithm) {
+            Ok(algorithm @ (Algorithm::HS256 | Algorithm::RS256 | Algorithm::ES256 | Algorithm::EdDSA)) => algorithm,
+            _ => return Err(key_error(format!("Unsupported JWT algorithm: {}", config.algorithm))),
+        };
+
+        let mut verifying = HashMap::new();
+        for secret in &config.previous_secrets {
+            verifying.insert(key_id(secret), hmac_key(secret));
+        }
+        if let Some(pem) = &config.previous_public_keys_pem {
+            let keys = pem::parse_many(pem).map_err(|e| key_error(format!("Invalid previous public keys: {}", e)))?;
+            for key in keys {
+                let (kid, key) = public_key(&pem::encode(&key))?;
+                verifying.insert(kid, key);
+            }
+        }
+
+        let (active_kid, signing) = if algorithm == Algorithm::HS256 {
+            if config.secret.is_empty() {
+                return Err(key_error("JWT secret is empty".to_string()));
+            }
+            let kid = key_id(&config.secret);
+            verifying.insert(kid.clone(), hmac_key(&config.secret));
+            (kid, EncodingKey::from_secret(config.secret.as_bytes()))
+        } else {
+            let private_pem = config.private_key_pem.as_deref()
+                .ok_or_else(|| key_error(format!("{} needs a JWT private key", config.algorithm)))?;
+            let public_pem = config.public_key_pem.as_deref()
+                .ok_or_else(|| key_error(format!("{} needs a JWT public key", config.algorithm)))?;
+
+            let (kid, key) = public_key(public_pem)?;
+            if key.algorithm != algorithm {
+                return Err(key_error(format!("JWT public key is not a {} key", config.algorithm)));
+            }
+            let signing = match algorithm {
+                Algorithm::RS256 => EncodingKey::from_rsa_pem(private_pem.as_bytes()),
+                Algorithm::ES256 => EncodingKey::from_ec_pem(private_pem.as_bytes()),
+                _ => EncodingKey::from_ed_pem(private_pem.as_bytes()),
+            }
+            .map_err(|e| key_error(format!("Invalid JWT private key: {}", e)))?;
+
+            verifying.insert(kid.clone(), key);
+            (kid, signing)
+        };
+
+        let keys = Self {
+            active_kid,
+            algorithm,
+            signing,
+            verifying,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            legacy_before,
+        };
+
+        // Catch a private key that does not belong to the public key now,
+        // rather than on the first login
+        let probe = Claims::new(uuid::Uuid::nil(), Role::User, uuid::Uuid::nil(), std::time::Duration::from_secs(60));
+        keys.verify(&keys.sign(&probe)?)
+            .map_err(|_| key_error("JWT private key does not match the public key".to_string()))?;
+
+        Ok(keys)
+    }
+
+    fn sign(&self, claims: &Claims) -> Result<String, AppError> {
+        let header = Header {
+            kid: Some(self.active_kid.clone()),
+            ..Header::new(self.algorithm)
+        };
+        let claims = Claims {
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            ..claims.clone()
+        };
+
+        encode(&header, &claims, &self.signing)
+            .map_err(|e| AppError::InternalServerError(format!("JWT generation error: {}", e)))
+    }
+
+    fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        let header = decode_header(token)
+            .map_err(|e| AppError::AuthenticationError(format!("Invalid token: {}", e)))?;
+
+        // Tokens issued before key ids were introduced carry none
+        let kid = header.kid.as_deref().unwrap_or(&self.active_kid);
+        let key = self
+            .verifying
+            .get(kid)
+            .ok_or_else(|| AppError::AuthenticationError("Invalid token: unknown signing key".to_string()))?;
+
+        // Only the key's own algorithm is accepted, whatever the header claims
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+        let invalid = |e: jsonwebtoken::errors::Error| AppError::AuthenticationError(format!("Invalid token: {}", e));
+        match decode::<Claims>(token, &key.decoding, &validation) {
+            Ok(decoded) => Ok(decoded.claims),
+            // Tokens issued before `iss` and `aud` were stamped on them carry
+            // neither. Those issued before this server started are accepted
+            // until they expire, so upgrading does not end every session.
+            Err(e) if matches!(e.kind(), ErrorKind::MissingRequiredClaim(_)) => {
+                validation.set_required_spec_claims(&["exp"]);
+                let claims = decode::<Claims>(token, &key.decoding, &validation).map_err(invalid)?.claims;
+                if !claims.iss.is_empty() || !claims.aud.is_empty() || claims.iat >= self.legacy_before {
+                    return Err(invalid(e));
+                }
+                Ok(claims)
+            }
+            Err(e) => Err(invalid(e)),
+        }
+    }
+}
+
+/// The keys used to sign and verify JWTs, built once at startup and shared as
+/// app data.
+///
+/// New tokens are signed with the active key and name it in the `kid` header.
+/// Retired keys stay available for verification, so rotating a key does not
+/// end every session. `reload` swaps in keys re-read from the configuration
+/// without a restart.
+pub struct KeyStore {
+    keys: RwLock<Arc<KeySet>>,
+}
+
+impl KeyStore {
+    pub fn new(config: &JwtKeyConfig) -> Result<Self, AppError> {
+        let started_at = Utc::now().timestamp() as usize;
+        Ok(Self {
+            keys: RwLock::new(Arc::new(KeySet::new(config, started_at)?)),
+        })
+    }
+
+    /// A store with a single HS256 key, for tools and tests
+    pub fn from_secret(secret: &str) -> Result<Self, AppError> {
+        Self::new(&JwtKeyConfig::from_secret(secret))
+    }
+
+    /// Re-read the keys from their files and environment, keeping the current
+    /// keys if that fails
+    pub fn reload(&self) -> Result<(), AppError> {
+        self.replace(&JwtKeyConfig::load())
+    }
+
+    pub fn replace(&self, config: &JwtKeyConfig) -> Result<(), AppError> {
+        let keys = Arc::new(KeySet::new(config, self.current()?.legacy_before)?);
+        *self.keys.write().map_err(|_| lock_poisoned())? = keys;
+        Ok(())
+    }
+
+    pub fn active_kid(&self) -> Result<String, AppError> {
+        Ok(self.current()?.active_kid.clone())
+    }
+
+    /// Every key id tokens are accepted from, sorted
+    pub fn kids(&self) -> Result<Vec<String>, AppError> {
+        let mut kids: Vec<String> = self.current()?.verifying.keys().cloned().collect();
+        kids.sort();
+        Ok(kids)
+    }
+
+    /// The public keys that verify tokens, active key first
+    pub fn jwks(&self) -> Result<JwkSet, AppError> {
+        let keys = self.current()?;
+        let mut jwks: Vec<PublicJwk> = keys.verifying.values().filter_map(|key| key.jwk.clone()).collect();
+        jwks.sort_by_key(|jwk| (jwk.kid != keys.active_kid, jwk.kid.clone()));
+        Ok(JwkSet { keys: jwks })
+    }
+
+    /// Sign `claims` with the active key, stamping this store's issuer and audience
+    pub fn sign(&self, claims: &Claims) -> Result<String, AppError> {
+        self.current()?.sign(claims)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        self.current()?.verify(token)
+    }
+
+    fn current(&self) -> Result<Arc<KeySet>, AppError> {
+        Ok(Arc::clone(&*self.keys.read().map_err(|_| lock_poisoned())?))
+    }
+}
+
+/// A stable id for an HMAC secret: a short prefix of its SHA-256 digest
+pub fn key_id(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))[..16].to_string()
+}
+
+fn hmac_key(secret: &str) -> VerifyingKey {
+    VerifyingKey {
+        algorithm: Algorithm::HS256,
+        decoding: DecodingKey::from_secret(secret.as_bytes()),
+        jwk: None,
+    }
+}
+
+// Load a PEM public key, taking its algorithm from the key type and its id
+// from its RFC 7638 thumbprint
+fn public_key(pem: &str) -> Result<(String, VerifyingKey), AppError> {
+    let parsed = pem::parse(pem).map_err(|e| key_error(format!("Invalid JWT public key: {}", e)))?;
+    if parsed.tag != "PUBLIC KEY" {
+        return Err(key_error(format!("Expected a PUBLIC KEY PEM block, found {}", parsed.tag)));
+    }
+    let (algorithm, jwk) = jwk_from_spki(&parsed.contents)?;
+
+    let decoding = match algorithm {
+        Algorithm::RS256 => DecodingKey::from_rsa_pem(pem.as_bytes()),
+        Algorithm::ES256 => DecodingKey::from_ec_pem(pem.as_bytes()),
+        _ => DecodingKey::from_ed_pem(pem.as_bytes()),
+    }
+    .map_err(|e| key_error(format!("Invalid JWT public key: {}", e)))?;
+
+    Ok((jwk.kid.clone(), VerifyingKey { algorithm, decoding, jwk: Some(jwk) }))
+}
+
+// Pull the JWK parameters out of a DER SubjectPublicKeyInfo
+fn jwk_from_spki(der: &[u8]) -> Result<(Algorithm, PublicJwk), AppError> {
+    let invalid = || key_error("Unsupported or malformed JWT public key".to_string());
+
+    let blocks = simple_asn1::from_der(der).map_err(|_| invalid())?;
+    let (algorithm_id, key_bits) = match blocks.as_slice() {
+        [ASN1Block::Sequence(_, items)] => match items.as_slice() {
+            [ASN1Block::Sequence(_, algorithm_id), ASN1Block::BitString(_, _, bits)] => (algorithm_id, bits),
+            _ => return Err(invalid()),
+        },
+        _ => return Err(invalid()),
+    };
+    let key_type = match algorithm_id.first() {
+        Some(ASN1Block::ObjectIdentifier(_, oid)) => oid,
+        _ => return Err(invalid()),
+    };
+
+    let jwk = |kty, alg, crv, n, e, x, y| PublicJwk { kty, kid: String::new(), alg, key_use: "sig", crv, n, e, x, y };
+
+    let (algorithm, mut jwk) = if *key_type == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        let (n, e) = match simple_asn1::from_der(key_bits).map_err(|_| invalid())?.as_slice() {
+            [ASN1Block::Sequence(_, numbers)] => match numbers.as_slice() {
+                [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => (n.to_bytes_be().1, e.to_bytes_be().1),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+        (Algorithm::RS256, jwk("RSA", "RS256", None, Some(b64(&n)), Some(b64(&e)), None, None))
+    } else if *key_type == oid!(1, 2, 840, 10045, 2, 1) {
+        let is_p256 = matches!(algorithm_id.get(1), Some(ASN1Block::ObjectIdentifier(_, curve)) if *curve == oid!(1, 2, 840, 10045, 3, 1, 7));
+        // An uncompressed point: 0x04 followed by x and y
+        if !is_p256 || key_bits.len() != 65 || key_bits[0] != 4 {
+            return Err(invalid());
+        }
+        let (x, y) = key_bits[1..].split_at(32);
+        (Algorithm::ES256, jwk("EC", "ES256", Some("P-256"), None, None, Some(b64(x)), Some(b64(y))))
+    } else if *key_type == oid!(1, 3, 101, 112) {
+        if key_bits.len() != 32 {
+            return Err(invalid());
+        }
+        (Algorithm::EdDSA, jwk("OKP", "EdDSA", Some("Ed25519"), None, None, Some(b64(key_bits)), None))
+    } else {
+        return Err(invalid());
+    };
+
+    jwk.kid = thumbprint(&jwk);
+    Ok((algorithm, jwk))
+}
+
+// RFC 7638: hash the required members in lexicographic order
+fn thumbprint(jwk: &PublicJwk) -> String {
+    let canonical = match jwk.kty {
+        "RSA" => format!(
+            r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+            jwk.e.as_deref().unwrap_or_default(),
+            jwk.n.as_deref().unwrap_or_default()
+        ),
+        "EC" => format!(
+            r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk.crv.unwrap_or_default(),
+            jwk.x.as_deref().unwrap_or_default(),
+            jwk.y.as_deref().unwrap_or_default()
+        ),
+        _ => format!(
+            r#"{{"crv":"{}","kty":"{}","x":"{}"}}"#,
+            jwk.crv.unwrap_or_default(),
+            jwk.kty,
+            jwk.x.as_deref().unwrap_or_default()
+        ),
+    };
+    b64(&Sha256::digest(canonical.as_bytes()))
+}
+
+fn b64(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn key_error(message: String) -> AppError {
+    AppError::InternalServerError(message)
+}
+
+fn lock_poisoned() -> AppError {
+    AppError::InternalServerError("JWT key store lock poisoned".to_string())
+}
@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::models::AppError;
+
+/// An email ready to be delivered
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers emails such as password reset links
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), AppError>;
+}
+
+/// Appends emails to a local file instead of sending them, so the flows that
+/// need email work without a mail server.
+///
+/// Each email is also logged, without its body since that may hold a token.
+pub struct FileMailer {
+    path: PathBuf,
+    from: String,
+    // Serializes writes so concurrent emails are not interleaved
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>, from: &str) -> Self {
+        Self {
+            path: path.into(),
+            from: from.to_string(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), AppError> {
+        let message = format!(
+            "Date: {}\nFrom: {}\nTo: {}\nSubject: {}\n\n{}\n\n",
+            Utc::now().to_rfc2822(),
+            self.from,
+            email.to,
+            email.subject,
+            email.body
+        );
+
+        let write_error = |e: std::io::Error| {
+            AppError::InternalServerError(format!("Failed to write email to {}: {}", self.path.display(), e))
+        };
+
+        {
+            let _guard = self
+                .lock
+                .lock()
+                .map_err(|_| AppError::InternalServerError("Mail outbox lock poisoned".to_string()))?;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .map_err(write_error)?;
+            file.write_all(message.as_bytes()).map_err(write_error)?;
+        }
+
+        info!("Wrote email \"{}\" to {} in {}", email.subject, email.to, self.path.display());
+        Ok(())
+    }
+}
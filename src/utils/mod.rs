@@ -1,4 +1,11 @@
 pub mod auth;
+pub mod fx;
+pub mod import;
+pub mod keys;
+pub mod mailer;
+pub mod statement;
+pub mod totp;
+pub mod xml;
 
 // No re-exports to avoid unused import warnings
-// Other modules should import directly from auth module
+// Other modules should import directly from the submodules
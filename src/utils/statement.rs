@@ -0,0 +1,342 @@
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use uuid::Uuid;
+
+use crate::models::ledger::EntryType;
+use crate::models::{Money, Statement, StatementEntry, StatementFormat};
+use crate::utils::xml::escape;
+
+// Longest texts the formats allow in the fields descriptions go into
+const OFX_NAME_CHARS: usize = 32;
+const OFX_MEMO_CHARS: usize = 255;
+const CAMT_REMITTANCE_CHARS: usize = 140;
+
+// A statement is written as its header, then each entry, then its footer, so
+// that the entries can be streamed as they are read. Every format needs both
+// balances before the first entry or after the last, which is why they are
+// known up front.
+
+/// The part of a statement before its entries
+pub fn header(format: StatementFormat, statement: &Statement) -> String {
+    match format {
+        StatementFormat::Csv => csv_header(statement),
+        StatementFormat::Ofx => ofx_header(statement),
+        StatementFormat::Camt053 => camt053_header(statement),
+    }
+}
+
+/// One entry of a statement
+pub fn entry(format: StatementFormat, entry: &StatementEntry) -> String {
+    match format {
+        StatementFormat::Csv => csv_entry(entry),
+        StatementFormat::Ofx => ofx_entry(entry),
+        StatementFormat::Camt053 => camt053_entry(entry),
+    }
+}
+
+/// The part of a statement after its entries
+pub fn footer(format: StatementFormat, statement: &Statement) -> String {
+    match format {
+        StatementFormat::Csv => csv_footer(statement),
+        StatementFormat::Ofx => ofx_footer(statement),
+        StatementFormat::Camt053 => camt053_footer(),
+    }
+}
+
+/// A file name for the statement, e.g. `statement-USD-2025-05-01-2025-05-31.csv`
+pub fn file_name(format: StatementFormat, statement: &Statement) -> String {
+    format!(
+        "statement-{}-{}-{}.{}",
+        statement.opening_balance.currency(),
+        statement.from,
+        statement.to,
+        format.extension()
+    )
+}
+
+// The first and last instants of the statement period
+fn period_start(statement: &Statement) -> DateTime<Utc> {
+    start_of(statement.from)
+}
+
+fn period_end(statement: &Statement) -> DateTime<Utc> {
+    start_of(statement.to) + Duration::days(1) - Duration::seconds(1)
+}
+
+/// Midnight UTC at the start of `date`
+pub fn start_of(date: NaiveDate) -> DateTime<Utc> {
+    DateTime::from_naive_utc_and_offset(date.and_hms_opt(0, 0, 0).unwrap_or_default(), Utc)
+}
+
+fn iso_date_time(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// The amount with a minus sign for debits
+fn signed_amount(entry: &StatementEntry) -> String {
+    match entry.entry_type {
+        EntryType::Credit => entry.amount.to_string(),
+        EntryType::Debit => format!("-{}", entry.amount),
+    }
+}
+
+// The amount without its sign, and whether it is a credit or a debit
+fn unsigned(money: &Money) -> (String, &'static str) {
+    let magnitude = Money::new(money.amount().abs(), money.currency()).unwrap_or_else(|_| money.clone());
+    let indicator = if *money.amount() < BigDecimal::zero() { "DBIT" } else { "CRDT" };
+    (magnitude.to_string(), indicator)
+}
+
+fn truncate(text: &str, max_chars: usize) -> &str {
+    text.char_indices().nth(max_chars).map_or(text, |(end, _)| &text[..end])
+}
+
+const CSV_COLUMNS: &str = "date,entry_id,transaction_id,counterparty_id,type,description,amount,currency,balance";
+
+fn csv_header(statement: &Statement) -> String {
+    format!(
+        "{}\r\n{}\r\n",
+        CSV_COLUMNS,
+        csv_balance_row(period_start(statement), "opening_balance", "Opening balance", &statement.opening_balance)
+    )
+}
+
+fn csv_entry(entry: &StatementEntry) -> String {
+    let optional = |id: Option<Uuid>| id.map(|id| id.to_string()).unwrap_or_default();
+    format!(
+        "{},{},{},{},{},{},{},{},{}\r\n",
+        iso_date_time(entry.booked_at),
+        entry.id,
+        optional(entry.transaction_id),
+        optional(entry.counterparty_id),
+        entry.entry_type.as_str(),
+        csv_field(entry.description.as_deref().unwrap_or_default()),
+        signed_amount(entry),
+        entry.amount.currency(),
+        entry.balance
+    )
+}
+
+fn csv_footer(statement: &Statement) -> String {
+    format!(
+        "{}\r\n",
+        csv_balance_row(period_end(statement), "closing_balance", "Closing balance", &statement.closing_balance)
+    )
+}
+
+fn csv_balance_row(at: DateTime<Utc>, kind: &str, description: &str, balance: &Money) -> String {
+    format!("{},,,,{},{},,{},{}", iso_date_time(at), kind, description, balance.currency(), balance)
+}
+
+// Quote a field if it needs it. Text a spreadsheet would read as a formula
+// gets a leading apostrophe, since descriptions are written by other users.
+fn csv_field(text: &str) -> String {
+    let text = if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    };
+
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+fn ofx_date_time(at: DateTime<Utc>) -> String {
+    at.format("%Y%m%d%H%M%S%.3f[0:GMT]").to_string()
+}
+
+fn ofx_header(statement: &Statement) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+<SIGNONMSGSRSV1>
+<SONRS>
+<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>
+<DTSERVER>{generated_at}</DTSERVER>
+<LANGUAGE>ENG</LANGUAGE>
+</SONRS>
+</SIGNONMSGSRSV1>
+<BANKMSGSRSV1>
+<STMTTRNRS>
+<TRNUID>{id}</TRNUID>
+<STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>
+<STMTRS>
+<CURDEF>{currency}</CURDEF>
+<BANKACCTFROM>
+<BANKID>DODO</BANKID>
+<ACCTID>{account_id}</ACCTID>
+<ACCTTYPE>CHECKING</ACCTTYPE>
+</BANKACCTFROM>
+<BANKTRANLIST>
+<DTSTART>{start}</DTSTART>
+<DTEND>{end}</DTEND>
+"#,
+        generated_at = ofx_date_time(statement.generated_at),
+        id = statement.id,
+        currency = statement.opening_balance.currency(),
+        account_id = statement.account_id,
+        start = ofx_date_time(period_start(statement)),
+        end = ofx_date_time(period_end(statement)),
+    )
+}
+
+// OFX has no field for the balance after each transaction; its closing and
+// opening balances are in LEDGERBAL and BALLIST
+fn ofx_entry(entry: &StatementEntry) -> String {
+    let name = match entry.description.as_deref() {
+        Some(description) => format!("<NAME>{}</NAME>\n", escape(truncate(description, OFX_NAME_CHARS))),
+        None => String::new(),
+    };
+    let memo = match entry.description.as_deref() {
+        Some(description) if description.chars().count() > OFX_NAME_CHARS => {
+            format!("<MEMO>{}</MEMO>\n", escape(truncate(description, OFX_MEMO_CHARS)))
+        }
+        _ => String::new(),
+    };
+
+    format!(
+        "<STMTTRN>\n<TRNTYPE>{}</TRNTYPE>\n<DTPOSTED>{}</DTPOSTED>\n<TRNAMT>{}</TRNAMT>\n<FITID>{}</FITID>\n{}{}</STMTTRN>\n",
+        match entry.entry_type {
+            EntryType::Credit => "CREDIT",
+            EntryType::Debit => "DEBIT",
+        },
+        ofx_date_time(entry.booked_at),
+        signed_amount(entry),
+        entry.id,
+        name,
+        memo
+    )
+}
+
+fn ofx_footer(statement: &Statement) -> String {
+    format!(
+        r#"</BANKTRANLIST>
+<LEDGERBAL><BALAMT>{closing}</BALAMT><DTASOF>{end}</DTASOF></LEDGERBAL>
+<BALLIST>
+<BAL><NAME>Opening balance</NAME><DESC>Balance at the start of the statement</DESC><BALTYPE>DOLLAR</BALTYPE><VALUE>{opening}</VALUE><DTASOF>{start}</DTASOF></BAL>
+</BALLIST>
+</STMTRS>
+</STMTTRNRS>
+</BANKMSGSRSV1>
+</OFX>
+"#,
+        closing = statement.closing_balance,
+        end = ofx_date_time(period_end(statement)),
+        opening = statement.opening_balance,
+        start = ofx_date_time(period_start(statement)),
+    )
+}
+
+fn camt053_header(statement: &Statement) -> String {
+    let currency = statement.opening_balance.currency();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <GrpHdr>
+      <MsgId>{id}</MsgId>
+      <CreDtTm>{generated_at}</CreDtTm>
+    </GrpHdr>
+    <Stmt>
+      <Id>{id}</Id>
+      <CreDtTm>{generated_at}</CreDtTm>
+      <FrToDt>
+        <FrDtTm>{start}</FrDtTm>
+        <ToDtTm>{end}</ToDtTm>
+      </FrToDt>
+      <Acct>
+        <Id><Othr><Id>{account_id}</Id></Othr></Id>
+        <Ccy>{currency}</Ccy>
+      </Acct>
+{opening}{closing}"#,
+        id = statement.id.simple(),
+        generated_at = iso_date_time(statement.generated_at),
+        start = iso_date_time(period_start(statement)),
+        end = iso_date_time(period_end(statement)),
+        account_id = statement.account_id,
+        currency = currency,
+        opening = camt053_balance("OPBD", &statement.opening_balance, statement.from),
+        closing = camt053_balance("CLBD", &statement.closing_balance, statement.to),
+    )
+}
+
+fn camt053_balance(code: &str, balance: &Money, date: NaiveDate) -> String {
+    let (amount, indicator) = unsigned(balance);
+    format!(
+        r#"      <Bal>
+        <Tp><CdOrPrtry><Cd>{}</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="{}">{}</Amt>
+        <CdtDbtInd>{}</CdtDbtInd>
+        <Dt><Dt>{}</Dt></Dt>
+      </Bal>
+"#,
+        code,
+        balance.currency(),
+        amount,
+        indicator,
+        date
+    )
+}
+
+// The entry's running balance goes in AddtlNtryInf, as camt.053 has no field
+// for it. The other user's id is in DbtrAcct or CdtrAcct, where pain.001
+// imports take it from.
+fn camt053_entry(entry: &StatementEntry) -> String {
+    let (indicator, family, party) = match entry.entry_type {
+        EntryType::Credit => ("CRDT", "RCDT", "DbtrAcct"),
+        EntryType::Debit => ("DBIT", "ICDT", "CdtrAcct"),
+    };
+    let booked_at = iso_date_time(entry.booked_at);
+
+    let mut details = String::new();
+    if let Some(transaction_id) = entry.transaction_id {
+        details.push_str(&format!("            <Refs><TxId>{}</TxId></Refs>\n", transaction_id.simple()));
+    }
+    if let Some(counterparty_id) = entry.counterparty_id {
+        details.push_str(&format!(
+            "            <RltdPties><{party}><Id><Othr><Id>{}</Id></Othr></Id></{party}></RltdPties>\n",
+            counterparty_id,
+            party = party
+        ));
+    }
+    if let Some(description) = entry.description.as_deref() {
+        details.push_str(&format!(
+            "            <RmtInf><Ustrd>{}</Ustrd></RmtInf>\n",
+            escape(truncate(description, CAMT_REMITTANCE_CHARS))
+        ));
+    }
+    if !details.is_empty() {
+        details = format!("        <NtryDtls>\n          <TxDtls>\n{}          </TxDtls>\n        </NtryDtls>\n", details);
+    }
+
+    format!(
+        r#"      <Ntry>
+        <NtryRef>{reference}</NtryRef>
+        <Amt Ccy="{currency}">{amount}</Amt>
+        <CdtDbtInd>{indicator}</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <BookgDt><DtTm>{booked_at}</DtTm></BookgDt>
+        <ValDt><DtTm>{booked_at}</DtTm></ValDt>
+        <AcctSvcrRef>{reference}</AcctSvcrRef>
+        <BkTxCd><Domn><Cd>PMNT</Cd><Fmly><Cd>{family}</Cd><SubFmlyCd>OTHR</SubFmlyCd></Fmly></Domn></BkTxCd>
+{details}        <AddtlNtryInf>Balance after entry: {balance} {currency}</AddtlNtryInf>
+      </Ntry>
+"#,
+        reference = entry.id.simple(),
+        currency = entry.amount.currency(),
+        amount = entry.amount,
+        indicator = indicator,
+        booked_at = booked_at,
+        family = family,
+        details = details,
+        balance = entry.balance,
+    )
+}
+
+fn camt053_footer() -> String {
+    "    </Stmt>\n  </BkToCstmrStmt>\n</Document>\n".to_string()
+}
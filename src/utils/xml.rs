@@ -0,0 +1,197 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fmt;
+
+// Deepest nesting accepted; bank files are a dozen levels deep at most
+const MAX_DEPTH: usize = 64;
+
+/// An element of a parsed XML document. Names are local names, with any
+/// namespace prefix dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    // Text directly inside the element, entities decoded
+    pub text: String,
+    // Line of the start tag, from 1
+    pub line: usize,
+}
+
+impl Element {
+    /// The first child named `name`
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Every child named `name`, in document order
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The descendant reached by following `path` one child at a time
+    pub fn find(&self, path: &[&str]) -> Option<&Element> {
+        path.iter().try_fold(self, |element, name| element.child(name))
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The element's text without surrounding whitespace
+    pub fn trimmed_text(&self) -> &str {
+        self.text.trim()
+    }
+}
+
+/// Why a document could not be read, and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmlError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+/// Parse a document into its root element.
+///
+/// Document type declarations are refused, so no entities beyond the
+/// predefined ones and character references are ever expanded.
+pub fn parse(input: &str) -> Result<Element, XmlError> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let mut reader = Reader::from_str(input);
+    // End tags are matched here, by local name, so the error can name both
+    reader.config_mut().check_end_names = false;
+
+    let lines = Lines::new(input);
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+
+    loop {
+        let line = lines.at(reader.buffer_position());
+        let event = reader
+            .read_event()
+            .map_err(|err| XmlError { line: lines.at(reader.error_position()), message: err.to_string() })?;
+        let error = |message: String| XmlError { line, message };
+
+        match event {
+            Event::Start(_) | Event::Empty(_) if root.is_some() => {
+                return Err(error("Content after the root element".to_string()));
+            }
+            Event::Start(_) | Event::Empty(_) if stack.len() >= MAX_DEPTH => {
+                return Err(error("Elements are nested too deeply".to_string()));
+            }
+            Event::Start(start) => stack.push(element(&start, line)?),
+            Event::Empty(start) => {
+                let element = element(&start, line)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => root = Some(element),
+                }
+            }
+            Event::End(end) => {
+                let name = utf8(end.local_name().as_ref(), line)?;
+                let element = stack.pop().ok_or_else(|| error(format!("Unexpected end tag </{}>", name)))?;
+                if name != element.name {
+                    return Err(error(format!("Expected </{}> but found </{}>", element.name, name)));
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => root = Some(element),
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(|err| error(err.to_string()))?;
+                match stack.last_mut() {
+                    Some(element) => element.text.push_str(&text),
+                    None if text.trim().is_empty() => {}
+                    None => return Err(error("Text outside the root element".to_string())),
+                }
+            }
+            Event::CData(cdata) => {
+                let text = cdata.decode().map_err(|err| error(err.to_string()))?;
+                match stack.last_mut() {
+                    Some(element) => element.text.push_str(&text),
+                    None => return Err(error("Text outside the root element".to_string())),
+                }
+            }
+            Event::DocType(_) => return Err(error("Document type declarations are not supported".to_string())),
+            Event::Decl(_) | Event::PI(_) | Event::Comment(_) => {}
+            Event::Eof => break,
+        }
+    }
+
+    if let Some(open) = stack.last() {
+        return Err(XmlError { line: open.line, message: format!("<{}> is never closed", open.name) });
+    }
+    root.ok_or_else(|| XmlError {
+        line: lines.at(reader.buffer_position()),
+        message: "The document has no root element".to_string(),
+    })
+}
+
+/// Escape text for use as element content or a quoted attribute value.
+/// Control characters XML cannot carry become spaces.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// The element a start tag opens, with no children yet
+fn element(start: &BytesStart, line: usize) -> Result<Element, XmlError> {
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|err| XmlError { line, message: err.to_string() })?;
+        let value = attribute.unescape_value().map_err(|err| XmlError { line, message: err.to_string() })?;
+        attributes.push((utf8(attribute.key.local_name().as_ref(), line)?, value.into_owned()));
+    }
+
+    Ok(Element {
+        name: utf8(start.local_name().as_ref(), line)?,
+        attributes,
+        children: Vec::new(),
+        text: String::new(),
+        line,
+    })
+}
+
+fn utf8(bytes: &[u8], line: usize) -> Result<String, XmlError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| XmlError { line, message: "Names must be valid UTF-8".to_string() })
+}
+
+// Turns byte offsets in the document into line numbers, from 1
+struct Lines {
+    starts: Vec<u64>,
+}
+
+impl Lines {
+    fn new(input: &str) -> Self {
+        let breaks = input.match_indices('\n').map(|(offset, _)| offset as u64 + 1);
+        Lines { starts: std::iter::once(0).chain(breaks).collect() }
+    }
+
+    fn at(&self, offset: u64) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+}
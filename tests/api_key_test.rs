@@ -0,0 +1,203 @@
+//! Tests for scoped API keys in `handlers::api_key` and `middleware::api_key`
+//!
+//! The database test needs a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::de::DeserializeOwned;
+use sqlx::Row;
+use uuid::Uuid;
+
+use dodo_payments::config::MfaConfig;
+use dodo_payments::handlers::api_key::{create_api_key, list_api_keys, revoke_api_key};
+use dodo_payments::middleware::api_key::{check_api_scope, RequiredApiScope};
+use dodo_payments::middleware::rate_limit::IpNetworks;
+use dodo_payments::middleware::{AcceptApiKeys, Auth};
+use dodo_payments::models::{
+    ApiKeyIdentity, ApiKeyListResponse, ApiScope, AppError, CreateApiKeyRequest, CreatedApiKeyResponse,
+};
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{create_user, setup_pool, token_config, PASSWORD};
+
+#[test]
+fn test_api_key_scopes_are_checked_per_request() {
+    let identity = ApiKeyIdentity {
+        key_id: Uuid::new_v4(),
+        scopes: vec![ApiScope::TransactionsRead, ApiScope::BalanceRead],
+    };
+
+    assert!(check_api_scope(&identity, Some(RequiredApiScope(Some(ApiScope::BalanceRead)))).is_ok());
+    assert!(check_api_scope(&identity, Some(RequiredApiScope(Some(ApiScope::TransactionsWrite)))).is_err());
+    // Routes that do not accept keys, and requests they cannot make, are refused
+    assert!(check_api_scope(&identity, Some(RequiredApiScope(None))).is_err());
+    assert!(check_api_scope(&identity, None).is_err());
+
+    assert_eq!(serde_json::to_string(&ApiScope::TransactionsWrite).unwrap(), "\"transactions:write\"");
+    assert_eq!("balance:read".parse::<ApiScope>().unwrap(), ApiScope::BalanceRead);
+    assert!("admin:write".parse::<ApiScope>().is_err());
+}
+
+#[test]
+fn test_ip_networks_match_addresses_and_ranges() {
+    let networks = IpNetworks::parse(["203.0.113.7", "10.0.0.0/8", "2001:db8::/32"]).unwrap();
+
+    assert!(networks.contains("203.0.113.7".parse().unwrap()));
+    assert!(networks.contains("10.20.30.40".parse().unwrap()));
+    assert!(networks.contains("::ffff:10.1.1.1".parse().unwrap()));
+    assert!(networks.contains("2001:db8::1".parse().unwrap()));
+    assert!(!networks.contains("203.0.113.8".parse().unwrap()));
+    assert_eq!(IpNetworks::parse(["10.0.0.0/33"]).unwrap_err(), "10.0.0.0/33");
+}
+
+async fn response_json<T: DeserializeOwned>(responder: impl Responder, req: &HttpRequest) -> T {
+    let body = responder.respond_to(req).map_into_boxed_body().into_body();
+    serde_json::from_slice(&actix_web::body::to_bytes(body).await.unwrap()).unwrap()
+}
+
+async fn current_user(user_id: web::ReqData<Uuid>) -> HttpResponse {
+    HttpResponse::Ok().body(user_id.into_inner().to_string())
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_api_keys_authenticate_within_their_scopes() {
+    let pool = setup_pool().await;
+    let (user_id, _) = create_user(&pool).await;
+
+    let req = actix_test::TestRequest::default().to_http_request();
+    req.extensions_mut().insert(user_id);
+    let owner = || web::ReqData::<Uuid>::extract(&req);
+
+    let create = |scopes: Vec<ApiScope>, allowed_ips: Option<Vec<String>>| CreateApiKeyRequest {
+        name: "reporting".to_string(),
+        scopes,
+        expires_at: Some(Utc::now() + ChronoDuration::days(30)),
+        allowed_ips,
+        current_password: Some(PASSWORD.to_string()),
+    };
+    let create_key = |key_data: CreateApiKeyRequest| {
+        let owner = owner();
+        let (req, pool) = (req.clone(), pool.clone());
+        async move {
+            create_api_key(
+                req,
+                owner.await.unwrap(),
+                web::Data::new(pool),
+                web::Data::new(token_config()),
+                web::Data::new(MfaConfig::from_env()),
+                web::Json(key_data),
+            )
+            .await
+        }
+    };
+
+    // A stolen access token is not enough to make a key
+    let without_password = CreateApiKeyRequest { current_password: None, ..create(vec![ApiScope::BalanceRead], None) };
+    assert!(matches!(create_key(without_password).await, Err(AppError::ForbiddenError(_))));
+    let wrong_password = CreateApiKeyRequest {
+        current_password: Some("Password2!".to_string()),
+        ..create(vec![ApiScope::BalanceRead], None)
+    };
+    assert!(matches!(create_key(wrong_password).await, Err(AppError::ForbiddenError(_))));
+
+    // Keys made without an expiry get the default one
+    let lasting = create_key(CreateApiKeyRequest { expires_at: None, ..create(vec![ApiScope::BalanceRead], None) })
+        .await
+        .unwrap();
+    let lasting: CreatedApiKeyResponse = response_json(lasting, &req).await;
+    let expires_in = lasting.api_key.expires_at.unwrap() - Utc::now();
+    assert!(expires_in > ChronoDuration::minutes(59) && expires_in <= ChronoDuration::hours(1));
+
+    let created = create_key(create(vec![ApiScope::BalanceRead], None)).await.unwrap();
+    let created: CreatedApiKeyResponse = response_json(created, &req).await;
+    assert!(created.key.starts_with(&format!("{}_", created.api_key.prefix)));
+
+    // Only a hash of the key is stored
+    let stored: i64 = sqlx::query("SELECT COUNT(*) AS count FROM api_keys WHERE key_hash = $1 OR prefix = $1")
+        .bind(&created.key)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("count");
+    assert_eq!(stored, 0);
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .service(
+                web::scope("/balance")
+                    .wrap(Auth)
+                    .wrap(AcceptApiKeys::read_only(ApiScope::BalanceRead))
+                    .route("", web::get().to(current_user))
+                    .route("", web::post().to(current_user))
+            )
+            .service(
+                web::scope("/transactions")
+                    .wrap(Auth)
+                    .wrap(AcceptApiKeys::new(ApiScope::TransactionsRead, ApiScope::TransactionsWrite))
+                    .route("", web::get().to(current_user))
+            )
+            .service(web::scope("/profile").wrap(Auth).route("", web::get().to(current_user))),
+    )
+    .await;
+
+    let status = |method: &str, uri: &str, key: &str| {
+        let req = match method {
+            "POST" => actix_test::TestRequest::post(),
+            _ => actix_test::TestRequest::get(),
+        }
+        .uri(uri)
+        .insert_header(("X-API-Key", key.to_string()))
+        .peer_addr("198.51.100.20:4000".parse().unwrap())
+        .to_request();
+        let app = &app;
+        async move {
+            match app.call(req).await {
+                Ok(resp) => resp.status().as_u16(),
+                Err(err) => err.error_response().status().as_u16(),
+            }
+        }
+    };
+
+    assert_eq!(status("GET", "/balance", &created.key).await, 200);
+    // Wrong scope, a write the route does not take from keys, and a route without keys
+    assert_eq!(status("GET", "/transactions", &created.key).await, 403);
+    assert_eq!(status("POST", "/balance", &created.key).await, 403);
+    assert_eq!(status("GET", "/profile", &created.key).await, 403);
+    assert_eq!(status("GET", "/balance", "dodo_00000000_unknown").await, 401);
+
+    // A key limited to other addresses is refused from this one
+    let pinned = create_key(create(vec![ApiScope::BalanceRead], Some(vec!["10.0.0.0/8".to_string()]))).await.unwrap();
+    let pinned: CreatedApiKeyResponse = response_json(pinned, &req).await;
+    assert_eq!(status("GET", "/balance", &pinned.key).await, 403);
+
+    // Expired and revoked keys stop working
+    sqlx::query("UPDATE api_keys SET expires_at = NOW() - INTERVAL '1 second' WHERE id = $1")
+        .bind(pinned.api_key.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(status("GET", "/balance", &pinned.key).await, 401);
+
+    revoke_api_key(owner().await.unwrap(), web::Data::new(pool.clone()), web::Path::from(created.api_key.id))
+        .await
+        .unwrap();
+    assert_eq!(status("GET", "/balance", &created.key).await, 401);
+
+    let listed = list_api_keys(owner().await.unwrap(), web::Data::new(pool.clone())).await.unwrap();
+    let listed: ApiKeyListResponse = response_json(listed, &req).await;
+    assert_eq!(listed.api_keys.len(), 3);
+    let first = listed.api_keys.iter().find(|key| key.id == created.api_key.id).unwrap();
+    assert!(first.revoked_at.is_some() && first.last_used_at.is_some());
+
+    // Other users cannot revoke the key
+    let other = actix_test::TestRequest::default().to_http_request();
+    other.extensions_mut().insert(Uuid::new_v4());
+    let stranger = web::ReqData::<Uuid>::extract(&other).await.unwrap();
+    assert!(revoke_api_key(stranger, web::Data::new(pool.clone()), web::Path::from(pinned.api_key.id)).await.is_err());
+}
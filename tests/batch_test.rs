@@ -0,0 +1,225 @@
+//! Tests for batch transfers in `handlers::batch`
+//!
+//! These tests need a Postgres database with `migrations/schema.sql` applied.
+//! Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App};
+use bigdecimal::BigDecimal;
+use serde_json::{json, Value};
+use sqlx::Row;
+use uuid::Uuid;
+
+use dodo_payments::handlers;
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{balance_of, create_funded_user, create_user, setup_pool};
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_batches_run_all_or_nothing_or_best_effort() {
+    std::env::set_var("BATCH_MAX_ITEMS", "3");
+    let pool = setup_pool().await;
+    let (sender, username) = create_funded_user(&pool, "100").await;
+    let (first, _) = create_funded_user(&pool, "0").await;
+    let (second, _) = create_funded_user(&pool, "0").await;
+    let (walletless, _) = create_user(&pool).await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest| {
+        let app = &app;
+        async move {
+            match app.call(req.peer_addr("192.0.2.10:5000".parse().unwrap()).to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+                    (status, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), Value::Null),
+            }
+        }
+    };
+    let login = actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+    let (_, body) = call(login).await;
+    let auth = format!("Bearer {}", body["token"].as_str().unwrap());
+    let batch = |mode: &str, items: Value| actix_test::TestRequest::post()
+        .uri("/api/transactions/batch")
+        .insert_header(("Authorization", auth.clone()))
+        .set_json(json!({ "mode": mode, "items": items }));
+    let item = |recipient: Uuid, amount: &str| json!({ "recipient_id": recipient, "amount": amount, "currency": "USD" });
+
+    // All transfers go through together
+    let (status, completed) = call(batch("all_or_nothing", json!([item(first, "30.00"), item(second, "20.00")]))).await;
+    assert_eq!((status, completed["status"].as_str()), (201, Some("completed")));
+    assert_eq!(completed["succeeded_count"], json!(2));
+    assert_eq!(completed["total_amount"], json!("50.00"));
+    assert!(completed["items"].as_array().unwrap().iter().all(|item| item["transaction_id"].is_string()));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(50));
+
+    // One transfer that cannot be made undoes the others
+    let (status, failed) = call(batch("all_or_nothing", json!([item(first, "10.00"), item(walletless, "10.00")]))).await;
+    assert_eq!((status, failed["status"].as_str()), (422, Some("failed")));
+    let outcomes: Vec<&str> = failed["items"].as_array().unwrap().iter().map(|item| item["status"].as_str().unwrap()).collect();
+    assert_eq!(outcomes, vec!["aborted", "failed"]);
+    assert!(failed["items"][1]["error"].as_str().unwrap().contains("no wallet"));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(50));
+    assert_eq!(balance_of(&pool, first).await, BigDecimal::from(30));
+
+    // Best effort makes what it can
+    let (status, partial) = call(batch("best_effort", json!([item(first, "10.00"), item(walletless, "10.00")]))).await;
+    assert_eq!((status, partial["status"].as_str()), (201, Some("partially_completed")));
+    assert_eq!(partial["items"][0]["status"], json!("succeeded"));
+    assert_eq!(partial["items"][1]["status"], json!("failed"));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(40));
+
+    // Only the transfers that went through are recorded as transactions, each
+    // alongside its batch item
+    let transactions: i64 = sqlx::query(
+        r#"
+        SELECT COUNT(*) AS count FROM transactions t
+        JOIN transfer_batch_items i ON i.transaction_id = t.id
+        WHERE t.sender_id = $1
+        "#
+    )
+    .bind(sender)
+    .fetch_one(&pool)
+    .await
+    .unwrap()
+    .get("count");
+    let all_transactions: i64 = sqlx::query("SELECT COUNT(*) AS count FROM transactions WHERE sender_id = $1")
+        .bind(sender)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("count");
+    assert_eq!((transactions, all_transactions), (3, 3));
+
+    // Batches are refused whole when the total is not covered, they are too
+    // large, or they mix currencies
+    let (status, _) = call(batch("best_effort", json!([item(first, "30.00"), item(second, "30.00")]))).await;
+    assert_eq!(status, 400);
+    let too_many = json!([item(first, "1.00"), item(first, "1.00"), item(first, "1.00"), item(first, "1.00")]);
+    assert_eq!(call(batch("best_effort", too_many)).await.0, 400);
+    let mixed = json!([item(first, "1.00"), { "recipient_id": second, "amount": "1.00", "currency": "EUR" }]);
+    assert_eq!(call(batch("all_or_nothing", mixed)).await.0, 400);
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(40));
+
+    let batches: i64 = sqlx::query("SELECT COUNT(*) AS count FROM transfer_batches WHERE user_id = $1")
+        .bind(sender)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("count");
+    assert_eq!(batches, 3);
+
+    // Batches can be fetched again by id
+    let get = |id: &str| actix_test::TestRequest::get()
+        .uri(&format!("/api/transactions/batch/{}", id))
+        .insert_header(("Authorization", auth.clone()));
+    let (status, fetched) = call(get(failed["id"].as_str().unwrap())).await;
+    assert_eq!((status, fetched["items"].as_array().unwrap().len()), (200, 2));
+    assert_eq!(fetched["mode"], json!("all_or_nothing"));
+    assert_eq!(call(get(&Uuid::new_v4().to_string())).await.0, 404);
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_a_stopped_best_effort_batch_is_replayed_not_paid_again() {
+    let pool = setup_pool().await;
+    let (sender, username) = create_funded_user(&pool, "100").await;
+    let (first, _) = create_funded_user(&pool, "0").await;
+    let (second, _) = create_funded_user(&pool, "0").await;
+
+    // Recording a transfer to `second` as made fails, as if the database went
+    // away part way through the batch
+    sqlx::query(&format!(
+        r#"
+        CREATE OR REPLACE FUNCTION fail_batch_item_{suffix}() RETURNS trigger AS $$
+        BEGIN
+            IF NEW.recipient_id = '{second}' AND NEW.status = 'succeeded' THEN
+                RAISE EXCEPTION 'connection lost';
+            END IF;
+            RETURN NEW;
+        END
+        $$ LANGUAGE plpgsql
+        "#,
+        suffix = second.simple(),
+        second = second
+    ))
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(&format!(
+        "CREATE TRIGGER fail_batch_item_{suffix} BEFORE UPDATE ON transfer_batch_items \
+         FOR EACH ROW EXECUTE FUNCTION fail_batch_item_{suffix}()",
+        suffix = second.simple()
+    ))
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest| {
+        let app = &app;
+        async move {
+            let resp = app.call(req.peer_addr("192.0.2.11:5000".parse().unwrap()).to_request()).await.unwrap();
+            let status = resp.status().as_u16();
+            let replayed = resp.headers().contains_key("Idempotency-Replayed");
+            let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+            (status, replayed, body)
+        }
+    };
+    let login = actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+    let (_, _, body) = call(login).await;
+    let auth = format!("Bearer {}", body["token"].as_str().unwrap());
+    let item = |recipient: Uuid, amount: &str| json!({ "recipient_id": recipient, "amount": amount, "currency": "USD" });
+    let items = json!([item(first, "10.00"), item(second, "10.00"), item(first, "10.00")]);
+    let batch = || actix_test::TestRequest::post()
+        .uri("/api/transactions/batch")
+        .insert_header(("Authorization", auth.clone()))
+        .insert_header(("Idempotency-Key", format!("batch-{}", sender)))
+        .set_json(json!({ "mode": "best_effort", "items": items }));
+
+    // The batch stops at the failure and settles on what went through
+    let (status, replayed, stopped) = call(batch()).await;
+    assert_eq!((status, replayed, stopped["status"].as_str()), (201, false, Some("partially_completed")));
+    let outcomes: Vec<&str> = stopped["items"].as_array().unwrap().iter().map(|item| item["status"].as_str().unwrap()).collect();
+    assert_eq!(outcomes, vec!["succeeded", "aborted", "aborted"]);
+    assert!(stopped["items"][2]["error"].as_str().unwrap().contains("batch stopped"));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(90));
+
+    // A retry with the same key gets the same batch back and pays nothing again
+    let (status, replayed, retried) = call(batch()).await;
+    assert_eq!((status, replayed), (201, true));
+    assert_eq!(retried["id"], stopped["id"]);
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(90));
+    assert_eq!(balance_of(&pool, second).await, BigDecimal::from(0));
+
+    sqlx::query(&format!("DROP TRIGGER fail_batch_item_{} ON transfer_batch_items", second.simple()))
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(&format!("DROP FUNCTION fail_batch_item_{}()", second.simple()))
+        .execute(&pool)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,122 @@
+//! Tests for email verification in `handlers::email`
+//!
+//! The database test needs a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{test as actix_test, web, FromRequest, HttpMessage};
+use async_trait::async_trait;
+use sqlx::Row;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use dodo_payments::config::MailConfig;
+use dodo_payments::handlers::email::verify_email;
+use dodo_payments::handlers::user::{register, update_profile, UpdateProfileRequest};
+use dodo_payments::models::{AppError, RegisterUserRequest, VerifyEmailRequest};
+use dodo_payments::utils::mailer::{Email, Mailer};
+
+use common::{setup_pool, token_config};
+
+// Keeps sent emails so a test can read the verification link
+#[derive(Default)]
+struct RecordingMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    async fn send(&self, email: &Email) -> Result<(), AppError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+impl RecordingMailer {
+    // The recipient and token of the most recent email
+    fn last_token(&self) -> (String, String) {
+        let sent = self.sent.lock().unwrap();
+        let email = sent.last().unwrap();
+        let link = email.body.split_whitespace().find(|word| word.contains("/verify-email?token=")).unwrap();
+        (email.to.clone(), link.rsplit('=').next().unwrap().to_string())
+    }
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_email_changes_stay_pending_until_verified() {
+    let pool = setup_pool().await;
+    let mailer = Arc::new(RecordingMailer::default());
+    let mail = MailConfig { mailer: mailer.clone(), public_url: "https://pay.example.com".to_string() };
+
+    let username = format!("user_{}", Uuid::new_v4().simple());
+    let email = format!("{}@example.com", username);
+    register(
+        web::Data::new(pool.clone()),
+        web::Data::new(token_config()),
+        web::Data::new(mail.clone()),
+        web::Json(RegisterUserRequest {
+            username: username.clone(),
+            email: email.clone(),
+            password: "Password123!".to_string(),
+        }),
+    )
+    .await
+    .unwrap();
+
+    let user_id: Uuid = sqlx::query("SELECT id FROM users WHERE username = $1")
+        .bind(&username)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("id");
+    let state = || async {
+        let row = sqlx::query("SELECT email, email_verified_at IS NOT NULL AS verified, pending_email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        (
+            row.get::<String, _>("email"),
+            row.get::<bool, _>("verified"),
+            row.get::<Option<String>, _>("pending_email"),
+        )
+    };
+    let verify = |token: String| verify_email(web::Data::new(pool.clone()), web::Json(VerifyEmailRequest { token }));
+
+    // Registration mails a link for the new address
+    let (to, token) = mailer.last_token();
+    assert_eq!(to, email);
+    assert_eq!(state().await, (email.clone(), false, None));
+    assert!(verify(token.clone()).await.is_ok());
+    assert_eq!(state().await, (email.clone(), true, None));
+    assert!(verify(token).await.is_err());
+
+    // A new address is held as pending and mailed its own link
+    let req = actix_test::TestRequest::default().to_http_request();
+    req.extensions_mut().insert(user_id);
+    let signed_in = web::ReqData::<Uuid>::extract(&req).await.unwrap();
+    let change = |new_email: &str| {
+        update_profile(
+            signed_in.clone(),
+            web::Data::new(pool.clone()),
+            web::Data::new(token_config()),
+            web::Data::new(mail.clone()),
+            web::Json(UpdateProfileRequest { email: Some(new_email.to_string()) }),
+        )
+    };
+    let first = format!("first_{}", email);
+    let second = format!("second_{}", email);
+    assert!(change(&first).await.is_ok());
+    let (_, stale_token) = mailer.last_token();
+    assert!(change(&second).await.is_ok());
+    let (to, token) = mailer.last_token();
+    assert_eq!(to, second);
+    assert_eq!(state().await, (email.clone(), true, Some(second.clone())));
+
+    // Only the link for the latest pending address works
+    assert!(verify(stale_token).await.is_err());
+    assert!(verify(token).await.is_ok());
+    assert_eq!(state().await, (second, true, None));
+}
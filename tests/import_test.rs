@@ -0,0 +1,270 @@
+//! Tests for importing transfers from CSV and pain.001 files in
+//! `utils::import` and `handlers::import`
+//!
+//! The database tests need a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App};
+use bigdecimal::BigDecimal;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use dodo_payments::handlers;
+use dodo_payments::models::{ImportFormat, ImportLineError};
+use dodo_payments::utils::import::parse_transfers;
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{balance_of, create_funded_user, setup_pool};
+
+const FIRST: &str = "11111111-1111-1111-1111-111111111111";
+const SECOND: &str = "22222222-2222-2222-2222-222222222222";
+
+fn pain001(transactions: &[(&str, &str, &str)], control_sum: &str) -> String {
+    let count = transactions.len();
+    let transactions: String = transactions
+        .iter()
+        .map(|(recipient, amount, currency)| {
+            format!(
+                r#"
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>E2E</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="{}">{}</InstdAmt></Amt>
+        <CdtrAcct><Id><Othr><Id>{}</Id></Othr></Id></CdtrAcct>
+        <RmtInf><Ustrd>Payout &amp; fees</Ustrd></RmtInf>
+      </CdtTrfTxInf>"#,
+                currency, amount, recipient
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <GrpHdr><MsgId>MSG-1</MsgId><NbOfTxs>{}</NbOfTxs><CtrlSum>{}</CtrlSum></GrpHdr>
+    <PmtInf>
+      <PmtInfId>PMT-1</PmtInfId>{}
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>
+"#,
+        count,
+        control_sum,
+        transactions
+    )
+}
+
+fn errors(format: ImportFormat, input: &str) -> Vec<(Option<usize>, String)> {
+    let parsed = parse_transfers(format, input, 10);
+    parsed.report(format, true).errors.into_iter().map(|ImportLineError { line, message }| (line, message)).collect()
+}
+
+#[test]
+fn test_csv_rows_become_transfer_requests() {
+    let input = format!(
+        "\u{feff}Recipient_ID,amount,currency,description,reference\r\n\
+         {},10.50,usd,\"Rent, \"\"March\"\"\",A-1\r\n\
+         \r\n\
+         {}, 4 ,USD,\"Two\nlines\",A-2\r\n",
+        FIRST, SECOND
+    );
+    let parsed = parse_transfers(ImportFormat::Csv, &input, 10);
+
+    assert!(parsed.is_valid(), "{:?}", parsed.errors);
+    let lines: Vec<usize> = parsed.transfers.iter().map(|transfer| transfer.line).collect();
+    assert_eq!(lines, vec![2, 4]);
+    let requests = parsed.requests();
+    assert_eq!(requests[0].recipient_id, Uuid::parse_str(FIRST).unwrap());
+    assert_eq!(requests[0].currency, "USD");
+    assert_eq!(requests[0].description.as_deref(), Some("Rent, \"March\""));
+    assert_eq!(requests[1].description.as_deref(), Some("Two\nlines"));
+    assert_eq!(requests[1].capture, None);
+    assert_eq!(parsed.total().unwrap().to_string(), "14.50");
+}
+
+#[test]
+fn test_csv_problems_are_reported_by_line() {
+    let input = format!(
+        "recipient_id,amount,currency\n\
+         {},1.00,USD\n\
+         not-a-user,1.00,USD\n\
+         {},abc,USD\n\
+         {},1.001,USD\n\
+         {},0,USD\n\
+         {},1.00,EUR\n\
+         {},1.00\n",
+        FIRST, FIRST, FIRST, FIRST, SECOND, SECOND
+    );
+
+    assert_eq!(
+        errors(ImportFormat::Csv, &input),
+        vec![
+            (Some(3), "recipient_id not-a-user is not a valid user id".to_string()),
+            (Some(4), "amount abc is not a number".to_string()),
+            (Some(5), "USD allows at most 2 decimal places".to_string()),
+            (Some(6), "amount must be greater than 0".to_string()),
+            (Some(7), "Every transfer in a batch must be in the same currency".to_string()),
+            (Some(8), "Expected 3 fields but found 2".to_string()),
+        ]
+    );
+
+    // Files that cannot be read at all stop at the first problem
+    assert_eq!(
+        errors(ImportFormat::Csv, "recipient_id,amount\n"),
+        vec![(Some(1), "The header row has no currency column".to_string())]
+    );
+    assert_eq!(
+        errors(ImportFormat::Csv, &format!("recipient_id,amount,currency\n{},\"1.00,USD\n", FIRST)),
+        vec![(Some(2), "A quoted field is never closed".to_string())]
+    );
+    assert_eq!(errors(ImportFormat::Csv, ""), vec![(None, "The file holds no transfers".to_string())]);
+
+    let too_many: String = std::iter::once("recipient_id,amount,currency\n".to_string())
+        .chain((0..11).map(|_| format!("{},1.00,USD\n", FIRST)))
+        .collect();
+    assert_eq!(
+        errors(ImportFormat::Csv, &too_many),
+        vec![(None, "A batch can hold at most 10 transfers".to_string())]
+    );
+}
+
+#[test]
+fn test_pain001_credit_transfers_become_transfer_requests() {
+    let input = pain001(&[(FIRST, "10.50", "USD"), (SECOND, "20.00", "USD")], "30.50");
+    let parsed = parse_transfers(ImportFormat::Pain001, &input, 10);
+
+    assert!(parsed.is_valid(), "{:?}", parsed.errors);
+    let lines: Vec<usize> = parsed.transfers.iter().map(|transfer| transfer.line).collect();
+    assert_eq!(lines, vec![7, 13]);
+    let requests = parsed.requests();
+    assert_eq!(requests[1].recipient_id, Uuid::parse_str(SECOND).unwrap());
+    assert_eq!(requests[1].amount, BigDecimal::from(20));
+    assert_eq!(requests[0].description.as_deref(), Some("Payout & fees"));
+    assert_eq!(parsed.total().unwrap().to_string(), "30.50");
+}
+
+#[test]
+fn test_pain001_problems_are_reported_by_line() {
+    // Amounts are checked against the control sum before each transfer is
+    let input = pain001(&[(FIRST, "10.50", "JPY"), (SECOND, "20.00", "USD")], "99");
+    assert_eq!(
+        errors(ImportFormat::Pain001, &input),
+        vec![
+            (Some(4), "CtrlSum is 99 but the transfers add up to 30.50".to_string()),
+            (Some(7), "JPY allows at most 0 decimal places".to_string()),
+        ]
+    );
+
+    let iban = pain001(&[("DE89370400440532013000", "20.00", "USD")], "20.00");
+    assert_eq!(
+        errors(ImportFormat::Pain001, &iban),
+        vec![(Some(7), "recipient_id DE89370400440532013000 is not a valid user id".to_string())]
+    );
+
+    let truncated = pain001(&[(FIRST, "10.50", "USD")], "10.50").replace("<NbOfTxs>1</NbOfTxs>", "<NbOfTxs>2</NbOfTxs>");
+    assert_eq!(
+        errors(ImportFormat::Pain001, &truncated),
+        vec![(Some(4), "NbOfTxs is 2 but the file holds 1 transfers".to_string())]
+    );
+
+    let malformed = pain001(&[(FIRST, "10.50", "USD")], "10.50").replace("</PmtInf>", "");
+    assert_eq!(
+        errors(ImportFormat::Pain001, &malformed),
+        vec![(Some(14), "Expected </PmtInf> but found </CstmrCdtTrfInitn>".to_string())]
+    );
+    assert_eq!(
+        errors(ImportFormat::Pain001, "<Document><CstmrDrctDbtInitn/></Document>"),
+        vec![(Some(1), "Not a pain.001 file: there is no CstmrCdtTrfInitn element".to_string())]
+    );
+    assert_eq!(
+        errors(ImportFormat::Pain001, "<!DOCTYPE x [<!ENTITY a \"b\">]><Document/>"),
+        vec![(Some(1), "Document type declarations are not supported".to_string())]
+    );
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_import_checks_files_before_making_transfers() {
+    let pool = setup_pool().await;
+    let (sender, username) = create_funded_user(&pool, "100").await;
+    let (first, _) = create_funded_user(&pool, "0").await;
+    let (second, _) = create_funded_user(&pool, "0").await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest| {
+        let app = &app;
+        async move {
+            match app.call(req.peer_addr("192.0.2.10:5000".parse().unwrap()).to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+                    (status, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), Value::Null),
+            }
+        }
+    };
+    let login = actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+    let (_, body) = call(login).await;
+    let auth = format!("Bearer {}", body["token"].as_str().unwrap());
+    let import = |query: &str, file: String| actix_test::TestRequest::post()
+        .uri(&format!("/api/transactions/import?{}", query))
+        .insert_header(("Authorization", auth.clone()))
+        .set_payload(file);
+
+    // A dry run reports transfers to unknown users or the sender by line
+    let unknown = Uuid::new_v4();
+    let file = format!(
+        "recipient_id,amount,currency\n{},10.00,USD\n{},5.00,USD\n{},5.00,USD\n",
+        first, unknown, sender
+    );
+    let (status, report) = call(import("format=csv&dry_run=true", file.clone())).await;
+    assert_eq!((status, report["valid"].as_bool()), (200, Some(false)));
+    assert_eq!(report["item_count"], json!(3));
+    assert_eq!(
+        report["errors"],
+        json!([
+            { "line": 3, "message": "Recipient not found" },
+            { "line": 4, "message": "Cannot send money to yourself" },
+        ])
+    );
+
+    // Without a dry run the same file is refused and nothing is made
+    let (status, refused) = call(import("format=csv", file)).await;
+    assert_eq!((status, refused["dry_run"].as_bool()), (422, Some(false)));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(100));
+
+    // A total the balance cannot cover is found before anything is made
+    let file = pain001(&[(&first.to_string(), "60.00", "USD"), (&second.to_string(), "60.00", "USD")], "120.00");
+    let (status, report) = call(import("format=pain001&dry_run=true", file)).await;
+    assert_eq!(status, 200);
+    assert_eq!(report["errors"][0]["line"], Value::Null);
+    assert!(report["errors"][0]["message"].as_str().unwrap().starts_with("Insufficient funds"));
+
+    // A clean file is checked, then made as a batch
+    let file = pain001(&[(&first.to_string(), "30.00", "USD"), (&second.to_string(), "20.00", "USD")], "50.00");
+    let (status, report) = call(import("format=pain001&dry_run=true", file.clone())).await;
+    assert_eq!((status, report["valid"].as_bool()), (200, Some(true)));
+    assert_eq!(report["total_amount"], json!("50.00"));
+    assert_eq!(report["items"][1]["line"], json!(13));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(100));
+
+    let (status, batch) = call(import("format=pain001&mode=best_effort", file)).await;
+    assert_eq!((status, batch["status"].as_str()), (201, Some("completed")));
+    assert_eq!(batch["mode"], json!("best_effort"));
+    assert_eq!(batch["items"][0]["description"], json!("Payout & fees"));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(50));
+    assert_eq!(balance_of(&pool, first).await, BigDecimal::from(30));
+    assert_eq!(balance_of(&pool, second).await, BigDecimal::from(20));
+}
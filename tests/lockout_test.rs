@@ -0,0 +1,162 @@
+//! Tests for login backoff and lockout in `handlers::lockout`
+//!
+//! The database test needs a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{test as actix_test, web, FromRequest, HttpMessage, ResponseError};
+use sqlx::Row;
+use std::time::Duration;
+use uuid::Uuid;
+
+use dodo_payments::config::{LoginConfig, MfaConfig, TokenConfig};
+use dodo_payments::handlers::admin::unlock_user;
+use dodo_payments::handlers::lockout::claim_login_attempt;
+use dodo_payments::handlers::session::ClientInfo;
+use dodo_payments::handlers::user::login;
+use dodo_payments::models::LoginUserRequest;
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{create_admin, create_user, setup_pool, PASSWORD};
+
+fn login_config() -> LoginConfig {
+    LoginConfig {
+        backoff_after: 2,
+        backoff_base: Duration::from_secs(1),
+        backoff_max: Duration::from_secs(4),
+        lockout_threshold: 6,
+        lockout_duration: Duration::from_secs(900),
+        failure_window: Duration::from_secs(3600),
+    }
+}
+
+#[test]
+fn test_backoff_doubles_then_locks_out() {
+    let config = login_config();
+    let delays: Vec<Option<u64>> = (0..8).map(|failures| config.block_after(failures).map(|d| d.as_secs())).collect();
+
+    assert_eq!(delays, vec![None, None, None, Some(1), Some(2), Some(4), Some(900), Some(900)]);
+    assert!(!config.is_lockout(5));
+    assert!(config.is_lockout(6));
+
+    // Very long runs of failures do not overflow the delay
+    let config = LoginConfig { lockout_threshold: u32::MAX, ..login_config() };
+    assert_eq!(config.block_after(1000), Some(Duration::from_secs(4)));
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_failed_logins_lock_known_and_unknown_usernames_alike() {
+    let pool = setup_pool().await;
+    let keys = web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap());
+    // No delays, so every failure can be retried at once until the lockout
+    let config = LoginConfig { backoff_base: Duration::ZERO, backoff_max: Duration::ZERO, ..login_config() };
+
+    let (user_id, username) = create_user(&pool).await;
+    let unknown = format!("ghost_{}", Uuid::new_v4().simple());
+
+    let attempt = |username: &str, password: &str, config: &LoginConfig| {
+        let request = LoginUserRequest { username: username.to_string(), password: password.to_string() };
+        let pool = pool.clone();
+        let keys = keys.clone();
+        let config = config.clone();
+        async move {
+            let result = login(
+                web::Data::new(pool),
+                web::Data::new(TokenConfig {
+                    access_token_ttl: Duration::from_secs(900),
+                    refresh_token_ttl: Duration::from_secs(3600),
+                    password_reset_ttl: Duration::from_secs(3600),
+                    email_verification_ttl: Duration::from_secs(3600),
+                    api_key_ttl: Duration::from_secs(3600),
+                }),
+                web::Data::new(MfaConfig::from_env()),
+                web::Data::new(config),
+                keys,
+                ClientInfo::default(),
+                web::Json(request),
+            )
+            .await;
+            match result {
+                Ok(_) => 200,
+                Err(err) => err.error_response().status().as_u16(),
+            }
+        }
+    };
+
+    // Known and unknown usernames get the same answers all the way to the lockout
+    for name in [username.as_str(), unknown.as_str()] {
+        let statuses = [
+            attempt(name, "wrong", &config).await,
+            attempt(name, "wrong", &config).await,
+            attempt(name, "wrong", &config).await,
+            attempt(name, "wrong", &config).await,
+            attempt(name, "wrong", &config).await,
+            attempt(name, "wrong", &config).await,
+            attempt(name, PASSWORD, &config).await,
+        ];
+        assert_eq!(statuses, [401, 401, 401, 401, 401, 401, 429]);
+    }
+
+    let locks: Vec<Option<Uuid>> = sqlx::query(
+        "SELECT target_id FROM admin_audit_log WHERE action = 'lock_account' AND actor_id IS NULL AND details->>'username' IN ($1, $2)"
+    )
+    .bind(&username)
+    .bind(&unknown)
+    .fetch_all(&pool)
+    .await
+    .unwrap()
+    .iter()
+    .map(|row| row.get("target_id"))
+    .collect();
+    assert_eq!(locks.len(), 2);
+    assert!(locks.contains(&Some(user_id)) && locks.contains(&None));
+
+    // An admin lifts the lockout
+    let (admin_id, _) = create_admin(&pool).await;
+    let req = actix_test::TestRequest::default().to_http_request();
+    req.extensions_mut().insert(admin_id);
+    let actor = web::ReqData::<Uuid>::extract(&req).await.unwrap();
+    assert!(unlock_user(actor, web::Data::new(pool.clone()), web::Path::from(user_id)).await.is_ok());
+
+    let unlocks: i64 = sqlx::query(
+        "SELECT COUNT(*) AS count FROM admin_audit_log WHERE action = 'unlock_account' AND actor_id = $1 AND target_id = $2"
+    )
+    .bind(admin_id)
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap()
+    .get("count");
+    assert_eq!(unlocks, 1);
+    assert_eq!(attempt(&username, PASSWORD, &config).await, 200);
+
+    // With real delays a quick retry is turned away before the password is checked
+    let backoff = login_config();
+    for _ in 0..3 {
+        assert_eq!(attempt(&username, "wrong", &backoff).await, 401);
+    }
+    assert_eq!(attempt(&username, PASSWORD, &backoff).await, 429);
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_parallel_login_attempts_are_counted_before_any_is_checked() {
+    let pool = setup_pool().await;
+    let config = login_config();
+    let username = format!("ghost_{}", Uuid::new_v4().simple());
+
+    // Every guess is counted as it starts, so only the attempts before the
+    // first backoff get through however many arrive at once
+    let attempts = (0..10).map(|_| claim_login_attempt(&pool, &config, &username));
+    let results = futures::future::join_all(attempts).await;
+
+    let mut failures: Vec<u32> = results.iter().filter_map(|result| result.as_ref().ok()).map(|attempt| attempt.failures).collect();
+    failures.sort();
+    assert_eq!(failures, vec![1, 2, 3]);
+    assert!(results
+        .iter()
+        .filter_map(|result| result.as_ref().err())
+        .all(|err| err.error_response().status().as_u16() == 429));
+}
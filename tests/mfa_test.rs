@@ -0,0 +1,238 @@
+//! Tests for TOTP two-factor authentication in `utils::totp` and `handlers::mfa`
+//!
+//! The database test needs a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{test as actix_test, web, App};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+
+use dodo_payments::handlers;
+use dodo_payments::utils::keys::KeyStore;
+use dodo_payments::utils::totp;
+
+use common::{create_funded_user, setup_pool};
+
+// The SHA-1 seed from RFC 6238 appendix B
+const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+#[test]
+fn test_totp_matches_rfc_6238_vectors() {
+    // The RFC lists 8-digit codes; 6-digit codes are their last six digits
+    let vectors = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+    for (unix_secs, code) in vectors {
+        assert_eq!(totp::code_at(RFC_SECRET, totp::time_step(unix_secs)), code);
+    }
+}
+
+#[test]
+fn test_totp_verify_allows_drift_but_not_replay() {
+    let secret = totp::base32_encode(RFC_SECRET);
+    assert_eq!(secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    assert_eq!(totp::base32_decode(&secret.to_lowercase()).unwrap(), RFC_SECRET);
+
+    let now = 1111111111;
+    let step = totp::time_step(now);
+    let previous = totp::code_at(RFC_SECRET, step - 1);
+
+    // One step of clock drift either way is tolerated
+    assert_eq!(totp::verify(&secret, &previous, now, None).unwrap(), Some(step - 1));
+    assert_eq!(totp::verify(&secret, &previous, now + 3 * totp::PERIOD_SECS, None).unwrap(), None);
+
+    // A code is not accepted again once its step has been used
+    assert_eq!(totp::verify(&secret, &previous, now, Some(step - 1)).unwrap(), None);
+    assert_eq!(totp::verify(&secret, "000000", now, None).unwrap(), None);
+
+    let uri = totp::provisioning_uri(&secret, "jane doe", "Dodo Payments");
+    assert_eq!(
+        uri,
+        "otpauth://totp/Dodo%20Payments:jane%20doe?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+         &issuer=Dodo%20Payments&algorithm=SHA1&digits=6&period=30"
+    );
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_mfa_login_challenge_and_step_up() {
+    let pool = setup_pool().await;
+    let (_, username) = create_funded_user(&pool, "2000").await;
+    let (recipient_id, _) = create_funded_user(&pool, "0").await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+    let peer: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+    let call = |req: actix_test::TestRequest, bearer: Option<&str>| {
+        let mut req = req.peer_addr(peer);
+        if let Some(bearer) = bearer {
+            req = req.insert_header(("Authorization", format!("Bearer {}", bearer)));
+        }
+        let app = &app;
+        async move {
+            let resp = actix_test::call_service(app, req.to_request()).await;
+            let status = resp.status().as_u16();
+            let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+            (status, body)
+        }
+    };
+    let login = || actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+    let transfer = |amount: &str| actix_test::TestRequest::post()
+        .uri("/api/transactions")
+        .set_json(json!({ "recipient_id": recipient_id, "amount": amount, "currency": "USD" }));
+
+    // Enroll while MFA is still off
+    let (status, body) = call(login(), None).await;
+    assert_eq!(status, 200);
+    let token = body["token"].as_str().unwrap().to_string();
+
+    let (status, body) = call(actix_test::TestRequest::post().uri("/api/users/mfa/enroll"), Some(&token)).await;
+    assert_eq!(status, 201);
+    let secret = body["secret"].as_str().unwrap().to_string();
+    assert!(body["provisioning_uri"].as_str().unwrap().contains(&secret));
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let code = totp::code_at(&totp::base32_decode(&secret).unwrap(), totp::time_step(now));
+    let verify = actix_test::TestRequest::post().uri("/api/users/mfa/verify").set_json(json!({ "code": code }));
+    let (status, body) = call(verify, Some(&token)).await;
+    assert_eq!(status, 200);
+    let recovery_codes: Vec<String> = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+
+    // Password logins now stop at a challenge
+    let (status, body) = call(login(), None).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["mfa_required"], true);
+    assert!(body.get("token").is_none());
+    let mfa_token = body["mfa_token"].as_str().unwrap().to_string();
+
+    // The code used for enrollment cannot be replayed
+    let complete = |code: &str| actix_test::TestRequest::post()
+        .uri("/api/users/login/mfa")
+        .set_json(json!({ "mfa_token": mfa_token, "code": code }));
+    assert_eq!(call(complete(&code), None).await.0, 401);
+
+    let (status, body) = call(complete(&recovery_codes[0].to_uppercase()), None).await;
+    assert_eq!(status, 200);
+    let token = body["token"].as_str().unwrap().to_string();
+    assert_eq!(call(complete(&recovery_codes[1]), None).await.0, 401);
+
+    // Small transfers go through; large ones need a step-up code
+    assert_eq!(call(transfer("10.00"), Some(&token)).await.0, 201);
+    let (status, body) = call(transfer("1500.00"), Some(&token)).await;
+    assert_eq!(status, 403);
+    assert!(body["message"].as_str().unwrap().contains("X-MFA-Code"));
+    let stepped_up = transfer("1500.00").insert_header(("X-MFA-Code", recovery_codes[0].clone()));
+    assert_eq!(call(stepped_up, Some(&token)).await.0, 403);
+    let stepped_up = transfer("1500.00").insert_header(("X-MFA-Code", recovery_codes[1].clone()));
+    assert_eq!(call(stepped_up, Some(&token)).await.0, 201);
+
+    let (status, body) = call(actix_test::TestRequest::get().uri("/api/users/mfa"), Some(&token)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["enabled"], true);
+    assert_eq!(body["recovery_codes_remaining"], 8);
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_wrong_mfa_codes_lock_out_every_endpoint() {
+    let pool = setup_pool().await;
+    let (user_id, username) = create_funded_user(&pool, "0").await;
+
+    // Wrong codes here should run into the MFA lockout, not the sign-in rate limit
+    let limits = std::env::temp_dir().join("mfa_test_rate_limits.json");
+    std::fs::write(&limits, r#"{ "users": { "key": "ip", "sustained": { "requests": 1000, "per_secs": 60 } } }"#).unwrap();
+    std::env::set_var("RATE_LIMITS_FILE", &limits);
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+    let peer: SocketAddr = "127.0.0.1:12346".parse().unwrap();
+
+    let call = |req: actix_test::TestRequest, bearer: Option<&str>| {
+        let mut req = req.peer_addr(peer);
+        if let Some(bearer) = bearer {
+            req = req.insert_header(("Authorization", format!("Bearer {}", bearer)));
+        }
+        let app = &app;
+        async move {
+            let resp = actix_test::call_service(app, req.to_request()).await;
+            let status = resp.status().as_u16();
+            let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+            (status, body)
+        }
+    };
+    let login = || actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+
+    let (_, body) = call(login(), None).await;
+    let token = body["token"].as_str().unwrap().to_string();
+    let (_, body) = call(actix_test::TestRequest::post().uri("/api/users/mfa/enroll"), Some(&token)).await;
+    let secret = totp::base32_decode(body["secret"].as_str().unwrap()).unwrap();
+    let code = totp::code_at(&secret, totp::time_step(chrono::Utc::now().timestamp() as u64));
+    let verify = actix_test::TestRequest::post().uri("/api/users/mfa/verify").set_json(json!({ "code": code }));
+    let (_, body) = call(verify, Some(&token)).await;
+    let recovery_codes: Vec<String> = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+
+    let disable = |code: &str| actix_test::TestRequest::post()
+        .uri("/api/users/mfa/disable")
+        .set_json(json!({ "code": code }));
+    let regenerate = |code: &str| actix_test::TestRequest::post()
+        .uri("/api/users/mfa/recovery-codes")
+        .set_json(json!({ "code": code }));
+
+    // A right code resets the count
+    for _ in 0..4 {
+        assert_eq!(call(regenerate("000000"), Some(&token)).await.0, 403);
+    }
+    let (status, body) = call(regenerate(&recovery_codes[0]), Some(&token)).await;
+    assert_eq!(status, 200);
+    let recovery_codes: Vec<String> = serde_json::from_value(body["recovery_codes"].clone()).unwrap();
+
+    // Wrong codes count across endpoints, login challenges included
+    for _ in 0..3 {
+        assert_eq!(call(disable("000000"), Some(&token)).await.0, 403);
+    }
+    let (_, body) = call(login(), None).await;
+    let complete = actix_test::TestRequest::post()
+        .uri("/api/users/login/mfa")
+        .set_json(json!({ "mfa_token": body["mfa_token"], "code": "000000" }));
+    assert_eq!(call(complete, None).await.0, 401);
+    assert_eq!(call(regenerate("not-a-code"), Some(&token)).await.0, 403);
+
+    // Now even a right code is refused, and it is not used up
+    assert_eq!(call(disable(&recovery_codes[0]), Some(&token)).await.0, 429);
+    let (_, body) = call(login(), None).await;
+    let complete = actix_test::TestRequest::post()
+        .uri("/api/users/login/mfa")
+        .set_json(json!({ "mfa_token": body["mfa_token"], "code": recovery_codes[0] }));
+    assert_eq!(call(complete, None).await.0, 429);
+
+    // Once the lockout ends the code works again
+    sqlx::query("UPDATE user_mfa SET locked_until = NOW() - INTERVAL '1 second' WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(call(disable(&recovery_codes[0]), Some(&token)).await.0, 204);
+}
@@ -0,0 +1,76 @@
+use bigdecimal::BigDecimal;
+use serde_json::json;
+use std::str::FromStr;
+
+use dodo_payments::models::{CreateTransactionRequest, Currency, Money};
+
+fn dec(amount: &str) -> BigDecimal {
+    BigDecimal::from_str(amount).unwrap()
+}
+
+#[test]
+fn test_money_rejects_excess_fractional_digits() {
+    assert!(Money::parse(dec("10.25"), "USD").is_ok());
+    assert!(Money::parse(dec("10.250"), "USD").is_ok());
+    assert!(Money::parse(dec("10.255"), "USD").is_err());
+
+    // Zero-decimal and three-decimal currencies
+    assert!(Money::parse(dec("500"), "JPY").is_ok());
+    assert!(Money::parse(dec("500.5"), "JPY").is_err());
+    assert!(Money::parse(dec("1.125"), "KWD").is_ok());
+
+    assert!(Money::parse(dec("10"), "XYZ").is_err());
+    assert!(Money::positive(dec("0"), "USD").is_err());
+    assert!(Money::positive(dec("-1"), "USD").is_err());
+}
+
+#[test]
+fn test_money_serializes_as_string_in_minor_units() {
+    let usd = Money::parse(dec("10.5000"), "USD").unwrap();
+    assert_eq!(serde_json::to_value(&usd).unwrap(), json!("10.50"));
+
+    let jpy = Money::new(dec("1200"), Currency::from_code("jpy").unwrap()).unwrap();
+    assert_eq!(jpy.currency().code(), "JPY");
+    assert_eq!(serde_json::to_value(&jpy).unwrap(), json!("1200"));
+}
+
+#[test]
+fn test_request_amounts_parse_exactly() {
+    let request: CreateTransactionRequest = serde_json::from_value(json!({
+        "recipient_id": "a1b2c3d4-e5f6-7890-abcd-1234567890ab",
+        "amount": "0.10",
+        "currency": "USD"
+    }))
+    .unwrap();
+    assert_eq!(request.amount, dec("0.1"));
+
+    // Numbers are still accepted from older clients
+    let request: CreateTransactionRequest = serde_json::from_value(json!({
+        "recipient_id": "a1b2c3d4-e5f6-7890-abcd-1234567890ab",
+        "amount": 19.99,
+        "currency": "USD"
+    }))
+    .unwrap();
+    assert_eq!(request.amount, dec("19.99"));
+
+    let invalid = serde_json::from_value::<CreateTransactionRequest>(json!({
+        "recipient_id": "a1b2c3d4-e5f6-7890-abcd-1234567890ab",
+        "amount": "ten",
+        "currency": "USD"
+    }));
+    assert!(invalid.is_err());
+}
+
+#[test]
+fn test_stored_amounts_round_to_minor_units() {
+    let usd = Currency::from_code("USD").unwrap();
+
+    // Legacy rows written as floats can carry extra digits
+    assert_eq!(Money::stored(dec("10.0050"), usd).to_string(), "10.01");
+    assert_eq!(Money::stored(dec("10.0049"), usd).to_string(), "10.00");
+    assert_eq!(Money::stored(dec("-10.0050"), usd).to_string(), "-10.01");
+    assert_eq!(Money::stored(dec("10.5000"), usd), Money::parse(dec("10.50"), "USD").unwrap());
+
+    assert!(Currency::stored("USD").is_ok());
+    assert!(matches!(Currency::stored("XYZ"), Err(dodo_payments::models::AppError::InternalServerError(_))));
+}
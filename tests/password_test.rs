@@ -0,0 +1,162 @@
+//! Tests for password changes and resets in `handlers::password`
+//!
+//! The database test needs a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::web;
+use async_trait::async_trait;
+use sqlx::Row;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use dodo_payments::config::MailConfig;
+use dodo_payments::handlers::password::{forgot_password, reset_password, send_reset_link};
+use dodo_payments::handlers::token::{issue_tokens, rotate_refresh_token};
+use dodo_payments::models::{AppError, ForgotPasswordRequest, ResetPasswordRequest, Role};
+use dodo_payments::utils::auth::verify_password;
+use dodo_payments::utils::keys::KeyStore;
+use dodo_payments::utils::mailer::{Email, FileMailer, Mailer};
+
+use common::{create_user, setup_pool, token_config};
+
+// Keeps sent emails so a test can read the reset link
+#[derive(Default)]
+struct RecordingMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+#[async_trait]
+impl Mailer for RecordingMailer {
+    async fn send(&self, email: &Email) -> Result<(), AppError> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+#[actix_rt::test]
+async fn test_file_mailer_appends_emails() {
+    let path = std::env::temp_dir().join(format!("outbox_{}.log", Uuid::new_v4().simple()));
+    let mailer = FileMailer::new(&path, "Dodo Payments <no-reply@example.com>");
+
+    for subject in ["First", "Second"] {
+        let email = Email {
+            to: "jane@example.com".to_string(),
+            subject: subject.to_string(),
+            body: "Hello".to_string(),
+        };
+        mailer.send(&email).await.unwrap();
+    }
+
+    let outbox = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(outbox.contains("From: Dodo Payments <no-reply@example.com>\nTo: jane@example.com\nSubject: First\n\nHello"));
+    assert!(outbox.contains("Subject: Second"));
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_password_reset_is_single_use_and_revokes_sessions() {
+    let pool = setup_pool().await;
+    let keys = KeyStore::from_secret("test_jwt_secret").unwrap();
+    let config = token_config();
+
+    let (user_id, username) = create_user(&pool).await;
+    let email = format!("{}@example.com", username);
+
+    // Two sessions signed in with the old password
+    let mut sessions = Vec::new();
+    for _ in 0..2 {
+        let mut tx = pool.begin().await.unwrap();
+        sessions.push(issue_tokens(&mut tx, &config, &keys, user_id, Role::User, Uuid::new_v4()).await.unwrap());
+        tx.commit().await.unwrap();
+    }
+
+    // An API key made with the old password
+    sqlx::query(
+        "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes) VALUES ($1, 'reporting', $2, $3, '{balance:read}')"
+    )
+    .bind(user_id)
+    .bind(format!("dodo_{}", &Uuid::new_v4().simple().to_string()[..8]))
+    .bind(Uuid::new_v4().to_string())
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let mailer = Arc::new(RecordingMailer::default());
+    let mail = MailConfig { mailer: mailer.clone(), public_url: "https://pay.example.com".to_string() };
+    let forgot = |email: &str| {
+        forgot_password(
+            web::Data::new(pool.clone()),
+            web::Data::new(token_config()),
+            web::Data::new(mail.clone()),
+            web::Json(ForgotPasswordRequest { email: email.to_string() }),
+        )
+    };
+
+    // Every address gets the same response, and the email is sent afterwards
+    assert!(forgot("nobody@example.com").await.is_ok());
+    assert!(forgot(&email).await.is_ok());
+    for _ in 0..50 {
+        if !mailer.sent.lock().unwrap().is_empty() {
+            break;
+        }
+        actix_rt::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(mailer.sent.lock().unwrap().len(), 1);
+    mailer.sent.lock().unwrap().clear();
+
+    // Unknown addresses get no email
+    send_reset_link(&pool, &config, &mail, "nobody@example.com").await.unwrap();
+    assert!(mailer.sent.lock().unwrap().is_empty());
+
+    // Asking twice leaves only the newest token usable
+    send_reset_link(&pool, &config, &mail, &email).await.unwrap();
+    send_reset_link(&pool, &config, &mail, &email).await.unwrap();
+    let tokens: Vec<String> = mailer
+        .sent
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|sent| {
+            assert_eq!(sent.to, email);
+            let link = sent.body.split_whitespace().find(|word| word.contains("/reset-password?token=")).unwrap();
+            assert!(link.starts_with("https://pay.example.com/"));
+            link.rsplit('=').next().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(tokens.len(), 2);
+
+    let reset = |token: &str| {
+        reset_password(
+            web::Data::new(pool.clone()),
+            web::Json(ResetPasswordRequest { token: token.to_string(), new_password: "NewPassword1!".to_string() }),
+        )
+    };
+    assert!(reset(&tokens[0]).await.is_err());
+    assert!(reset(&tokens[1]).await.is_ok());
+    assert!(reset(&tokens[1]).await.is_err());
+
+    let password_hash: String = sqlx::query("SELECT password_hash FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("password_hash");
+    assert!(verify_password("NewPassword1!", &password_hash).unwrap());
+
+    // Every session from before the reset is signed out
+    for session in &sessions {
+        assert!(rotate_refresh_token(&pool, &config, &keys, &session.refresh_token).await.is_err());
+    }
+
+    // And API keys stop working too
+    let active_keys: i64 = sqlx::query("SELECT COUNT(*) AS count FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("count");
+    assert_eq!(active_keys, 0);
+}
@@ -0,0 +1,150 @@
+//! Tests for payment requests in `handlers::payment_request`
+//!
+//! These tests need a Postgres database with `migrations/schema.sql` applied.
+//! Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App};
+use bigdecimal::BigDecimal;
+use chrono::{Duration as ChronoDuration, Utc};
+use serde_json::{json, Value};
+use sqlx::Row;
+use std::time::Duration;
+use uuid::Uuid;
+
+use dodo_payments::handlers;
+use dodo_payments::handlers::payment_request::expire_payment_requests;
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{balance_of, create_funded_user, setup_pool};
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_payment_requests_are_paid_declined_cancelled_and_expire() {
+    let pool = setup_pool().await;
+    let (requester, requester_name) = create_funded_user(&pool, "0").await;
+    let (payer, payer_name) = create_funded_user(&pool, "30").await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest| {
+        let app = &app;
+        async move {
+            match app.call(req.peer_addr("192.0.2.10:5000".parse().unwrap()).to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+                    (status, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), Value::Null),
+            }
+        }
+    };
+    let login = |username: &str| actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+    let (_, body) = call(login(&requester_name)).await;
+    let requester_auth = format!("Bearer {}", body["token"].as_str().unwrap());
+    let (_, body) = call(login(&payer_name)).await;
+    let payer_auth = format!("Bearer {}", body["token"].as_str().unwrap());
+
+    let create = |body: Value| actix_test::TestRequest::post()
+        .uri("/api/payment-requests")
+        .insert_header(("Authorization", requester_auth.clone()))
+        .set_json(body);
+    let action = |auth: &str, id: &Value, action: &str| actix_test::TestRequest::post()
+        .uri(&format!("/api/payment-requests/{}/{}", id.as_str().unwrap(), action))
+        .insert_header(("Authorization", auth.to_string()));
+
+    // Requests cannot be made of yourself or expire in the past
+    let own = create(json!({ "payer_id": requester, "amount": "20.00", "currency": "USD" }));
+    assert_eq!(call(own).await.0, 400);
+    let past = create(json!({
+        "payer_id": payer, "amount": "20.00", "currency": "USD",
+        "expires_at": Utc::now() - ChronoDuration::minutes(1),
+    }));
+    assert_eq!(call(past).await.0, 400);
+
+    let (status, paid) = call(create(json!({
+        "payer_id": payer, "amount": "20.00", "currency": "USD", "note": "Dinner",
+    })))
+    .await;
+    assert_eq!((status, paid["status"].as_str()), (201, Some("pending")));
+
+    // The payer sees it among their pending requests
+    let incoming = actix_test::TestRequest::get()
+        .uri("/api/payment-requests?direction=incoming&status=pending")
+        .insert_header(("Authorization", payer_auth.clone()));
+    let (_, listed) = call(incoming).await;
+    let listed = listed["payment_requests"].as_array().unwrap().clone();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["note"], json!("Dinner"));
+
+    // Only the payer can pay it, and only once
+    assert_eq!(call(action(&requester_auth, &paid["id"], "accept")).await.0, 403);
+    let (status, accepted) = call(action(&payer_auth, &paid["id"], "accept")).await;
+    assert_eq!((status, accepted["status"].as_str()), (200, Some("accepted")));
+    assert_eq!(call(action(&payer_auth, &paid["id"], "accept")).await.0, 409);
+    assert_eq!(balance_of(&pool, requester).await, BigDecimal::from(20));
+    assert_eq!(balance_of(&pool, payer).await, BigDecimal::from(10));
+    let transaction = sqlx::query("SELECT sender_id, recipient_id, description FROM transactions WHERE id = $1::uuid")
+        .bind(accepted["transaction_id"].as_str().unwrap())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(transaction.get::<Uuid, _>("sender_id"), payer);
+    assert_eq!(transaction.get::<Uuid, _>("recipient_id"), requester);
+    assert_eq!(transaction.get::<Option<String>, _>("description").as_deref(), Some("Dinner"));
+
+    // A request the payer cannot cover stays pending until declined
+    let (_, declined) = call(create(json!({ "payer_id": payer, "amount": "50.00", "currency": "USD" }))).await;
+    assert_eq!(call(action(&payer_auth, &declined["id"], "accept")).await.0, 400);
+    let (status, body) = call(action(&payer_auth, &declined["id"], "decline")).await;
+    assert_eq!((status, body["status"].as_str()), (200, Some("declined")));
+    assert_eq!(body["transaction_id"], Value::Null);
+
+    // Wait out the burst allowed by the transactions rate limit
+    actix_rt::time::sleep(Duration::from_secs(1)).await;
+
+    // The requester can withdraw a request, after which it cannot be answered
+    let (_, cancelled) = call(create(json!({ "payer_id": payer, "amount": "5.00", "currency": "USD" }))).await;
+    assert_eq!(call(action(&payer_auth, &cancelled["id"], "cancel")).await.0, 403);
+    let (status, body) = call(action(&requester_auth, &cancelled["id"], "cancel")).await;
+    assert_eq!((status, body["status"].as_str()), (200, Some("cancelled")));
+    assert_eq!(call(action(&payer_auth, &cancelled["id"], "decline")).await.0, 409);
+
+    // Requests past their expiry read as expired and can no longer be paid
+    let (_, expired) = call(create(json!({ "payer_id": payer, "amount": "5.00", "currency": "USD" }))).await;
+    sqlx::query("UPDATE payment_requests SET expires_at = NOW() - INTERVAL '1 second' WHERE id = $1::uuid")
+        .bind(expired["id"].as_str().unwrap())
+        .execute(&pool)
+        .await
+        .unwrap();
+    let get = actix_test::TestRequest::get()
+        .uri(&format!("/api/payment-requests/{}", expired["id"].as_str().unwrap()))
+        .insert_header(("Authorization", payer_auth.clone()));
+    assert_eq!(call(get).await.1["status"], json!("expired"));
+    assert_eq!(call(action(&payer_auth, &expired["id"], "accept")).await.0, 409);
+    assert!(expire_payment_requests(&pool).await.unwrap() >= 1);
+    let stored: String = sqlx::query("SELECT status FROM payment_requests WHERE id = $1::uuid")
+        .bind(expired["id"].as_str().unwrap())
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("status");
+    assert_eq!(stored, "expired");
+
+    let outgoing = actix_test::TestRequest::get()
+        .uri("/api/payment-requests?direction=outgoing")
+        .insert_header(("Authorization", requester_auth.clone()));
+    let (_, listed) = call(outgoing).await;
+    let statuses: Vec<&str> = listed["payment_requests"].as_array().unwrap().iter().map(|r| r["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, vec!["expired", "cancelled", "declined", "accepted"]);
+}
@@ -0,0 +1,99 @@
+//! Tests for the Postgres rate limit backend in `middleware::rate_limit_backend`
+//!
+//! These tests need a Postgres database with `migrations/schema.sql` applied.
+//! Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_extensible_rate_limit::backend::{Backend, SimpleInput};
+use sqlx::Row;
+use std::time::Duration;
+use uuid::Uuid;
+
+use dodo_payments::middleware::rate_limit_backend::PgBackend;
+
+use common::setup_pool;
+
+fn input(key: &str, max_requests: u64, interval_secs: u64) -> SimpleInput {
+    SimpleInput {
+        interval: Duration::from_secs(interval_secs),
+        max_requests,
+        key: key.to_string(),
+    }
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_instances_share_counters() {
+    let pool = setup_pool().await;
+    // Two backends on separate pools stand in for two replicas of the app
+    let first = PgBackend::new(pool);
+    let second = PgBackend::new(setup_pool().await);
+    let key = format!("test:{}", Uuid::new_v4());
+
+    // Concurrent requests are each counted exactly once
+    let requests = (0..6).map(|i| {
+        let backend = if i % 2 == 0 { first.clone() } else { second.clone() };
+        let input = input(&key, 5, 3600);
+        async move { backend.request(input).await.unwrap() }
+    });
+    let results = futures::future::join_all(requests).await;
+    assert_eq!(results.iter().filter(|(allow, _, _)| *allow).count(), 5);
+    assert!(results.iter().all(|(_, output, _)| output.limit == 5));
+
+    // Taking back a request frees its slot on every instance
+    let (allow, _, token) = first.request(input(&key, 7, 3600)).await.unwrap();
+    assert!(allow);
+    let (allow, output, _) = second.request(input(&key, 7, 3600)).await.unwrap();
+    assert!(!allow);
+    assert_eq!(output.remaining, 0);
+    first.rollback(token).await.unwrap();
+    second.rollback(results.into_iter().next().unwrap().2).await.unwrap();
+    assert!(first.request(input(&key, 7, 3600)).await.unwrap().0);
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_previous_window_counts_towards_the_sliding_limit() {
+    let pool = setup_pool().await;
+    let backend = PgBackend::new(pool.clone());
+    let key = format!("test:{}", Uuid::new_v4());
+
+    let window_index: i64 = sqlx::query("SELECT FLOOR(EXTRACT(EPOCH FROM clock_timestamp()) / 3600)::BIGINT AS i")
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("i");
+    let seed = |windows_ago: i64| {
+        sqlx::query(
+            r#"
+            INSERT INTO rate_limit_counters (key, window_index, count, previous_count, expires_at)
+            VALUES ($1, $2, 1000000, 0, NOW() + INTERVAL '2 hours')
+            ON CONFLICT (key) DO UPDATE SET window_index = EXCLUDED.window_index, count = EXCLUDED.count
+            "#
+        )
+        .bind(format!("{}:3600", key))
+        .bind(window_index - windows_ago)
+        .execute(&pool)
+    };
+
+    // A flood in the last window still weighs on this one
+    seed(1).await.unwrap();
+    let (allow, output, _) = backend.request(input(&key, 10, 3600)).await.unwrap();
+    assert!(!allow);
+    assert_eq!(output.remaining, 0);
+
+    // Anything older has slid out of the window
+    seed(2).await.unwrap();
+    let (allow, output, _) = backend.request(input(&key, 10, 3600)).await.unwrap();
+    assert!(allow);
+    assert_eq!(output.remaining, 9);
+
+    // Counters past both of their windows are purged
+    sqlx::query("UPDATE rate_limit_counters SET expires_at = NOW() - INTERVAL '1 second' WHERE key = $1")
+        .bind(format!("{}:3600", key))
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(backend.purge_expired().await.unwrap() >= 1);
+}
@@ -1,15 +1,20 @@
 //! Test suite for rate limiting functionality
 //! These tests validate that the rate limiting middleware works as expected
 
-use actix_web::{test, web, App, HttpResponse};
+use actix_web::{dev::Service, test, web, App, HttpMessage, HttpResponse};
 use actix_web::http::StatusCode;
 use actix_extensible_rate_limit::{
     backend::SimpleInputFunctionBuilder,
     backend::memory::InMemoryBackend,
     RateLimiter,
 };
+use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::sleep;
+use uuid::Uuid;
+
+use dodo_payments::models::ApiKeyIdentity;
+use dodo_payments::middleware::rate_limit::{policy_limiter, Quota, RateLimitKey, RateLimitPolicy, TrustedProxies};
 
 // A simple handler for testing
 async fn test_handler() -> HttpResponse {
@@ -19,7 +24,7 @@ async fn test_handler() -> HttpResponse {
 #[actix_web::test]
 async fn test_rate_limiter_functionality() {    // Configure a strict rate limiter for testing (2 requests per minute)
     let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 2)
-        .key_extractor(|req| Some("test_key".to_string()))
+        .custom_key("test_key")
         .build();
     let backend = InMemoryBackend::builder().build();
     let rate_limit = RateLimiter::builder(backend, input)
@@ -61,7 +66,7 @@ async fn test_rate_limiter_functionality() {    // Configure a strict rate limit
 #[actix_web::test]
 async fn test_rate_limiter_with_different_paths() {    // Configure a rate limiter (3 requests per minute)
     let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 3)
-        .key_extractor(|req| Some("test_key".to_string()))
+        .custom_key("test_key")
         .build();
     let backend = InMemoryBackend::builder().build();
     let rate_limit = RateLimiter::builder(backend, input)
@@ -111,7 +116,7 @@ async fn test_rate_limiter_with_different_paths() {    // Configure a rate limit
 #[actix_web::test]
 async fn test_rate_limiter_reset() {    // Configure a rate limiter with a very short window (2 seconds, 1 request)    // Using 2 seconds instead of 1 to give more buffer for timing issues
     let input = SimpleInputFunctionBuilder::new(Duration::from_secs(2), 1)
-        .key_extractor(|req| Some("test_key".to_string()))
+        .custom_key("test_key")
         .build();
     let backend = InMemoryBackend::builder().build();
     let rate_limit = RateLimiter::builder(backend, input)
@@ -147,3 +152,168 @@ async fn test_rate_limiter_reset() {    // Configure a rate limiter with a very
     let resp3 = test::call_service(&app, req3).await;
     assert_eq!(resp3.status(), StatusCode::OK, "Request after rate limit reset should succeed");
 }
+
+#[actix_web::test]
+async fn test_trusted_proxies_resolve_client_ip() {
+    let proxies = TrustedProxies::parse("10.0.0.0/8, 192.168.1.5, ::1").unwrap();
+    let ip = |addr: &str| addr.parse::<IpAddr>().unwrap();
+
+    assert!(proxies.contains(ip("10.20.30.40")));
+    assert!(proxies.contains(ip("::ffff:10.1.2.3")));
+    assert!(!proxies.contains(ip("192.168.1.6")));
+    assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+
+    // Untrusted peers cannot pick their own address with the header
+    let header = Some("203.0.113.7");
+    assert_eq!(proxies.client_ip(Some(ip("198.51.100.1")), header), Some(ip("198.51.100.1")));
+
+    // Behind trusted proxies the rightmost untrusted hop is the client
+    let chain = Some("1.1.1.1, 203.0.113.7, 10.0.0.2");
+    assert_eq!(proxies.client_ip(Some(ip("10.0.0.1")), chain), Some(ip("203.0.113.7")));
+    assert_eq!(proxies.client_ip(Some(ip("::1")), None), Some(ip("::1")));
+}
+
+#[actix_web::test]
+async fn test_policy_limits_bursts_and_sustained_rate_separately() {
+    let policy = RateLimitPolicy {
+        key: RateLimitKey::Ip,
+        burst: Some(Quota::new(2, 1)),
+        sustained: Quota::new(3, 60),
+    };
+    let rate_limit = policy_limiter(
+        "test",
+        &policy,
+        Arc::new(TrustedProxies::default()),
+        InMemoryBackend::builder().build(),
+    );
+
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/policy")
+                    .wrap(rate_limit)
+                    .route("", web::get().to(test_handler))
+            )
+    ).await;
+    let call = || async {
+        let req = test::TestRequest::get().uri("/policy").peer_addr("127.0.0.1:4000".parse().unwrap()).to_request();
+        test::call_service(&app, req).await
+    };
+
+    assert_eq!(call().await.status(), StatusCode::OK);
+    assert_eq!(call().await.status(), StatusCode::OK);
+    let resp = call().await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS, "Burst should be limited");
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "1");
+
+    // Once the burst window passes, the sustained quota still applies
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let resp = call().await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("x-ratelimit-remaining").unwrap(), "0");
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let resp = call().await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS, "Sustained rate should be limited");
+    let retry_after: u64 = resp.headers().get("retry-after").unwrap().to_str().unwrap().parse().unwrap();
+    assert!(retry_after > 50);
+}
+
+#[actix_web::test]
+async fn test_policy_counts_each_user_separately() {
+    let policy = RateLimitPolicy { key: RateLimitKey::User, burst: None, sustained: Quota::new(1, 60) };
+    let rate_limit = policy_limiter(
+        "test",
+        &policy,
+        Arc::new(TrustedProxies::default()),
+        InMemoryBackend::builder().build(),
+    );
+
+    // Stands in for `Auth`, which puts the user id in the request
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/users")
+                    .wrap(rate_limit)
+                    .wrap_fn(|req, srv| {
+                        let user = req.headers().get("X-Test-User").and_then(|v| v.to_str().ok()).map(str::to_string);
+                        if let Some(user) = user {
+                            req.extensions_mut().insert(user.parse::<Uuid>().unwrap());
+                        }
+                        srv.call(req)
+                    })
+                    .route("", web::get().to(test_handler))
+            )
+    ).await;
+    let call = |user: Option<Uuid>| {
+        let mut req = test::TestRequest::get().uri("/users").peer_addr("127.0.0.1:4000".parse().unwrap());
+        if let Some(user) = user {
+            req = req.insert_header(("X-Test-User", user.to_string()));
+        }
+        let app = &app;
+        async move { test::call_service(app, req.to_request()).await.status() }
+    };
+
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    assert_eq!(call(Some(alice)).await, StatusCode::OK);
+    assert_eq!(call(Some(alice)).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(Some(bob)).await, StatusCode::OK);
+
+    // Requests without a user are counted by IP
+    assert_eq!(call(None).await, StatusCode::OK);
+    assert_eq!(call(None).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn test_api_key_policy_counts_the_authenticated_key_not_the_header() {
+    let policy = RateLimitPolicy { key: RateLimitKey::ApiKey, burst: None, sustained: Quota::new(1, 60) };
+    let rate_limit = policy_limiter(
+        "test",
+        &policy,
+        Arc::new(TrustedProxies::default()),
+        InMemoryBackend::builder().build(),
+    );
+
+    // Stands in for `Auth`, which ignores `X-API-Key` when a token is sent
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/transactions")
+                    .wrap(rate_limit)
+                    .wrap_fn(|req, srv| {
+                        let user = req.headers().get("X-Test-User").and_then(|v| v.to_str().ok()).map(str::to_string);
+                        let key = req.headers().get("X-Test-Key").and_then(|v| v.to_str().ok()).map(str::to_string);
+                        if let Some(user) = user {
+                            req.extensions_mut().insert(user.parse::<Uuid>().unwrap());
+                        }
+                        if let Some(key) = key {
+                            req.extensions_mut().insert(ApiKeyIdentity { key_id: key.parse().unwrap(), scopes: vec![] });
+                        }
+                        srv.call(req)
+                    })
+                    .route("", web::get().to(test_handler))
+            )
+    ).await;
+    let call = |user: Uuid, key: Option<Uuid>| {
+        let mut req = test::TestRequest::get()
+            .uri("/transactions")
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .insert_header(("X-Test-User", user.to_string()))
+            .insert_header(("X-API-Key", Uuid::new_v4().to_string()));
+        if let Some(key) = key {
+            req = req.insert_header(("X-Test-Key", key.to_string()));
+        }
+        let app = &app;
+        async move { test::call_service(app, req.to_request()).await.status() }
+    };
+
+    // A fresh unchecked header each time does not earn a fresh bucket
+    let alice = Uuid::new_v4();
+    assert_eq!(call(alice, None).await, StatusCode::OK);
+    assert_eq!(call(alice, None).await, StatusCode::TOO_MANY_REQUESTS);
+
+    // Each authenticated key has its own bucket
+    let (bob, first_key, second_key) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    assert_eq!(call(bob, Some(first_key)).await, StatusCode::OK);
+    assert_eq!(call(bob, Some(first_key)).await, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(call(bob, Some(second_key)).await, StatusCode::OK);
+}
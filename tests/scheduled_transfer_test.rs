@@ -0,0 +1,222 @@
+//! Tests for scheduled and recurring transfers in `handlers::scheduled_transfer`
+//!
+//! The database tests need a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use serde_json::{json, Value};
+use sqlx::postgres::PgPool;
+use sqlx::Row;
+use std::time::Duration;
+use uuid::Uuid;
+
+use dodo_payments::config::ScheduleConfig;
+use dodo_payments::handlers;
+use dodo_payments::handlers::scheduled_transfer::run_due_transfers;
+use dodo_payments::models::scheduled_transfer::Schedule;
+use dodo_payments::models::Frequency;
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{balance_of, create_funded_user, setup_pool, transfer_config, PASSWORD};
+
+fn at(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, 9, 0, 0).unwrap()
+}
+
+#[test]
+fn test_monthly_occurrences_keep_the_day_of_month() {
+    let start = at(2025, 1, 31);
+
+    assert_eq!(Frequency::Monthly.occurrence(start, 1), Some(at(2025, 2, 28)));
+    assert_eq!(Frequency::Monthly.occurrence(start, 3), Some(at(2025, 4, 30)));
+    assert_eq!(Frequency::Monthly.occurrence(start, 4), Some(at(2025, 5, 31)));
+    assert_eq!(Frequency::Weekly.occurrence(start, 2), Some(at(2025, 2, 14)));
+    assert_eq!(Frequency::Once.occurrence(start, 0), Some(start));
+    assert_eq!(Frequency::Once.occurrence(start, 1), None);
+}
+
+#[test]
+fn test_schedules_end_and_pass_over_missed_occurrences() {
+    let daily = Schedule {
+        frequency: Frequency::Daily,
+        start_at: at(2025, 3, 1),
+        end_at: None,
+        max_occurrences: Some(5),
+    };
+
+    assert_eq!(daily.next_due(1, at(2025, 3, 1)), Some((1, at(2025, 3, 2))));
+    // Occurrences missed while the scheduler was down are passed over, but count
+    assert_eq!(daily.next_due(1, at(2025, 3, 3) + ChronoDuration::hours(1)), Some((3, at(2025, 3, 4))));
+    assert_eq!(daily.next_due(5, at(2025, 3, 1)), None);
+    assert_eq!(daily.next_due(1, at(2025, 4, 1)), None);
+
+    let until = Schedule { end_at: Some(at(2025, 3, 2)), max_occurrences: None, ..daily };
+    assert_eq!(until.next_due(1, at(2025, 3, 1)), Some((1, at(2025, 3, 2))));
+    assert_eq!(until.next_due(2, at(2025, 3, 1)), None);
+
+    let once = Schedule { frequency: Frequency::Once, end_at: None, max_occurrences: None, ..daily };
+    assert_eq!(once.next_due(1, at(2025, 3, 1)), None);
+}
+
+fn schedule_config() -> ScheduleConfig {
+    ScheduleConfig {
+        poll_interval: Duration::from_secs(1),
+        retry_interval: Duration::from_secs(60),
+        max_retries: 1,
+    }
+}
+
+// Make the schedule's next occurrence due now
+async fn make_due(pool: &PgPool, scheduled_id: &str) {
+    sqlx::query("UPDATE scheduled_transfers SET next_run_at = NOW() - INTERVAL '1 second' WHERE id = $1::uuid")
+        .bind(scheduled_id)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_scheduled_transfers_run_and_follow_their_policy() {
+    let pool = setup_pool().await;
+    let (sender, username) = create_funded_user(&pool, "25").await;
+    let (recipient, _) = create_funded_user(&pool, "0").await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest| {
+        let app = &app;
+        async move {
+            match app.call(req.peer_addr("192.0.2.10:5000".parse().unwrap()).to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+                    (status, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), Value::Null),
+            }
+        }
+    };
+
+    let login = actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": PASSWORD }));
+    let (_, login) = call(login).await;
+    let bearer = format!("Bearer {}", login["token"].as_str().unwrap());
+    let schedule = |body: Value| actix_test::TestRequest::post()
+        .uri("/api/scheduled-transfers")
+        .insert_header(("Authorization", bearer.clone()))
+        .set_json(body);
+    let action = |id: &str, action: &str| actix_test::TestRequest::post()
+        .uri(&format!("/api/scheduled-transfers/{}/{}", id, action))
+        .insert_header(("Authorization", bearer.clone()));
+
+    // Start times in the past and limits on one-off transfers are refused
+    let past = schedule(json!({
+        "recipient_id": recipient, "amount": "10.00", "currency": "USD", "frequency": "daily",
+        "start_at": Utc::now() - ChronoDuration::hours(1),
+    }));
+    assert_eq!(call(past).await.0, 400);
+    let limited_once = schedule(json!({
+        "recipient_id": recipient, "amount": "10.00", "currency": "USD", "frequency": "once",
+        "start_at": Utc::now(), "max_occurrences": 2,
+    }));
+    assert_eq!(call(limited_once).await.0, 400);
+
+    // Two daily payments of 10 from a balance of 25, skipping any it cannot cover
+    let (status, skipping) = call(schedule(json!({
+        "recipient_id": recipient, "amount": "10.00", "currency": "USD", "frequency": "daily",
+        "start_at": Utc::now(), "max_occurrences": 3,
+    })))
+    .await;
+    assert_eq!(status, 201);
+    assert_eq!(skipping["status"], json!("active"));
+    let skipping_id = skipping["id"].as_str().unwrap().to_string();
+
+    // A weekly payment of 20, retried once when it cannot be covered
+    let (_, retrying) = call(schedule(json!({
+        "recipient_id": recipient, "amount": "20.00", "currency": "USD", "frequency": "weekly",
+        "start_at": Utc::now() + ChronoDuration::days(1), "on_insufficient_funds": "retry",
+    })))
+    .await;
+    let retrying_id = retrying["id"].as_str().unwrap().to_string();
+
+    run_due_transfers(&pool, &transfer_config(), &schedule_config()).await.unwrap();
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(10));
+    make_due(&pool, &skipping_id).await;
+    run_due_transfers(&pool, &transfer_config(), &schedule_config()).await.unwrap();
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(5));
+
+    // The third occurrence is skipped for lack of funds, which completes the schedule
+    make_due(&pool, &skipping_id).await;
+    run_due_transfers(&pool, &transfer_config(), &schedule_config()).await.unwrap();
+    let get = actix_test::TestRequest::get()
+        .uri(&format!("/api/scheduled-transfers/{}", skipping_id))
+        .insert_header(("Authorization", bearer.clone()));
+    let (status, skipping) = call(get).await;
+    assert_eq!(status, 200);
+    assert_eq!(skipping["status"], json!("completed"));
+    assert_eq!(skipping["occurrences_done"], json!(3));
+    assert_eq!(skipping["next_run_at"], Value::Null);
+    let outcomes: Vec<&str> = skipping["runs"].as_array().unwrap().iter().map(|run| run["status"].as_str().unwrap()).collect();
+    assert_eq!(outcomes, vec!["skipped", "succeeded", "succeeded"]);
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(5));
+
+    // The retrying schedule waits and tries the same occurrence again, then fails it
+    make_due(&pool, &retrying_id).await;
+    run_due_transfers(&pool, &transfer_config(), &schedule_config()).await.unwrap();
+    let row = sqlx::query("SELECT occurrence, attempt, next_run_at > NOW() AS later FROM scheduled_transfers WHERE id = $1::uuid")
+        .bind(&retrying_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!((row.get::<i32, _>("occurrence"), row.get::<i32, _>("attempt"), row.get::<bool, _>("later")), (0, 1, true));
+    make_due(&pool, &retrying_id).await;
+    run_due_transfers(&pool, &transfer_config(), &schedule_config()).await.unwrap();
+    let runs: Vec<String> = sqlx::query("SELECT status FROM scheduled_transfer_runs WHERE scheduled_transfer_id = $1::uuid ORDER BY created_at")
+        .bind(&retrying_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get("status"))
+        .collect();
+    assert_eq!(runs, vec!["retrying", "failed"]);
+
+    // Paused schedules do not run; cancelled ones cannot be resumed. Wait out
+    // the burst allowed by the transactions rate limit first.
+    actix_rt::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(call(action(&retrying_id, "pause")).await.0, 200);
+    assert_eq!(call(action(&retrying_id, "pause")).await.0, 409);
+    make_due(&pool, &retrying_id).await;
+    run_due_transfers(&pool, &transfer_config(), &schedule_config()).await.unwrap();
+    let (status, resumed) = call(action(&retrying_id, "resume")).await;
+    assert_eq!((status, resumed["status"].as_str()), (200, Some("active")));
+    let (status, cancelled) = call(action(&retrying_id, "cancel")).await;
+    assert_eq!((status, cancelled["next_run_at"].clone()), (200, Value::Null));
+    assert_eq!(call(action(&retrying_id, "resume")).await.0, 409);
+    assert_eq!(call(action(&Uuid::new_v4().to_string(), "cancel")).await.0, 404);
+
+    let list = actix_test::TestRequest::get()
+        .uri("/api/scheduled-transfers")
+        .insert_header(("Authorization", bearer.clone()));
+    let (_, listed) = call(list).await;
+    assert_eq!(listed["scheduled_transfers"].as_array().unwrap().len(), 2);
+    let runs: i64 = sqlx::query("SELECT COUNT(*) AS count FROM scheduled_transfer_runs WHERE scheduled_transfer_id = $1::uuid")
+        .bind(&retrying_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("count");
+    assert_eq!(runs, 2);
+}
@@ -1,13 +1,10 @@
 //! Tests for authentication and security features
 //! This file tests JWT token validation and rate limiting functionality
 
-use actix_web::{test, web, App, HttpResponse, middleware::DefaultHeaders};
+use actix_web::{test, web, App, HttpResponse};
 use serde_json::json;
-use std::fs;
-use std::io::Write;
 
-// Import your crate's modules
-use dodo_payments::handlers;
+use dodo_payments::middleware::Auth;
 
 // Simple test for rate limiting without Auth middleware
 #[actix_web::test]
@@ -18,7 +15,7 @@ async fn test_rate_limiting() {
         RateLimiter,    };
     use std::time::Duration;    // Create a rate limiter that only allows 5 requests per minute
     let input = SimpleInputFunctionBuilder::new(Duration::from_secs(60), 5)
-        .key_extractor(|req| Some("test_key".to_string()))
+        .custom_key("test_key")
         .build();
     let backend = InMemoryBackend::builder().build();
     let rate_limit = RateLimiter::builder(backend, input)
@@ -75,13 +72,8 @@ async fn test_auth_routes() {
         App::new()
             .service(
                 web::scope("/api/protected")
-                    .wrap(
-                        // Use DefaultHeaders middleware to check for Authorization
-                        DefaultHeaders::new()
-                            .add(("Authorization", "required"))
-                    )
+                    .wrap(Auth)
                     .route("", web::get().to(|| async {
-                        // Simple middleware to simulate auth check
                         HttpResponse::Ok().json(json!({"success": true}))
                     }))
             )
@@ -92,8 +84,59 @@ async fn test_auth_routes() {
         .uri("/api/protected")
         .to_request();
 
-    let resp = test::call_service(&app, req).await;
+    // The auth middleware rejects with an error, which actix renders as the response
+    let status = match test::try_call_service(&app, req).await {
+        Ok(resp) => resp.status().as_u16(),
+        Err(err) => err.as_response_error().status_code().as_u16(),
+    };
     
     // Should not return 200 - this is a simple check to ensure auth middleware is working
-    assert_ne!(resp.status().as_u16(), 200, "Unauthenticated request should not succeed");
+    assert_ne!(status, 200, "Unauthenticated request should not succeed");
+}
+
+// Admin routes only admit roles that hold the route's permission
+#[actix_web::test]
+async fn test_authorize_enforces_role_permissions() {
+    use actix_web::{dev::Service, HttpMessage};
+    use dodo_payments::middleware::Authorize;
+    use dodo_payments::models::{Permission, Role};
+
+    // Stand in for Auth by taking the caller's role from a header
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/admin")
+                    .wrap(Authorize::new(Permission::RefundTransactions))
+                    .wrap_fn(|req, srv| {
+                        let role = req
+                            .headers()
+                            .get("X-Test-Role")
+                            .and_then(|role| role.to_str().ok())
+                            .and_then(|role| role.parse::<Role>().ok());
+                        if let Some(role) = role {
+                            req.extensions_mut().insert(role);
+                        }
+                        srv.call(req)
+                    })
+                    .route("/refund", web::post().to(|| async {
+                        HttpResponse::Ok().json(json!({"success": true}))
+                    }))
+            )
+    ).await;
+
+    let mut statuses = Vec::new();
+    for role in [None, Some("user"), Some("auditor"), Some("support"), Some("admin")] {
+        let mut req = test::TestRequest::post().uri("/admin/refund");
+        if let Some(role) = role {
+            req = req.insert_header(("X-Test-Role", role));
+        }
+
+        let status = match test::try_call_service(&app, req.to_request()).await {
+            Ok(resp) => resp.status().as_u16(),
+            Err(err) => err.error_response().status().as_u16(),
+        };
+        statuses.push(status);
+    }
+
+    assert_eq!(statuses, vec![401, 403, 403, 200, 200]);
 }
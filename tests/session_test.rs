@@ -0,0 +1,162 @@
+//! Tests for login sessions in `handlers::session`
+//!
+//! These tests need a Postgres database with `migrations/schema.sql` applied.
+//! Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App};
+use serde_json::{json, Value};
+use sqlx::Row;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use dodo_payments::handlers;
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{create_admin, create_user, setup_pool, PASSWORD};
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_sessions_are_listed_and_revoked_per_device() {
+    let pool = setup_pool().await;
+    let (_, username) = create_user(&pool).await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest, peer: &str, bearer: Option<&str>| {
+        let mut req = req.peer_addr(peer.parse::<SocketAddr>().unwrap());
+        if let Some(bearer) = bearer {
+            req = req.insert_header(("Authorization", format!("Bearer {}", bearer)));
+        }
+        let app = &app;
+        async move {
+            match app.call(req.to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+                    (status, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), Value::Null),
+            }
+        }
+    };
+    let login = |user_agent: &str| actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .insert_header(("User-Agent", user_agent.to_string()))
+        .set_json(json!({ "username": username, "password": PASSWORD }));
+    let sessions = || actix_test::TestRequest::get().uri("/api/users/sessions");
+
+    let (status, laptop) = call(login("Laptop/1.0"), "192.0.2.10:5000", None).await;
+    assert_eq!(status, 200);
+    let (_, phone) = call(login("Phone/2.0"), "192.0.2.20:5000", None).await;
+    let (_, tablet) = call(login("Tablet/3.0"), "192.0.2.30:5000", None).await;
+    let laptop_token = laptop["token"].as_str().unwrap();
+    let phone_token = phone["token"].as_str().unwrap();
+
+    // Each login is a session with the device it came from
+    let (status, listed) = call(sessions(), "192.0.2.10:5000", Some(laptop_token)).await;
+    assert_eq!(status, 200);
+    let listed = listed["sessions"].as_array().unwrap().clone();
+    assert_eq!(listed.len(), 3);
+    let current: Vec<&Value> = listed.iter().filter(|session| session["current"] == json!(true)).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["user_agent"], json!("Laptop/1.0"));
+    assert_eq!(current[0]["ip_address"], json!("192.0.2.10"));
+    let phone_session = listed.iter().find(|session| session["user_agent"] == json!("Phone/2.0")).unwrap();
+
+    // Revoking the phone's session stops its access and refresh tokens
+    let revoke = actix_test::TestRequest::delete()
+        .uri(&format!("/api/users/sessions/{}", phone_session["id"].as_str().unwrap()));
+    assert_eq!(call(revoke, "192.0.2.10:5000", Some(laptop_token)).await.0, 204);
+    assert_eq!(call(sessions(), "192.0.2.20:5000", Some(phone_token)).await.0, 401);
+    let refresh = actix_test::TestRequest::post()
+        .uri("/api/users/token/refresh")
+        .set_json(json!({ "refresh_token": phone["refresh_token"] }));
+    assert_eq!(call(refresh, "192.0.2.20:5000", None).await.0, 401);
+
+    // Another user's session cannot be revoked
+    let stranger = actix_test::TestRequest::delete().uri(&format!("/api/users/sessions/{}", Uuid::new_v4()));
+    assert_eq!(call(stranger, "192.0.2.10:5000", Some(laptop_token)).await.0, 404);
+
+    // Logging out everywhere ends the rest, this session included
+    let everywhere = actix_test::TestRequest::delete().uri("/api/users/sessions");
+    let (status, body) = call(everywhere, "192.0.2.10:5000", Some(laptop_token)).await;
+    assert_eq!((status, body["revoked_sessions"].as_u64()), (200, Some(2)));
+    assert_eq!(call(sessions(), "192.0.2.10:5000", Some(laptop_token)).await.0, 401);
+    assert_eq!(call(sessions(), "192.0.2.30:5000", Some(tablet["token"].as_str().unwrap())).await.0, 401);
+
+    let active: i64 = sqlx::query(
+        "SELECT COUNT(*) AS count FROM sessions s JOIN users u ON u.id = s.user_id WHERE u.username = $1 AND s.revoked_at IS NULL"
+    )
+    .bind(&username)
+    .fetch_one(&pool)
+    .await
+    .unwrap()
+    .get("count");
+    assert_eq!(active, 0);
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_a_role_change_revokes_the_users_sessions() {
+    let pool = setup_pool().await;
+    let (_, admin) = create_admin(&pool).await;
+    let (demoted_id, demoted) = create_admin(&pool).await;
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest, bearer: Option<&str>| {
+        let mut req = req.peer_addr("192.0.2.40:5000".parse::<SocketAddr>().unwrap());
+        if let Some(bearer) = bearer {
+            req = req.insert_header(("Authorization", format!("Bearer {}", bearer)));
+        }
+        let app = &app;
+        async move {
+            match app.call(req.to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let body: Value = serde_json::from_slice(&actix_test::read_body(resp).await).unwrap_or(Value::Null);
+                    (status, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), Value::Null),
+            }
+        }
+    };
+    let login = |username: &str| actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": PASSWORD }));
+    let audit_log = || actix_test::TestRequest::get().uri("/admin/audit-log");
+
+    let (_, body) = call(login(&admin), None).await;
+    let admin_token = body["token"].as_str().unwrap().to_string();
+    let (_, body) = call(login(&demoted), None).await;
+    let demoted_token = body["token"].as_str().unwrap().to_string();
+    assert_eq!(call(audit_log(), Some(&demoted_token)).await.0, 200);
+
+    let demote = actix_test::TestRequest::put()
+        .uri(&format!("/admin/users/{}/role", demoted_id))
+        .set_json(json!({ "role": "user" }));
+    let (status, body) = call(demote, Some(&admin_token)).await;
+    assert_eq!((status, body["role"].as_str()), (200, Some("user")));
+
+    // The token issued with the old role stops working at once
+    assert_eq!(call(audit_log(), Some(&demoted_token)).await.0, 401);
+
+    // Logging in again gives a token with the new role
+    let (_, body) = call(login(&demoted), None).await;
+    assert_eq!(call(audit_log(), Some(body["token"].as_str().unwrap())).await.0, 403);
+    assert_eq!(call(audit_log(), Some(&admin_token)).await.0, 200);
+}
@@ -0,0 +1,240 @@
+//! Tests for account statements in `utils::statement` and `handlers::statement`
+//!
+//! The database tests need a Postgres database with `migrations/schema.sql`
+//! applied. Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{dev::Service, test as actix_test, web, App};
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde_json::json;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use dodo_payments::handlers;
+use dodo_payments::models::ledger::EntryType;
+use dodo_payments::models::{Money, Statement, StatementEntry, StatementFormat};
+use dodo_payments::utils::keys::KeyStore;
+use dodo_payments::utils::statement;
+use dodo_payments::utils::xml;
+
+use common::{create_funded_user, setup_pool};
+
+fn usd(amount: &str) -> Money {
+    Money::parse(BigDecimal::from_str(amount).unwrap(), "USD").unwrap()
+}
+
+fn sample_statement() -> Statement {
+    Statement {
+        id: Uuid::nil(),
+        account_id: Uuid::parse_str("11111111-1111-1111-1111-111111111111").unwrap(),
+        from: NaiveDate::from_ymd_opt(2025, 5, 1).unwrap(),
+        to: NaiveDate::from_ymd_opt(2025, 5, 31).unwrap(),
+        opening_balance: usd("100.00"),
+        closing_balance: usd("75.00"),
+        generated_at: Utc.with_ymd_and_hms(2025, 6, 1, 9, 0, 0).unwrap(),
+    }
+}
+
+fn sample_entries() -> Vec<StatementEntry> {
+    vec![
+        StatementEntry {
+            id: Uuid::parse_str("22222222-2222-2222-2222-222222222222").unwrap(),
+            transaction_id: Some(Uuid::parse_str("33333333-3333-3333-3333-333333333333").unwrap()),
+            counterparty_id: Some(Uuid::parse_str("44444444-4444-4444-4444-444444444444").unwrap()),
+            entry_type: EntryType::Debit,
+            amount: usd("30.00"),
+            description: Some("=HYPERLINK(\"x\"), <rent> & more".to_string()),
+            booked_at: Utc.with_ymd_and_hms(2025, 5, 3, 12, 30, 0).unwrap(),
+            balance: usd("70.00"),
+        },
+        StatementEntry {
+            id: Uuid::parse_str("55555555-5555-5555-5555-555555555555").unwrap(),
+            transaction_id: None,
+            counterparty_id: None,
+            entry_type: EntryType::Credit,
+            amount: usd("5.00"),
+            description: None,
+            booked_at: Utc.with_ymd_and_hms(2025, 5, 9, 8, 0, 0).unwrap(),
+            balance: usd("75.00"),
+        },
+    ]
+}
+
+fn render(format: StatementFormat) -> String {
+    let statement = sample_statement();
+    let entries: String = sample_entries().iter().map(|entry| statement::entry(format, entry)).collect();
+    format!("{}{}{}", statement::header(format, &statement), entries, statement::footer(format, &statement))
+}
+
+#[test]
+fn test_csv_statements_list_balances_around_the_entries() {
+    let csv = render(StatementFormat::Csv);
+    let rows: Vec<&str> = csv.split("\r\n").collect();
+
+    assert_eq!(
+        rows,
+        vec![
+            "date,entry_id,transaction_id,counterparty_id,type,description,amount,currency,balance",
+            "2025-05-01T00:00:00Z,,,,opening_balance,Opening balance,,USD,100.00",
+            "2025-05-03T12:30:00Z,22222222-2222-2222-2222-222222222222,33333333-3333-3333-3333-333333333333,\
+             44444444-4444-4444-4444-444444444444,debit,\"'=HYPERLINK(\"\"x\"\"), <rent> & more\",-30.00,USD,70.00",
+            "2025-05-09T08:00:00Z,55555555-5555-5555-5555-555555555555,,,credit,,5.00,USD,75.00",
+            "2025-05-31T23:59:59Z,,,,closing_balance,Closing balance,,USD,75.00",
+            "",
+        ]
+    );
+    assert_eq!(statement::file_name(StatementFormat::Csv, &sample_statement()), "statement-USD-2025-05-01-2025-05-31.csv");
+}
+
+#[test]
+fn test_camt053_statements_carry_balances_and_running_balances() {
+    let document = xml::parse(&render(StatementFormat::Camt053)).unwrap();
+    let statement = document.find(&["BkToCstmrStmt", "Stmt"]).unwrap();
+
+    let balances: Vec<(&str, &str, &str)> = statement
+        .children_named("Bal")
+        .map(|balance| {
+            (
+                balance.find(&["Tp", "CdOrPrtry", "Cd"]).unwrap().trimmed_text(),
+                balance.child("Amt").unwrap().trimmed_text(),
+                balance.find(&["Dt", "Dt"]).unwrap().trimmed_text(),
+            )
+        })
+        .collect();
+    assert_eq!(balances, vec![("OPBD", "100.00", "2025-05-01"), ("CLBD", "75.00", "2025-05-31")]);
+
+    let entries: Vec<_> = statement.children_named("Ntry").collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].child("CdtDbtInd").unwrap().trimmed_text(), "DBIT");
+    assert_eq!(entries[0].child("Amt").unwrap().attribute("Ccy"), Some("USD"));
+    assert_eq!(
+        entries[0].find(&["NtryDtls", "TxDtls", "RmtInf", "Ustrd"]).unwrap().trimmed_text(),
+        "=HYPERLINK(\"x\"), <rent> & more"
+    );
+    assert_eq!(
+        entries[0].find(&["NtryDtls", "TxDtls", "RltdPties", "CdtrAcct", "Id", "Othr", "Id"]).unwrap().trimmed_text(),
+        "44444444-4444-4444-4444-444444444444"
+    );
+    assert_eq!(entries[0].child("AddtlNtryInf").unwrap().trimmed_text(), "Balance after entry: 70.00 USD");
+    assert!(entries[1].child("NtryDtls").is_none());
+}
+
+#[test]
+fn test_ofx_statements_are_well_formed() {
+    let document = xml::parse(&render(StatementFormat::Ofx)).unwrap();
+    let statement = document.find(&["BANKMSGSRSV1", "STMTTRNRS", "STMTRS"]).unwrap();
+
+    assert_eq!(statement.child("CURDEF").unwrap().trimmed_text(), "USD");
+    let transactions: Vec<(&str, &str)> = statement
+        .child("BANKTRANLIST")
+        .unwrap()
+        .children_named("STMTTRN")
+        .map(|transaction| {
+            (transaction.child("TRNTYPE").unwrap().trimmed_text(), transaction.child("TRNAMT").unwrap().trimmed_text())
+        })
+        .collect();
+    assert_eq!(transactions, vec![("DEBIT", "-30.00"), ("CREDIT", "5.00")]);
+    assert_eq!(statement.find(&["LEDGERBAL", "BALAMT"]).unwrap().trimmed_text(), "75.00");
+    assert_eq!(statement.find(&["BALLIST", "BAL", "VALUE"]).unwrap().trimmed_text(), "100.00");
+    assert_eq!(statement.find(&["LEDGERBAL", "DTASOF"]).unwrap().trimmed_text(), "20250531235959.000[0:GMT]");
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_statements_stream_every_entry_with_running_balances() {
+    let pool = setup_pool().await;
+    let (sender, username) = create_funded_user(&pool, "100").await;
+    let (recipient, _) = create_funded_user(&pool, "0").await;
+
+    // Funding from before the period only shows in the opening balance
+    sqlx::query("UPDATE ledger_entries SET created_at = NOW() - INTERVAL '3 days' WHERE account_id = (SELECT id FROM accounts WHERE user_id = $1)")
+        .bind(sender)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let app = actix_test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(KeyStore::from_secret("test_jwt_secret").unwrap()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+
+    let call = |req: actix_test::TestRequest| {
+        let app = &app;
+        async move {
+            match app.call(req.peer_addr("192.0.2.10:5000".parse().unwrap()).to_request()).await {
+                Ok(resp) => {
+                    let status = resp.status().as_u16();
+                    let content_type = resp
+                        .headers()
+                        .get("Content-Type")
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string();
+                    let body = String::from_utf8(actix_test::read_body(resp).await.to_vec()).unwrap();
+                    (status, content_type, body)
+                }
+                Err(err) => (err.error_response().status().as_u16(), String::new(), String::new()),
+            }
+        }
+    };
+    let login = actix_test::TestRequest::post()
+        .uri("/api/users/login")
+        .set_json(json!({ "username": username, "password": "Password123!" }));
+    let (_, _, body) = call(login).await;
+    let token: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let auth = format!("Bearer {}", token["token"].as_str().unwrap());
+
+    for (amount, description) in [("30.00", "Rent, May"), ("12.50", "Groceries")] {
+        let transfer = actix_test::TestRequest::post()
+            .uri("/api/transactions")
+            .insert_header(("Authorization", auth.clone()))
+            .set_json(json!({ "recipient_id": recipient, "amount": amount, "currency": "USD", "description": description }));
+        assert_eq!(call(transfer).await.0, 201);
+    }
+
+    let today = Utc::now().date_naive();
+    let statement = |query: String| actix_test::TestRequest::get()
+        .uri(&format!("/api/accounts/statement?{}", query))
+        .insert_header(("Authorization", auth.clone()));
+    let period = format!("from={}&to={}", today - chrono::Duration::days(1), today);
+
+    let (status, content_type, csv) = call(statement(format!("{}&format=csv", period))).await;
+    assert_eq!((status, content_type.as_str()), (200, "text/csv; charset=utf-8"));
+    let rows: Vec<Vec<&str>> = csv.lines().skip(1).map(|row| row.split(',').collect()).collect();
+    let balances: Vec<&str> = rows.iter().map(|row| *row.last().unwrap()).collect();
+    assert_eq!(balances, vec!["100.00", "70.00", "57.50", "57.50"]);
+    assert_eq!(rows[1][4], "debit");
+    assert_eq!(rows[1][3], recipient.to_string());
+    assert_eq!(rows[2][5..7], ["Groceries", "-12.50"]);
+
+    let (status, content_type, camt) = call(statement(format!("{}&format=camt053", period))).await;
+    assert_eq!((status, content_type.as_str()), (200, "application/xml"));
+    let document = xml::parse(&camt).unwrap();
+    let entries = document.find(&["BkToCstmrStmt", "Stmt"]).unwrap().children_named("Ntry").count();
+    assert_eq!(entries, 2);
+
+    let (status, _, ofx) = call(statement(format!("{}&format=ofx", period))).await;
+    assert_eq!(status, 200);
+    assert!(xml::parse(&ofx).is_ok());
+
+    // A period before any activity has equal balances and no entries
+    let (_, _, empty) = call(statement("from=2020-01-01&to=2020-01-31&format=csv".to_string())).await;
+    assert_eq!(empty.lines().count(), 3);
+    assert!(empty.ends_with(",USD,0.00\r\n"));
+
+    let (status, _, _) = call(statement(format!("from={}&to=2020-01-01&format=csv", today))).await;
+    assert_eq!(status, 400);
+    let (status, _, _) = call(statement("from=2024-01-01&to=2025-01-01&format=csv".to_string())).await;
+    assert_eq!(status, 400);
+    let (status, _, _) = call(statement("from=2024-01-01&to=2024-12-31&format=csv".to_string())).await;
+    assert_eq!(status, 200);
+    let (status, _, _) = call(statement(format!("{}&format=csv&currency=EUR", period))).await;
+    assert_eq!(status, 404);
+    let (status, _, _) = call(statement(format!("{}&format=pdf", period))).await;
+    assert_eq!(status, 400);
+}
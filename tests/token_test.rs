@@ -0,0 +1,100 @@
+//! Database tests for refresh tokens and logout in `handlers::token`
+//!
+//! These tests need a Postgres database with `migrations/schema.sql` applied.
+//! Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use actix_web::{test, web, App};
+use sqlx::postgres::PgPool;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use dodo_payments::handlers;
+use dodo_payments::handlers::token::{issue_tokens, rotate_refresh_token};
+use dodo_payments::models::{Role, TokenResponse};
+use dodo_payments::utils::keys::KeyStore;
+
+use common::{create_user, setup_pool, token_config};
+
+fn test_keys() -> KeyStore {
+    KeyStore::from_secret("test_jwt_secret").unwrap()
+}
+
+// Insert a user and log them in, starting a new token family
+async fn login_user(pool: &PgPool) -> TokenResponse {
+    let (user_id, _) = create_user(pool).await;
+
+    let mut tx = pool.begin().await.unwrap();
+    let tokens = issue_tokens(&mut tx, &token_config(), &test_keys(), user_id, Role::User, Uuid::new_v4())
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    tokens
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_refresh_token_reuse_revokes_the_family() {
+    let pool = setup_pool().await;
+    let config = token_config();
+    let keys = test_keys();
+    let first = login_user(&pool).await;
+
+    let second = rotate_refresh_token(&pool, &config, &keys, &first.refresh_token).await.unwrap();
+    assert_ne!(second.refresh_token, first.refresh_token);
+    assert_eq!(second.expires_in, 900);
+
+    // Replaying the rotated-out token fails and takes its successor down with it
+    assert!(rotate_refresh_token(&pool, &config, &keys, &first.refresh_token).await.is_err());
+    assert!(rotate_refresh_token(&pool, &config, &keys, &second.refresh_token).await.is_err());
+
+    assert!(rotate_refresh_token(&pool, &config, &keys, "not-a-token").await.is_err());
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_logout_revokes_the_access_and_refresh_tokens() {
+    let pool = setup_pool().await;
+    let tokens = login_user(&pool).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(test_keys()))
+            .configure(handlers::config_routes)
+    )
+    .await;
+    let peer: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+    let bearer = format!("Bearer {}", tokens.token);
+
+    let status = |req: actix_http::Request| {
+        let app = &app;
+        async move {
+            match test::try_call_service(app, req).await {
+                Ok(resp) => resp.status().as_u16(),
+                Err(err) => err.error_response().status().as_u16(),
+            }
+        }
+    };
+
+    let profile = || {
+        test::TestRequest::get()
+            .uri("/api/users/profile")
+            .peer_addr(peer)
+            .insert_header(("Authorization", bearer.clone()))
+            .to_request()
+    };
+    assert_eq!(status(profile()).await, 200);
+
+    let logout = test::TestRequest::post()
+        .uri("/api/users/logout")
+        .peer_addr(peer)
+        .insert_header(("Authorization", bearer.clone()))
+        .to_request();
+    assert_eq!(status(logout).await, 204);
+
+    assert_eq!(status(profile()).await, 401);
+    assert!(rotate_refresh_token(&pool, &token_config(), &test_keys(), &tokens.refresh_token).await.is_err());
+}
@@ -0,0 +1,349 @@
+//! Database tests for the transfer path in `handlers::transaction`
+//!
+//! These tests need a Postgres database with `migrations/schema.sql` applied.
+//! Point `DATABASE_URL` at it and run with `cargo test -- --ignored`.
+
+mod common;
+
+use bigdecimal::BigDecimal;
+use sqlx::postgres::PgPool;
+use sqlx::Row;
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+
+use dodo_payments::config::TransferConfig;
+use dodo_payments::handlers::fx::issue_quote;
+use dodo_payments::handlers::ledger::check_ledger;
+use dodo_payments::handlers::transaction::{
+    expire_holds, process_capture, process_refund, process_transfer, process_void, RefundInitiator,
+};
+use dodo_payments::models::{
+    AppError, CaptureTransactionRequest, CreateQuoteRequest, CreateTransactionRequest,
+    RefundTransactionRequest, TransactionStatus,
+};
+
+use common::{balance_of, create_funded_user, create_user, open_wallet, setup_pool, transfer_config};
+
+async fn wallet_balance(pool: &PgPool, user_id: Uuid, currency: &str) -> BigDecimal {
+    sqlx::query("SELECT balance FROM accounts WHERE user_id = $1 AND currency = $2")
+        .bind(user_id)
+        .bind(currency)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+        .get("balance")
+}
+
+fn dec(amount: &str) -> BigDecimal {
+    BigDecimal::from_str(amount).unwrap()
+}
+
+fn transfer_request(recipient_id: Uuid, amount: &str) -> CreateTransactionRequest {
+    CreateTransactionRequest {
+        recipient_id,
+        amount: dec(amount),
+        currency: "USD".to_string(),
+        description: None,
+        capture: None,
+        quote_id: None,
+    }
+}
+
+fn hold_request(recipient_id: Uuid, amount: &str) -> CreateTransactionRequest {
+    CreateTransactionRequest {
+        capture: Some(false),
+        ..transfer_request(recipient_id, amount)
+    }
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_concurrent_transfers_cannot_overdraw() {
+    let pool = setup_pool().await;
+    let sender = create_funded_user(&pool, "100").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+
+    // Ten transfers of 20 race for a balance that only covers five of them
+    let transfers = (0..10).map(|_| {
+        let pool = pool.clone();
+        async move { process_transfer(&pool, &transfer_config(), sender, &transfer_request(recipient, "20")).await }
+    });
+    let results = futures::future::join_all(transfers).await;
+
+    let succeeded = results.iter().filter(|r| r.is_ok()).count();
+    assert_eq!(succeeded, 5);
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(0));
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(100));
+
+    // The cached balances must agree with the journal
+    let report = check_ledger(&pool).await.unwrap();
+    assert!(report
+        .account_drift
+        .iter()
+        .all(|drift| drift.user_id != sender && drift.user_id != recipient));
+    assert!(report.unbalanced_journals.is_empty());
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_failed_transfer_is_recorded_with_reason() {
+    let pool = setup_pool().await;
+    let sender = create_funded_user(&pool, "5").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+
+    let result = process_transfer(&pool, &transfer_config(), sender, &transfer_request(recipient, "10")).await;
+    assert!(result.is_err());
+
+    let row = sqlx::query("SELECT status, failure_reason FROM transactions WHERE sender_id = $1")
+        .bind(sender)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    let status: i32 = row.get("status");
+    let reason: Option<String> = row.get("failure_reason");
+
+    assert_eq!(status, 2);
+    assert!(reason.unwrap().contains("Insufficient funds"));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(5));
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(0));
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_refunds_cannot_exceed_original_amount() {
+    let pool = setup_pool().await;
+    let sender = create_funded_user(&pool, "50").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+    let admin = create_funded_user(&pool, "0").await.0;
+
+    let original = process_transfer(&pool, &transfer_config(), sender, &transfer_request(recipient, "30"))
+        .await
+        .unwrap();
+
+    // Only the recipient may refund
+    let partial = RefundTransactionRequest { amount: Some(dec("10")), description: None };
+    assert!(process_refund(&pool, original.id, RefundInitiator::Recipient(sender), &partial).await.is_err());
+
+    let refund = process_refund(&pool, original.id, RefundInitiator::Recipient(recipient), &partial).await.unwrap();
+    assert_eq!(refund.sender_id, recipient);
+    assert_eq!(refund.recipient_id, sender);
+    assert_eq!(refund.refund_of, Some(original.id));
+
+    // 25 more would bring the refunds past the original 30
+    let too_much = RefundTransactionRequest { amount: Some(dec("25")), description: None };
+    assert!(process_refund(&pool, original.id, RefundInitiator::Admin(admin), &too_much).await.is_err());
+
+    // Without an amount the remainder is refunded
+    let remainder = RefundTransactionRequest { amount: None, description: None };
+    let admin_refund = process_refund(&pool, original.id, RefundInitiator::Admin(admin), &remainder).await.unwrap();
+
+    // Only the admin's refund is audited, along with the money movement
+    let audited: Vec<serde_json::Value> = sqlx::query(
+        "SELECT details FROM admin_audit_log WHERE actor_id = $1 AND action = 'refund_transaction' AND target_id = $2"
+    )
+    .bind(admin)
+    .bind(original.id)
+    .fetch_all(&pool)
+    .await
+    .unwrap()
+    .iter()
+    .map(|row| row.get("details"))
+    .collect();
+    assert_eq!(audited.len(), 1);
+    assert_eq!(audited[0]["refund_id"], serde_json::json!(admin_refund.id));
+
+    let refunded: BigDecimal = sqlx::query("SELECT refunded_amount FROM transactions WHERE id = $1")
+        .bind(original.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("refunded_amount");
+    assert_eq!(refunded, BigDecimal::from(30));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(50));
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(0));
+
+    assert!(process_refund(&pool, original.id, RefundInitiator::Admin(admin), &remainder).await.is_err());
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_holds_reserve_funds_until_captured_or_voided() {
+    let pool = setup_pool().await;
+    let config = transfer_config();
+    let sender = create_funded_user(&pool, "100").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+
+    // A hold leaves the ledger balance alone but is not available to spend
+    let hold = process_transfer(&pool, &config, sender, &hold_request(recipient, "60"))
+        .await
+        .unwrap();
+    assert!(matches!(hold.status, TransactionStatus::Pending));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(100));
+    assert!(process_transfer(&pool, &config, sender, &transfer_request(recipient, "50")).await.is_err());
+
+    // Only the parties to the hold can capture it, and not beyond the authorization
+    let partial = CaptureTransactionRequest { amount: Some(dec("25")) };
+    let stranger = create_funded_user(&pool, "0").await.0;
+    assert!(process_capture(&pool, hold.id, stranger, &partial).await.is_err());
+    let too_much = CaptureTransactionRequest { amount: Some(dec("61")) };
+    assert!(process_capture(&pool, hold.id, recipient, &too_much).await.is_err());
+
+    // A partial capture settles the amount and releases the rest
+    let captured = process_capture(&pool, hold.id, recipient, &partial).await.unwrap();
+    assert!(matches!(captured.status, TransactionStatus::Completed));
+    assert_eq!(captured.amount, BigDecimal::from(25));
+    assert_eq!(captured.authorized_amount, Some(BigDecimal::from(60)));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(75));
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(25));
+    assert!(process_void(&pool, hold.id, sender).await.is_err());
+
+    // A voided hold frees the funds without moving them
+    let hold = process_transfer(&pool, &config, sender, &hold_request(recipient, "75"))
+        .await
+        .unwrap();
+    let voided = process_void(&pool, hold.id, sender).await.unwrap();
+    assert!(matches!(voided.status, TransactionStatus::Voided));
+    process_transfer(&pool, &config, sender, &transfer_request(recipient, "75"))
+        .await
+        .unwrap();
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(0));
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_uncaptured_holds_expire() {
+    let pool = setup_pool().await;
+    let config = TransferConfig {
+        hold_ttl: Duration::from_secs(0),
+        ..transfer_config()
+    };
+    let sender = create_funded_user(&pool, "10").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+
+    let hold = process_transfer(&pool, &config, sender, &hold_request(recipient, "10"))
+        .await
+        .unwrap();
+
+    // Past its TTL the hold no longer reserves funds and cannot be captured
+    let capture = CaptureTransactionRequest { amount: None };
+    assert!(process_capture(&pool, hold.id, recipient, &capture).await.is_err());
+    assert!(expire_holds(&pool).await.unwrap() >= 1);
+
+    let status: i32 = sqlx::query("SELECT status FROM transactions WHERE id = $1")
+        .bind(hold.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .get("status");
+    assert_eq!(status, TransactionStatus::Expired as i32);
+
+    process_transfer(&pool, &transfer_config(), sender, &transfer_request(recipient, "10"))
+        .await
+        .unwrap();
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(10));
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_transfers_use_the_wallet_in_their_currency() {
+    let pool = setup_pool().await;
+    let config = transfer_config();
+    let sender = create_funded_user(&pool, "100").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+    open_wallet(&pool, sender, "EUR", "50").await;
+
+    open_wallet(&pool, recipient, "EUR", "0").await;
+
+    let eur_transfer = CreateTransactionRequest {
+        currency: "EUR".to_string(),
+        ..transfer_request(recipient, "20")
+    };
+    let transaction = process_transfer(&pool, &config, sender, &eur_transfer).await.unwrap();
+    assert!(transaction.converted_currency.is_none());
+
+    assert_eq!(wallet_balance(&pool, sender, "EUR").await, BigDecimal::from(30));
+    assert_eq!(wallet_balance(&pool, recipient, "EUR").await, BigDecimal::from(20));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(100));
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(0));
+
+    // EUR funds cannot cover a USD transfer
+    let too_much = transfer_request(recipient, "120");
+    assert!(process_transfer(&pool, &config, sender, &too_much).await.is_err());
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_transfers_convert_into_the_recipients_currency() {
+    let pool = setup_pool().await;
+    let config = transfer_config();
+    let sender = create_funded_user(&pool, "100").await.0;
+
+    // The recipient only holds EUR
+    let (recipient, _) = create_user(&pool).await;
+    open_wallet(&pool, recipient, "EUR", "0").await;
+
+    // 1% of 50 USD is kept and the remaining 49.50 converts at 0.9
+    let transaction = process_transfer(&pool, &config, sender, &transfer_request(recipient, "50"))
+        .await
+        .unwrap();
+    assert_eq!(transaction.fx_rate, Some(dec("0.9")));
+    assert_eq!(transaction.fx_fee, Some(dec("0.5")));
+    assert_eq!(transaction.converted_amount, Some(dec("44.55")));
+    assert_eq!(transaction.converted_currency.as_deref(), Some("EUR"));
+    assert_eq!(balance_of(&pool, sender).await, dec("50"));
+    assert_eq!(wallet_balance(&pool, recipient, "EUR").await, dec("44.55"));
+
+    // A quote locks the rate and pays for exactly one matching transfer
+    let quote_request = CreateQuoteRequest {
+        from_currency: "USD".to_string(),
+        to_currency: "EUR".to_string(),
+        amount: dec("20"),
+    };
+    let quote = issue_quote(&pool, &config, sender, &quote_request).await.unwrap();
+    assert_eq!(quote.converted_amount, dec("17.82"));
+
+    let mismatched = CreateTransactionRequest {
+        quote_id: Some(quote.id),
+        ..transfer_request(recipient, "25")
+    };
+    assert!(process_transfer(&pool, &config, sender, &mismatched).await.is_err());
+
+    let quoted = CreateTransactionRequest {
+        quote_id: Some(quote.id),
+        ..transfer_request(recipient, "20")
+    };
+    let transaction = process_transfer(&pool, &config, sender, &quoted).await.unwrap();
+    assert_eq!(transaction.fx_quote_id, Some(quote.id));
+    assert!(process_transfer(&pool, &config, sender, &quoted).await.is_err());
+    assert_eq!(wallet_balance(&pool, recipient, "EUR").await, dec("62.37"));
+
+    // Every journal balances within its own currency
+    let report = check_ledger(&pool).await.unwrap();
+    assert!(report.unbalanced_journals.is_empty());
+    assert!(report
+        .account_drift
+        .iter()
+        .all(|drift| drift.user_id != sender && drift.user_id != recipient));
+}
+
+#[actix_rt::test]
+#[ignore = "requires a running Postgres database"]
+async fn test_unverified_senders_can_be_refused() {
+    let pool = setup_pool().await;
+    let sender = create_funded_user(&pool, "50").await.0;
+    let recipient = create_funded_user(&pool, "0").await.0;
+    let config = TransferConfig { require_verified_email: true, ..transfer_config() };
+
+    let result = process_transfer(&pool, &config, sender, &transfer_request(recipient, "10")).await;
+    assert!(matches!(result, Err(AppError::ForbiddenError(_))));
+    assert_eq!(balance_of(&pool, sender).await, BigDecimal::from(50));
+
+    sqlx::query("UPDATE users SET email_verified_at = NOW() WHERE id = $1")
+        .bind(sender)
+        .execute(&pool)
+        .await
+        .unwrap();
+    process_transfer(&pool, &config, sender, &transfer_request(recipient, "10")).await.unwrap();
+    assert_eq!(balance_of(&pool, recipient).await, BigDecimal::from(10));
+}